@@ -1,13 +1,29 @@
+use crate::emu::gb::Speed;
 use crate::emu::joypad::JoypadButton;
 use std::sync::mpsc::{Receiver, Sender};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ControlMessage {
     Exit,
     JoypadInput {
         button: JoypadButton,
         pressed: bool,
     },
+    /// Step back to the newest rewind snapshot
+    Rewind,
+    SetSpeed(Speed),
+    /// Run uncapped while held, then go back to the set speed
+    FastForward(bool),
+    Pause,
+    Resume,
+    /// Run one frame, pausing if running
+    FrameAdvance,
+    /// Start over from power on, keeping the cartridge RAM
+    Reset,
+    SaveState(u8),
+    LoadState(u8),
+    /// Save the screen as a PNG next to the ROM
+    Screenshot,
 }
 
 pub type ControlSender = Sender<ControlMessage>;
@@ -0,0 +1,654 @@
+use crate::app::json::Json;
+use crate::emu::cpu::Op;
+use crate::emu::gb::{Event, Gameboy};
+use crate::emu::symbols::Symbols;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+
+/// Instructions run between checks for new requests while running
+const POLL_STEPS: usize = 10_000;
+
+/// The largest request body read, far above what any client sends
+const MAX_MESSAGE: usize = 1 << 20;
+
+const REGISTERS: i64 = 1;
+const IO: i64 = 2;
+
+const IO_REGISTERS: &[(&str, u16)] = &[
+    ("P1", 0xFF00),
+    ("SB", 0xFF01),
+    ("SC", 0xFF02),
+    ("DIV", 0xFF04),
+    ("TIMA", 0xFF05),
+    ("TMA", 0xFF06),
+    ("TAC", 0xFF07),
+    ("IF", 0xFF0F),
+    ("LCDC", 0xFF40),
+    ("STAT", 0xFF41),
+    ("SCY", 0xFF42),
+    ("SCX", 0xFF43),
+    ("LY", 0xFF44),
+    ("LYC", 0xFF45),
+    ("DMA", 0xFF46),
+    ("BGP", 0xFF47),
+    ("OBP0", 0xFF48),
+    ("OBP1", 0xFF49),
+    ("WY", 0xFF4A),
+    ("WX", 0xFF4B),
+    ("IE", 0xFFFF),
+];
+
+/// A source line breakpoint, resolved through the label it is on
+struct SourceBreakpoint {
+    line: i64,
+    target: Option<(usize, u16)>,
+}
+
+#[derive(Default)]
+pub struct DapServer {
+    seq: i64,
+    /// Where the labels of the source files seen so far are defined
+    labels: HashMap<String, (String, i64)>,
+    source_breakpoints: HashMap<String, Vec<SourceBreakpoint>>,
+    instruction_breakpoints: Vec<u16>,
+    stop_on_entry: bool,
+    running: bool,
+    closed: bool,
+    /// Output events for debug messages logged while running, sent ahead of
+    /// the next stopped event
+    output: Vec<Json>,
+}
+
+impl DapServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn running(&self) -> bool {
+        self.running
+    }
+
+    pub fn closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Waits for a client on localhost:`port` and serves it until it disconnects
+    pub fn listen(&mut self, gb: &mut Gameboy, port: u16) -> io::Result<()> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        eprintln!("Waiting for a debug adapter client on 127.0.0.1:{}", port);
+        let (stream, addr) = listener.accept()?;
+        eprintln!("Debug adapter client connected from {}", addr);
+        stream.set_nodelay(true)?;
+        self.serve(gb, stream)
+    }
+
+    pub fn serve(&mut self, gb: &mut Gameboy, stream: TcpStream) -> io::Result<()> {
+        // Fetch the first opcode so PC points at the next instruction to run
+        if gb.cpu.retired() == 0 {
+            gb.step(1);
+        }
+
+        let mut out = stream.try_clone()?;
+        let mut input = BufReader::new(stream.try_clone()?);
+        while !self.closed {
+            if self.running && !pending(&input, &stream) {
+                for message in self.run(gb, POLL_STEPS) {
+                    write_message(&mut out, &message)?;
+                }
+                continue;
+            }
+
+            let Some(request) = read_message(&mut input)? else {
+                return Ok(());
+            };
+            for message in self.handle(gb, &request) {
+                write_message(&mut out, &message)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a request, giving back its response and any events
+    pub fn handle(&mut self, gb: &mut Gameboy, request: &Json) -> Vec<Json> {
+        let args = request.get("arguments");
+        let command = request.get("command").as_str().unwrap_or("");
+
+        match command {
+            "initialize" => {
+                let capabilities = Json::object([
+                    ("supportsConfigurationDoneRequest", true.into()),
+                    ("supportsInstructionBreakpoints", true.into()),
+                ]);
+                vec![
+                    self.response(request, Ok(capabilities)),
+                    self.event("initialized", Json::object([])),
+                ]
+            }
+            "launch" | "attach" => {
+                self.stop_on_entry = args.get("stopOnEntry").as_bool().unwrap_or(false);
+                let loaded = match args.get("symbols").as_str() {
+                    Some(path) => Symbols::load(Path::new(path)).map(|symbols| {
+                        gb.load_symbols(symbols);
+                    }),
+                    None => Ok(()),
+                };
+                vec![self.response(request, loaded.map(|_| Json::Null))]
+            }
+            "setBreakpoints" => {
+                let path = args.get("source").get("path").as_str().unwrap_or("");
+                let lines: Vec<i64> = args
+                    .get("breakpoints")
+                    .as_array()
+                    .iter()
+                    .filter_map(|b| b.get("line").as_i64())
+                    .collect();
+                let breakpoints = self.set_breakpoints(gb, path, &lines);
+                vec![self.response(
+                    request,
+                    Ok(Json::object([("breakpoints", breakpoints.into())])),
+                )]
+            }
+            "setInstructionBreakpoints" => {
+                self.instruction_breakpoints.clear();
+                let mut breakpoints = Vec::new();
+                for breakpoint in args.get("breakpoints").as_array() {
+                    let offset = breakpoint.get("offset").as_i64().unwrap_or(0);
+                    let addr = breakpoint
+                        .get("instructionReference")
+                        .as_str()
+                        .and_then(parse_addr)
+                        .map(|addr| addr.wrapping_add(offset as u16));
+                    if let Some(addr) = addr {
+                        self.instruction_breakpoints.push(addr);
+                    }
+                    breakpoints.push(Json::object([("verified", addr.is_some().into())]));
+                }
+                vec![self.response(
+                    request,
+                    Ok(Json::object([("breakpoints", breakpoints.into())])),
+                )]
+            }
+            "configurationDone" => {
+                let response = self.response(request, Ok(Json::Null));
+                if self.stop_on_entry {
+                    vec![response, self.stopped("entry", None)]
+                } else {
+                    self.running = true;
+                    vec![response]
+                }
+            }
+            "threads" => {
+                let thread = Json::object([("id", 1.into()), ("name", "SM83".into())]);
+                vec![self.response(
+                    request,
+                    Ok(Json::object([("threads", vec![thread].into())])),
+                )]
+            }
+            "stackTrace" => {
+                let addrs = std::iter::once(gb.cpu.cur_pc()).chain(gb.call_stack());
+                let frames: Vec<Json> = addrs
+                    .enumerate()
+                    .map(|(id, addr)| self.frame(gb, id as i64, addr))
+                    .collect();
+                let total = frames.len() as i64;
+                vec![self.response(
+                    request,
+                    Ok(Json::object([
+                        ("stackFrames", frames.into()),
+                        ("totalFrames", total.into()),
+                    ])),
+                )]
+            }
+            "scopes" => {
+                let scope = |name: &str, reference: i64| {
+                    Json::object([
+                        ("name", name.into()),
+                        ("variablesReference", reference.into()),
+                        ("expensive", false.into()),
+                    ])
+                };
+                let scopes = vec![scope("Registers", REGISTERS), scope("IO", IO)];
+                vec![self.response(request, Ok(Json::object([("scopes", scopes.into())])))]
+            }
+            "variables" => {
+                let variables = match args.get("variablesReference").as_i64() {
+                    Some(REGISTERS) => registers(gb),
+                    Some(IO) => IO_REGISTERS
+                        .iter()
+                        .map(|(name, addr)| {
+                            variable(name, format!("${:02X}", gb.mem_dbg_read(*addr)))
+                        })
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                vec![self.response(request, Ok(Json::object([("variables", variables.into())])))]
+            }
+            "continue" => {
+                self.running = true;
+                vec![self.response(
+                    request,
+                    Ok(Json::object([("allThreadsContinued", true.into())])),
+                )]
+            }
+            "next" | "stepIn" | "stepOut" => {
+                let response = self.response(request, Ok(Json::Null));
+                let event = match command {
+                    "next" => self.step_over(gb),
+                    "stepIn" => self.step(gb),
+                    _ => self.step_out(gb),
+                };
+                let stopped = match event {
+                    Some(event) => self.event_stopped(event),
+                    None => self.stopped("step", None),
+                };
+                let mut messages = vec![response];
+                messages.append(&mut self.output);
+                messages.push(stopped);
+                messages
+            }
+            "pause" => {
+                self.running = false;
+                let response = self.response(request, Ok(Json::Null));
+                vec![response, self.stopped("pause", None)]
+            }
+            "disconnect" => {
+                self.closed = true;
+                vec![self.response(request, Ok(Json::Null))]
+            }
+            _ => vec![self.response(request, Err(format!("unsupported request: {}", command)))],
+        }
+    }
+
+    /// Runs up to `steps` instructions while running, giving a stopped
+    /// event if a breakpoint or an event was hit
+    pub fn run(&mut self, gb: &mut Gameboy, steps: usize) -> Vec<Json> {
+        let mut stopped = None;
+        for _ in 0..steps {
+            if !self.running {
+                break;
+            }
+            if let Some(event) = self.step(gb) {
+                stopped = Some(self.event_stopped(event));
+                break;
+            }
+            if self.at_breakpoint(gb) {
+                self.running = false;
+                stopped = Some(self.stopped("breakpoint", None));
+                break;
+            }
+        }
+        let mut messages = std::mem::take(&mut self.output);
+        messages.extend(stopped);
+        messages
+    }
+
+    /// Runs one instruction, sending a debug message as output rather than
+    /// stopping on it
+    fn step(&mut self, gb: &mut Gameboy) -> Option<Event> {
+        match gb.step(1) {
+            Some(Event::Message { text, .. }) => {
+                let body = Json::object([
+                    ("category", "console".into()),
+                    ("output", format!("{}\n", text).into()),
+                ]);
+                let output = self.event("output", body);
+                self.output.push(output);
+                None
+            }
+            event => event,
+        }
+    }
+
+    fn step_over(&mut self, gb: &mut Gameboy) -> Option<Event> {
+        let current = gb.disasm(gb.cpu.cur_pc());
+        let call = matches!(
+            Op::from(current.bytes[0]),
+            Op::CallImm16 | Op::CallCondImm16 | Op::RstTgt3
+        );
+        if let Some(event) = self.step(gb) {
+            return Some(event);
+        }
+        while call && gb.cpu.cur_pc() != current.next_addr() {
+            if let Some(event) = self.step(gb) {
+                return Some(event);
+            }
+        }
+        None
+    }
+
+    /// Runs until a return leaves the current function
+    fn step_out(&mut self, gb: &mut Gameboy) -> Option<Event> {
+        let sp = gb.cpu.sp();
+        loop {
+            let ret = matches!(Op::from(gb.cpu.ir()), Op::Ret | Op::RetCond | Op::Reti);
+            if let Some(event) = self.step(gb) {
+                return Some(event);
+            }
+            if ret && gb.cpu.sp() > sp {
+                return None;
+            }
+        }
+    }
+
+    fn at_breakpoint(&self, gb: &Gameboy) -> bool {
+        let pc = gb.cpu.cur_pc();
+        if self.instruction_breakpoints.contains(&pc) {
+            return true;
+        }
+        self.source_breakpoints
+            .values()
+            .flatten()
+            .filter_map(|b| b.target)
+            .any(|target| mapped_at(gb, target, pc))
+    }
+
+    fn set_breakpoints(&mut self, gb: &Gameboy, path: &str, lines: &[i64]) -> Vec<Json> {
+        let source: Vec<String> = fs::read_to_string(path)
+            .map(|text| text.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+        let labels = source_labels(&source);
+        for (line, label) in labels.iter().enumerate() {
+            if let Some(label) = label {
+                self.labels
+                    .insert(label.clone(), (path.to_string(), line as i64 + 1));
+            }
+        }
+
+        let mut breakpoints = Vec::new();
+        let mut replies = Vec::new();
+        for &line in lines {
+            let label = label_for_line(&source, &labels, line);
+            let target = label
+                .as_deref()
+                .and_then(|label| gb.symbols().lookup(label))
+                .map(|symbol| (symbol.bank, symbol.addr));
+            let reply = match (&label, target) {
+                (_, Some((_, addr))) => Json::object([
+                    ("verified", true.into()),
+                    ("line", line.into()),
+                    ("instructionReference", format!("0x{:04X}", addr).into()),
+                ]),
+                (Some(label), None) => Json::object([
+                    ("verified", false.into()),
+                    ("line", line.into()),
+                    (
+                        "message",
+                        format!("{} is not in the symbol file", label).into(),
+                    ),
+                ]),
+                (None, None) => Json::object([
+                    ("verified", false.into()),
+                    ("line", line.into()),
+                    (
+                        "message",
+                        "breakpoints need a label on or right before the line".into(),
+                    ),
+                ]),
+            };
+            breakpoints.push(SourceBreakpoint { line, target });
+            replies.push(reply);
+        }
+        self.source_breakpoints
+            .insert(path.to_string(), breakpoints);
+        replies
+    }
+
+    fn frame(&self, gb: &Gameboy, id: i64, addr: u16) -> Json {
+        let name = gb.symbolize(addr).unwrap_or_else(|| gb.disasm(addr).text);
+
+        // Breakpoint lines and labels are the only lines known to match an address
+        let location = self
+            .source_breakpoints
+            .iter()
+            .find_map(|(path, breakpoints)| {
+                breakpoints
+                    .iter()
+                    .find(|b| b.target.is_some_and(|target| mapped_at(gb, target, addr)))
+                    .map(|b| (path.clone(), b.line))
+            })
+            .or_else(|| {
+                gb.label(addr)
+                    .and_then(|label| self.labels.get(label).cloned())
+            });
+
+        let mut fields = vec![
+            ("id".to_string(), id.into()),
+            ("name".to_string(), name.into()),
+            ("column".to_string(), 0.into()),
+            (
+                "instructionPointerReference".to_string(),
+                format!("0x{:04X}", addr).into(),
+            ),
+        ];
+        match location {
+            Some((path, line)) => {
+                fields.push(("line".to_string(), line.into()));
+                fields.push(("source".to_string(), Json::object([("path", path.into())])));
+            }
+            None => fields.push(("line".to_string(), 0.into())),
+        }
+        Json::Object(fields)
+    }
+
+    fn event_stopped(&mut self, event: Event) -> Json {
+        self.running = false;
+        match event {
+            Event::CpuLocked { pc, opcode } => self.stopped(
+                "exception",
+                Some(format!(
+                    "CPU locked up on opcode ${:02X} at ${:04X}",
+                    opcode, pc
+                )),
+            ),
+            Event::Watch(hit) => self.stopped("data breakpoint", Some(hit.to_string())),
+            Event::SoftBreak { pc, opcode } => self.stopped(
+                "breakpoint",
+                Some(format!(
+                    "Software breakpoint ${:02X} at ${:04X}",
+                    opcode, pc
+                )),
+            ),
+            Event::Message { text, .. } => self.stopped("breakpoint", Some(text)),
+        }
+    }
+
+    fn stopped(&mut self, reason: &str, description: Option<String>) -> Json {
+        let mut body = vec![
+            ("reason".to_string(), reason.into()),
+            ("threadId".to_string(), 1.into()),
+            ("allThreadsStopped".to_string(), true.into()),
+        ];
+        if let Some(description) = description {
+            body.push(("description".to_string(), description.into()));
+        }
+        self.event("stopped", Json::Object(body))
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> Json {
+        self.seq += 1;
+        Json::object([
+            ("seq", self.seq.into()),
+            ("type", "event".into()),
+            ("event", event.into()),
+            ("body", body),
+        ])
+    }
+
+    fn response(&mut self, request: &Json, result: Result<Json, String>) -> Json {
+        self.seq += 1;
+        let mut fields = vec![
+            ("seq".to_string(), self.seq.into()),
+            ("type".to_string(), "response".into()),
+            (
+                "request_seq".to_string(),
+                request.get("seq").as_i64().unwrap_or(0).into(),
+            ),
+            ("command".to_string(), request.get("command").clone()),
+            ("success".to_string(), result.is_ok().into()),
+        ];
+        match result {
+            Ok(Json::Null) => {}
+            Ok(body) => fields.push(("body".to_string(), body)),
+            Err(message) => fields.push(("message".to_string(), message.into())),
+        }
+        Json::Object(fields)
+    }
+}
+
+/// Whether `target` is `addr`, with banked ROM labels only matching while
+/// their bank is mapped in
+fn mapped_at(gb: &Gameboy, (bank, target): (usize, u16), addr: u16) -> bool {
+    target == addr && (!(0x4000..=0x7FFF).contains(&addr) || gb.mem_rom_bank(addr) == bank)
+}
+
+fn registers(gb: &Gameboy) -> Vec<Json> {
+    let cpu = &gb.cpu;
+    let flag = |set: u8, name: char| if set != 0 { name } else { '-' };
+    let byte = |v: u8| format!("${:02X}", v);
+    let word = |v: u16| format!("${:04X}", v);
+    vec![
+        variable("A", byte(cpu.a())),
+        variable("F", byte(cpu.f())),
+        variable("B", byte(cpu.b())),
+        variable("C", byte(cpu.c())),
+        variable("D", byte(cpu.d())),
+        variable("E", byte(cpu.e())),
+        variable("H", byte(cpu.h())),
+        variable("L", byte(cpu.l())),
+        variable("SP", word(cpu.sp())),
+        variable("PC", word(cpu.cur_pc())),
+        variable(
+            "Flags",
+            format!(
+                "{}{}{}{}",
+                flag(cpu.zero(), 'Z'),
+                flag(cpu.bcdn(), 'N'),
+                flag(cpu.bcdh(), 'H'),
+                flag(cpu.carry(), 'C')
+            ),
+        ),
+        variable("IME", cpu.ime().to_string()),
+    ]
+}
+
+fn variable(name: &str, value: String) -> Json {
+    Json::object([
+        ("name", name.into()),
+        ("value", value.into()),
+        ("variablesReference", 0.into()),
+    ])
+}
+
+/// The label defined on each line of an rgbds source file, locals included
+/// as `Parent.local` the way rgblink writes them to the `.sym` file
+fn source_labels(source: &[String]) -> Vec<Option<String>> {
+    let mut parent = String::new();
+    source
+        .iter()
+        .map(|line| {
+            let code = line.split(';').next().unwrap_or("").trim();
+            let end = code
+                .find(|c: char| !(c.is_ascii_alphanumeric() || "_.#@$".contains(c)))
+                .unwrap_or(code.len());
+            let (name, rest) = code.split_at(end);
+            let local = name.starts_with('.');
+            if name.is_empty() || !(rest.starts_with(':') || local && rest.trim().is_empty()) {
+                return None;
+            }
+            if local {
+                Some(format!("{}{}", parent, name))
+            } else {
+                if let Some((global, _)) = name.split_once('.') {
+                    parent = global.to_string();
+                } else {
+                    parent = name.to_string();
+                }
+                Some(name.to_string())
+            }
+        })
+        .collect()
+}
+
+/// The label a breakpoint on `line` stops at: one defined on the line, or
+/// one right before it with only blank lines and comments in between
+fn label_for_line(source: &[String], labels: &[Option<String>], line: i64) -> Option<String> {
+    let index = usize::try_from(line - 1).ok()?;
+    for i in (0..=index.min(source.len().checked_sub(1)?)).rev() {
+        if let Some(label) = &labels[i] {
+            return Some(label.clone());
+        }
+        let code = source[i].split(';').next().unwrap_or("").trim();
+        if i != index && !code.is_empty() {
+            return None;
+        }
+    }
+    None
+}
+
+fn parse_addr(value: &str) -> Option<u16> {
+    let digits = value
+        .trim_start_matches("0x")
+        .trim_start_matches("0X")
+        .trim_start_matches('$');
+    u16::from_str_radix(digits, 16).ok()
+}
+
+/// Whether a request is waiting, without blocking
+fn pending(input: &BufReader<TcpStream>, stream: &TcpStream) -> bool {
+    if !input.buffer().is_empty() {
+        return true;
+    }
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let mut byte = [0u8];
+    let pending = matches!(stream.peek(&mut byte), Ok(1));
+    let _ = stream.set_nonblocking(false);
+    pending
+}
+
+/// Reads one `Content-Length` framed message
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("Content-Length")
+        {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let length = length.unwrap_or(0);
+    if length > MAX_MESSAGE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {} bytes is over {}", length, MAX_MESSAGE),
+        ));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    let text = String::from_utf8_lossy(&body);
+    Json::parse(&text)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_message(out: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()
+}
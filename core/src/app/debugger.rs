@@ -0,0 +1,570 @@
+use crate::emu::bess::Bess;
+use crate::emu::cpu::Op;
+use crate::emu::gb::{Comp, Event, Gameboy, Reverse};
+use crate::emu::state;
+use crate::emu::symbols::Symbols;
+use crate::emu::watch::{Access, Watchpoint};
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+const HELP: &str = "\
+step|s [n]             run n instructions, stepping into calls
+next|n [n]             run n instructions, stepping over calls
+tick|t [n]             run n T-cycles
+continue|c             run until a breakpoint or event
+rstep|rs [n]           go back n instructions
+rcontinue|rc           go back to the last breakpoint or watchpoint hit
+regs|r                 show registers and flags
+set <reg> <hex>        set a, f, b, c, d, e, h, l, af, bc, de, hl, sp or pc
+flag <z|n|h|c> <0|1>   set a flag
+mem|x <addr> [len]     hexdump memory
+poke <addr> <byte>..   write bytes to memory
+disasm|d [addr] [n]    disassemble n instructions, from PC by default
+break|b <addr>         break when PC reaches addr, or bank:addr
+break|b op <opcode>    break before an opcode is executed
+bt|backtrace           show PC and the return addresses on the stack
+breaks                 list breakpoints
+delete [index]         delete one or all breakpoints
+watch <rwx> <addr>[-<end>] [comp] [=<hex>]
+                       stop on reads, writes or opcode fetches in a range,
+                       optionally only by cpu, ppu, timer, serial or joypad
+                       and only for a value
+watches                list watchpoints
+unwatch [id]           delete one or all watchpoints
+symbols <file>         load labels from an rgbds .sym file
+save [bess] <file>     save the machine state, as BESS for other emulators
+load <file>            load a state saved by save or a BESS state
+rewind [n]             go back n snapshots in the rewind buffer
+quit|q                 exit the debugger
+
+Addresses are hex or a label from the loaded symbols. Going back replays
+from the snapshots of the rewind buffer";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Breakpoint {
+    Pc(u16),
+    /// PC in a ROM bank, only while that bank is mapped in
+    Banked(usize, u16),
+    Opcode(u8),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    Text(String),
+    Quit,
+}
+
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    last: String,
+    /// Debug messages the program logged while running, shown before the
+    /// reply
+    messages: Vec<String>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// Reads commands from `input` until it ends or `quit` is entered
+    pub fn run(&mut self, gb: &mut Gameboy, input: impl BufRead, mut out: impl Write) {
+        // Fetch the first opcode so PC points at the next instruction to run
+        if gb.cpu.retired() == 0 {
+            gb.step(1);
+        }
+        let _ = writeln!(out, "{}", location(gb));
+
+        let mut lines = input.lines();
+        loop {
+            let _ = write!(out, "(gzdb) ");
+            let _ = out.flush();
+            let Some(Ok(line)) = lines.next() else {
+                return;
+            };
+
+            // An empty line repeats the previous command
+            let line = if line.trim().is_empty() {
+                self.last.clone()
+            } else {
+                self.last = line.clone();
+                line
+            };
+
+            match self.execute(gb, &line) {
+                Ok(Reply::Text(text)) => {
+                    let _ = writeln!(out, "{}", text);
+                }
+                Ok(Reply::Quit) => return,
+                Err(err) => {
+                    let _ = writeln!(out, "error: {}", err);
+                }
+            }
+        }
+    }
+
+    pub fn execute(&mut self, gb: &mut Gameboy, line: &str) -> Result<Reply, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&cmd, args)) = words.split_first() else {
+            return Ok(Reply::Text(String::new()));
+        };
+
+        let text = match cmd {
+            "help" | "h" => HELP.to_string(),
+            "step" | "s" => {
+                let n = count(args.first(), 1)?;
+                self.step(gb, n, false)
+            }
+            "next" | "n" => {
+                let n = count(args.first(), 1)?;
+                self.step(gb, n, true)
+            }
+            "tick" | "t" => {
+                let n = count(args.first(), 1)?;
+                gb.tick(n as u128);
+                format!("t: {} mc: {:?}\n{}", gb.t, gb.cpu.mc(), location(gb))
+            }
+            "continue" | "c" => self.cont(gb),
+            "rstep" | "rs" => {
+                let n = count(args.first(), 1)?;
+                let reverse = gb.step_back(n)?;
+                reversed(gb, reverse)
+            }
+            "rcontinue" | "rc" => {
+                let reverse = gb.reverse_continue(|gb| self.hit(gb).is_some())?;
+                match self.hit(gb) {
+                    Some(hit) if reverse == Reverse::Done => {
+                        format!("Hit {:?}\n{}", hit, location(gb))
+                    }
+                    _ => reversed(gb, reverse),
+                }
+            }
+            "regs" | "r" => registers(gb),
+            "set" => {
+                let [reg, value] = args else {
+                    return Err("usage: set <reg> <hex>".to_string());
+                };
+                set_register(gb, reg, hex(value)?)?;
+                registers(gb)
+            }
+            "flag" => {
+                let [flag, value] = args else {
+                    return Err("usage: flag <z|n|h|c> <0|1>".to_string());
+                };
+                let bit = match *value {
+                    "0" => 0,
+                    "1" => 1,
+                    _ => return Err(format!("invalid flag value: {}", value)),
+                };
+                match *flag {
+                    "z" => gb.cpu.set_zero(bit),
+                    "n" => gb.cpu.set_bcdn(bit),
+                    "h" => gb.cpu.set_bcdh(bit),
+                    "c" => gb.cpu.set_carry(bit),
+                    _ => return Err(format!("unknown flag: {}", flag)),
+                }
+                registers(gb)
+            }
+            "mem" | "x" => {
+                let Some(addr) = args.first() else {
+                    return Err("usage: mem <addr> [len]".to_string());
+                };
+                hexdump(gb, address(gb, addr)?, count(args.get(1), 0x40)?)
+            }
+            "poke" => {
+                let Some((addr, bytes)) = args.split_first() else {
+                    return Err("usage: poke <addr> <byte>..".to_string());
+                };
+                let addr = address(gb, addr)?;
+                for (i, byte) in bytes.iter().enumerate() {
+                    let byte = u8::try_from(hex(byte)?).map_err(|e| e.to_string())?;
+                    gb.mem_dbg_write(addr.wrapping_add(i as u16), byte);
+                }
+                hexdump(gb, addr, bytes.len().max(1))
+            }
+            "disasm" | "d" => {
+                let from = match args.first() {
+                    Some(addr) => address(gb, addr)?,
+                    None => gb.cpu.cur_pc(),
+                };
+                let mut addr = from;
+                let mut lines = Vec::new();
+                for _ in 0..count(args.get(1), 10)? {
+                    if let Some(label) = gb.label(addr) {
+                        lines.push(format!("{}:", label));
+                    }
+                    let instruction = gb.disasm(addr);
+                    let marker = if addr == gb.cpu.cur_pc() { "=>" } else { "  " };
+                    lines.push(format!("{} {}", marker, instruction));
+                    addr = instruction.next_addr();
+                }
+                lines.join("\n")
+            }
+            "break" | "b" => {
+                let breakpoint = match args {
+                    ["op", opcode] => {
+                        Breakpoint::Opcode(u8::try_from(hex(opcode)?).map_err(|e| e.to_string())?)
+                    }
+                    [addr] => breakpoint(gb, addr)?,
+                    _ => return Err("usage: break <addr> | break op <opcode>".to_string()),
+                };
+                self.breakpoints.push(breakpoint);
+                format!("#{} {:?}", self.breakpoints.len() - 1, breakpoint)
+            }
+            "bt" | "backtrace" => {
+                let pc = gb.cpu.cur_pc();
+                let mut lines = vec![format!("#0 {}", frame(gb, pc))];
+                for (i, ret) in gb.call_stack().into_iter().enumerate() {
+                    lines.push(format!("#{} {}", i + 1, frame(gb, ret)));
+                }
+                lines.join("\n")
+            }
+            "breaks" => self
+                .breakpoints
+                .iter()
+                .enumerate()
+                .map(|(i, b)| format!("#{} {:?}", i, b))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            "delete" => {
+                match args.first() {
+                    Some(index) => {
+                        let index = count(Some(index), 0)?;
+                        if index >= self.breakpoints.len() {
+                            return Err(format!("no breakpoint #{}", index));
+                        }
+                        self.breakpoints.remove(index);
+                    }
+                    None => self.breakpoints.clear(),
+                }
+                String::new()
+            }
+            "watch" => {
+                let watchpoint = watchpoint(gb, args)?;
+                let id = gb.add_watchpoint(watchpoint);
+                format!("watch #{}", id)
+            }
+            "watches" => gb
+                .watchpoints()
+                .iter()
+                .map(|(id, w)| format!("#{} {}", id, w))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            "unwatch" => {
+                match args.first() {
+                    Some(id) => {
+                        let id = count(Some(id), 0)?;
+                        if !gb.remove_watchpoint(id) {
+                            return Err(format!("no watchpoint #{}", id));
+                        }
+                    }
+                    None => {
+                        for (id, _) in gb.watchpoints() {
+                            gb.remove_watchpoint(id);
+                        }
+                    }
+                }
+                String::new()
+            }
+            "symbols" => {
+                let [path] = args else {
+                    return Err("usage: symbols <file>".to_string());
+                };
+                let symbols = Symbols::load(Path::new(path))?;
+                let text = format!("{} symbols", symbols.len());
+                gb.load_symbols(symbols);
+                text
+            }
+            "save" => {
+                let (data, path) = match args {
+                    ["bess", path] => (gb.export_bess()?, path),
+                    [path] => (gb.save_state(), path),
+                    _ => return Err("usage: save [bess] <file>".to_string()),
+                };
+                fs::write(path, &data).map_err(|e| format!("{}: {}", path, e))?;
+                format!("saved {} bytes", data.len())
+            }
+            "load" => {
+                let [path] = args else {
+                    return Err("usage: load <file>".to_string());
+                };
+                let data = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+                if !data.starts_with(state::MAGIC) && Bess::detect(&data) {
+                    gb.import_bess(&data)?;
+                } else {
+                    gb.load_state(&data)?;
+                }
+                location(gb)
+            }
+            "rewind" => {
+                let n = count(args.first(), 1)?;
+                // Stops at the oldest snapshot when asked to go further back
+                for i in 0..n {
+                    match gb.rewind() {
+                        Err(err) if i == 0 => return Err(err),
+                        Err(_) => break,
+                        Ok(()) => {}
+                    }
+                }
+                format!("t: {}\n{}", gb.t, location(gb))
+            }
+            "quit" | "q" => return Ok(Reply::Quit),
+            _ => return Err(format!("unknown command: {} (try help)", cmd)),
+        };
+        let messages: String = self
+            .messages
+            .drain(..)
+            .map(|message| format!("Message: {}\n", message))
+            .collect();
+        Ok(Reply::Text(messages + &text))
+    }
+
+    fn step(&mut self, gb: &mut Gameboy, n: usize, over: bool) -> String {
+        for _ in 0..n {
+            let current = gb.disasm(gb.cpu.cur_pc());
+            let call = matches!(
+                Op::from(current.bytes[0]),
+                Op::CallImm16 | Op::CallCondImm16 | Op::RstTgt3
+            );
+
+            if let Some(event) = self.step_one(gb) {
+                return stopped(gb, event);
+            }
+
+            // Stepping over a call runs until it returns to the next instruction
+            if over && call {
+                while gb.cpu.cur_pc() != current.next_addr() {
+                    if let Some(event) = self.step_one(gb) {
+                        return stopped(gb, event);
+                    }
+                    if let Some(hit) = self.hit(gb) {
+                        return format!("Hit {:?}\n{}", hit, location(gb));
+                    }
+                }
+            }
+        }
+        location(gb)
+    }
+
+    fn cont(&mut self, gb: &mut Gameboy) -> String {
+        loop {
+            if let Some(event) = self.step_one(gb) {
+                return stopped(gb, event);
+            }
+            if let Some(hit) = self.hit(gb) {
+                return format!("Hit {:?}\n{}", hit, location(gb));
+            }
+        }
+    }
+
+    /// Runs one instruction, keeping a debug message for the reply rather
+    /// than stopping on it
+    fn step_one(&mut self, gb: &mut Gameboy) -> Option<Event> {
+        match gb.step(1) {
+            Some(Event::Message { text, .. }) => {
+                self.messages.push(text);
+                None
+            }
+            event => event,
+        }
+    }
+
+    fn hit(&self, gb: &Gameboy) -> Option<Breakpoint> {
+        self.breakpoints.iter().copied().find(|b| match b {
+            Breakpoint::Pc(pc) => gb.cpu.cur_pc() == *pc,
+            Breakpoint::Banked(bank, pc) => gb.cpu.cur_pc() == *pc && gb.mem_rom_bank(*pc) == *bank,
+            Breakpoint::Opcode(op) => gb.cpu.ir() == *op,
+        })
+    }
+}
+
+fn watchpoint(gb: &Gameboy, args: &[&str]) -> Result<Watchpoint, String> {
+    let usage = || "usage: watch <rwx> <addr>[-<end>] [comp] [=<hex>]".to_string();
+    let [kinds, range, rest @ ..] = args else {
+        return Err(usage());
+    };
+
+    let mut access = Vec::new();
+    for kind in kinds.chars() {
+        access.push(match kind {
+            'r' => Access::Read,
+            'w' => Access::Write,
+            'x' => Access::Execute,
+            _ => return Err(usage()),
+        });
+    }
+
+    let range = match range.split_once('-') {
+        Some((start, end)) => address(gb, start)?..=address(gb, end)?,
+        None => address(gb, range)?..=address(gb, range)?,
+    };
+
+    let mut watchpoint = Watchpoint::new(range, &access);
+    for arg in rest {
+        watchpoint = match *arg {
+            "cpu" => watchpoint.with_comp(Comp::Cpu),
+            "ppu" => watchpoint.with_comp(Comp::Ppu),
+            "timer" => watchpoint.with_comp(Comp::Timer),
+            "serial" => watchpoint.with_comp(Comp::Serial),
+            "joypad" => watchpoint.with_comp(Comp::Joypad),
+            value if value.starts_with('=') => {
+                let value = u8::try_from(hex(&value[1..])?).map_err(|e| e.to_string())?;
+                watchpoint.with_value(value)
+            }
+            _ => return Err(usage()),
+        };
+    }
+    Ok(watchpoint)
+}
+
+fn stopped(gb: &Gameboy, event: Event) -> String {
+    match event {
+        Event::Watch(hit) => format!("Hit {}\n{}", hit, location(gb)),
+        Event::SoftBreak { pc, opcode } => format!(
+            "Software breakpoint ${:02X} at ${:04X}\n{}",
+            opcode,
+            pc,
+            location(gb)
+        ),
+        event => format!("Stopped: {:?}\n{}", event, location(gb)),
+    }
+}
+
+fn reversed(gb: &Gameboy, reverse: Reverse) -> String {
+    match reverse {
+        Reverse::Done => location(gb),
+        Reverse::Watch(hit) => stopped(gb, Event::Watch(hit)),
+        Reverse::Oldest => format!("Reached the oldest snapshot\n{}", location(gb)),
+    }
+}
+
+fn location(gb: &Gameboy) -> String {
+    let pc = gb.cpu.cur_pc();
+    match gb.symbolize(pc) {
+        Some(name) => format!("=> {}  ; {}", gb.disasm(pc), name),
+        None => format!("=> {}", gb.disasm(pc)),
+    }
+}
+
+fn frame(gb: &Gameboy, addr: u16) -> String {
+    let bank = gb.mem_rom_bank(addr);
+    match gb.symbolize(addr) {
+        Some(name) => format!("{:02X}:{:04X} {}", bank, addr, name),
+        None => format!("{:02X}:{:04X}", bank, addr),
+    }
+}
+
+fn registers(gb: &Gameboy) -> String {
+    let cpu = &gb.cpu;
+    let flag = |set: u8, name: char| if set != 0 { name } else { '-' };
+    format!(
+        "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} [{}{}{}{}] IME:{}{}\n{}",
+        cpu.a(),
+        cpu.f(),
+        cpu.b(),
+        cpu.c(),
+        cpu.d(),
+        cpu.e(),
+        cpu.h(),
+        cpu.l(),
+        cpu.sp(),
+        cpu.cur_pc(),
+        flag(cpu.zero(), 'Z'),
+        flag(cpu.bcdn(), 'N'),
+        flag(cpu.bcdh(), 'H'),
+        flag(cpu.carry(), 'C'),
+        cpu.ime(),
+        if cpu.halted() { " HALTED" } else { "" },
+        location(gb)
+    )
+}
+
+fn set_register(gb: &mut Gameboy, reg: &str, value: u16) -> Result<(), String> {
+    let cpu = &mut gb.cpu;
+    let byte = || u8::try_from(value).map_err(|_| format!("{} takes a byte", reg));
+    match reg {
+        "a" => cpu.set_a(byte()?),
+        "f" => cpu.set_af((cpu.af() & 0xFF00) | byte()? as u16),
+        "b" => cpu.set_b(byte()?),
+        "c" => cpu.set_c(byte()?),
+        "d" => cpu.set_d(byte()?),
+        "e" => cpu.set_e(byte()?),
+        "h" => cpu.set_h(byte()?),
+        "l" => cpu.set_l(byte()?),
+        "af" => cpu.set_af(value),
+        "bc" => cpu.set_bc(value),
+        "de" => cpu.set_de(value),
+        "hl" => cpu.set_hl(value),
+        "sp" => cpu.set_sp(value),
+        "pc" => cpu.dbg_jump(value),
+        _ => return Err(format!("unknown register: {}", reg)),
+    }
+    Ok(())
+}
+
+fn hexdump(gb: &Gameboy, addr: u16, len: usize) -> String {
+    let mut lines = Vec::new();
+    for row in (0..len).step_by(16) {
+        let start = addr.wrapping_add(row as u16);
+        let bytes: Vec<u8> = (0..16.min(len - row))
+            .map(|i| gb.mem_dbg_read_mapped(start.wrapping_add(i as u16)))
+            .collect();
+        let hex = bytes
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let ascii: String = bytes
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
+            .collect();
+        lines.push(format!("{:04X}: {:<47}  {}", start, hex, ascii));
+    }
+    lines.join("\n")
+}
+
+/// Parses a breakpoint address, keeping the bank of banked ROM labels and
+/// of `bank:addr` pairs
+fn breakpoint(gb: &Gameboy, value: &str) -> Result<Breakpoint, String> {
+    let banked = |bank: usize, addr: u16| match addr {
+        0x4000..=0x7FFF => Breakpoint::Banked(bank, addr),
+        _ => Breakpoint::Pc(addr),
+    };
+    if let Some((bank, addr)) = value.split_once(':') {
+        let bank =
+            usize::from_str_radix(bank, 16).map_err(|_| format!("invalid bank: {}", bank))?;
+        return Ok(banked(bank, hex(addr)?));
+    }
+    match gb.symbols().lookup(value) {
+        Some(symbol) => Ok(banked(symbol.bank, symbol.addr)),
+        None => hex(value).map(Breakpoint::Pc),
+    }
+}
+
+/// Parses a hex address or a label
+fn address(gb: &Gameboy, value: &str) -> Result<u16, String> {
+    match gb.symbols().lookup(value) {
+        Some(symbol) => Ok(symbol.addr),
+        None => hex(value).map_err(|_| format!("invalid address or unknown label: {}", value)),
+    }
+}
+
+fn hex(value: &str) -> Result<u16, String> {
+    let digits = value.trim_start_matches("0x").trim_start_matches('$');
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid hex value: {}", value))
+}
+
+fn count(value: Option<&&str>, default: usize) -> Result<usize, String> {
+    match value {
+        Some(value) => value
+            .parse()
+            .map_err(|_| format!("invalid count: {}", value)),
+        None => Ok(default),
+    }
+}
@@ -0,0 +1,403 @@
+use crate::emu::gb::{Event, Gameboy, Reverse};
+use crate::emu::watch::{Access, Watchpoint};
+use std::io::{self, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+/// Registers are sent as little endian 16-bit pairs in this order
+const REGISTERS: usize = 6;
+
+/// Instructions run between checks for a ^C from the client
+const POLL_STEPS: usize = 10_000;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    /// Packet data to send back, empty for unsupported packets
+    Reply(String),
+    /// Resume execution, then reply with a stop reason
+    Resume { step: bool },
+    /// The client detached or killed the session
+    Close,
+}
+
+#[derive(Default)]
+pub struct GdbStub {
+    /// `(type, addr)` of the breakpoints added with `Z0` and `Z1`
+    breakpoints: Vec<(u8, u16)>,
+    /// `(type, addr, len, id)` of the watchpoints added with `Z2`..`Z4`
+    watchpoints: Vec<(u8, u16, u16, usize)>,
+}
+
+impl GdbStub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn breakpoints(&self) -> &[(u8, u16)] {
+        &self.breakpoints
+    }
+
+    /// Waits for a client on localhost:`port` and serves it until it detaches
+    pub fn listen(&mut self, gb: &mut Gameboy, port: u16) -> io::Result<()> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        eprintln!("Waiting for gdb on 127.0.0.1:{}", port);
+        let (stream, addr) = listener.accept()?;
+        eprintln!("gdb connected from {}", addr);
+        stream.set_nodelay(true)?;
+        self.serve(gb, stream)
+    }
+
+    pub fn serve(&mut self, gb: &mut Gameboy, stream: TcpStream) -> io::Result<()> {
+        // Fetch the first opcode so PC points at the next instruction to run
+        if gb.cpu.retired() == 0 {
+            gb.step(1);
+        }
+
+        let mut out = stream.try_clone()?;
+        let mut input = BufReader::new(stream.try_clone()?).bytes();
+        loop {
+            let Some(packet) = read_packet(&mut input, &mut out)? else {
+                return Ok(());
+            };
+            let reply = match self.packet(gb, &packet) {
+                Response::Reply(reply) => reply,
+                Response::Resume { step } => self.resume(gb, step, || interrupted(&stream)),
+                Response::Close => {
+                    write_packet(&mut out, "OK")?;
+                    return Ok(());
+                }
+            };
+            write_packet(&mut out, &reply)?;
+        }
+    }
+
+    /// Handles the data of one packet, without the `$` and checksum
+    pub fn packet(&mut self, gb: &mut Gameboy, packet: &str) -> Response {
+        let reply = |r: &str| Response::Reply(r.to_string());
+        let Some(cmd) = packet.chars().next() else {
+            return reply("");
+        };
+        // Lossy decoding may have turned the command into a multibyte char
+        let Some(args) = packet.get(1..) else {
+            return reply("E01");
+        };
+
+        match cmd {
+            '?' => Response::Reply(signal(SIGTRAP)),
+            'g' => Response::Reply(
+                registers(gb)
+                    .iter()
+                    .map(|r| format!("{:04x}", r.swap_bytes()))
+                    .collect(),
+            ),
+            'G' => match decode_hex(args) {
+                Some(bytes) if bytes.len() >= REGISTERS * 2 => {
+                    for (n, pair) in bytes.chunks(2).take(REGISTERS).enumerate() {
+                        set_register(gb, n, u16::from_le_bytes([pair[0], pair[1]]));
+                    }
+                    reply("OK")
+                }
+                _ => reply("E01"),
+            },
+            'p' => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < REGISTERS => {
+                    Response::Reply(format!("{:04x}", registers(gb)[n].swap_bytes()))
+                }
+                _ => reply("E01"),
+            },
+            'P' => {
+                let parsed = args.split_once('=').and_then(|(n, value)| {
+                    let n = usize::from_str_radix(n, 16).ok()?;
+                    let bytes = decode_hex(value)?;
+                    let value = match bytes[..] {
+                        [lo] => lo as u16,
+                        [lo, hi] => u16::from_le_bytes([lo, hi]),
+                        _ => return None,
+                    };
+                    (n < REGISTERS).then_some((n, value))
+                });
+                match parsed {
+                    Some((n, value)) => {
+                        set_register(gb, n, value);
+                        reply("OK")
+                    }
+                    None => reply("E01"),
+                }
+            }
+            'm' => match addr_len(args) {
+                Some((addr, len)) => Response::Reply(
+                    (0..len)
+                        .map(|i| format!("{:02x}", gb.mem_dbg_read_mapped(addr.wrapping_add(i))))
+                        .collect(),
+                ),
+                None => reply("E01"),
+            },
+            'M' => {
+                let parsed = args.split_once(':').and_then(|(range, data)| {
+                    let (addr, len) = addr_len(range)?;
+                    let bytes = decode_hex(data)?;
+                    (bytes.len() == len as usize).then_some((addr, bytes))
+                });
+                match parsed {
+                    Some((addr, bytes)) => {
+                        for (i, byte) in bytes.into_iter().enumerate() {
+                            gb.mem_dbg_write(addr.wrapping_add(i as u16), byte);
+                        }
+                        reply("OK")
+                    }
+                    None => reply("E01"),
+                }
+            }
+            'c' | 's' => {
+                if let Ok(addr) = u16::from_str_radix(args, 16) {
+                    gb.cpu.dbg_jump(addr);
+                }
+                Response::Resume { step: cmd == 's' }
+            }
+            'b' if args == "s" || args == "c" => Response::Reply(self.reverse(gb, args == "c")),
+            'Z' | 'z' => match self.point(gb, cmd == 'Z', args) {
+                Some(()) => reply("OK"),
+                None => reply("E01"),
+            },
+            'H' | 'T' => reply("OK"),
+            'q' if packet.starts_with("qSupported") => {
+                reply("PacketSize=1000;swbreak+;hwbreak+;ReverseStep+;ReverseContinue+")
+            }
+            'q' if packet == "qAttached" => reply("1"),
+            'q' if packet == "qC" => reply("QC1"),
+            'q' if packet == "qfThreadInfo" => reply("m1"),
+            'q' if packet == "qsThreadInfo" => reply("l"),
+            'D' | 'k' => Response::Close,
+            _ => reply(""),
+        }
+    }
+
+    /// Runs one instruction or until a breakpoint, an event or `interrupted`
+    /// returns true, and gives the stop reply for it
+    pub fn resume(
+        &mut self,
+        gb: &mut Gameboy,
+        step: bool,
+        mut interrupted: impl FnMut() -> bool,
+    ) -> String {
+        let mut steps = 0;
+        loop {
+            match gb.step(1) {
+                // BGB only logs debug messages, without stopping
+                Some(Event::Message { text, .. }) => eprintln!("{text}"),
+                Some(event) => return self.stop_reply(event),
+                None => {}
+            }
+            if step {
+                return signal(SIGTRAP);
+            }
+            if let Some(reply) = self.breakpoint_reply(gb.cpu.cur_pc()) {
+                return reply;
+            }
+            steps += 1;
+            if steps % POLL_STEPS == 0 && interrupted() {
+                return signal(SIGINT);
+            }
+        }
+    }
+
+    /// Runs back one instruction, or to the last breakpoint or watchpoint
+    /// hit, `replaylog:begin` tells the client the history ran out
+    fn reverse(&mut self, gb: &mut Gameboy, cont: bool) -> String {
+        let reverse = if cont {
+            gb.reverse_continue(|gb| self.breakpoint_reply(gb.cpu.cur_pc()).is_some())
+        } else {
+            gb.step_back(1)
+        };
+        match reverse {
+            Ok(Reverse::Done) if cont => self
+                .breakpoint_reply(gb.cpu.cur_pc())
+                .unwrap_or_else(|| signal(SIGTRAP)),
+            Ok(Reverse::Done) => signal(SIGTRAP),
+            Ok(Reverse::Watch(hit)) => self.stop_reply(Event::Watch(hit)),
+            Ok(Reverse::Oldest) => format!("T{:02x}replaylog:begin;", SIGTRAP),
+            Err(_) => "E01".to_string(),
+        }
+    }
+
+    fn breakpoint_reply(&self, pc: u16) -> Option<String> {
+        let (kind, _) = self.breakpoints.iter().find(|(_, addr)| *addr == pc)?;
+        let reason = if *kind == 0 { "swbreak" } else { "hwbreak" };
+        Some(format!("T{:02x}{}:;", SIGTRAP, reason))
+    }
+
+    fn stop_reply(&self, event: Event) -> String {
+        match event {
+            Event::CpuLocked { .. } => signal(SIGILL),
+            Event::SoftBreak { .. } => format!("T{:02x}swbreak:;", SIGTRAP),
+            Event::Message { .. } => signal(SIGTRAP),
+            Event::Watch(hit) => {
+                let kind = self
+                    .watchpoints
+                    .iter()
+                    .find(|(_, _, _, id)| *id == hit.id)
+                    .map(|(kind, _, _, _)| *kind);
+                let reason = match kind {
+                    Some(2) => "watch",
+                    Some(3) => "rwatch",
+                    _ => "awatch",
+                };
+                format!("T{:02x}{}:{:04x};", SIGTRAP, reason, hit.addr)
+            }
+        }
+    }
+
+    /// Adds or removes a breakpoint from a `Z`/`z` packet, `type,addr,kind`
+    fn point(&mut self, gb: &mut Gameboy, insert: bool, args: &str) -> Option<()> {
+        let (kind, rest) = args.split_once(',')?;
+        let kind: u8 = kind.parse().ok()?;
+        let (addr, len) = addr_len(rest)?;
+
+        match (kind, insert) {
+            // Software and hardware breakpoints both compare PC, ROM can't be patched
+            (0 | 1, true) => {
+                if !self.breakpoints.contains(&(kind, addr)) {
+                    self.breakpoints.push((kind, addr));
+                }
+            }
+            (0 | 1, false) => self.breakpoints.retain(|b| *b != (kind, addr)),
+            (2..=4, true) => {
+                let access: &[Access] = match kind {
+                    2 => &[Access::Write],
+                    3 => &[Access::Read],
+                    _ => &[Access::Read, Access::Write],
+                };
+                let end = addr.saturating_add(len.max(1) - 1);
+                let id = gb.add_watchpoint(Watchpoint::new(addr..=end, access));
+                self.watchpoints.push((kind, addr, len, id));
+            }
+            (2..=4, false) => {
+                let index = self
+                    .watchpoints
+                    .iter()
+                    .position(|(k, a, l, _)| (*k, *a, *l) == (kind, addr, len))?;
+                let (_, _, _, id) = self.watchpoints.remove(index);
+                gb.remove_watchpoint(id);
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+fn registers(gb: &Gameboy) -> [u16; REGISTERS] {
+    let cpu = &gb.cpu;
+    [
+        cpu.af(),
+        cpu.bc(),
+        cpu.de(),
+        cpu.hl(),
+        cpu.sp(),
+        cpu.cur_pc(),
+    ]
+}
+
+fn set_register(gb: &mut Gameboy, n: usize, value: u16) {
+    let cpu = &mut gb.cpu;
+    match n {
+        0 => cpu.set_af(value),
+        1 => cpu.set_bc(value),
+        2 => cpu.set_de(value),
+        3 => cpu.set_hl(value),
+        4 => cpu.set_sp(value),
+        // Only jump when PC changed, a redirect re-fetches the opcode
+        _ if value != cpu.cur_pc() => cpu.dbg_jump(value),
+        _ => {}
+    }
+}
+
+fn signal(signal: u8) -> String {
+    format!("S{:02x}", signal)
+}
+
+fn addr_len(args: &str) -> Option<(u16, u16)> {
+    let (addr, len) = args.split_once(',')?;
+    let addr = u16::from_str_radix(addr, 16).ok()?;
+    let len = u16::from_str_radix(len, 16).ok()?;
+    Some((addr, len))
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b))
+}
+
+/// Reads until a complete `$data#xx` packet, acknowledging it
+fn read_packet(
+    input: &mut impl Iterator<Item = io::Result<u8>>,
+    out: &mut impl Write,
+) -> io::Result<Option<String>> {
+    loop {
+        // Skip acks and ^C between packets
+        loop {
+            match input.next().transpose()? {
+                Some(b'$') => break,
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+        }
+
+        let mut data = Vec::new();
+        loop {
+            match input.next().transpose()? {
+                Some(b'#') => break,
+                Some(b) => data.push(b),
+                None => return Ok(None),
+            }
+        }
+        let mut sum = [0u8; 2];
+        for digit in sum.iter_mut() {
+            let Some(b) = input.next().transpose()? else {
+                return Ok(None);
+            };
+            *digit = b;
+        }
+
+        let data = String::from_utf8_lossy(&data).into_owned();
+        let valid = std::str::from_utf8(&sum)
+            .ok()
+            .and_then(|sum| u8::from_str_radix(sum, 16).ok())
+            == Some(checksum(&data));
+        if valid {
+            out.write_all(b"+")?;
+            return Ok(Some(data));
+        }
+        out.write_all(b"-")?;
+    }
+}
+
+fn write_packet(out: &mut impl Write, data: &str) -> io::Result<()> {
+    write!(out, "${}#{:02x}", data, checksum(data))?;
+    out.flush()
+}
+
+/// Checks, without blocking, whether the client sent a ^C
+fn interrupted(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let mut byte = [0u8];
+    let hit = matches!(stream.peek(&mut byte), Ok(1) if byte[0] == 0x03);
+    if hit {
+        let _ = (&*stream).read(&mut byte);
+    }
+    let _ = stream.set_nonblocking(false);
+    hit
+}
@@ -1,2 +1,6 @@
 pub mod control;
+pub mod dap;
+pub mod debugger;
+pub mod gdb;
+pub mod json;
 pub mod window;
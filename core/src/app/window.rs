@@ -35,6 +35,7 @@ pub fn create_frame_channel() -> (FrameSender, std::sync::mpsc::Receiver<Vec<u8>
 
 pub fn run(
     scale: u32,
+    dimensions: (u32, u32),
     frame_rx: std::sync::mpsc::Receiver<Vec<u8>>,
     control_tx: ControlSender,
 ) -> Result<(), EventLoopError> {
@@ -63,21 +64,25 @@ pub fn run(
         }
     });
 
-    let mut app = WindowApp::new(scale, control_tx);
+    let mut app = WindowApp::new(scale, dimensions, control_tx);
     event_loop.run_app(&mut app)
 }
 
 struct WindowApp {
     scale: u32,
+    width: u32,
+    height: u32,
     window: Option<Arc<Window>>,
     pixels: Option<Pixels<'static>>,
     frame_queue: VecDeque<Vec<u8>>,
     control_tx: ControlSender,
     exit_requested: bool,
+    /// Whether Space last paused the emulator
+    paused: bool,
 }
 
 impl WindowApp {
-    fn new(scale: u32, control_tx: ControlSender) -> Self {
+    fn new(scale: u32, (width, height): (u32, u32), control_tx: ControlSender) -> Self {
         let safe_scale = scale.clamp(1, MAX_SCALE);
 
         debug_assert_eq!(
@@ -87,16 +92,19 @@ impl WindowApp {
 
         Self {
             scale: safe_scale,
+            width,
+            height,
             window: None,
             pixels: None,
             frame_queue: VecDeque::new(),
             control_tx,
             exit_requested: false,
+            paused: false,
         }
     }
 
     fn scaled_dimensions(&self) -> (u32, u32) {
-        (SCREEN_WIDTH * self.scale, SCREEN_HEIGHT * self.scale)
+        (self.width * self.scale, self.height * self.scale)
     }
 }
 
@@ -130,7 +138,7 @@ impl ApplicationHandler<WindowMessage> for WindowApp {
         let surface_texture =
             SurfaceTexture::new(target_size.width, target_size.height, window.clone());
 
-        let pixels = match PixelsBuilder::new(SCREEN_WIDTH, SCREEN_HEIGHT, surface_texture)
+        let pixels = match PixelsBuilder::new(self.width, self.height, surface_texture)
             .present_mode(PresentMode::Fifo)
             .build()
         {
@@ -170,10 +178,49 @@ impl ApplicationHandler<WindowMessage> for WindowApp {
                         | PhysicalKey::Code(KeyCode::KeyP) => {
                             self.request_exit(event_loop);
                         }
+                        // Held down, key repeat keeps going back
+                        PhysicalKey::Code(KeyCode::Backspace)
+                            if self.control_tx.send(ControlMessage::Rewind).is_err() =>
+                        {
+                            eprintln!("failed to send rewind");
+                        }
                         _ => {}
                     }
                 }
 
+                // Fast-forward while held
+                if key_event.physical_key == PhysicalKey::Code(KeyCode::Tab) && !key_event.repeat {
+                    let on = key_event.state == ElementState::Pressed;
+                    if self
+                        .control_tx
+                        .send(ControlMessage::FastForward(on))
+                        .is_err()
+                    {
+                        eprintln!("failed to send fast-forward");
+                    }
+                }
+
+                if key_event.state == ElementState::Pressed && !key_event.repeat {
+                    let message = match key_event.physical_key {
+                        PhysicalKey::Code(KeyCode::Space) => {
+                            self.paused = !self.paused;
+                            Some(if self.paused {
+                                ControlMessage::Pause
+                            } else {
+                                ControlMessage::Resume
+                            })
+                        }
+                        PhysicalKey::Code(KeyCode::Period) => {
+                            self.paused = true;
+                            Some(ControlMessage::FrameAdvance)
+                        }
+                        key => map_key_to_control(&key),
+                    };
+                    if message.is_some_and(|m| self.control_tx.send(m).is_err()) {
+                        eprintln!("failed to send {:?}", message);
+                    }
+                }
+
                 if let Some(button) = map_key_to_joypad_button(&key_event.physical_key) {
                     let pressed = key_event.state == ElementState::Pressed;
                     if self
@@ -277,6 +324,23 @@ impl WindowApp {
     }
 }
 
+/// F1-F4 save to slots 1-4, F5-F8 load them
+fn map_key_to_control(key: &PhysicalKey) -> Option<ControlMessage> {
+    match key {
+        PhysicalKey::Code(KeyCode::F1) => Some(ControlMessage::SaveState(1)),
+        PhysicalKey::Code(KeyCode::F2) => Some(ControlMessage::SaveState(2)),
+        PhysicalKey::Code(KeyCode::F3) => Some(ControlMessage::SaveState(3)),
+        PhysicalKey::Code(KeyCode::F4) => Some(ControlMessage::SaveState(4)),
+        PhysicalKey::Code(KeyCode::F5) => Some(ControlMessage::LoadState(1)),
+        PhysicalKey::Code(KeyCode::F6) => Some(ControlMessage::LoadState(2)),
+        PhysicalKey::Code(KeyCode::F7) => Some(ControlMessage::LoadState(3)),
+        PhysicalKey::Code(KeyCode::F8) => Some(ControlMessage::LoadState(4)),
+        PhysicalKey::Code(KeyCode::F9) => Some(ControlMessage::Reset),
+        PhysicalKey::Code(KeyCode::F12) => Some(ControlMessage::Screenshot),
+        _ => None,
+    }
+}
+
 fn map_key_to_joypad_button(key: &PhysicalKey) -> Option<JoypadButton> {
     match key {
         PhysicalKey::Code(KeyCode::KeyW) => Some(JoypadButton::Up),
@@ -0,0 +1,488 @@
+use crate::emu::cpu::CpuLogView;
+use crate::emu::gb::{FRAME_CYCLES, Gameboy};
+use crate::emu::state;
+use crate::emu::testrom::MOONEYE_PASS;
+use std::any::Any;
+use std::fmt;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+
+/// Frames a job runs for unless given a budget, 30 seconds
+pub const DEFAULT_FRAMES: u64 = 1800;
+
+/// How long a job may run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Budget {
+    Cycles(u128),
+    Frames(u64),
+}
+
+impl Budget {
+    pub fn cycles(self) -> u128 {
+        match self {
+            Budget::Cycles(cycles) => cycles,
+            Budget::Frames(frames) => frames as u128 * FRAME_CYCLES,
+        }
+    }
+}
+
+/// When a job is done before its budget runs out, checked after every
+/// instruction
+pub enum Until {
+    /// The serial output ends with the text, e.g. blargg's "Passed"
+    Serial(String),
+    /// The serial output is mooneye-test-suite's pass sequence
+    Mooneye,
+    /// The instruction at the address is reached
+    Pc(u16),
+    /// The function returns true
+    When(Box<dyn Fn(&Gameboy) -> bool + Send>),
+}
+
+impl Until {
+    /// Parses `serial:<text>`, `pc:<hex addr>` or `mooneye`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        if value == "mooneye" {
+            return Ok(Until::Mooneye);
+        }
+        if let Some(text) = value.strip_prefix("serial:") {
+            return Ok(Until::Serial(text.to_string()));
+        }
+        let Some(addr) = value.strip_prefix("pc:") else {
+            return Err(format!(
+                "expected serial:<text>, pc:<addr> or mooneye, got: {}",
+                value
+            ));
+        };
+        let digits = addr.trim_start_matches("0x").trim_start_matches('$');
+        u16::from_str_radix(digits, 16)
+            .map(Until::Pc)
+            .map_err(|_| format!("invalid address: {}", addr))
+    }
+
+    fn met(&self, gb: &Gameboy) -> bool {
+        let serial = &gb.bus().serial.buf;
+        match self {
+            Until::Serial(text) => serial.ends_with(text.as_bytes()),
+            Until::Mooneye => serial[..] == MOONEYE_PASS,
+            Until::Pc(addr) => gb.cpu.cur_pc() == *addr,
+            Until::When(done) => done(gb),
+        }
+    }
+}
+
+/// How a job ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// The `Until` condition was met
+    Done,
+    /// The whole budget ran, there was nothing to wait for
+    Ran,
+    /// The budget ran out before the `Until` condition was met
+    TimedOut,
+    /// The CPU locked up on an illegal opcode
+    Locked { pc: u16, opcode: u8 },
+    /// Setting up or running the machine panicked, e.g. on an unsupported
+    /// cartridge
+    Panicked(String),
+}
+
+impl Status {
+    pub fn ok(&self) -> bool {
+        matches!(self, Status::Done | Status::Ran)
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Status::Done => write!(f, "done"),
+            Status::Ran => write!(f, "ran"),
+            Status::TimedOut => write!(f, "timed out"),
+            Status::Locked { pc, opcode } => write!(f, "locked at ${:04X} (${:02X})", pc, opcode),
+            Status::Panicked(message) => write!(f, "panicked: {}", message),
+        }
+    }
+}
+
+/// A machine to run on its own thread, made there by `setup`
+pub struct Job {
+    pub name: String,
+    setup: Box<dyn FnOnce() -> Gameboy + Send>,
+    budget: Budget,
+    until: Option<Until>,
+}
+
+impl Job {
+    /// A job running whatever `setup` returns, which can be a machine
+    /// made beforehand and moved in
+    pub fn new(name: &str, setup: impl FnOnce() -> Gameboy + Send + 'static) -> Self {
+        Job {
+            name: name.to_string(),
+            setup: Box::new(setup),
+            budget: Budget::Frames(DEFAULT_FRAMES),
+            until: None,
+        }
+    }
+
+    pub fn budget(mut self, budget: Budget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    pub fn until(mut self, until: Until) -> Self {
+        self.until = Some(until);
+        self
+    }
+
+    fn run(self) -> Outcome {
+        let mut outcome = Outcome {
+            name: self.name,
+            status: Status::Ran,
+            cycles: 0,
+            serial: Vec::new(),
+            regs: CpuLogView::default(),
+            pc: 0,
+            screen: 0,
+        };
+        let (setup, budget, until) = (self.setup, self.budget, self.until);
+        let run = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut gb = setup();
+            let status = run_until(&mut gb, budget.cycles(), until.as_ref());
+            (gb, status)
+        }));
+        let (mut gb, status) = match run {
+            Ok(run) => run,
+            Err(payload) => {
+                outcome.status = Status::Panicked(panic_message(payload));
+                return outcome;
+            }
+        };
+        let (_, _, frame) = gb.bus_mut().frame();
+        outcome.status = status;
+        outcome.cycles = gb.t;
+        outcome.serial = gb.bus().serial.buf.clone();
+        outcome.regs = gb.cpu.log_view(false);
+        outcome.pc = gb.cpu.cur_pc();
+        outcome.screen = state::fnv1a(&frame);
+        outcome
+    }
+}
+
+/// What a job left behind
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub name: String,
+    pub status: Status,
+    /// The T-cycle the machine stopped at
+    pub cycles: u128,
+    pub serial: Vec<u8>,
+    pub regs: CpuLogView,
+    /// The address of the instruction the machine stopped at
+    pub pc: u16,
+    /// A hash of the screen, to compare against a known good run
+    pub screen: u64,
+}
+
+/// Runs a machine for `cycles`, stopping at the first instruction boundary
+/// where `until` is met
+fn run_until(gb: &mut Gameboy, cycles: u128, until: Option<&Until>) -> Status {
+    let end = gb.t + cycles;
+    while gb.t < end {
+        let retired = gb.cpu.retired();
+        gb.tick(1);
+        if gb.cpu.locked() {
+            return Status::Locked {
+                pc: gb.cpu.cur_pc(),
+                opcode: gb.cpu.ir(),
+            };
+        }
+        if retired == gb.cpu.retired() {
+            continue;
+        }
+        if until.is_some_and(|until| until.met(gb)) {
+            return Status::Done;
+        }
+    }
+    match until {
+        Some(_) => Status::TimedOut,
+        None => Status::Ran,
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
+
+/// Runs the jobs on `threads` threads, returning their outcomes in the
+/// order given
+pub fn run(jobs: Vec<Job>, threads: usize) -> Vec<Outcome> {
+    let count = jobs.len();
+    let queue = Mutex::new(jobs.into_iter().enumerate());
+    let outcomes = Mutex::new(Vec::with_capacity(count));
+    thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, count.max(1)) {
+            scope.spawn(|| {
+                loop {
+                    let next = queue.lock().unwrap().next();
+                    let Some((index, job)) = next else {
+                        return;
+                    };
+                    let outcome = job.run();
+                    outcomes.lock().unwrap().push((index, outcome));
+                }
+            });
+        }
+    });
+    let mut outcomes = outcomes.into_inner().unwrap();
+    outcomes.sort_by_key(|(index, _)| *index);
+    outcomes.into_iter().map(|(_, outcome)| outcome).collect()
+}
+
+/// A ROM listed in a manifest
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    pub rom: PathBuf,
+    pub sgb: bool,
+    pub budget: Budget,
+    pub until: Option<String>,
+}
+
+/// The ROMs `gamezoea batch` runs, from a TOML file like
+///
+/// ```toml
+/// threads = 4
+/// frames = 600             # the defaults for every ROM
+///
+/// [[rom]]
+/// path = "cpu_instrs.gb"   # relative to the manifest
+/// until = "serial:Passed"  # or pc:<addr> or mooneye
+/// cycles = 300_000_000     # instead of frames
+/// sgb = false
+/// name = "cpu_instrs"      # the file name without .gb by default
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub threads: Option<usize>,
+    pub entries: Vec<Entry>,
+}
+
+/// A `key = value` pair of the manifest
+struct Field {
+    line: usize,
+    key: String,
+    value: Value,
+}
+
+enum Value {
+    String(String),
+    Integer(u128),
+    Bool(bool),
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Manifest::parse(&text, dir).map_err(|e| format!("{}:{}", path.display(), e))
+    }
+
+    /// Parses the manifest, resolving ROM paths from `dir`. Errors start
+    /// with the line number
+    pub fn parse(text: &str, dir: &Path) -> Result<Self, String> {
+        let mut defaults = Vec::new();
+        let mut roms: Vec<(usize, Vec<Field>)> = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line_no = i + 1;
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line == "[[rom]]" {
+                roms.push((line_no, Vec::new()));
+                continue;
+            }
+            if line.starts_with('[') {
+                return Err(format!("{}: unknown table {}", line_no, line));
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(format!("{}: expected key = value", line_no));
+            };
+            let field = Field {
+                line: line_no,
+                key: key.trim().to_string(),
+                value: parse_value(value.trim()).map_err(|e| format!("{}: {}", line_no, e))?,
+            };
+            match roms.last_mut() {
+                Some((_, rom)) => rom.push(field),
+                None => defaults.push(field),
+            }
+        }
+
+        let mut threads = None;
+        let mut base = Template::default();
+        for field in &defaults {
+            match (field.key.as_str(), &field.value) {
+                ("threads", Value::Integer(n)) if *n > 0 => threads = Some(*n as usize),
+                ("threads", _) => return Err(format!("{}: invalid value for threads", field.line)),
+                ("name" | "path", _) => {
+                    return Err(format!(
+                        "{}: {} belongs in a [[rom]]",
+                        field.line, field.key
+                    ));
+                }
+                _ => base.set(field)?,
+            }
+        }
+        let entries = roms
+            .iter()
+            .map(|(line, fields)| {
+                let mut rom = base.clone();
+                for field in fields {
+                    rom.set(field)?;
+                }
+                rom.entry(dir, *line)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Manifest { threads, entries })
+    }
+
+    /// A job for each entry, the ROMs are read now and the machines made on
+    /// the threads running them
+    pub fn jobs(&self) -> Result<Vec<Job>, String> {
+        self.entries
+            .iter()
+            .map(|entry| {
+                let rom =
+                    fs::read(&entry.rom).map_err(|e| format!("{}: {}", entry.rom.display(), e))?;
+                let sgb = entry.sgb;
+                let mut job = Job::new(&entry.name, move || {
+                    if sgb {
+                        Gameboy::headless_sgb(&rom)
+                    } else {
+                        Gameboy::headless_dmg(&rom)
+                    }
+                })
+                .budget(entry.budget);
+                if let Some(until) = &entry.until {
+                    job = job.until(Until::parse(until)?);
+                }
+                Ok(job)
+            })
+            .collect()
+    }
+}
+
+/// The settings of a `[[rom]]` table so far, starting from the defaults
+#[derive(Clone, Default)]
+struct Template {
+    name: Option<String>,
+    path: Option<String>,
+    sgb: bool,
+    budget: Option<Budget>,
+    until: Option<String>,
+}
+
+impl Template {
+    fn set(&mut self, field: &Field) -> Result<(), String> {
+        match (field.key.as_str(), &field.value) {
+            ("name", Value::String(name)) => self.name = Some(name.clone()),
+            ("path", Value::String(path)) => self.path = Some(path.clone()),
+            ("sgb", Value::Bool(sgb)) => self.sgb = *sgb,
+            ("cycles", Value::Integer(n)) => self.budget = Some(Budget::Cycles(*n)),
+            ("frames", Value::Integer(n)) => self.budget = Some(Budget::Frames(*n as u64)),
+            ("until", Value::String(until)) => {
+                Until::parse(until).map_err(|e| format!("{}: {}", field.line, e))?;
+                self.until = Some(until.clone());
+            }
+            ("name" | "path" | "sgb" | "cycles" | "frames" | "until", _) => {
+                return Err(format!("{}: invalid value for {}", field.line, field.key));
+            }
+            (key, _) => return Err(format!("{}: unknown key {}", field.line, key)),
+        }
+        Ok(())
+    }
+
+    /// The entry for the `[[rom]]` table at `line`
+    fn entry(self, dir: &Path, line: usize) -> Result<Entry, String> {
+        let Some(path) = self.path else {
+            return Err(format!("{}: [[rom]] has no path", line));
+        };
+        let rom = dir.join(&path);
+        let name = self.name.unwrap_or_else(|| {
+            let stem = Path::new(&path).file_stem().unwrap_or_default();
+            stem.to_string_lossy().to_string()
+        });
+        Ok(Entry {
+            name,
+            rom,
+            sgb: self.sgb,
+            budget: self.budget.unwrap_or(Budget::Frames(DEFAULT_FRAMES)),
+            until: self.until,
+        })
+    }
+}
+
+/// Drops a `#` comment, unless it is in a string
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Parses a basic string, an integer or a boolean
+fn parse_value(value: &str) -> Result<Value, String> {
+    match value {
+        "true" => return Ok(Value::Bool(true)),
+        "false" => return Ok(Value::Bool(false)),
+        _ => {}
+    }
+    if let Some(quoted) = value.strip_prefix('"') {
+        let Some(body) = quoted.strip_suffix('"') else {
+            return Err("unterminated string".to_string());
+        };
+        let mut text = String::new();
+        let mut chars = body.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                text.push(c);
+                continue;
+            }
+            text.push(match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('"') => '"',
+                Some('\\') => '\\',
+                other => return Err(format!("invalid escape \\{}", other.unwrap_or(' '))),
+            });
+        }
+        return Ok(Value::String(text));
+    }
+    let digits = value.replace('_', "");
+    let parsed = match digits.strip_prefix("0x") {
+        Some(hex) => u128::from_str_radix(hex, 16),
+        None => digits.parse(),
+    };
+    parsed
+        .map(Value::Integer)
+        .map_err(|_| format!("invalid value {}", value))
+}
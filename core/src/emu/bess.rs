@@ -0,0 +1,249 @@
+use crate::emu::state::{Reader, Writer};
+
+/// The last four bytes of a file with BESS blocks
+const FOOTER: &[u8; 4] = b"BESS";
+
+const MAJOR: u16 = 1;
+const MINOR: u16 = 1;
+const CORE_LEN: usize = 0xD0;
+const INFO_LEN: usize = 0x12;
+const RTC_LEN: usize = 0x30;
+
+/// The ROM title at $0134 and global checksum at $014E, as stored in INFO
+pub type Info = [u8; INFO_LEN];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    Dmg,
+    Sgb,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Execution {
+    Running,
+    Halted,
+    Stopped,
+}
+
+/// The CORE block, the machine between two instructions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Core {
+    pub model: Model,
+    /// The next instruction to run
+    pub pc: u16,
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+    pub sp: u16,
+    pub ime: bool,
+    pub ie: u8,
+    pub execution: Execution,
+    /// $FF00-$FF7F
+    pub io: [u8; 0x80],
+    pub ram: Vec<u8>,
+    pub vram: Vec<u8>,
+    pub mbc_ram: Vec<u8>,
+    pub oam: Vec<u8>,
+    pub hram: Vec<u8>,
+}
+
+/// The RTC block of MBC3 cartridges, registers in S, M, H, DL, DH order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rtc {
+    pub current: [u8; 5],
+    pub latched: [u8; 5],
+    /// UNIX time the state was saved at
+    pub timestamp: u64,
+}
+
+/// A "Best Effort Save State" as used by SameBoy and others, blocks after
+/// the emulator's own data found through a footer at the end of the file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bess {
+    pub name: Option<String>,
+    pub info: Option<Info>,
+    pub core: Core,
+    /// `(addr, value)` writes that restore the MBC registers
+    pub mbc: Vec<(u16, u8)>,
+    pub rtc: Option<Rtc>,
+}
+
+impl Bess {
+    /// Whether `data` ends with a BESS footer
+    pub fn detect(data: &[u8]) -> bool {
+        data.ends_with(FOOTER)
+    }
+
+    pub fn write(&self) -> Vec<u8> {
+        let core = &self.core;
+        let mut out = Writer::new();
+        // The memory buffers come first, CORE refers to them by offset
+        let mut buffers = Vec::new();
+        let mut offset = 0;
+        for buffer in [&core.ram, &core.vram, &core.mbc_ram, &core.oam, &core.hram] {
+            buffers.push((buffer.len() as u32, offset));
+            out.array(buffer);
+            offset += buffer.len() as u32;
+        }
+        // Background and object palettes only exist on the CGB
+        buffers.extend([(0, 0), (0, 0)]);
+
+        if let Some(name) = &self.name {
+            out.section(b"NAME", |out| out.array(name.as_bytes()));
+        }
+        if let Some(info) = &self.info {
+            out.section(b"INFO", |out| out.array(info));
+        }
+        out.section(b"CORE", |out| {
+            out.u16(MAJOR);
+            out.u16(MINOR);
+            out.array(match core.model {
+                Model::Dmg => b"GD  ",
+                Model::Sgb => b"SN  ",
+            });
+            for r in [core.pc, core.af, core.bc, core.de, core.hl, core.sp] {
+                out.u16(r);
+            }
+            out.bool(core.ime);
+            out.u8(core.ie);
+            out.u8(core.execution as u8);
+            out.u8(0);
+            out.array(&core.io);
+            for (size, offset) in buffers {
+                out.u32(size);
+                out.u32(offset);
+            }
+        });
+        if !self.mbc.is_empty() {
+            out.section(b"MBC ", |out| {
+                for (addr, value) in &self.mbc {
+                    out.u16(*addr);
+                    out.u8(*value);
+                }
+            });
+        }
+        if let Some(rtc) = &self.rtc {
+            out.section(b"RTC ", |out| {
+                for reg in rtc.current.iter().chain(&rtc.latched) {
+                    out.u32(*reg as u32);
+                }
+                out.u64(rtc.timestamp);
+            });
+        }
+        out.section(b"END ", |_| {});
+
+        out.u32(offset);
+        out.array(FOOTER);
+        out.finish()
+    }
+
+    pub fn read(data: &[u8]) -> Result<Self, String> {
+        if !Self::detect(data) || data.len() < 8 {
+            return Err("no BESS footer".to_string());
+        }
+        let footer = &data[data.len() - 8..data.len() - 4];
+        let start = u32::from_le_bytes(footer.try_into().unwrap()) as usize;
+        let blocks = data
+            .get(start..data.len() - 8)
+            .ok_or("BESS blocks start past the end of the file")?;
+
+        let mut input = Reader::new(blocks, 0);
+        let (mut name, mut info, mut core, mut mbc, mut rtc) = (None, None, None, Vec::new(), None);
+        loop {
+            let id: [u8; 4] = input.array()?;
+            let len = input.u32()? as usize;
+            let mut body = Reader::new(input.slice(len)?, 0);
+            match &id {
+                b"END " => break,
+                b"NAME" => name = Some(String::from_utf8_lossy(body.slice(len)?).into_owned()),
+                b"INFO" if len == INFO_LEN => info = Some(body.array()?),
+                b"CORE" if len >= CORE_LEN => core = Some(read_core(&mut body, data)?),
+                b"MBC " if len.is_multiple_of(3) => {
+                    for _ in 0..len / 3 {
+                        mbc.push((body.u16()?, body.u8()?));
+                    }
+                }
+                b"RTC " if len == RTC_LEN => {
+                    let mut regs = [0u8; 10];
+                    for reg in regs.iter_mut() {
+                        *reg = body.u32()? as u8;
+                    }
+                    rtc = Some(Rtc {
+                        current: regs[..5].try_into().unwrap(),
+                        latched: regs[5..].try_into().unwrap(),
+                        timestamp: body.u64()?,
+                    });
+                }
+                b"INFO" | b"CORE" | b"MBC " | b"RTC " => {
+                    return Err(format!(
+                        "BESS {} block has an invalid length",
+                        String::from_utf8_lossy(&id).trim()
+                    ));
+                }
+                // Blocks for hardware we don't emulate are skipped
+                _ => {}
+            }
+        }
+
+        Ok(Bess {
+            name,
+            info,
+            core: core.ok_or("BESS state has no CORE block")?,
+            mbc,
+            rtc,
+        })
+    }
+}
+
+fn read_core(input: &mut Reader, file: &[u8]) -> Result<Core, String> {
+    let major = input.u16()?;
+    let _minor = input.u16()?;
+    if major != MAJOR {
+        return Err(format!("unsupported BESS version {}", major));
+    }
+    let model: [u8; 4] = input.array()?;
+    let model = match model[0] {
+        b'G' => Model::Dmg,
+        b'S' => Model::Sgb,
+        b'C' => return Err("Game Boy Color states are not supported".to_string()),
+        _ => return Err(format!("unknown model {}", String::from_utf8_lossy(&model))),
+    };
+    let (pc, af, bc, de) = (input.u16()?, input.u16()?, input.u16()?, input.u16()?);
+    let (hl, sp) = (input.u16()?, input.u16()?);
+    let (ime, ie, execution, _) = (input.bool()?, input.u8()?, input.u8()?, input.u8()?);
+    let execution = match execution {
+        0 => Execution::Running,
+        1 => Execution::Halted,
+        2 => Execution::Stopped,
+        x => return Err(format!("invalid execution state {}", x)),
+    };
+    let io = input.array()?;
+    let mut buffer = || -> Result<Vec<u8>, String> {
+        let size = input.u32()? as usize;
+        let offset = input.u32()? as usize;
+        offset
+            .checked_add(size)
+            .and_then(|end| file.get(offset..end))
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| "BESS buffer lies outside the file".to_string())
+    };
+    Ok(Core {
+        model,
+        pc,
+        af,
+        bc,
+        de,
+        hl,
+        sp,
+        ime,
+        ie,
+        execution,
+        io,
+        ram: buffer()?,
+        vram: buffer()?,
+        mbc_ram: buffer()?,
+        oam: buffer()?,
+        hram: buffer()?,
+    })
+}
@@ -0,0 +1,265 @@
+use crate::emu::gb::Comp;
+use crate::emu::joypad::{Joypad, JoypadButton};
+use crate::emu::mem::Memory;
+use crate::emu::ppu::Ppu;
+use crate::emu::regs::*;
+use crate::emu::sched::Scheduler;
+use crate::emu::serial::{SB, SC, Serial};
+use crate::emu::state::{Reader, Writer};
+use crate::emu::timer::Timer;
+use crate::emu::watch::Access;
+
+/// IO registers a component keeps, everything else is in memory
+const ROUTED: [u16; 18] = [
+    P1, SB, SC, DIV, TIMA, TMA, TAC, LCDC, STAT, SCY, SCX, LY, LYC, BGP, OBP0, OBP1, WY, WX,
+];
+
+/// The component that keeps the register at `addr`, if it isn't memory
+pub fn owner(addr: u16) -> Option<Comp> {
+    match addr {
+        P1 => Some(Comp::Joypad),
+        SB | SC => Some(Comp::Serial),
+        DIV..=TAC => Some(Comp::Timer),
+        LCDC..=WX if addr != DMA => Some(Comp::Ppu),
+        _ => None,
+    }
+}
+
+/// Connects the CPU to memory and the other components. Reads and writes of
+/// an IO register go to the component that keeps it, which is woken as the
+/// write may change when it next has work to do
+pub struct Bus {
+    pub mem: Memory,
+    pub ppu: Ppu,
+    pub timer: Timer,
+    pub serial: Serial,
+    pub joypad: Joypad,
+    scheduler: Scheduler,
+    addr: u16,
+    data: u8,
+    /// The value CPU reads of LY return instead of the current line
+    ly_stub: Option<u8>,
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Bus::new(Memory::empty(), Ppu::headless_dmg())
+    }
+}
+
+impl Bus {
+    pub fn new(mem: Memory, ppu: Ppu) -> Self {
+        Bus {
+            mem,
+            ppu,
+            timer: Timer::init_dmg(),
+            serial: Serial::init_dmg(),
+            joypad: Joypad::init_dmg(),
+            scheduler: Scheduler::default(),
+            addr: 0x0000,
+            data: 0x00,
+            ly_stub: None,
+        }
+    }
+
+    pub fn addr(&self) -> u16 {
+        self.addr
+    }
+
+    pub fn data(&self) -> u8 {
+        self.data
+    }
+
+    pub fn set_addr(&mut self, addr: u16) {
+        self.addr = addr
+    }
+
+    pub fn set_data(&mut self, data: u8) {
+        self.data = data
+    }
+
+    /// Reads the latched address as the CPU
+    pub fn read(&mut self) {
+        self.data = self.cpu_read(self.addr);
+        self.mem
+            .observe(Access::Read, self.addr, self.data, Comp::Cpu);
+    }
+
+    /// Reads an opcode, which watchpoints see as an execute access
+    pub fn fetch(&mut self) {
+        self.data = self.cpu_read(self.addr);
+        self.mem
+            .observe(Access::Execute, self.addr, self.data, Comp::Cpu);
+    }
+
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match owner(addr) {
+            Some(_) if addr == LY => self.ly_stub.unwrap_or(self.ppu.read(LY)),
+            Some(_) => self.dbg_read(addr),
+            None => self.mem.read(addr),
+        }
+    }
+
+    /// Writes the latched data to the latched address as the CPU
+    pub fn write(&mut self) {
+        let (addr, data) = (self.addr, self.data);
+        self.mem.observe(Access::Write, addr, data, Comp::Cpu);
+        let Some(comp) = owner(addr) else {
+            self.mem.write(addr, data);
+            return;
+        };
+        self.scheduler.wake(comp);
+        match comp {
+            Comp::Joypad => {
+                let select = self.joypad.write(data);
+                self.mem.write_sgb_p1(select, self.ppu.read(LCDC));
+            }
+            Comp::Serial => self.serial.write(addr, data),
+            Comp::Timer => self.timer.write(addr, data),
+            _ => self.ppu.write(addr, data),
+        }
+    }
+
+    pub fn dbg_read(&self, addr: u16) -> u8 {
+        match owner(addr) {
+            Some(Comp::Joypad) => self.joypad.read(),
+            Some(Comp::Serial) => self.serial.read(addr),
+            Some(Comp::Timer) => self.timer.read(addr),
+            Some(_) => self.ppu.read(addr),
+            None => self.mem.dbg_read(addr),
+        }
+    }
+
+    /// Reads `addr` through the MBC, as the CPU currently sees it
+    pub fn dbg_read_mapped(&self, addr: u16) -> u8 {
+        match owner(addr) {
+            Some(_) => self.dbg_read(addr),
+            None => self.mem.dbg_read_mapped(addr),
+        }
+    }
+
+    /// Sets `addr` as is, waking the component whose register it is
+    pub fn dbg_write(&mut self, addr: u16, data: u8) {
+        let Some(comp) = owner(addr) else {
+            self.mem.dbg_write(addr, data);
+            return;
+        };
+        self.scheduler.wake(comp);
+        match comp {
+            Comp::Joypad => self.joypad.poke(data),
+            Comp::Serial => self.serial.poke(addr, data),
+            Comp::Timer => self.timer.poke(addr, data),
+            _ => self.ppu.poke(addr, data),
+        }
+    }
+
+    /// Runs DMA and the timer for T-cycle `t`, before the CPU. STOP halts
+    /// the system clock, so the timer stands still while `stopped`
+    pub fn tick_timer(&mut self, t: u128, stopped: bool) {
+        self.mem.tick(t);
+        if stopped {
+            self.timer.hold(t);
+        } else if self.scheduler.due(Comp::Timer, t) {
+            let next = self.timer.tick(t, &mut self.mem);
+            self.scheduler.schedule(Comp::Timer, next);
+        }
+    }
+
+    /// Runs the PPU, serial port and joypad for T-cycle `t`, after the CPU
+    /// whose writes may have woken them. Only the joypad runs while the CPU
+    /// is `stopped`, it is what wakes it
+    pub fn tick_devices(&mut self, t: u128, stopped: bool) {
+        if stopped {
+            self.ppu.hold(t);
+        } else {
+            if self.scheduler.due(Comp::Ppu, t) {
+                let next = self.ppu.tick(t, &mut self.mem);
+                self.scheduler.schedule(Comp::Ppu, next);
+            }
+            if self.scheduler.due(Comp::Serial, t) {
+                let next = self.serial.tick(t, &mut self.mem);
+                self.scheduler.schedule(Comp::Serial, next);
+            }
+        }
+        if self.scheduler.due(Comp::Joypad, t) {
+            let next = self.joypad.tick(t, &mut self.mem);
+            self.scheduler.schedule(Comp::Joypad, next);
+        }
+    }
+
+    pub fn enqueue_input(&mut self, button: JoypadButton, pressed: bool) {
+        self.joypad.enqueue_input(button, pressed);
+        self.scheduler.wake(Comp::Joypad);
+    }
+
+    /// Blanks the screen, as the LCD does while the CPU is in STOP
+    pub fn blank(&mut self) {
+        self.ppu.blank(&mut self.mem);
+    }
+
+    /// The screen as `(width, height, RGBA pixels)`, with the border when
+    /// running as a Super Game Boy
+    pub fn frame(&mut self) -> (usize, usize, Vec<u8>) {
+        self.ppu.frame(&mut self.mem)
+    }
+
+    pub fn scheduler(&self) -> &Scheduler {
+        &self.scheduler
+    }
+
+    pub fn set_ly_stub(&mut self, ly: Option<u8>) {
+        self.ly_stub = ly;
+    }
+
+    /// Wakes every component to reschedule after their state was replaced,
+    /// carrying on from `t`
+    pub fn resync(&mut self, t: u128) {
+        self.timer.resume_from(t);
+        self.ppu.resume_from(t);
+        self.scheduler.wake_all();
+    }
+
+    /// Powers back on with the same cartridge, see `Memory::reset`
+    pub fn reset(&mut self) {
+        self.mem.reset();
+        self.ppu.reset();
+        self.timer = Timer::init_dmg();
+        self.serial = Serial::init_dmg();
+        self.joypad = Joypad::init_dmg();
+        self.addr = 0x0000;
+        self.data = 0x00;
+    }
+
+    /// Saves the bus latches, then the address space as the CPU sees it,
+    /// `Memory::save` has the layout
+    pub fn save(&self, out: &mut Writer) {
+        out.u16(self.addr);
+        out.u8(self.data);
+        let io: Vec<u8> = (0xFF00..0xFF80).map(|addr| self.dbg_read(addr)).collect();
+        self.mem.save(out, &io);
+    }
+
+    pub fn load(&mut self, input: &mut Reader) -> Result<(), String> {
+        self.addr = input.u16()?;
+        self.data = input.u8()?;
+        self.mem.load(input)?;
+        self.route_registers();
+        Ok(())
+    }
+
+    /// Overwrites the IO registers from a BESS state, the timer and PPU
+    /// then rebuild what they keep besides them
+    pub fn restore_io(&mut self, io: &[u8]) {
+        self.mem.restore(0xFF00, io);
+        self.route_registers();
+        self.timer.sync();
+        self.ppu.sync(&mut self.mem);
+    }
+
+    /// Hands the registers memory was given to the components keeping them
+    fn route_registers(&mut self) {
+        for addr in ROUTED {
+            self.dbg_write(addr, self.mem.dbg_read(addr));
+        }
+    }
+}
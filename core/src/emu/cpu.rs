@@ -1,9 +1,11 @@
+use crate::emu::bus::Bus;
 use crate::emu::gb::Comp;
 use crate::emu::mem::Memory;
+use crate::emu::ppu::Ppu;
+use crate::emu::state::{Reader, Writer};
+use crate::log;
 use macros::*;
-use std::cell::RefCell;
 use std::fmt;
-use std::rc::Rc;
 
 #[allow(dead_code)]
 const M43: u8 = 0b00011000;
@@ -11,6 +13,18 @@ const M54: u8 = 0b00110000;
 const M543: u8 = 0b00111000;
 const M210: u8 = 0b00000111;
 
+/// `LD B,B`, the software breakpoint of BGB and mooneye-test-suite
+pub const LD_B_B: u8 = 0x40;
+/// `LD D,D`, which BGB takes as a debug message when followed by one
+pub const LD_D_D: u8 = 0x52;
+
+/// Whether `opcode` is a `LD r,r` loading a register into itself, which
+/// does nothing and so can be a software breakpoint
+pub fn is_ld_self(opcode: u8) -> bool {
+    let reg = opcode & M210;
+    opcode & 0xC0 == 0x40 && (opcode & M543) >> 3 == reg && reg != 6
+}
+
 // {{{ Register Enums
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum R8 {
@@ -187,6 +201,222 @@ impl Mc {
 }
 // }}}
 
+// {{{ Opcode Enums
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Nop,
+    LdR16Imm16,
+    LdMr16memA,
+    LdAMr16mem,
+    LdMimm16Sp,
+    IncR16,
+    DecR16,
+    AddHlR16,
+    IncR8,
+    IncMhl,
+    DecR8,
+    DecMhl,
+    LdR8Imm8,
+    LdMhlImm8,
+    Rlca,
+    Rrca,
+    Rla,
+    Rra,
+    Daa,
+    Cpl,
+    Scf,
+    Ccf,
+    JrImm8,
+    JrCondImm8,
+    Stop,
+    LdR8R8,
+    LdR8Mhl,
+    LdMhlR8,
+    Halt,
+    AddAR8,
+    AdcAR8,
+    SubAR8,
+    SbcAR8,
+    AndAR8,
+    XorAR8,
+    OrAR8,
+    CpAR8,
+    AddAImm8,
+    AdcAImm8,
+    SubAImm8,
+    SbcAImm8,
+    AndAImm8,
+    XorAImm8,
+    OrAImm8,
+    CpAImm8,
+    RetCond,
+    Ret,
+    Reti,
+    JpCondImm16,
+    JpImm16,
+    JpHl,
+    CallCondImm16,
+    CallImm16,
+    RstTgt3,
+    PopR16stk,
+    PushR16stk,
+    CbPrefix,
+    LdhMcA,
+    LdhMimm8A,
+    LdMimm16A,
+    LdhAMc,
+    LdhAMimm8,
+    LdAMimm16,
+    AddSpImm8,
+    LdHlSpPlusImm8,
+    LdSpHl,
+    Di,
+    Ei,
+    Illegal,
+    // CB prefixed
+    RlcR8,
+    RrcR8,
+    RlR8,
+    RrR8,
+    SlaR8,
+    SraR8,
+    SwapR8,
+    SrlR8,
+    BitB3R8,
+    ResB3R8,
+    SetB3R8,
+}
+
+impl Op {
+    pub fn from(ir: u8) -> Self {
+        #[allow(clippy::manual_range_patterns)]
+        match ir {
+            // Block 0
+            0x00 => Op::Nop,
+            0x01 | 0x21 | 0x11 | 0x31 => Op::LdR16Imm16,
+            0x02 | 0x22 | 0x12 | 0x32 => Op::LdMr16memA,
+            0x0A | 0x2A | 0x1A | 0x3A => Op::LdAMr16mem,
+            0x08 => Op::LdMimm16Sp,
+            //
+            0x03 | 0x23 | 0x13 | 0x33 => Op::IncR16,
+            0x0B | 0x2B | 0x1B | 0x3B => Op::DecR16,
+            0x09 | 0x29 | 0x19 | 0x39 => Op::AddHlR16,
+            //
+            0x04 | 0x24 | 0x14 | 0x0C | 0x2C | 0x1C | 0x3C => Op::IncR8,
+            0x34 => Op::IncMhl,
+            0x05 | 0x25 | 0x15 | 0x0D | 0x2D | 0x1D | 0x3D => Op::DecR8,
+            0x35 => Op::DecMhl,
+            //
+            0x06 | 0x26 | 0x16 | 0x0E | 0x2E | 0x1E | 0x3E => Op::LdR8Imm8,
+            0x36 => Op::LdMhlImm8,
+            //
+            0x07 => Op::Rlca,
+            0x0F => Op::Rrca,
+            0x17 => Op::Rla,
+            0x1F => Op::Rra,
+            0x27 => Op::Daa,
+            0x2F => Op::Cpl,
+            0x37 => Op::Scf,
+            0x3F => Op::Ccf,
+            //
+            0x18 => Op::JrImm8,
+            0x20 | 0x30 | 0x28 | 0x38 => Op::JrCondImm8,
+            //
+            0x10 => Op::Stop,
+
+            // Block 1
+            0x40 | 0x60 | 0x50 | 0x48 | 0x68 | 0x58 | 0x78 | 0x44 | 0x64 | 0x54 | 0x4C | 0x6C
+            | 0x5C | 0x7C | 0x42 | 0x62 | 0x52 | 0x4A | 0x6A | 0x5A | 0x7A | 0x41 | 0x61 | 0x51
+            | 0x49 | 0x69 | 0x59 | 0x79 | 0x45 | 0x65 | 0x55 | 0x4D | 0x6D | 0x5D | 0x7D | 0x43
+            | 0x63 | 0x53 | 0x4B | 0x6B | 0x5B | 0x7B | 0x47 | 0x67 | 0x57 | 0x4F | 0x6F | 0x5F
+            | 0x7F => Op::LdR8R8,
+            0x46 | 0x66 | 0x56 | 0x4E | 0x6E | 0x5E | 0x7E => Op::LdR8Mhl,
+            0x70 | 0x74 | 0x72 | 0x71 | 0x75 | 0x73 | 0x77 => Op::LdMhlR8,
+            //
+            0x76 => Op::Halt,
+
+            // Block 2
+            0x80 | 0x84 | 0x82 | 0x81 | 0x85 | 0x83 | 0x86 | 0x87 => Op::AddAR8,
+            0x88 | 0x8C | 0x8A | 0x89 | 0x8D | 0x8B | 0x8E | 0x8F => Op::AdcAR8,
+            0x90 | 0x94 | 0x92 | 0x96 | 0x91 | 0x95 | 0x93 | 0x97 => Op::SubAR8,
+            0x98 | 0x9C | 0x9A | 0x9E | 0x99 | 0x9D | 0x9B | 0x9F => Op::SbcAR8,
+            0xA0 | 0xA4 | 0xA2 | 0xA6 | 0xA1 | 0xA5 | 0xA3 | 0xA7 => Op::AndAR8,
+            0xA8 | 0xAC | 0xAA | 0xAE | 0xA9 | 0xAD | 0xAB | 0xAF => Op::XorAR8,
+            0xB0 | 0xB4 | 0xB2 | 0xB6 | 0xB1 | 0xB5 | 0xB3 | 0xB7 => Op::OrAR8,
+            0xB8 | 0xBC | 0xBA | 0xBE | 0xB9 | 0xBD | 0xBB | 0xBF => Op::CpAR8,
+
+            // Block 3
+            0xC6 => Op::AddAImm8,
+            0xCE => Op::AdcAImm8,
+            0xD6 => Op::SubAImm8,
+            0xDE => Op::SbcAImm8,
+            0xE6 => Op::AndAImm8,
+            0xEE => Op::XorAImm8,
+            0xF6 => Op::OrAImm8,
+            0xFE => Op::CpAImm8,
+            //
+            0xC0 | 0xD0 | 0xC8 | 0xD8 => Op::RetCond,
+            0xC9 => Op::Ret,
+            0xD9 => Op::Reti,
+            0xC2 | 0xD2 | 0xCA | 0xDA => Op::JpCondImm16,
+            0xC3 => Op::JpImm16,
+            0xE9 => Op::JpHl,
+            0xC4 | 0xD4 | 0xCC | 0xDC => Op::CallCondImm16,
+            0xCD => Op::CallImm16,
+            0xC7 | 0xE7 | 0xD7 | 0xF7 | 0xCF | 0xEF | 0xDF | 0xFF => Op::RstTgt3,
+            //
+            0xC1 | 0xE1 | 0xD1 | 0xF1 => Op::PopR16stk,
+            0xC5 | 0xE5 | 0xD5 | 0xF5 => Op::PushR16stk,
+            //
+            0xCB => Op::CbPrefix,
+            //
+            0xE2 => Op::LdhMcA,
+            0xE0 => Op::LdhMimm8A,
+            0xEA => Op::LdMimm16A,
+            0xF2 => Op::LdhAMc,
+            0xF0 => Op::LdhAMimm8,
+            0xFA => Op::LdAMimm16,
+            //
+            0xE8 => Op::AddSpImm8,
+            0xF8 => Op::LdHlSpPlusImm8,
+            0xF9 => Op::LdSpHl,
+            //
+            0xF3 => Op::Di,
+            0xFB => Op::Ei,
+            //
+            0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => {
+                Op::Illegal
+            }
+        }
+    }
+
+    pub fn from_cb(ir: u8) -> Self {
+        match ir {
+            0x00..=0x07 => Op::RlcR8,
+            0x08..=0x0F => Op::RrcR8,
+            0x10..=0x17 => Op::RlR8,
+            0x18..=0x1F => Op::RrR8,
+            0x20..=0x27 => Op::SlaR8,
+            0x28..=0x2F => Op::SraR8,
+            0x30..=0x37 => Op::SwapR8,
+            0x38..=0x3F => Op::SrlR8,
+            //
+            0x40..=0x7F => Op::BitB3R8,
+            0x80..=0xBF => Op::ResB3R8,
+            0xC0..=0xFF => Op::SetB3R8,
+        }
+    }
+}
+// }}}
+
+/// Where `executing` came from, function pointers can't be saved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Decoded {
+    Opcode(u8),
+    Cb(u8),
+    Dispatch,
+}
+
 #[derive(Clone, Copy)]
 pub struct Registers {
     ir: u8,
@@ -201,7 +431,7 @@ pub struct Registers {
 }
 
 pub struct Cpu {
-    mem: Rc<RefCell<Memory>>,
+    bus: Bus,
     r: Registers,
     log_regs_prev: Registers,
     log_regs_cur: Registers,
@@ -209,139 +439,121 @@ pub struct Cpu {
     cb: u8,
     mc: Mc,
     executing: fn(&mut Cpu),
+    decoded: Decoded,
     halted: bool,
     haltbug: bool,
+    stopped: bool,
+    locked: bool,
+    boundary: bool,
+    dispatching: bool,
+    ei_delay: bool,
     retired: u64,
     cur_pc: u16,
     prev_pc: u16,
-    dbg_break: u8,
+    /// The `LD r,r` no-ops that are software breakpoints, a bit per register
+    soft_breaks: u8,
+    /// The address and opcode of the software breakpoint that last ran
+    soft_break: Option<(u16, u8)>,
 }
 
 impl Cpu {
     // {{{ Execute Functions
     pub fn decode(&mut self) -> fn(&mut Cpu) {
-        #[allow(clippy::manual_range_patterns)]
-        if self.cb != 0 {
+        let op = if self.cb != 0 {
             self.cb = 0;
-            match self.ir() {
-                0x00..=0x07 => Cpu::rlc_r8,
-                0x08..=0x0F => Cpu::rrc_r8,
-                0x10..=0x17 => Cpu::rl_r8,
-                0x18..=0x1F => Cpu::rr_r8,
-                0x20..=0x27 => Cpu::sla_r8,
-                0x28..=0x2F => Cpu::sra_r8,
-                0x30..=0x37 => Cpu::swap_r8,
-                0x38..=0x3F => Cpu::srl_r8,
-                //
-                0x40..=0x7F => Cpu::bit_b3_r8,
-                0x80..=0xBF => Cpu::res_b3_r8,
-                0xC0..=0xFF => Cpu::set_b3_r8,
-            }
+            self.decoded = Decoded::Cb(self.ir());
+            Op::from_cb(self.ir())
         } else {
             let (result, _) = self.retired.overflowing_add(1);
             self.retired = result;
-
-            match self.ir() {
-                // Block 0
-                0x00 => Cpu::nop,
-                0x01 | 0x21 | 0x11 | 0x31 => Cpu::ld_r16_imm16,
-                0x02 | 0x22 | 0x12 | 0x32 => Cpu::ld_mr16mem_a,
-                0x0A | 0x2A | 0x1A | 0x3A => Cpu::ld_a_mr16mem,
-                0x08 => Cpu::ld_mimm16_sp,
-                //
-                0x03 | 0x23 | 0x13 | 0x33 => Cpu::inc_r16,
-                0x0B | 0x2B | 0x1B | 0x3B => Cpu::dec_r16,
-                0x09 | 0x29 | 0x19 | 0x39 => Cpu::add_hl_r16,
-                //
-                0x04 | 0x24 | 0x14 | 0x0C | 0x2C | 0x1C | 0x3C => Cpu::inc_r8,
-                0x34 => Cpu::inc_mhl,
-                0x05 | 0x25 | 0x15 | 0x0D | 0x2D | 0x1D | 0x3D => Cpu::dec_r8,
-                0x35 => Cpu::dec_mhl,
-                //
-                0x06 | 0x26 | 0x16 | 0x0E | 0x2E | 0x1E | 0x3E => Cpu::ld_r8_imm8,
-                0x36 => Cpu::ld_mhl_imm8,
-                //
-                0x07 => Cpu::rlca,
-                0x0F => Cpu::rrca,
-                0x17 => Cpu::rla,
-                0x1F => Cpu::rra,
-                0x27 => Cpu::daa,
-                0x2F => Cpu::cpl,
-                0x37 => Cpu::scf,
-                0x3F => Cpu::ccf,
-                //
-                0x18 => Cpu::jr_imm8,
-                0x20 | 0x30 | 0x28 | 0x38 => Cpu::jr_cond_imm8,
-                //
-                0x10 => Cpu::stop,
-
-                // Block 1
-                0x40 | 0x60 | 0x50 | 0x48 | 0x68 | 0x58 | 0x78 | 0x44 | 0x64 | 0x54 | 0x4C
-                | 0x6C | 0x5C | 0x7C | 0x42 | 0x62 | 0x52 | 0x4A | 0x6A | 0x5A | 0x7A | 0x41
-                | 0x61 | 0x51 | 0x49 | 0x69 | 0x59 | 0x79 | 0x45 | 0x65 | 0x55 | 0x4D | 0x6D
-                | 0x5D | 0x7D | 0x43 | 0x63 | 0x53 | 0x4B | 0x6B | 0x5B | 0x7B | 0x47 | 0x67
-                | 0x57 | 0x4F | 0x6F | 0x5F | 0x7F => Cpu::ld_r8_r8,
-                0x46 | 0x66 | 0x56 | 0x4E | 0x6E | 0x5E | 0x7E => Cpu::ld_r8_mhl,
-                0x70 | 0x74 | 0x72 | 0x71 | 0x75 | 0x73 | 0x77 => Cpu::ld_mhl_r8,
-                //
-                0x76 => Cpu::halt,
-
-                // Block 2
-                0x80 | 0x84 | 0x82 | 0x81 | 0x85 | 0x83 | 0x86 | 0x87 => Cpu::add_a_r8,
-                0x88 | 0x8C | 0x8A | 0x89 | 0x8D | 0x8B | 0x8E | 0x8F => Cpu::adc_a_r8,
-                0x90 | 0x94 | 0x92 | 0x96 | 0x91 | 0x95 | 0x93 | 0x97 => Cpu::sub_a_r8,
-                0x98 | 0x9C | 0x9A | 0x9E | 0x99 | 0x9D | 0x9B | 0x9F => Cpu::sbc_a_r8,
-                0xA0 | 0xA4 | 0xA2 | 0xA6 | 0xA1 | 0xA5 | 0xA3 | 0xA7 => Cpu::and_a_r8,
-                0xA8 | 0xAC | 0xAA | 0xAE | 0xA9 | 0xAD | 0xAB | 0xAF => Cpu::xor_a_r8,
-                0xB0 | 0xB4 | 0xB2 | 0xB6 | 0xB1 | 0xB5 | 0xB3 | 0xB7 => Cpu::or_a_r8,
-                0xB8 | 0xBC | 0xBA | 0xBE | 0xB9 | 0xBD | 0xBB | 0xBF => Cpu::cp_a_r8,
-
-                // Block 3
-                0xC6 => Cpu::add_a_imm8,
-                0xCE => Cpu::adc_a_imm8,
-                0xD6 => Cpu::sub_a_imm8,
-                0xDE => Cpu::sbc_a_imm8,
-                0xE6 => Cpu::and_a_imm8,
-                0xEE => Cpu::xor_a_imm8,
-                0xF6 => Cpu::or_a_imm8,
-                0xFE => Cpu::cp_a_imm8,
-                //
-                0xC0 | 0xD0 | 0xC8 | 0xD8 => Cpu::ret_cond,
-                0xC9 => Cpu::ret,
-                0xD9 => Cpu::reti,
-                0xC2 | 0xD2 | 0xCA | 0xDA => Cpu::jp_cond_imm16,
-                0xC3 => Cpu::jp_imm16,
-                0xE9 => Cpu::jp_hl,
-                0xC4 | 0xD4 | 0xCC | 0xDC => Cpu::call_cond_imm16,
-                0xCD => Cpu::call_imm16,
-                0xC7 | 0xE7 | 0xD7 | 0xF7 | 0xCF | 0xEF | 0xDF | 0xFF => Cpu::rst_tgt3,
-                //
-                0xC1 | 0xE1 | 0xD1 | 0xF1 => Cpu::pop_r16stk,
-                0xC5 | 0xE5 | 0xD5 | 0xF5 => Cpu::push_r16stk,
-                //
-                0xCB => Cpu::cb_prefix,
-                //
-                0xE2 => Cpu::ldh_mc_a,
-                0xE0 => Cpu::ldh_mimm8_a,
-                0xEA => Cpu::ld_mimm16_a,
-                0xF2 => Cpu::ldh_a_mc,
-                0xF0 => Cpu::ldh_a_mimm8,
-                0xFA => Cpu::ld_a_mimm16,
-                //
-                0xE8 => Cpu::add_sp_imm8,
-                0xF8 => Cpu::ld_hl_sp_plus_imm8,
-                0xF9 => Cpu::ld_sp_hl,
-                //
-                0xF3 => Cpu::di,
-                0xFB => Cpu::ei,
-                //
-                0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xF4 | 0xFC | 0xFD => panic!(
-                    "Opcode hard-locked the CPU!: 0x{:02x} (pc: {:04x})",
-                    self.ir(),
-                    self.pc()
-                ),
-                _ => panic!("Opcode not implemented: 0x{:02x}", self.ir()),
-            }
+            self.decoded = Decoded::Opcode(self.ir());
+            Op::from(self.ir())
+        };
+        Cpu::handler(op)
+    }
+
+    fn handler(op: Op) -> fn(&mut Cpu) {
+        match op {
+            Op::Nop => Cpu::nop,
+            Op::LdR16Imm16 => Cpu::ld_r16_imm16,
+            Op::LdMr16memA => Cpu::ld_mr16mem_a,
+            Op::LdAMr16mem => Cpu::ld_a_mr16mem,
+            Op::LdMimm16Sp => Cpu::ld_mimm16_sp,
+            Op::IncR16 => Cpu::inc_r16,
+            Op::DecR16 => Cpu::dec_r16,
+            Op::AddHlR16 => Cpu::add_hl_r16,
+            Op::IncR8 => Cpu::inc_r8,
+            Op::IncMhl => Cpu::inc_mhl,
+            Op::DecR8 => Cpu::dec_r8,
+            Op::DecMhl => Cpu::dec_mhl,
+            Op::LdR8Imm8 => Cpu::ld_r8_imm8,
+            Op::LdMhlImm8 => Cpu::ld_mhl_imm8,
+            Op::Rlca => Cpu::rlca,
+            Op::Rrca => Cpu::rrca,
+            Op::Rla => Cpu::rla,
+            Op::Rra => Cpu::rra,
+            Op::Daa => Cpu::daa,
+            Op::Cpl => Cpu::cpl,
+            Op::Scf => Cpu::scf,
+            Op::Ccf => Cpu::ccf,
+            Op::JrImm8 => Cpu::jr_imm8,
+            Op::JrCondImm8 => Cpu::jr_cond_imm8,
+            Op::Stop => Cpu::stop,
+            Op::LdR8R8 => Cpu::ld_r8_r8,
+            Op::LdR8Mhl => Cpu::ld_r8_mhl,
+            Op::LdMhlR8 => Cpu::ld_mhl_r8,
+            Op::Halt => Cpu::halt,
+            Op::AddAR8 => Cpu::add_a_r8,
+            Op::AdcAR8 => Cpu::adc_a_r8,
+            Op::SubAR8 => Cpu::sub_a_r8,
+            Op::SbcAR8 => Cpu::sbc_a_r8,
+            Op::AndAR8 => Cpu::and_a_r8,
+            Op::XorAR8 => Cpu::xor_a_r8,
+            Op::OrAR8 => Cpu::or_a_r8,
+            Op::CpAR8 => Cpu::cp_a_r8,
+            Op::AddAImm8 => Cpu::add_a_imm8,
+            Op::AdcAImm8 => Cpu::adc_a_imm8,
+            Op::SubAImm8 => Cpu::sub_a_imm8,
+            Op::SbcAImm8 => Cpu::sbc_a_imm8,
+            Op::AndAImm8 => Cpu::and_a_imm8,
+            Op::XorAImm8 => Cpu::xor_a_imm8,
+            Op::OrAImm8 => Cpu::or_a_imm8,
+            Op::CpAImm8 => Cpu::cp_a_imm8,
+            Op::RetCond => Cpu::ret_cond,
+            Op::Ret => Cpu::ret,
+            Op::Reti => Cpu::reti,
+            Op::JpCondImm16 => Cpu::jp_cond_imm16,
+            Op::JpImm16 => Cpu::jp_imm16,
+            Op::JpHl => Cpu::jp_hl,
+            Op::CallCondImm16 => Cpu::call_cond_imm16,
+            Op::CallImm16 => Cpu::call_imm16,
+            Op::RstTgt3 => Cpu::rst_tgt3,
+            Op::PopR16stk => Cpu::pop_r16stk,
+            Op::PushR16stk => Cpu::push_r16stk,
+            Op::CbPrefix => Cpu::cb_prefix,
+            Op::LdhMcA => Cpu::ldh_mc_a,
+            Op::LdhMimm8A => Cpu::ldh_mimm8_a,
+            Op::LdMimm16A => Cpu::ld_mimm16_a,
+            Op::LdhAMc => Cpu::ldh_a_mc,
+            Op::LdhAMimm8 => Cpu::ldh_a_mimm8,
+            Op::LdAMimm16 => Cpu::ld_a_mimm16,
+            Op::AddSpImm8 => Cpu::add_sp_imm8,
+            Op::LdHlSpPlusImm8 => Cpu::ld_hl_sp_plus_imm8,
+            Op::LdSpHl => Cpu::ld_sp_hl,
+            Op::Di => Cpu::di,
+            Op::Ei => Cpu::ei,
+            Op::Illegal => Cpu::illegal,
+            Op::RlcR8 => Cpu::rlc_r8,
+            Op::RrcR8 => Cpu::rrc_r8,
+            Op::RlR8 => Cpu::rl_r8,
+            Op::RrR8 => Cpu::rr_r8,
+            Op::SlaR8 => Cpu::sla_r8,
+            Op::SraR8 => Cpu::sra_r8,
+            Op::SwapR8 => Cpu::swap_r8,
+            Op::SrlR8 => Cpu::srl_r8,
+            Op::BitB3R8 => Cpu::bit_b3_r8,
+            Op::ResB3R8 => Cpu::res_b3_r8,
+            Op::SetB3R8 => Cpu::set_b3_r8,
         }
     }
 
@@ -358,20 +570,59 @@ impl Cpu {
 
     pub fn fetch_next(&mut self) {
         self.set_addr(self.pc());
-        self.mem_read();
+        self.bus.fetch();
         self.set_ir(self.data());
         self.push_pc(self.pc());
-        self.inc_pc();
+        // The HALT bug reads the next opcode without incrementing PC
+        if self.haltbug {
+            self.haltbug = false;
+        } else {
+            self.inc_pc();
+        }
+        // Interrupts are only dispatched between instructions, never between
+        // the CB prefix and its opcode
+        self.boundary = self.cb == 0;
+        self.ei_delay = false;
+        self.dispatching = false;
         self.mc = M0;
         self.executing = self.decode();
         (self.executing)(self);
     }
 
+    /// Continues between instructions at `pc`, halted or stopped if asked,
+    /// for states from other emulators
+    pub fn resume_at(&mut self, pc: u16, halted: bool, stopped: bool) {
+        self.cb = 0;
+        self.haltbug = false;
+        self.locked = false;
+        self.ei_delay = false;
+        self.dispatching = false;
+        self.halted = halted;
+        self.stopped = stopped;
+        if halted || stopped {
+            // Waking up fetches the opcode at PC
+            self.set_pc(pc);
+            self.push_pc(pc);
+            self.mc = M1;
+            self.executing = Cpu::nop;
+            self.decoded = Decoded::Opcode(0x00);
+        } else {
+            self.dbg_jump(pc);
+        }
+    }
+
     pub fn fetch_next_addr(&mut self, addr: u16) {
         self.set_pc(addr);
         self.fetch_next();
     }
 
+    /// Redirects execution to `addr` between instructions, refetching the
+    /// opcode there
+    pub fn dbg_jump(&mut self, addr: u16) {
+        self.fetch_next_addr(addr);
+        self.mc = self.mc.next();
+    }
+
     pub fn pop_imm8_into_z(&mut self) {
         self.set_addr(self.pc());
         self.mem_read();
@@ -382,63 +633,58 @@ impl Cpu {
     pub fn handle_interrupts(&mut self) {
         let reg_ie = self.mem_dbg_read(0xFFFF);
         let reg_if = self.mem_dbg_read(0xFF0F);
-        let hit = reg_ie & reg_if;
-        let washalted = self.halted;
-        if hit != 0x00 {
+        let hit = reg_ie & reg_if & 0x1F;
+        if hit == 0x00 {
+            return;
+        }
+
+        if self.halted {
+            // Waking up takes the M-cycle the interrupt was noticed in, no
+            // opcode has been fetched yet so there is nothing to undo
             self.halted = false;
+            log!(Cpu, Trace, "Unhalting, IE & IF = {:02X}", hit);
             if self.ime == 1 {
-                self.set_ime(0x00);
-                self.mc = M0;
-                self.executing = if hit & 0x01 != 0 {
-                    self.mem_dbg_write(0xFF0F, reg_if & !0x01);
-                    self.push_pc(0x0040);
-                    Cpu::int_vblank
-                } else if hit & 0x02 != 0 {
-                    self.mem_dbg_write(0xFF0F, reg_if & !0x02);
-                    self.push_pc(0x0048);
-                    Cpu::int_stat
-                } else if hit & 0x04 != 0 {
-                    self.mem_dbg_write(0xFF0F, reg_if & !0x04);
-                    self.push_pc(0x0050);
-                    Cpu::int_timer
-                } else if hit & 0x08 != 0 {
-                    self.mem_dbg_write(0xFF0F, reg_if & !0x08);
-                    self.push_pc(0x0058);
-                    Cpu::int_serial
-                } else if hit & 0x10 != 0 {
-                    self.mem_dbg_write(0xFF0F, reg_if & !0x10);
-                    self.push_pc(0x0060);
-                    eprintln!("JOYPAD!!!!!!!!!!!!!!!!!!!!!!!!");
-                    Cpu::int_joypad
-                } else {
-                    panic!("Invalid value in interrupt registers");
-                };
-                (self.executing)(self);
-                self.mc = self.mc.next();
-                self.decode();
-            } else if washalted {
-                eprintln!("Unhalting!");
-                eprintln!("m:{:?}", self.mc);
-                if self.haltbug {
-                    self.set_pc(self.pc() - 1);
-                }
+                self.retired = self.retired.wrapping_add(1);
+                self.dispatch();
+            } else {
                 self.fetch_next();
                 self.mc = self.mc.next();
-                eprintln!("m:{:?}", self.mc);
             }
+        } else if self.boundary && self.ime == 1 {
+            // The opcode fetched this M-cycle is discarded and PC rewound so
+            // the handler returns to it
+            self.set_pc(self.pc().wrapping_sub(1));
+            self.dispatch();
         }
     }
 
+    fn wake_from_stop(&mut self) {
+        // Only an input line going low wakes the CPU from STOP
+        if self.mem_dbg_read(0xFF00) & 0x0F != 0x0F {
+            self.stopped = false;
+            self.fetch_next();
+            self.mc = self.mc.next();
+        }
+    }
+
+    fn dispatch(&mut self) {
+        self.dispatching = true;
+        self.mc = M0;
+        self.decoded = Decoded::Dispatch;
+        self.executing = Cpu::int_dispatch;
+        (self.executing)(self);
+        self.mc = self.mc.next();
+    }
+
     pub fn mask_bit(&self) -> u8 {
         1 << ((self.ir() & M543) >> 3)
     }
 
     pub fn init_dmg(rom: &[u8]) -> Self {
-        let mem = Rc::new(RefCell::new(Memory::new(rom)));
-        Self::init_dmg_with_memory(mem)
+        Self::init_dmg_with_bus(Bus::new(Memory::new(rom), Ppu::headless_dmg()))
     }
 
-    pub fn init_dmg_with_memory(mem: Rc<RefCell<Memory>>) -> Self {
+    pub fn init_dmg_with_bus(bus: Bus) -> Self {
         let r = Registers {
             ir: 0x00,
             ie: 0x00,
@@ -452,7 +698,7 @@ impl Cpu {
         };
         let initial_pc = r.pc;
         Cpu {
-            mem,
+            bus,
             r,
             log_regs_prev: r,
             log_regs_cur: r,
@@ -460,15 +706,33 @@ impl Cpu {
             cb: 0,
             mc: Mc::M1,
             executing: Cpu::nop,
+            decoded: Decoded::Opcode(0x00),
             halted: false,
             haltbug: false,
+            stopped: false,
+            locked: false,
+            boundary: false,
+            dispatching: false,
+            ei_delay: false,
             retired: 0,
             cur_pc: initial_pc,
             prev_pc: initial_pc,
-            dbg_break: 0,
+            soft_breaks: 0,
+            soft_break: None,
         }
     }
 
+    pub fn init_sgb_with_bus(bus: Bus) -> Self {
+        let mut cpu = Self::init_dmg_with_bus(bus);
+        cpu.set_af(0x0100);
+        cpu.set_bc(0x0014);
+        cpu.set_de(0x0000);
+        cpu.set_hl(0xC060);
+        cpu.log_regs_prev = cpu.r;
+        cpu.log_regs_cur = cpu.r;
+        cpu
+    }
+
     // {{{ opcode nop
     pub fn nop(&mut self) {
         match self.mc {
@@ -1044,8 +1308,29 @@ impl Cpu {
     pub fn stop(&mut self) {
         match self.mc {
             M1 => {
-                // TODO: Handle stop correctly
-                self.fetch_next();
+                let pressed = self.mem_dbg_read(0xFF00) & 0x0F != 0x0F;
+                let reg_ie = self.mem_dbg_read(0xFFFF);
+                let reg_if = self.mem_dbg_read(0xFF0F);
+                let hit = reg_ie & reg_if & 0x1F;
+                // STOP swallows the byte after it unless an interrupt is
+                // pending
+                if hit == 0 {
+                    self.inc_pc();
+                }
+                if !pressed {
+                    // Entering STOP mode resets DIV
+                    self.set_addr(0xFF04);
+                    self.set_data(0x00);
+                    self.mem_write();
+                    self.stopped = true;
+                    self.set_mc(M2);
+                } else if hit == 0 {
+                    // A held button turns STOP into a HALT
+                    self.halted = true;
+                    self.set_mc(M2);
+                } else {
+                    self.fetch_next();
+                }
             }
             M0 => self.set_mc(M2),
             _ => panic!("Invalid mc in {}: {:?}", function!(), self.mc),
@@ -1062,8 +1347,8 @@ impl Cpu {
                 let r8_source = self.r8_operand();
                 let r8_dest = R8::from((self.ir() & M543) >> 3);
                 self.set_r8(r8_dest, self.r8(r8_source));
-                if r8_source == r8_dest && r8_source == R8::B {
-                    //self.dbg_break += 1;
+                if r8_source == r8_dest && self.soft_breaks & (1 << (self.ir() & M210)) != 0 {
+                    self.soft_break = Some((self.cur_pc(), self.ir()));
                 }
                 self.fetch_next();
             }
@@ -1116,18 +1401,19 @@ impl Cpu {
             M1 => {
                 let reg_ie = self.mem_dbg_read(0xFFFF);
                 let reg_if = self.mem_dbg_read(0xFF0F);
-                let hit = reg_ie & reg_if;
-                if hit != 0 && self.ime == 0 {
-                    // TODO: implement ei and rst special cases for halt bug
-                    self.set_pc(self.pc() - 1);
+                let hit = reg_ie & reg_if & 0x1F;
+                // An EI right before HALT has not taken effect yet
+                let ime = if self.ei_delay { 0 } else { self.ime };
+                if hit != 0 && ime == 0 {
+                    // HALT bug: the CPU does not halt and the byte after HALT
+                    // is read twice. After EI the interrupt is then serviced
+                    // and returns to the HALT, and an RST returns to itself.
+                    self.haltbug = true;
                     self.fetch_next();
-                    self.haltbug = false;
                 } else {
                     self.halted = true;
-                    self.haltbug = false;
+                    self.set_mc(M2);
                 }
-                // TODO: halt has a lot of interactions to implement still
-                self.set_mc(M2);
             }
             M0 => self.set_mc(M2),
             _ => panic!("Invalid mc in {}: {:?}", function!(), self.mc),
@@ -2251,6 +2537,19 @@ impl Cpu {
     }
     // }}}
 
+    // {{{ opcode illegal
+    pub fn illegal(&mut self) {
+        match self.mc {
+            // Hard-locks the CPU until power-off, interrupts included
+            M0 => {
+                self.locked = true;
+                self.set_mc(M2);
+            }
+            _ => panic!("Invalid mc in {}: {:?}", function!(), self.mc),
+        }
+    }
+    // }}}
+
     // {{{ opcode rlc_r8
     pub fn rlc_r8(&mut self) {
         let r8 = self.r8_operand();
@@ -2788,22 +3087,39 @@ impl Cpu {
     // }}} end Execute Functions
 
     // {{{ Interrupt Functions
-    pub fn int_common(&mut self, addr: u16) {
+    pub fn int_dispatch(&mut self) {
         match self.mc {
-            M5 => {} // Pad to 5 M-cycles
-            M4 => self.dec_sp(),
-            M3 => {
+            M5 => self.dec_sp(),
+            M4 => {
                 self.set_addr(self.sp());
                 self.set_data(self.pch());
                 self.mem_write();
                 self.dec_sp();
+                // IE is sampled after the high byte is pushed, so pushing
+                // into $FFFF can change which interrupt is taken
+                self.set_z(self.mem_dbg_read(0xFFFF));
             }
-            M2 => {
+            M3 => {
                 self.set_addr(self.sp());
                 self.set_data(self.pcl());
                 self.mem_write();
-                self.set_pc(addr);
+                let reg_if = self.mem_dbg_read(0xFF0F);
+                let hit = self.z() & reg_if & 0x1F;
+                // With nothing left pending the dispatch is cancelled and
+                // jumps to $0000 instead
+                let vector = if hit == 0 {
+                    0x0000
+                } else {
+                    let bit = hit.trailing_zeros() as u16;
+                    let vector = 0x0040 + bit * 8;
+                    log!(Cpu, Debug, "Interrupt {} to {:04X}", bit, vector);
+                    let reg_if = reg_if & !(1 << bit);
+                    self.bus.mem.write_as(Comp::Cpu, 0xFF0F, reg_if);
+                    vector
+                };
+                self.set_pc(vector);
             }
+            M2 => {} // Pad to 5 M-cycles
             M1 => {
                 self.fetch_next();
             }
@@ -2814,103 +3130,107 @@ impl Cpu {
             _ => panic!("Invalid mc in {}: {:?}", function!(), self.mc),
         }
     }
-
-    pub fn int_vblank(&mut self) {
-        self.int_common(0x0040);
-    }
-
-    pub fn int_stat(&mut self) {
-        self.int_common(0x0048);
-    }
-
-    pub fn int_timer(&mut self) {
-        self.int_common(0x0050);
-    }
-
-    pub fn int_serial(&mut self) {
-        self.int_common(0x0058);
-    }
-
-    pub fn int_joypad(&mut self) {
-        self.int_common(0x0060);
-    }
     // }}}
 
     // {{{ Cycle Functions
     pub fn tick(&mut self, t: u128) {
-        self.own(true);
-        if self.dbg_break >= 2 {
-            //            panic!("Mooneye break!");
-        }
-        if t.is_multiple_of(4) {
-            if !self.halted {
+        if t.is_multiple_of(4) && !self.locked {
+            self.boundary = false;
+            if self.stopped {
+                self.wake_from_stop();
+            } else if !self.halted {
                 self.execute();
             }
-            self.handle_interrupts();
+            if !self.stopped && !self.locked {
+                self.handle_interrupts();
+            }
             if self.ime() == 0x2 {
                 self.set_ime(0x1);
+                self.ei_delay = true;
             }
         }
-        self.own(false);
     }
     // }}}
 
     // {{{ Memory Functions
     pub fn mem_read(&mut self) {
-        self.with_mem_mut(|mem| {
-            mem.read();
-        });
+        self.bus.read();
     }
 
     pub fn mem_dbg_read(&self, addr: u16) -> u8 {
-        self.with_mem(|mem| mem.dbg_read(addr))
+        self.bus.dbg_read(addr)
     }
 
     pub fn mem_write(&mut self) {
-        self.with_mem_mut(|mem| {
-            mem.write();
-        });
+        self.bus.write();
     }
 
     pub fn mem_dbg_write(&mut self, addr: u16, data: u8) {
-        self.with_mem_mut(|mem| mem.dbg_write(addr, data));
+        self.bus.dbg_write(addr, data);
     }
 
     pub fn mem_bulk_write(&mut self, addr: u16, data: &[u8]) {
-        self.with_mem_mut(|mem| mem.bulk_write(addr, data));
+        self.bus.mem.bulk_write(addr, data);
     }
 
     pub fn addr(&self) -> u16 {
-        self.with_mem(|mem| mem.addr())
+        self.bus.addr()
     }
 
     pub fn data(&self) -> u8 {
-        self.with_mem(|mem| mem.data())
+        self.bus.data()
     }
 
     pub fn set_addr(&mut self, addr: u16) {
-        self.with_mem_mut(|mem| mem.set_addr(addr));
+        self.bus.set_addr(addr);
     }
 
     pub fn set_data(&mut self, data: u8) {
-        self.with_mem_mut(|mem| mem.set_data(data));
+        self.bus.set_data(data);
     }
 
-    fn with_mem_mut<R>(&self, f: impl FnOnce(&mut Memory) -> R) -> R {
-        let mut mem = self.mem.borrow_mut();
-        f(&mut mem)
+    pub fn bus(&self) -> &Bus {
+        &self.bus
     }
 
-    fn with_mem<R>(&self, f: impl FnOnce(&Memory) -> R) -> R {
-        let mem = self.mem.borrow();
-        f(&mem)
+    pub fn bus_mut(&mut self) -> &mut Bus {
+        &mut self.bus
+    }
+
+    /// Powers back on, keeping the bus with the cartridge it was given
+    pub fn reset(&mut self) {
+        let sgb = self.bus.mem.sgb().is_some();
+        let soft_breaks = self.soft_breaks;
+        let mut bus = std::mem::take(&mut self.bus);
+        bus.reset();
+        *self = if sgb {
+            Cpu::init_sgb_with_bus(bus)
+        } else {
+            Cpu::init_dmg_with_bus(bus)
+        };
+        self.soft_breaks = soft_breaks;
     }
 
-    pub fn own(&mut self, own: bool) {
-        let owner = if own { Comp::Cpu } else { Comp::None };
-        self.with_mem_mut(|mem| mem.set_owner(owner))
+    /// Makes `opcode`, one of the `LD r,r` that do nothing like `LD_B_B`,
+    /// a software breakpoint or not. Returns false for any other opcode
+    pub fn set_soft_break(&mut self, opcode: u8, enabled: bool) -> bool {
+        if !is_ld_self(opcode) {
+            return false;
+        }
+        let reg = opcode & M210;
+        if enabled {
+            self.soft_breaks |= 1 << reg;
+        } else {
+            self.soft_breaks &= !(1 << reg);
+        }
+        true
     }
 
+    /// Takes the address and opcode of the software breakpoint that ran
+    /// since the last call, if any
+    pub fn take_soft_break(&mut self) -> Option<(u16, u8)> {
+        self.soft_break.take()
+    }
     // }}}
 
     // {{{ CPU Getters
@@ -2988,6 +3308,20 @@ impl Cpu {
         self.halted
     }
 
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Whether an interrupt is being dispatched, the opcode fetched before
+    /// it was discarded and the handler's first one is not fetched yet
+    pub fn dispatching(&self) -> bool {
+        self.dispatching
+    }
+
     pub fn retired(&self) -> u64 {
         self.retired
     }
@@ -3282,11 +3616,11 @@ impl Cpu {
     }
 
     pub fn inc_sp(&mut self) {
-        self.r.sp += 1;
+        self.r.sp = self.r.sp.wrapping_add(1);
     }
 
     pub fn dec_sp(&mut self) {
-        self.r.sp -= 1;
+        self.r.sp = self.r.sp.wrapping_sub(1);
     }
 
     pub fn set_pc(&mut self, pc: u16) {
@@ -3332,7 +3666,7 @@ impl Cpu {
     // }}}
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Default)]
 pub struct CpuLogView {
     pub a: u8,
     pub f: u8,
@@ -3361,6 +3695,97 @@ impl CpuLogView {
     }
 }
 
+// {{{ Save states
+impl Registers {
+    fn save(&self, out: &mut Writer) {
+        out.u8(self.ir);
+        out.u8(self.ie);
+        for r in [
+            self.af, self.bc, self.de, self.hl, self.sp, self.pc, self.wz,
+        ] {
+            out.u16(r);
+        }
+    }
+
+    fn load(input: &mut Reader) -> Result<Self, String> {
+        Ok(Registers {
+            ir: input.u8()?,
+            ie: input.u8()?,
+            af: input.u16()?,
+            bc: input.u16()?,
+            de: input.u16()?,
+            hl: input.u16()?,
+            sp: input.u16()?,
+            pc: input.u16()?,
+            wz: input.u16()?,
+        })
+    }
+}
+
+const MCS: [Mc; 8] = [M7, M6, M5, M4, M3, M2, M1, M0];
+
+impl Cpu {
+    pub fn save(&self, out: &mut Writer) {
+        self.r.save(out);
+        self.log_regs_prev.save(out);
+        self.log_regs_cur.save(out);
+        out.u8(self.ime);
+        out.u8(self.cb);
+        out.u8(self.mc as u8);
+        match self.decoded {
+            Decoded::Opcode(ir) => out.array(&[0, ir]),
+            Decoded::Cb(ir) => out.array(&[1, ir]),
+            Decoded::Dispatch => out.array(&[2, 0]),
+        }
+        for flag in [
+            self.halted,
+            self.haltbug,
+            self.stopped,
+            self.locked,
+            self.boundary,
+            self.dispatching,
+            self.ei_delay,
+        ] {
+            out.bool(flag);
+        }
+        out.u64(self.retired);
+        out.u16(self.cur_pc);
+        out.u16(self.prev_pc);
+    }
+
+    pub fn load(&mut self, input: &mut Reader) -> Result<(), String> {
+        self.r = Registers::load(input)?;
+        self.log_regs_prev = Registers::load(input)?;
+        self.log_regs_cur = Registers::load(input)?;
+        self.ime = input.u8()?;
+        self.cb = input.u8()?;
+        self.mc = *MCS.get(input.u8()? as usize).ok_or("invalid M-cycle")?;
+        // The in-flight instruction is decoded again from what it was
+        // decoded from, without counting it as retired twice
+        let (executing, decoded) = match input.array()? {
+            [0, ir] => (Cpu::handler(Op::from(ir)), Decoded::Opcode(ir)),
+            [1, ir] => (Cpu::handler(Op::from_cb(ir)), Decoded::Cb(ir)),
+            [2, _] => (Cpu::int_dispatch as fn(&mut Cpu), Decoded::Dispatch),
+            _ => return Err("invalid executing instruction".to_string()),
+        };
+        self.executing = executing;
+        self.decoded = decoded;
+        self.halted = input.bool()?;
+        self.haltbug = input.bool()?;
+        self.stopped = input.bool()?;
+        self.locked = input.bool()?;
+        self.boundary = input.bool()?;
+        self.dispatching = input.bool()?;
+        self.ei_delay = input.bool()?;
+        self.retired = input.u64()?;
+        self.cur_pc = input.u16()?;
+        self.prev_pc = input.u16()?;
+        self.soft_break = None;
+        Ok(())
+    }
+}
+// }}}
+
 // {{{ Defaults
 #[allow(clippy::derivable_impls)]
 impl std::default::Default for Cpu {
@@ -3377,7 +3802,7 @@ impl std::default::Default for Cpu {
             wz: 0x0000,
         };
         Cpu {
-            mem: Rc::new(RefCell::new(Memory::new(&[]))),
+            bus: Bus::default(),
             r,
             log_regs_prev: r,
             log_regs_cur: r,
@@ -3385,12 +3810,19 @@ impl std::default::Default for Cpu {
             cb: 0,
             mc: Mc::M1,
             executing: Cpu::nop,
+            decoded: Decoded::Opcode(0x00),
             halted: false,
             haltbug: false,
+            stopped: false,
+            locked: false,
+            boundary: false,
+            dispatching: false,
+            ei_delay: false,
             retired: 0,
             cur_pc: 0,
             prev_pc: 0,
-            dbg_break: 0,
+            soft_breaks: 0,
+            soft_break: None,
         }
     }
 }
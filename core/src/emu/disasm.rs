@@ -0,0 +1,295 @@
+use crate::emu::cpu::{Cond, Op, R8, R16, R16mem, R16stk};
+use crate::emu::symbols::Symbols;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub bank: usize,
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+    /// The address a jump, call or memory operand refers to
+    pub target: Option<u16>,
+}
+
+impl Instruction {
+    pub fn next_addr(&self) -> u16 {
+        self.addr.wrapping_add(self.bytes.len() as u16)
+    }
+
+    /// Replaces the target address in the text with the name `name` gives it
+    pub fn name_target(&mut self, name: impl Fn(u16) -> Option<String>) {
+        let Some(target) = self.target else {
+            return;
+        };
+        let Some(label) = name(target) else {
+            return;
+        };
+        let hex = if self.text.starts_with("rst") {
+            format!("${:02X}", target)
+        } else {
+            format!("${:04X}", target)
+        };
+        self.text = self.text.replacen(&hex, &label, 1);
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let bytes = self
+            .bytes
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<_>>()
+            .join(" ");
+        write!(
+            f,
+            "{:02X}:{:04X}  {:<8}  {}",
+            self.bank, self.addr, bytes, self.text
+        )
+    }
+}
+
+/// Decodes the instruction at `addr`, reading its bytes through `read`
+pub fn decode(bank: usize, addr: u16, read: impl Fn(u16) -> u8) -> Instruction {
+    let ir = read(addr);
+    let imm8 = read(addr.wrapping_add(1));
+    let imm16 = u16::from_le_bytes([imm8, read(addr.wrapping_add(2))]);
+
+    let (len, text) = match Op::from(ir) {
+        Op::Nop => (1, "nop".to_string()),
+        Op::LdR16Imm16 => (3, format!("ld {}, ${:04X}", r16(ir), imm16)),
+        Op::LdMr16memA => (1, format!("ld {}, a", r16mem(ir))),
+        Op::LdAMr16mem => (1, format!("ld a, {}", r16mem(ir))),
+        Op::LdMimm16Sp => (3, format!("ld [${:04X}], sp", imm16)),
+        Op::IncR16 => (1, format!("inc {}", r16(ir))),
+        Op::DecR16 => (1, format!("dec {}", r16(ir))),
+        Op::AddHlR16 => (1, format!("add hl, {}", r16(ir))),
+        Op::IncR8 | Op::IncMhl => (1, format!("inc {}", r8_dest(ir))),
+        Op::DecR8 | Op::DecMhl => (1, format!("dec {}", r8_dest(ir))),
+        Op::LdR8Imm8 | Op::LdMhlImm8 => (2, format!("ld {}, ${:02X}", r8_dest(ir), imm8)),
+        Op::Rlca => (1, "rlca".to_string()),
+        Op::Rrca => (1, "rrca".to_string()),
+        Op::Rla => (1, "rla".to_string()),
+        Op::Rra => (1, "rra".to_string()),
+        Op::Daa => (1, "daa".to_string()),
+        Op::Cpl => (1, "cpl".to_string()),
+        Op::Scf => (1, "scf".to_string()),
+        Op::Ccf => (1, "ccf".to_string()),
+        Op::JrImm8 => (2, format!("jr ${:04X}", relative(addr, imm8))),
+        Op::JrCondImm8 => (2, format!("jr {}, ${:04X}", cond(ir), relative(addr, imm8))),
+        Op::Stop => (2, "stop".to_string()),
+        Op::LdR8R8 | Op::LdR8Mhl | Op::LdMhlR8 => {
+            (1, format!("ld {}, {}", r8_dest(ir), r8_source(ir)))
+        }
+        Op::Halt => (1, "halt".to_string()),
+        Op::AddAR8 => (1, format!("add a, {}", r8_source(ir))),
+        Op::AdcAR8 => (1, format!("adc a, {}", r8_source(ir))),
+        Op::SubAR8 => (1, format!("sub a, {}", r8_source(ir))),
+        Op::SbcAR8 => (1, format!("sbc a, {}", r8_source(ir))),
+        Op::AndAR8 => (1, format!("and a, {}", r8_source(ir))),
+        Op::XorAR8 => (1, format!("xor a, {}", r8_source(ir))),
+        Op::OrAR8 => (1, format!("or a, {}", r8_source(ir))),
+        Op::CpAR8 => (1, format!("cp a, {}", r8_source(ir))),
+        Op::AddAImm8 => (2, format!("add a, ${:02X}", imm8)),
+        Op::AdcAImm8 => (2, format!("adc a, ${:02X}", imm8)),
+        Op::SubAImm8 => (2, format!("sub a, ${:02X}", imm8)),
+        Op::SbcAImm8 => (2, format!("sbc a, ${:02X}", imm8)),
+        Op::AndAImm8 => (2, format!("and a, ${:02X}", imm8)),
+        Op::XorAImm8 => (2, format!("xor a, ${:02X}", imm8)),
+        Op::OrAImm8 => (2, format!("or a, ${:02X}", imm8)),
+        Op::CpAImm8 => (2, format!("cp a, ${:02X}", imm8)),
+        Op::RetCond => (1, format!("ret {}", cond(ir))),
+        Op::Ret => (1, "ret".to_string()),
+        Op::Reti => (1, "reti".to_string()),
+        Op::JpCondImm16 => (3, format!("jp {}, ${:04X}", cond(ir), imm16)),
+        Op::JpImm16 => (3, format!("jp ${:04X}", imm16)),
+        Op::JpHl => (1, "jp hl".to_string()),
+        Op::CallCondImm16 => (3, format!("call {}, ${:04X}", cond(ir), imm16)),
+        Op::CallImm16 => (3, format!("call ${:04X}", imm16)),
+        Op::RstTgt3 => (1, format!("rst ${:02X}", ir & 0x38)),
+        Op::PopR16stk => (1, format!("pop {}", r16stk(ir))),
+        Op::PushR16stk => (1, format!("push {}", r16stk(ir))),
+        Op::CbPrefix => (2, decode_cb(imm8)),
+        Op::LdhMcA => (1, "ldh [c], a".to_string()),
+        Op::LdhMimm8A => (2, format!("ldh [$FF{:02X}], a", imm8)),
+        Op::LdMimm16A => (3, format!("ld [${:04X}], a", imm16)),
+        Op::LdhAMc => (1, "ldh a, [c]".to_string()),
+        Op::LdhAMimm8 => (2, format!("ldh a, [$FF{:02X}]", imm8)),
+        Op::LdAMimm16 => (3, format!("ld a, [${:04X}]", imm16)),
+        Op::AddSpImm8 => (
+            2,
+            format!("add sp, {}", signed(imm8).trim_start_matches('+')),
+        ),
+        Op::LdHlSpPlusImm8 => (2, format!("ld hl, sp{}", signed(imm8))),
+        Op::LdSpHl => (1, "ld sp, hl".to_string()),
+        Op::Di => (1, "di".to_string()),
+        Op::Ei => (1, "ei".to_string()),
+        Op::Illegal => (1, format!("db ${:02X}", ir)),
+        op => unreachable!("CB opcode {:?} outside of the CB table", op),
+    };
+
+    let target = match Op::from(ir) {
+        Op::JrImm8 | Op::JrCondImm8 => Some(relative(addr, imm8)),
+        Op::LdR16Imm16
+        | Op::LdMimm16Sp
+        | Op::JpCondImm16
+        | Op::JpImm16
+        | Op::CallCondImm16
+        | Op::CallImm16
+        | Op::LdMimm16A
+        | Op::LdAMimm16 => Some(imm16),
+        Op::RstTgt3 => Some((ir & 0x38) as u16),
+        Op::LdhMimm8A | Op::LdhAMimm8 => Some(0xFF00 | imm8 as u16),
+        _ => None,
+    };
+
+    let bytes = (0..len).map(|i| read(addr.wrapping_add(i))).collect();
+    Instruction {
+        bank,
+        addr,
+        bytes,
+        text,
+        target,
+    }
+}
+
+fn decode_cb(ir: u8) -> String {
+    let r8 = r8_source(ir);
+    let b3 = (ir >> 3) & 0x07;
+    match Op::from_cb(ir) {
+        Op::RlcR8 => format!("rlc {}", r8),
+        Op::RrcR8 => format!("rrc {}", r8),
+        Op::RlR8 => format!("rl {}", r8),
+        Op::RrR8 => format!("rr {}", r8),
+        Op::SlaR8 => format!("sla {}", r8),
+        Op::SraR8 => format!("sra {}", r8),
+        Op::SwapR8 => format!("swap {}", r8),
+        Op::SrlR8 => format!("srl {}", r8),
+        Op::BitB3R8 => format!("bit {}, {}", b3, r8),
+        Op::ResB3R8 => format!("res {}, {}", b3, r8),
+        Op::SetB3R8 => format!("set {}, {}", b3, r8),
+        op => unreachable!("Opcode {:?} inside of the CB table", op),
+    }
+}
+
+/// Reads `addr` from `rom` with `bank` mapped at $4000-$7FFF, as an MBC would
+pub fn rom_read(rom: &[u8], bank: usize, addr: u16) -> u8 {
+    let offset = match addr {
+        0x0000..=0x3FFF => addr as usize,
+        0x4000..=0x7FFF => (bank << 14) | (addr as usize & 0x3FFF),
+        _ => return 0xFF,
+    };
+    rom.get(offset).copied().unwrap_or(0xFF)
+}
+
+/// Disassembles `count` instructions of `rom` starting at `from`
+pub fn disassemble(rom: &[u8], bank: usize, from: u16, count: usize) -> Vec<Instruction> {
+    let mut addr = from;
+    let mut out = Vec::with_capacity(count);
+    for _ in 0..count {
+        let shown = if addr < 0x4000 { 0 } else { bank };
+        let instruction = decode(shown, addr, |a| rom_read(rom, bank, a));
+        addr = instruction.next_addr();
+        out.push(instruction);
+    }
+    out
+}
+
+/// Disassembles like `disassemble`, naming targets from `symbols` and
+/// putting a `Label:` line before each labelled address
+pub fn listing(rom: &[u8], bank: usize, from: u16, count: usize, symbols: &Symbols) -> Vec<String> {
+    // Targets in $4000-$7FFF are taken to be in the bank being listed
+    let name = |addr: u16| {
+        let bank = match addr {
+            0x0000..=0x3FFF => 0,
+            0x4000..=0x7FFF => bank,
+            0xD000..=0xDFFF => 1,
+            _ => 0,
+        };
+        match symbols.name(bank, addr) {
+            None if addr >= 0x8000 => symbols.name(0, addr),
+            name => name,
+        }
+    };
+
+    let mut lines = Vec::new();
+    for mut instruction in disassemble(rom, bank, from, count) {
+        if let Some(label) = name(instruction.addr) {
+            lines.push(format!("{}:", label));
+        }
+        instruction.name_target(|target| name(target).map(str::to_string));
+        lines.push(instruction.to_string());
+    }
+    lines
+}
+
+fn r8_name(r8: R8) -> &'static str {
+    match r8 {
+        R8::B => "b",
+        R8::C => "c",
+        R8::D => "d",
+        R8::E => "e",
+        R8::H => "h",
+        R8::L => "l",
+        R8::HL => "[hl]",
+        R8::A => "a",
+    }
+}
+
+fn r8_source(ir: u8) -> &'static str {
+    r8_name(R8::from(ir & 0x07))
+}
+
+fn r8_dest(ir: u8) -> &'static str {
+    r8_name(R8::from((ir >> 3) & 0x07))
+}
+
+fn r16(ir: u8) -> &'static str {
+    match R16::from((ir >> 4) & 0x03) {
+        R16::BC => "bc",
+        R16::DE => "de",
+        R16::HL => "hl",
+        R16::SP => "sp",
+    }
+}
+
+fn r16stk(ir: u8) -> &'static str {
+    match R16stk::from((ir >> 4) & 0x03) {
+        R16stk::BC => "bc",
+        R16stk::DE => "de",
+        R16stk::HL => "hl",
+        R16stk::AF => "af",
+    }
+}
+
+fn r16mem(ir: u8) -> &'static str {
+    match R16mem::from((ir >> 4) & 0x03) {
+        R16mem::BC => "[bc]",
+        R16mem::DE => "[de]",
+        R16mem::HLi => "[hl+]",
+        R16mem::HLd => "[hl-]",
+    }
+}
+
+fn cond(ir: u8) -> &'static str {
+    match Cond::from((ir >> 3) & 0x03) {
+        Cond::NZ => "nz",
+        Cond::Z => "z",
+        Cond::NC => "nc",
+        Cond::C => "c",
+    }
+}
+
+fn relative(addr: u16, imm8: u8) -> u16 {
+    addr.wrapping_add(2).wrapping_add(imm8 as i8 as u16)
+}
+
+fn signed(imm8: u8) -> String {
+    let e = imm8 as i8;
+    if e < 0 {
+        format!("-${:02X}", e.unsigned_abs())
+    } else {
+        format!("+${:02X}", e)
+    }
+}
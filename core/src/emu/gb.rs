@@ -2,27 +2,43 @@ use crate::app::{
     control::{ControlMessage, ControlReceiver},
     window::*,
 };
-use crate::emu::cpu::Cpu;
-use crate::emu::joypad::Joypad;
+use crate::emu::bess::{self, Bess};
+use crate::emu::bus::{self, Bus};
+use crate::emu::cpu::{Cpu, LD_D_D, Op};
+use crate::emu::disasm::{self, Instruction};
 use crate::emu::mem::Memory;
+use crate::emu::png;
 use crate::emu::ppu::*;
 use crate::emu::regs::*;
-use crate::emu::serial::Serial;
-use crate::emu::timer::*;
-use std::cell::RefCell;
-use std::rc::Rc;
+use crate::emu::rewind::Rewind;
+use crate::emu::state::{self, Reader, Writer};
+use crate::emu::symbols::Symbols;
+use crate::emu::trace::{self, Format, Trace};
+use crate::emu::watch::{Hit, Watchpoint};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::mpsc::TryRecvError;
+use std::thread;
 use std::time::{Duration, Instant};
 
 const NORMAL_CLOCK: f64 = 1.0 / 4_194_304.0;
 
-const L_CPU: u8 = 1 << 0;
-const L_ADJ: u8 = 1 << 1;
-const L_TIMER: u8 = 1 << 2;
-const L_R: u8 = 1 << 3;
-const L_MEM: u8 = 1 << 4;
+/// T-cycles in one frame, 154 lines of 456 dots
+pub const FRAME_CYCLES: u128 = 70224;
 
-#[derive(Debug, PartialEq, Clone)]
+/// Frames `run` may fall behind real time before giving up on catching up
+const MAX_LAG_FRAMES: u32 = 4;
+
+/// Frames shown per second when running faster than real time, the rest
+/// are skipped
+const PRESENT_RATE: f64 = 60.0;
+
+/// Stack words searched for return addresses by `call_stack`
+const STACK_SCAN: u16 = 128;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Comp {
     None,
     Cpu,
@@ -32,54 +48,159 @@ pub enum Comp {
     Joypad,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    CpuLocked {
+        pc: u16,
+        opcode: u8,
+    },
+    Watch(Hit),
+    /// A `LD r,r` enabled with `Cpu::set_soft_break` ran at `pc`
+    SoftBreak {
+        pc: u16,
+        opcode: u8,
+    },
+    /// A BGB debug message, `LD D,D` at `pc` jumping over the text
+    Message {
+        pc: u16,
+        text: String,
+    },
+}
+
+/// How fast `run` goes compared to the real hardware
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Speed {
+    /// A multiple of real time, from `Speed::MIN` to `Speed::MAX`
+    Times(f64),
+    /// As fast as the host can go
+    Uncapped,
+}
+
+impl Speed {
+    pub const NORMAL: Speed = Speed::Times(1.0);
+    pub const MIN: f64 = 0.25;
+    pub const MAX: f64 = 8.0;
+
+    /// How long a frame takes in real time, `None` when uncapped
+    pub fn frame_time(self) -> Option<Duration> {
+        match self {
+            Speed::Times(times) => Some(Duration::from_secs_f64(
+                FRAME_CYCLES as f64 * NORMAL_CLOCK / times,
+            )),
+            Speed::Uncapped => None,
+        }
+    }
+
+    /// Whether frames come faster than real time
+    pub fn faster(self) -> bool {
+        match self {
+            Speed::Times(times) => times > 1.0,
+            Speed::Uncapped => true,
+        }
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            Speed::Times(times) => write!(f, "{}x", times),
+            Speed::Uncapped => write!(f, "uncapped"),
+        }
+    }
+}
+
+impl FromStr for Speed {
+    type Err = String;
+
+    /// Parses a multiplier like `2`, `0.5x` or `uncapped`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "uncapped" {
+            return Ok(Speed::Uncapped);
+        }
+        match s.trim_end_matches('x').parse::<f64>() {
+            Ok(times) if (Speed::MIN..=Speed::MAX).contains(&times) => Ok(Speed::Times(times)),
+            _ => Err(format!(
+                "invalid speed: {} (expected {}x to {}x or uncapped)",
+                s,
+                Speed::MIN,
+                Speed::MAX
+            )),
+        }
+    }
+}
+
+/// Where running backwards stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reverse {
+    /// Went back as far as asked, or to where the stop condition held
+    Done,
+    Watch(Hit),
+    /// Nothing older is left in the rewind buffer, the machine is at the
+    /// oldest snapshot
+    Oldest,
+}
+
 #[allow(dead_code)]
 pub struct Gameboy {
     pub t: u128,
     pub cpu: Cpu,
-    pub ppu: Ppu,
-    pub timer: Timer,
-    pub serial: Serial,
-    pub joypad: Joypad,
-    mem: Rc<RefCell<Memory>>,
+    symbols: Symbols,
+    trace: Option<Trace>,
+    rewind: Option<Rewind>,
+    speed: Speed,
+    /// Runs uncapped while set, whatever `speed` is
+    fast_forward: bool,
+    paused: bool,
+    /// Frames to run while paused
+    advance: u32,
+    /// Slot states and screenshots are named after this path
+    file_base: Option<PathBuf>,
 }
 
 impl Gameboy {
     pub fn cartless_dmg() -> Self {
-        let mem = Rc::new(RefCell::new(Memory::empty()));
-        Gameboy {
-            t: 0,
-            cpu: Cpu::init_dmg_with_memory(mem.clone()),
-            ppu: Ppu::headless_dmg(mem.clone()),
-            timer: Timer::init_dmg(mem.clone()),
-            serial: Serial::init_dmg(mem.clone()),
-            joypad: Joypad::init_dmg(mem.clone()),
-            mem,
-        }
+        let bus = Bus::new(Memory::empty(), Ppu::headless_dmg());
+        Gameboy::with_cpu(Cpu::init_dmg_with_bus(bus))
     }
 
     pub fn headless_dmg(rom: &[u8]) -> Self {
-        let mem = Rc::new(RefCell::new(Memory::new(rom)));
-        Gameboy {
-            t: 0,
-            cpu: Cpu::init_dmg_with_memory(mem.clone()),
-            ppu: Ppu::headless_dmg(mem.clone()),
-            timer: Timer::init_dmg(mem.clone()),
-            serial: Serial::init_dmg(mem.clone()),
-            joypad: Joypad::init_dmg(mem.clone()),
-            mem,
-        }
+        let bus = Bus::new(Memory::new(rom), Ppu::headless_dmg());
+        Gameboy::with_cpu(Cpu::init_dmg_with_bus(bus))
     }
 
     pub fn dmg(rom: &[u8], frame_tx: FrameSender) -> Self {
-        let mem = Rc::new(RefCell::new(Memory::new(rom)));
+        let bus = Bus::new(Memory::new(rom), Ppu::init_dmg(frame_tx));
+        Gameboy::with_cpu(Cpu::init_dmg_with_bus(bus))
+    }
+
+    pub fn headless_sgb(rom: &[u8]) -> Self {
+        let mut mem = Memory::new(rom);
+        mem.enable_sgb();
+        let bus = Bus::new(mem, Ppu::headless_dmg());
+        Gameboy::with_cpu(Cpu::init_sgb_with_bus(bus))
+    }
+
+    pub fn sgb(rom: &[u8], frame_tx: FrameSender) -> Self {
+        let mut mem = Memory::new(rom);
+        mem.enable_sgb();
+        let bus = Bus::new(mem, Ppu::init_dmg(frame_tx));
+        Gameboy::with_cpu(Cpu::init_sgb_with_bus(bus))
+    }
+
+    /// A machine at power on around `cpu`, with the debugging and pacing
+    /// options off
+    fn with_cpu(cpu: Cpu) -> Self {
         Gameboy {
             t: 0,
-            cpu: Cpu::init_dmg_with_memory(mem.clone()),
-            ppu: Ppu::init_dmg(frame_tx, mem.clone()),
-            timer: Timer::init_dmg(mem.clone()),
-            serial: Serial::init_dmg(mem.clone()),
-            joypad: Joypad::init_dmg(mem.clone()),
-            mem,
+            cpu,
+            symbols: Symbols::default(),
+            trace: None,
+            rewind: None,
+            speed: Speed::NORMAL,
+            fast_forward: false,
+            paused: false,
+            advance: 0,
+            file_base: None,
         }
     }
 
@@ -87,201 +208,794 @@ impl Gameboy {
         for _ in 0..count {
             let cur = self.cpu.retired();
 
-            self.with_mem_mut(|mem| mem.tick(self.t));
-            self.timer.tick(self.t);
-            self.cpu.tick(self.t);
-            self.ppu.tick(self.t);
-            self.serial.tick(self.t);
-            self.joypad.tick(self.t);
+            let stopped = self.cpu.stopped();
+            let t = self.t;
+
+            // Components sleep until their next event, see `emu::sched`
+            self.bus_mut().tick_timer(t, stopped);
+            self.cpu.tick(t);
+            if !stopped && self.cpu.stopped() {
+                self.bus_mut().blank();
+            }
+            let stopped = self.cpu.stopped();
+            self.bus_mut().tick_devices(t, stopped);
             self.t += 1;
-            if cur != self.cpu.retired() || (self.cpu.halted()) {
-                //self.log_status(L_CPU + L_ADJ + L_R + L_TIMER);
-                //                self.log_status(L_CPU);
-                //   self.log_status(L_CPU + L_TIMER + L_MEM);
+            if cur != self.cpu.retired() && !self.cpu.dispatching() {
+                self.trace_instruction();
+                self.record_rewind();
             }
         }
     }
 
-    pub fn step(&mut self, count: u128) {
+    pub fn step(&mut self, count: u128) -> Option<Event> {
+        // Only breaks from this call count, not ones ticked past before
+        self.cpu.take_soft_break();
         let mut i = count;
         while i > 0 {
             let cur = self.cpu.retired();
             self.tick(1);
             if cur != self.cpu.retired() {
                 i -= 1;
-                //self.log_status(L_CPU + L_TIMER);
-                //self.log_status(L_CPU + L_ADJ + L_R + L_TIMER);
+            }
+            if let Some(event) = self.event() {
+                return Some(event);
+            }
+            if let Some(hit) = self.bus_mut().mem.watches_mut().take_hit() {
+                return Some(Event::Watch(hit));
+            }
+            if let Some(event) = self.take_soft_break() {
+                return Some(event);
             }
         }
+        None
     }
 
-    pub fn step_blargg(&mut self, count: u128, check: &str) {
-        let mut i = count;
-        let expected = format!("{}\n\n\nPassed\n", check);
-        while i > 0 {
-            let cur = self.cpu.retired();
+    /// The software breakpoint that ran since the last call, as a debug
+    /// message when it is one
+    fn take_soft_break(&mut self) -> Option<Event> {
+        let (pc, opcode) = self.cpu.take_soft_break()?;
+        Some(match self.debug_message(pc, opcode) {
+            Some(text) => Event::Message { pc, text },
+            None => Event::SoftBreak { pc, opcode },
+        })
+    }
+
+    /// Ticks through a frame, logging debug messages as they come, and
+    /// stops early at a software breakpoint
+    fn run_frame(&mut self) -> Option<Event> {
+        for _ in 0..FRAME_CYCLES {
             self.tick(1);
-            if cur != self.cpu.retired() {
-                i -= 1;
-                //self.log_status(L_CPU + L_TIMER);
-                //self.log_status(L_CPU + L_ADJ + L_R + L_TIMER);
+            match self.take_soft_break() {
+                Some(Event::Message { text, .. }) => eprintln!("{text}"),
+                Some(event) => return Some(event),
+                None => {}
             }
+        }
+        None
+    }
 
-            if self.serial.buffmt() == expected {
-                return;
-            }
+    /// The text of a BGB debug message at `pc`, `LD D,D` then `JR` over
+    /// $6464, $0000 and the text
+    fn debug_message(&self, pc: u16, opcode: u8) -> Option<String> {
+        let byte = |offset: u16| self.mem_dbg_read_mapped(pc.wrapping_add(offset));
+        let len = byte(2) as u16;
+        let header = [byte(1), byte(3), byte(4), byte(5), byte(6)];
+        if opcode != LD_D_D || header != [0x18, 0x64, 0x64, 0x00, 0x00] || len < 4 {
+            return None;
         }
+        let text: Vec<u8> = (7..len + 3).map(byte).collect();
+        Some(String::from_utf8_lossy(&text).to_string())
     }
 
-    pub fn step_mooneye(&mut self, count: u128) {
-        let mut i = count;
-        let pass = [3, 5, 8, 13, 21, 34];
-        let fail = [0x42; 6];
-        while i > 0 {
-            let cur = self.cpu.retired();
-            self.tick(1);
-            if cur != self.cpu.retired() {
-                i -= 1;
-            }
-            if self.serial.buf == pass {
-                return;
-            }
-            if self.serial.buf == fail {
-                panic!("Mooneye test failure!")
-            }
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        self.bus_mut().mem.watches_mut().add(watchpoint)
+    }
+
+    pub fn remove_watchpoint(&mut self, id: usize) -> bool {
+        self.bus_mut().mem.watches_mut().remove(id)
+    }
+
+    /// Lists the watchpoints as `(id, description)`
+    pub fn watchpoints(&self) -> Vec<(usize, String)> {
+        self.bus()
+            .mem
+            .watches()
+            .iter()
+            .map(|(id, watchpoint)| (*id, format!("{:?}", watchpoint)))
+            .collect()
+    }
+
+    /// Returns the event that stopped the emulated machine, if any
+    pub fn event(&self) -> Option<Event> {
+        if self.cpu.locked() {
+            return Some(Event::CpuLocked {
+                pc: self.cpu.cur_pc(),
+                opcode: self.cpu.ir(),
+            });
         }
+        None
     }
 
+    /// Runs at `speed`, a whole frame at full speed and then sleeping until
+    /// it is due, until told to exit. Input and control messages are
+    /// handled between frames. A software breakpoint pauses, or without a
+    /// frontend to resume from stops
     pub fn run(&mut self, control_rx: Option<ControlReceiver>) {
-        let normal_cycle = Duration::from_secs_f64(NORMAL_CLOCK);
-        let _double_cycle = Duration::from_secs_f64(NORMAL_CLOCK / 2.0);
+        self.cpu.take_soft_break();
+        let present = Duration::from_secs_f64(1.0 / PRESENT_RATE);
+        let mut deadline = Instant::now();
+        let mut presented = Instant::now();
         let mut animate = Instant::now() + Duration::from_secs_f64(0.5);
         loop {
-            self.tick(1);
-            let target = Instant::now() + normal_cycle;
-            while Instant::now() < target {}
-            if Instant::now() > animate {
-                self.ppu.testing = self.ppu.testing.wrapping_add(1);
-                animate = Instant::now() + Duration::from_secs_f64(1.0 / 30.0);
-            }
             if let Some(rx) = control_rx.as_ref() {
-                loop {
-                    match rx.try_recv() {
-                        Ok(ControlMessage::Exit) => {
-                            println!("{}", self.serial.buffmt());
-                            return;
-                        }
-                        Ok(ControlMessage::JoypadInput { button, pressed }) => {
-                            self.joypad.enqueue_input(button, pressed);
-                        }
-                        Err(TryRecvError::Empty) => break,
-                        Err(TryRecvError::Disconnected) => return,
+                if !self.poll_control(rx) {
+                    return;
+                }
+                if self.paused && self.advance == 0 {
+                    match rx.recv() {
+                        Ok(message) if self.control(message) => {}
+                        _ => return,
                     }
+                    deadline = Instant::now();
+                    continue;
                 }
             }
+            self.advance = self.advance.saturating_sub(1);
+            let speed = if self.fast_forward {
+                Speed::Uncapped
+            } else {
+                self.speed
+            };
+            // Faster than real time the frontend only gets as many frames as
+            // it can show, so it doesn't hold emulation back
+            let skip = speed.faster() && presented.elapsed() < present;
+            self.bus_mut().ppu.skip_frames(skip);
+            if !skip {
+                presented = Instant::now();
+            }
+            if let Some(event) = self.run_frame() {
+                eprintln!("Stopped: {:?}", event);
+                if control_rx.is_none() {
+                    return;
+                }
+                self.paused = true;
+                self.advance = 0;
+            }
+
+            let now = Instant::now();
+            if now > animate {
+                let ppu = &mut self.bus_mut().ppu;
+                ppu.testing = ppu.testing.wrapping_add(1);
+                animate = now + Duration::from_secs_f64(1.0 / 30.0);
+            }
+            let Some(frame) = speed.frame_time() else {
+                deadline = now;
+                continue;
+            };
+            deadline += frame;
+            if deadline > now {
+                thread::sleep(deadline - now);
+            } else if now - deadline > frame * MAX_LAG_FRAMES {
+                // Too far behind to catch up, e.g. after a stall, so carry on
+                // from now rather than running flat out
+                deadline = now;
+            }
         }
     }
 
-    #[allow(dead_code)]
-    fn log_status_all(&self) {
-        self.log_status(0xFF);
+    pub fn speed(&self) -> Speed {
+        self.speed
     }
 
-    fn log_status(&self, f: u8) {
-        let cpu = f & L_CPU != 0x00;
-        let adj = f & L_ADJ != 0x00;
-        let timer = f & L_TIMER != 0x00;
-        let retired = f & L_R != 0x00;
-        let mem = f & L_MEM != 0x00;
-        if adj && self.cpu.prev_pc() == self.cpu.cur_pc() {
-            return;
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speed = speed;
+    }
+
+    /// Handles the messages sent since the last frame, false when the
+    /// emulator should stop
+    fn poll_control(&mut self, rx: &ControlReceiver) -> bool {
+        loop {
+            match rx.try_recv() {
+                Ok(message) if self.control(message) => {}
+                Ok(_) => return false,
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Disconnected) => return false,
+            }
         }
+    }
 
-        let pc = if adj {
-            self.cpu.prev_pc()
-        } else {
-            self.cpu.cur_pc()
+    /// Acts on a message from the frontend, false when told to exit
+    fn control(&mut self, message: ControlMessage) -> bool {
+        match message {
+            ControlMessage::Exit => {
+                println!("{}", self.bus().serial.buffmt());
+                return false;
+            }
+            ControlMessage::JoypadInput { button, pressed } => {
+                self.bus_mut().enqueue_input(button, pressed);
+            }
+            ControlMessage::Rewind => {
+                if let Err(err) = self.rewind() {
+                    eprintln!("Rewind: {err}");
+                }
+            }
+            ControlMessage::SetSpeed(speed) => self.speed = speed,
+            ControlMessage::FastForward(on) => self.fast_forward = on,
+            ControlMessage::Pause => self.paused = true,
+            ControlMessage::Resume => {
+                self.paused = false;
+                self.advance = 0;
+            }
+            ControlMessage::FrameAdvance => {
+                self.paused = true;
+                self.advance += 1;
+            }
+            ControlMessage::Reset => self.reset(),
+            ControlMessage::SaveState(slot) => match self.save_slot(slot) {
+                Ok(path) => eprintln!("Saved state to {}", path.display()),
+                Err(err) => eprintln!("Save state: {err}"),
+            },
+            ControlMessage::LoadState(slot) => match self.load_slot(slot) {
+                Ok(path) => eprintln!("Loaded state from {}", path.display()),
+                Err(err) => eprintln!("Load state: {err}"),
+            },
+            ControlMessage::Screenshot => match self.screenshot() {
+                Ok(path) => eprintln!("Saved screenshot to {}", path.display()),
+                Err(err) => eprintln!("Screenshot: {err}"),
+            },
+        }
+        true
+    }
+
+    /// Soft reset, everything starts over from power on except the
+    /// cartridge RAM, `t` keeps counting
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        self.resync();
+    }
+
+    /// Names slot states `<base>.ss<slot>` and screenshots `<base>-<n>.png`,
+    /// usually the ROM path without its extension
+    pub fn set_file_base(&mut self, base: PathBuf) {
+        self.file_base = Some(base);
+    }
+
+    fn file_path(&self, suffix: &str) -> Result<PathBuf, String> {
+        let base = self.file_base.as_ref().ok_or("no path for saved files")?;
+        let mut path = base.clone().into_os_string();
+        path.push(suffix);
+        Ok(PathBuf::from(path))
+    }
+
+    pub fn save_slot(&mut self, slot: u8) -> Result<PathBuf, String> {
+        let path = self.file_path(&format!(".ss{}", slot))?;
+        fs::write(&path, self.save_state()).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(path)
+    }
+
+    pub fn load_slot(&mut self, slot: u8) -> Result<PathBuf, String> {
+        let path = self.file_path(&format!(".ss{}", slot))?;
+        let data = fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        self.load_state(&data)?;
+        Ok(path)
+    }
+
+    /// Writes the screen to the first free `<base>-<n>.png`
+    pub fn screenshot(&mut self) -> Result<PathBuf, String> {
+        let mut n = 1;
+        let path = loop {
+            let path = self.file_path(&format!("-{}.png", n))?;
+            if !path.exists() {
+                break path;
+            }
+            n += 1;
         };
-        if pc == 0x0000 {
-            return;
+        let (width, height, frame) = self.bus_mut().frame();
+        fs::write(&path, png::encode(width, height, &frame))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(path)
+    }
+
+    /// Snapshots the whole machine, see `emu::state` for the layout
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Writer::new();
+        state::write_header(&mut out, self.rom_hash());
+        out.section(b"GB  ", |out| out.u128(self.t));
+        out.section(b"CPU ", |out| self.cpu.save(out));
+        let bus = self.bus();
+        out.section(b"PPU ", |out| bus.ppu.save(out, self.t));
+        out.section(b"TIMR", |out| bus.timer.save(out, self.t));
+        out.section(b"SERL", |out| bus.serial.save(out));
+        out.section(b"JOYP", |out| bus.joypad.save(out));
+        out.section(b"MEM ", |out| bus.save(out));
+        out.finish()
+    }
+
+    /// Restores a `save_state` snapshot of the same ROM, leaving the machine
+    /// untouched if the state can't be loaded
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let sections = state::read(data, self.rom_hash())?;
+        let backup = self.save_state();
+        let result = self.load_sections(sections);
+        if result.is_err() {
+            let sections = state::read(&backup, self.rom_hash())?;
+            self.load_sections(sections)?;
         }
+        result
+    }
 
-        let regs_view = self.cpu.log_view(adj);
-
-        let cpustr = if cpu {
-            let pcmem = [
-                self.mem_dbg_read(pc),
-                self.mem_dbg_read(pc.wrapping_add(1)),
-                self.mem_dbg_read(pc.wrapping_add(2)),
-                self.mem_dbg_read(pc.wrapping_add(3)),
-            ];
-            format!(
-                "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X} ",
-                regs_view.a,
-                regs_view.f,
-                regs_view.b,
-                regs_view.c,
-                regs_view.d,
-                regs_view.e,
-                regs_view.h,
-                regs_view.l,
-                regs_view.sp,
-                pc,
-                pcmem[0],
-                pcmem[1],
-                pcmem[2],
-                pcmem[3],
-            )
-        } else {
-            String::new()
+    fn load_sections(&mut self, mut sections: Vec<([u8; 4], Reader)>) -> Result<(), String> {
+        let mut section = |tag: &[u8; 4]| {
+            let index = sections.iter().position(|(t, _)| t == tag);
+            index.map(|i| sections.swap_remove(i).1).ok_or_else(|| {
+                format!(
+                    "state has no {} section",
+                    String::from_utf8_lossy(tag).trim()
+                )
+            })
         };
+        self.t = section(b"GB  ")?.u128()?;
+        self.cpu.load(&mut section(b"CPU ")?)?;
+        let bus = self.cpu.bus_mut();
+        bus.ppu.load(&mut section(b"PPU ")?)?;
+        bus.timer.load(&mut section(b"TIMR")?)?;
+        bus.serial.load(&mut section(b"SERL")?)?;
+        bus.joypad.load(&mut section(b"JOYP")?)?;
+        bus.load(&mut section(b"MEM ")?)?;
+        self.resync();
+        Ok(())
+    }
 
-        let retiredstr = if retired {
-            let retired = self.cpu.retired().saturating_sub(2);
-            format!("|| R:{:04X} ", retired)
+    /// Wakes every component to reschedule after their state was replaced,
+    /// carrying on from `t`
+    fn resync(&mut self) {
+        let t = self.t;
+        self.bus_mut().resync(t);
+    }
+
+    /// Exports the machine as a BESS state other emulators can load, best
+    /// taken between instructions as BESS has no mid-instruction state
+    pub fn export_bess(&self) -> Result<Vec<u8>, String> {
+        let ram_size = self.bess_ram_size()?;
+        let region = |start: u16, len: u16| -> Vec<u8> {
+            (start..start + len).map(|a| self.mem_dbg_read(a)).collect()
+        };
+        let cpu = &self.cpu;
+        let execution = if cpu.stopped() {
+            bess::Execution::Stopped
+        } else if cpu.halted() {
+            bess::Execution::Halted
         } else {
-            String::new()
+            bess::Execution::Running
         };
+        let mem = &self.bus().mem;
+        let (sgb, mbc) = (mem.sgb().is_some(), mem.mbc_writes());
 
-        let timerstr = if timer {
-            format!(
-                "|| DIV:{:02X} TIMA:{:02X} TMA:{:02X} TAC:{:02X} ",
-                self.mem_dbg_read(DIV),
-                self.mem_dbg_read(TIMA),
-                self.mem_dbg_read(TMA),
-                self.mem_dbg_read(TAC)
-            )
-        } else {
-            String::new()
+        Ok(Bess {
+            name: Some(format!("gamezoea {}", env!("CARGO_PKG_VERSION"))),
+            info: self.rom_info(),
+            core: bess::Core {
+                model: if sgb {
+                    bess::Model::Sgb
+                } else {
+                    bess::Model::Dmg
+                },
+                pc: if execution == bess::Execution::Running {
+                    cpu.cur_pc()
+                } else {
+                    cpu.pc()
+                },
+                af: cpu.af(),
+                bc: cpu.bc(),
+                de: cpu.de(),
+                hl: cpu.hl(),
+                sp: cpu.sp(),
+                ime: cpu.ime() == 1,
+                ie: self.mem_dbg_read(IE),
+                execution,
+                io: region(0xFF00, 0x80).try_into().unwrap(),
+                ram: region(0xC000, 0x2000),
+                vram: region(0x8000, 0x2000),
+                mbc_ram: region(0xA000, ram_size as u16),
+                oam: region(0xFE00, 0xA0),
+                hram: region(0xFF80, 0x7F),
+            },
+            mbc,
+            rtc: None,
+        }
+        .write())
+    }
+
+    /// Imports a BESS state saved by another emulator for the same ROM.
+    /// Only what the CORE and MBC blocks describe is restored, the PPU
+    /// restarts the current line and the timer keeps DIV's precision
+    pub fn import_bess(&mut self, data: &[u8]) -> Result<(), String> {
+        let state = Bess::read(data)?;
+        let core = &state.core;
+        if let (Some(theirs), Some(ours)) = (state.info, self.rom_info())
+            && theirs != ours
+        {
+            return Err("state is for a different ROM".to_string());
+        }
+        let sgb = self.bus().mem.sgb().is_some();
+        match (core.model, sgb) {
+            (bess::Model::Sgb, false) => return Err("state is from a Super Game Boy".to_string()),
+            (bess::Model::Dmg, true) => {
+                return Err("state is not from a Super Game Boy".to_string());
+            }
+            _ => {}
+        }
+        let sizes = [
+            (&core.ram, 0x2000),
+            (&core.vram, 0x2000),
+            (&core.oam, 0xA0),
+            (&core.hram, 0x7F),
+        ];
+        if sizes.iter().any(|(buffer, len)| buffer.len() != *len) {
+            return Err("BESS memory sizes don't match a Game Boy".to_string());
+        }
+        let ram_size = self.bess_ram_size()?;
+        if core.mbc_ram.len() != ram_size {
+            return Err(format!(
+                "state has {} bytes of cartridge RAM, the cartridge has {}",
+                core.mbc_ram.len(),
+                ram_size
+            ));
+        }
+        // No MBC with a clock is emulated to restore it to
+        if state.rtc.is_some() {
+            return Err("state has an RTC, which the cartridge doesn't".to_string());
+        }
+
+        let bus = self.bus_mut();
+        bus.mem.replay_mbc_writes(&state.mbc);
+        bus.mem.restore(0xC000, &core.ram);
+        bus.mem.restore(0x8000, &core.vram);
+        bus.mem.restore(0xA000, &core.mbc_ram);
+        bus.mem.restore(0xFE00, &core.oam);
+        bus.mem.restore(0xFF80, &core.hram);
+        bus.mem.restore(IE, &[core.ie]);
+        bus.restore_io(&core.io);
+        self.resync();
+
+        let cpu = &mut self.cpu;
+        cpu.set_af(core.af);
+        cpu.set_bc(core.bc);
+        cpu.set_de(core.de);
+        cpu.set_hl(core.hl);
+        cpu.set_sp(core.sp);
+        cpu.set_ime(core.ime as u8);
+        cpu.resume_at(
+            core.pc,
+            core.execution == bess::Execution::Halted,
+            core.execution == bess::Execution::Stopped,
+        );
+        Ok(())
+    }
+
+    /// The cartridge RAM a BESS state holds, all of it where memory keeps
+    /// only the bank at $A000-$BFFF
+    fn bess_ram_size(&self) -> Result<usize, String> {
+        match self.bus().mem.cartridge_ram_size() {
+            size @ 0..=0x2000 => Ok(size),
+            size => Err(format!(
+                "unsupported RAM size: {} KiB, only 8 KiB of cartridge RAM is emulated",
+                size / 1024
+            )),
+        }
+    }
+
+    /// The title and global checksum from the cartridge header
+    fn rom_info(&self) -> Option<bess::Info> {
+        let rom = self.bus().mem.cartridge();
+        let mut info = [0u8; 0x12];
+        info[..0x10].copy_from_slice(rom.get(0x134..0x144)?);
+        info[0x10..].copy_from_slice(rom.get(0x14E..0x150)?);
+        Some(info)
+    }
+
+    fn rom_hash(&self) -> u64 {
+        state::rom_hash(self.bus().mem.cartridge())
+    }
+
+    /// Keeps snapshots to step back to with `rewind`, `None` turns it off
+    pub fn set_rewind(&mut self, rewind: Option<Rewind>) {
+        self.rewind = rewind;
+    }
+
+    pub fn rewind_buffer(&self) -> Option<&Rewind> {
+        self.rewind.as_ref()
+    }
+
+    /// Goes back to the newest snapshot in the rewind buffer
+    pub fn rewind(&mut self) -> Result<(), String> {
+        let rewind = self.rewind.as_mut().ok_or("rewind is off")?;
+        let state = rewind.pop().ok_or("nothing left to rewind")?;
+        self.load_state(&state)?;
+        if let Some(rewind) = self.rewind.as_mut() {
+            rewind.schedule(self.t);
+        }
+        Ok(())
+    }
+
+    /// Goes back `count` instructions
+    pub fn step_back(&mut self, count: usize) -> Result<Reverse, String> {
+        self.search_back(count, |_, boundary, _| boundary)
+    }
+
+    /// Goes back to the last instruction before now where `stop` holds, or
+    /// the last watchpoint hit
+    pub fn reverse_continue(
+        &mut self,
+        mut stop: impl FnMut(&Gameboy) -> bool,
+    ) -> Result<Reverse, String> {
+        self.search_back(1, |gb, boundary, hit| {
+            hit.is_some() || (boundary && stop(gb))
+        })
+    }
+
+    /// Finds the `count`th newest point before now where `stop` holds by
+    /// replaying from each snapshot in the rewind buffer, newest first.
+    /// `stop` sees every instruction boundary and watchpoint hit, the
+    /// snapshots replayed past are dropped as they'd be taken again
+    fn search_back(
+        &mut self,
+        count: usize,
+        mut stop: impl FnMut(&Gameboy, bool, Option<&Hit>) -> bool,
+    ) -> Result<Reverse, String> {
+        if count == 0 {
+            return Ok(Reverse::Done);
+        }
+        let mut rewind = self.rewind.take().ok_or("rewind is off")?;
+        // Replays neither take snapshots nor log instructions again
+        let trace = self.trace.take();
+        let mut end = self.t;
+        let mut found = 0;
+        let mut oldest: Option<Vec<u8>> = None;
+        let result = loop {
+            let Some(state) = rewind.pop() else {
+                break match oldest.take() {
+                    Some(state) => self.load_state(&state).map(|_| {
+                        rewind.push(self.t, &state);
+                        Reverse::Oldest
+                    }),
+                    None => Err("nothing left to rewind".to_string()),
+                };
+            };
+            if let Err(err) = self.load_state(&state) {
+                break Err(err);
+            }
+            let start = self.t;
+            let stops = self.replay(end, &mut stop);
+            if found + stops.len() >= count {
+                let target = stops[stops.len() - (count - found)];
+                break self.load_state(&state).map(|_| {
+                    rewind.push(self.t, &state);
+                    self.replay_to(target).map_or(Reverse::Done, Reverse::Watch)
+                });
+            }
+            found += stops.len();
+            end = end.min(start);
+            oldest = Some(state);
         };
+        self.rewind = Some(rewind);
+        self.trace = trace;
+        result
+    }
 
-        let memstr = if mem {
-            let addr = [P1, IF, IE];
-            let s = format!(
-                "||{}",
-                addr.iter()
-                    .map(|&a| format!(" {:04X}:{:02X}", a, self.mem_dbg_read(a)))
-                    .collect::<String>()
-            );
-            s
-        } else {
-            String::new()
+    /// Runs up to T-cycle `end`, returning the T-cycles before it where
+    /// `stop` held, starting with the current one
+    fn replay(
+        &mut self,
+        end: u128,
+        stop: &mut impl FnMut(&Gameboy, bool, Option<&Hit>) -> bool,
+    ) -> Vec<u128> {
+        let mut stops = Vec::new();
+        if self.t < end && stop(self, true, None) {
+            stops.push(self.t);
+        }
+        while self.t < end {
+            let cur = self.cpu.retired();
+            self.tick(1);
+            let hit = self.bus_mut().mem.watches_mut().take_hit();
+            let boundary = cur != self.cpu.retired();
+            if self.t < end && (boundary || hit.is_some()) && stop(self, boundary, hit.as_ref()) {
+                stops.push(self.t);
+            }
+        }
+        stops
+    }
+
+    /// Runs up to T-cycle `t`, returning a watchpoint hit in its last tick
+    fn replay_to(&mut self, t: u128) -> Option<Hit> {
+        let mut hit = None;
+        while self.t < t {
+            self.tick(1);
+            hit = self.bus_mut().mem.watches_mut().take_hit();
+        }
+        hit
+    }
+
+    fn record_rewind(&mut self) {
+        if !self.rewind.as_ref().is_some_and(|r| r.due(self.t)) {
+            return;
+        }
+        let state = self.save_state();
+        if let Some(rewind) = self.rewind.as_mut() {
+            rewind.push(self.t, &state);
+        }
+    }
+
+    /// Traces every instruction from now on, LY reads return what
+    /// gameboy-doctor expects for its format
+    pub fn set_trace(&mut self, trace: Trace) {
+        let ly = (trace.format() == Format::Doctor).then_some(trace::DOCTOR_LY);
+        self.bus_mut().set_ly_stub(ly);
+        self.trace = Some(trace);
+    }
+
+    pub fn take_trace(&mut self) -> Option<Trace> {
+        self.bus_mut().set_ly_stub(None);
+        let mut trace = self.trace.take()?;
+        let _ = trace.flush();
+        Some(trace)
+    }
+
+    fn trace_instruction(&mut self) {
+        let Some(mut trace) = self.trace.take() else {
+            return;
         };
+        let format = trace.format();
+        trace.instruction(self.cpu.cur_pc(), || self.trace_line(format));
+        self.trace = Some(trace);
+    }
+
+    /// The state before the instruction at PC runs, as a trace line
+    pub fn trace_line(&self, format: Format) -> String {
+        let pc = self.cpu.cur_pc();
+        let regs = self.cpu.log_view(false);
+        let pcmem = |i: u16| self.mem_dbg_read_mapped(pc.wrapping_add(i));
+        let line = format!(
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            regs.a,
+            regs.f,
+            regs.b,
+            regs.c,
+            regs.d,
+            regs.e,
+            regs.h,
+            regs.l,
+            regs.sp,
+            pc,
+            pcmem(0),
+            pcmem(1),
+            pcmem(2),
+            pcmem(3),
+        );
+        if format == Format::Doctor {
+            return line;
+        }
+
+        let label = match self.symbolize(pc) {
+            Some(label) => format!(" ; {}", label),
+            None => String::new(),
+        };
+        format!(
+            "{} || R:{:04X} || DIV:{:02X} TIMA:{:02X} TMA:{:02X} TAC:{:02X} || P1:{:02X} IF:{:02X} IE:{:02X}{}",
+            line,
+            self.cpu.retired(),
+            self.mem_dbg_read(DIV),
+            self.mem_dbg_read(TIMA),
+            self.mem_dbg_read(TMA),
+            self.mem_dbg_read(TAC),
+            self.mem_dbg_read(P1),
+            self.mem_dbg_read(IF),
+            self.mem_dbg_read(IE),
+            label
+        )
+    }
 
-        println!("{}{}{}{}", cpustr, retiredstr, timerstr, memstr);
+    pub fn bus(&self) -> &Bus {
+        self.cpu.bus()
     }
 
-    fn with_mem<R>(&self, f: impl FnOnce(&Memory) -> R) -> R {
-        let mem = self.mem.borrow();
-        f(&mem)
+    pub fn bus_mut(&mut self) -> &mut Bus {
+        self.cpu.bus_mut()
     }
 
     pub fn mem_dbg_read(&self, addr: u16) -> u8 {
-        self.with_mem(|mem| mem.dbg_read(addr))
+        self.bus().dbg_read(addr)
     }
 
-    fn with_mem_mut<R>(&self, f: impl FnOnce(&mut Memory) -> R) -> R {
-        let mut mem = self.mem.borrow_mut();
-        f(&mut mem)
+    pub fn mem_dbg_read_mapped(&self, addr: u16) -> u8 {
+        self.bus().dbg_read_mapped(addr)
+    }
+
+    /// Pokes memory or the register of the component `addr` belongs to,
+    /// waking it
+    pub fn mem_dbg_write(&mut self, addr: u16, data: u8) {
+        let t = self.t;
+        let bus = self.bus_mut();
+        if bus::owner(addr) == Some(Comp::Timer) {
+            // The cycles slept through so far count with the old TAC
+            bus.timer.catch_up(t);
+        }
+        bus.dbg_write(addr, data);
+    }
+
+    /// The ROM bank currently mapped at `addr`
+    pub fn mem_rom_bank(&self, addr: u16) -> usize {
+        self.bus().mem.rom_bank(addr)
+    }
+
+    /// Disassembles the instruction at `addr` in the currently mapped bank,
+    /// naming its target from the symbols
+    pub fn disasm(&self, addr: u16) -> Instruction {
+        let bus = self.bus();
+        let mut instruction =
+            disasm::decode(bus.mem.rom_bank(addr), addr, |a| bus.dbg_read_mapped(a));
+        instruction.name_target(|target| self.label(target).map(str::to_string));
+        instruction
+    }
+
+    pub fn load_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    /// The label at `addr` in the bank currently mapped there
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        self.symbol_banks(addr)
+            .into_iter()
+            .find_map(|bank| self.symbols.name(bank, addr))
+    }
+
+    /// Names `addr` as `Label+$offset` from the closest label before it
+    pub fn symbolize(&self, addr: u16) -> Option<String> {
+        let (symbol, offset) = self
+            .symbol_banks(addr)
+            .into_iter()
+            .find_map(|bank| self.symbols.nearest(bank, addr))?;
+        match offset {
+            0 => Some(symbol.name.clone()),
+            offset => Some(format!("{}+${:X}", symbol.name, offset)),
+        }
+    }
+
+    /// The mapped bank first, then bank 0 for RAM linked with rgblink -w
+    fn symbol_banks(&self, addr: u16) -> Vec<usize> {
+        let bank = self.bus().mem.symbol_bank(addr);
+        if bank == 0 || addr < 0x8000 {
+            vec![bank]
+        } else {
+            vec![bank, 0]
+        }
+    }
+
+    /// Return addresses on the stack, innermost first. The SM83 keeps no
+    /// frame records, so any stacked word that points just past a call or
+    /// rst instruction is taken as one
+    pub fn call_stack(&self) -> Vec<u16> {
+        let mut frames = Vec::new();
+        let mut sp = self.cpu.sp();
+        for _ in 0..STACK_SCAN {
+            if sp >= 0xFFFE {
+                break;
+            }
+            let ret = u16::from_le_bytes([
+                self.mem_dbg_read_mapped(sp),
+                self.mem_dbg_read_mapped(sp + 1),
+            ]);
+            let call = Op::from(self.mem_dbg_read_mapped(ret.wrapping_sub(3)));
+            let rst = Op::from(self.mem_dbg_read_mapped(ret.wrapping_sub(1)));
+            if matches!(call, Op::CallImm16 | Op::CallCondImm16) || matches!(rst, Op::RstTgt3) {
+                frames.push(ret);
+            }
+            sp += 2;
+        }
+        frames
     }
 }
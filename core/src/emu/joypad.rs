@@ -1,10 +1,11 @@
 use crate::emu::gb::Comp;
 use crate::emu::mem::Memory;
 use crate::emu::regs::*;
-use crate::{bit, setbit};
-use std::cell::RefCell;
+use crate::emu::sched::NEVER;
+use crate::emu::state::{Reader, Writer};
+use crate::emu::watch::Access;
+use crate::{bit, log, setbit};
 use std::collections::VecDeque;
-use std::rc::Rc;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum JoypadButton {
@@ -18,6 +19,19 @@ pub enum JoypadButton {
     Select,
 }
 
+impl JoypadButton {
+    pub const ALL: [JoypadButton; 8] = [
+        JoypadButton::Right,
+        JoypadButton::Left,
+        JoypadButton::Up,
+        JoypadButton::Down,
+        JoypadButton::A,
+        JoypadButton::B,
+        JoypadButton::Start,
+        JoypadButton::Select,
+    ];
+}
+
 #[derive(Debug, Clone, Copy)]
 struct JoypadEvent {
     button: JoypadButton,
@@ -90,91 +104,140 @@ impl JoypadState {
 }
 
 pub struct Joypad {
-    mem: Rc<RefCell<Memory>>,
+    p1: u8,
     queue: VecDeque<JoypadEvent>,
     state: JoypadState,
 }
 
 impl Joypad {
-    pub fn init_dmg(mem: Rc<RefCell<Memory>>) -> Self {
-        let mut joypad = Joypad {
-            mem,
+    pub fn init_dmg() -> Self {
+        Joypad {
+            p1: 0xCF,
             queue: VecDeque::new(),
             state: JoypadState::default(),
-        };
+        }
+    }
 
-        joypad.mem_write(P1, 0xCF);
+    pub fn read(&self) -> u8 {
+        self.p1
+    }
 
-        joypad
+    /// Writes P1 as the CPU does, only the select bits can be written.
+    /// Returns them for the Super Game Boy, which listens to P1 for packets
+    pub fn write(&mut self, data: u8) -> u8 {
+        let select = data & 0x30;
+        self.p1 = 0xC0 | select | (self.p1 & 0x0F);
+        select
     }
 
-    pub fn tick(&mut self, t: u128) {
-        self.own(true);
+    pub fn poke(&mut self, data: u8) {
+        self.p1 = data;
+    }
 
+    /// Runs T-cycle `t`, returning when the joypad next has work to do
+    pub fn tick(&mut self, t: u128, mem: &mut Memory) -> u128 {
+        // Sleeps until there is input or P1 is written
         if t.is_multiple_of(4) {
-            self.check_queue();
+            self.check_queue(mem);
+            NEVER
+        } else {
+            t.next_multiple_of(4)
         }
-
-        self.own(false);
     }
 
-    pub fn enqueue_input(&mut self, button: JoypadButton, pressed: bool) {
+    /// Queues a button change for the next tick, `Bus::enqueue_input` also
+    /// wakes the joypad for it
+    pub(crate) fn enqueue_input(&mut self, button: JoypadButton, pressed: bool) {
         self.queue.push_back(JoypadEvent { button, pressed });
     }
 
-    fn check_queue(&mut self) {
+    pub fn save(&self, out: &mut Writer) {
+        let state = &self.state;
+        for held in [
+            state.right,
+            state.left,
+            state.up,
+            state.down,
+            state.a,
+            state.b,
+            state.start,
+            state.select,
+        ] {
+            out.bool(held);
+        }
+        out.u32(self.queue.len() as u32);
+        for event in &self.queue {
+            out.u8(event.button as u8);
+            out.bool(event.pressed);
+        }
+    }
+
+    pub fn load(&mut self, input: &mut Reader) -> Result<(), String> {
+        self.state = JoypadState::default();
+        for button in JoypadButton::ALL {
+            let held = input.bool()?;
+            self.state.set(button, held);
+        }
+        self.queue.clear();
+        for _ in 0..input.u32()? {
+            let button = *JoypadButton::ALL
+                .get(input.u8()? as usize)
+                .ok_or("invalid joypad button")?;
+            let pressed = input.bool()?;
+            self.queue.push_back(JoypadEvent { button, pressed });
+        }
+        Ok(())
+    }
+
+    fn check_queue(&mut self, mem: &mut Memory) {
         let mut request_interrupt = false;
         while let Some(event) = self.queue.pop_front() {
+            log!(
+                Joypad,
+                Debug,
+                "{:?} pressed:{}",
+                event.button,
+                event.pressed
+            );
             if self.state.set(event.button, event.pressed) {
                 request_interrupt |= event.pressed;
             }
         }
 
-        self.update_p1();
+        self.update_p1(mem);
 
         if request_interrupt {
-            let mut reg_if = self.mem_read(IF);
+            let mut reg_if = mem.dbg_read(IF);
             setbit!(reg_if, 4);
-            self.mem_write(IF, reg_if);
+            mem.write_as(Comp::Joypad, IF, reg_if);
         }
     }
 
-    fn update_p1(&mut self) {
-        let cur = self.mem_read(P1);
+    fn update_p1(&mut self, mem: &mut Memory) {
+        let cur = self.p1;
         let mut column = 0x0F;
+        let sgb_player = mem.sgb().and_then(|sgb| sgb.joypad_id());
 
-        if cur & bit!(4) == 0 {
+        // Only the first SGB controller is connected to our input
+        let connected = sgb_player.unwrap_or(0) == 0;
+
+        if cur & bit!(4) == 0 && connected {
             column &= self.state.directions_column();
         }
 
-        if cur & bit!(5) == 0 {
+        if cur & bit!(5) == 0 && connected {
             column &= self.state.buttons_column();
         }
 
-        let upper = (cur & 0x30) | 0xC0;
-        self.mem_write(P1, upper | column);
-    }
-
-    fn with_mem_mut<R>(&self, f: impl FnOnce(&mut Memory) -> R) -> R {
-        let mut mem = self.mem.borrow_mut();
-        f(&mut mem)
-    }
-
-    fn with_mem<R>(&self, f: impl FnOnce(&Memory) -> R) -> R {
-        let mem = self.mem.borrow();
-        f(&mem)
-    }
-
-    pub fn mem_read(&self, addr: u16) -> u8 {
-        self.with_mem(|mem| mem.dbg_read(addr))
-    }
-    pub fn mem_write(&mut self, addr: u16, data: u8) {
-        self.with_mem_mut(|mem| mem.dbg_write(addr, data));
-    }
+        if cur & 0x30 == 0x30
+            && let Some(player) = sgb_player
+        {
+            column = 0x0F - player;
+        }
 
-    pub fn own(&mut self, own: bool) {
-        let owner = if own { Comp::Joypad } else { Comp::None };
-        self.with_mem_mut(|mem| mem.set_owner(owner))
+        let upper = (cur & 0x30) | 0xC0;
+        self.p1 = upper | column;
+        mem.observe(Access::Write, P1, self.p1, Comp::Joypad);
     }
 }
 
@@ -185,21 +248,16 @@ mod tests {
 
     #[test]
     fn joypad_updates_p1_when_button_pressed() {
-        let mem = Rc::new(RefCell::new(Memory::empty()));
-        let mut joypad = Joypad::init_dmg(mem.clone());
+        let mut mem = Memory::empty();
+        let mut joypad = Joypad::init_dmg();
 
         // CPU selects the directions column (bit 4 low)
-        {
-            let mut mem_mut = mem.borrow_mut();
-            mem_mut.set_addr(P1);
-            mem_mut.set_data(0x20);
-            mem_mut.write();
-        }
+        joypad.write(0x20);
 
         joypad.enqueue_input(JoypadButton::Right, true);
-        joypad.check_queue();
+        joypad.check_queue(&mut mem);
 
-        let value = mem.borrow().dbg_read(P1);
+        let value = joypad.read();
         assert_eq!(value & 0x0F, 0x0E, "Right press should clear bit 0");
     }
 }
@@ -0,0 +1,171 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Environment variable read for the filter when `--log` isn't given
+pub const LOG_ENV: &str = "GAMEZOEA_LOG";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    /// Per-cycle and per-pixel details
+    Trace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Cpu,
+    Ppu,
+    Mem,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+const LEVELS: [Level; 6] = [
+    Level::Off,
+    Level::Error,
+    Level::Warn,
+    Level::Info,
+    Level::Debug,
+    Level::Trace,
+];
+
+impl Target {
+    pub const ALL: [Target; 6] = [
+        Target::Cpu,
+        Target::Ppu,
+        Target::Mem,
+        Target::Timer,
+        Target::Serial,
+        Target::Joypad,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Target::Cpu => "cpu",
+            Target::Ppu => "ppu",
+            Target::Mem => "mem",
+            Target::Timer => "timer",
+            Target::Serial => "serial",
+            Target::Joypad => "joypad",
+        }
+    }
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Off => "off",
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Target::ALL
+            .into_iter()
+            .find(|t| t.name() == s)
+            .ok_or_else(|| format!("unknown log target: {}", s))
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        LEVELS
+            .into_iter()
+            .find(|l| l.name() == s)
+            .ok_or_else(|| format!("unknown log level: {}", s))
+    }
+}
+
+// Indexed by `Target`, warnings and errors show until configured otherwise
+static FILTER: [AtomicU8; Target::ALL.len()] =
+    [const { AtomicU8::new(Level::Warn as u8) }; Target::ALL.len()];
+
+/// Whether messages of `level` for `target` are shown, a single relaxed load
+/// so disabled logging in hot paths is just a branch
+#[inline(always)]
+pub fn enabled(target: Target, level: Level) -> bool {
+    level as u8 <= FILTER[target as usize].load(Ordering::Relaxed)
+}
+
+pub fn level(target: Target) -> Level {
+    LEVELS[FILTER[target as usize].load(Ordering::Relaxed) as usize]
+}
+
+pub fn set_level(target: Target, level: Level) {
+    FILTER[target as usize].store(level as u8, Ordering::Relaxed);
+}
+
+/// Parses a filter like `info,ppu=trace,timer=off`, a bare level applies to
+/// every target
+pub fn parse(spec: &str) -> Result<Vec<(Option<Target>, Level)>, String> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(|part| match part.split_once('=') {
+            Some((target, level)) => Ok((Some(target.trim().parse()?), level.trim().parse()?)),
+            None => Ok((None, part.parse()?)),
+        })
+        .collect()
+}
+
+/// Applies a filter from `parse`, later entries win
+pub fn configure(spec: &str) -> Result<(), String> {
+    for (target, level) in parse(spec)? {
+        match target {
+            Some(target) => set_level(target, level),
+            None => Target::ALL.into_iter().for_each(|t| set_level(t, level)),
+        }
+    }
+    Ok(())
+}
+
+#[cold]
+pub fn write(target: Target, level: Level, args: fmt::Arguments) {
+    eprintln!("[{} {}] {}", level, target, args);
+}
+
+/// Logs to a subsystem, e.g. `log!(Ppu, Trace, "OBJECT {}", oa)`, the
+/// arguments are only evaluated when the level is enabled
+#[macro_export]
+macro_rules! log {
+    ($target:ident, $level:ident, $($arg:tt)+) => {
+        if $crate::emu::log::enabled(
+            $crate::emu::log::Target::$target,
+            $crate::emu::log::Level::$level,
+        ) {
+            $crate::emu::log::write(
+                $crate::emu::log::Target::$target,
+                $crate::emu::log::Level::$level,
+                format_args!($($arg)+),
+            );
+        }
+    };
+}
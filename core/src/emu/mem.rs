@@ -1,5 +1,9 @@
 use crate::emu::gb::Comp;
 use crate::emu::regs::*;
+use crate::emu::sgb::Sgb;
+use crate::emu::state::{Reader, Writer};
+use crate::emu::watch::{Access, Watches};
+use crate::log;
 
 const DMA_TRANSFER_CYCLES: usize = 160 * 4;
 const DMA_START_DELAY_CYCLES: u8 = 8;
@@ -24,7 +28,6 @@ enum Mbc {
 
 #[allow(dead_code)]
 pub struct Memory {
-    owner: Comp,
     dma: usize,
     dma_start_delay: u8,
     dma_delay_block: bool,
@@ -34,11 +37,6 @@ pub struct Memory {
     mbc: Mbc,
     mem: [u8; 0x10000],
     cartridge: Vec<u8>,
-    data: u8,
-    addr: u16,
-    write_div: bool,
-    write_tac: bool,
-    tima_overflow: bool,
     cartridge_type: u8,
     rom_bank_count: u16,
     ram_bank_count: u8,
@@ -46,12 +44,13 @@ pub struct Memory {
     mbc1rombank: u8,
     mbc1rambank: u8,
     mbc1bankmode: u8,
+    sgb: Option<Sgb>,
+    watches: Watches,
 }
 
 impl Memory {
     pub fn empty() -> Self {
         Memory {
-            owner: Comp::Cpu,
             dma: 0,
             dma_start_delay: 0,
             dma_delay_block: false,
@@ -61,11 +60,6 @@ impl Memory {
             mbc: Mbc::None,
             mem: [0u8; 0x10000],
             cartridge: [0u8; 0x10000].to_vec(),
-            data: 0x00,
-            addr: 0x0000,
-            write_div: false,
-            write_tac: false,
-            tima_overflow: false,
             cartridge_type: 0x00,
             rom_bank_count: 0x0000,
             ram_bank_count: 0x00,
@@ -73,6 +67,8 @@ impl Memory {
             mbc1rombank: 0x00,
             mbc1rambank: 0x00,
             mbc1bankmode: 0x00,
+            sgb: None,
+            watches: Watches::default(),
         }
     }
 
@@ -83,7 +79,6 @@ impl Memory {
         let ram_bank_count = Memory::ram_bank_count_decode(cartridge);
         mem[0x0000..cartridge.len()].copy_from_slice(cartridge);
         let mem = Memory {
-            owner: Comp::Cpu,
             dma: 0,
             dma_start_delay: 0,
             dma_delay_block: false,
@@ -93,11 +88,6 @@ impl Memory {
             mbc,
             mem,
             cartridge: cartridge.to_vec(),
-            data: 0x00,
-            addr: 0x0000,
-            write_div: false,
-            write_tac: false,
-            tima_overflow: false,
             cartridge_type,
             rom_bank_count,
             ram_bank_count,
@@ -105,10 +95,16 @@ impl Memory {
             mbc1rombank: 0x00,
             mbc1rambank: 0x00,
             mbc1bankmode: 0x00,
+            sgb: None,
+            watches: Watches::default(),
         };
-        eprintln!(
-            "MEM: rom_bank_count:#{} ram_bank_count:#{} mbc:{:?}",
-            mem.rom_bank_count, mem.ram_bank_count, mem.mbc
+        log!(
+            Mem,
+            Info,
+            "rom_bank_count:#{} ram_bank_count:#{} mbc:{:?}",
+            mem.rom_bank_count,
+            mem.ram_bank_count,
+            mem.mbc
         );
         mem
     }
@@ -120,7 +116,7 @@ impl Memory {
             0x01..=0x03 => Mbc::MBC1,
             x => todo!("MBC {:02X} not implemented!", x),
         };
-        eprintln!("MBC {:?} found", mbc);
+        log!(Mem, Info, "MBC {:?} found", mbc);
         (mbc, cartridge_type)
     }
 
@@ -144,29 +140,21 @@ impl Memory {
         }
     }
 
-    pub fn read(&mut self) {
-        let addr = self.addr;
-        if self.owner == Comp::Cpu {
-            if self.dma_blocks_cpu(addr) {
-                self.data = 0xFF;
-                return;
-            }
-            if self.oam_busy && (0xFE00..0xFEA0).contains(&addr) {
-                self.data = 0xFF;
-                return;
-            }
-            if self.vram_busy && (0x8000..0xA000).contains(&addr) {
-                self.data = 0xFF;
-                return;
-            }
+    /// Reads `addr` as the CPU, which can't see what DMA or the PPU are
+    /// using
+    pub fn read(&mut self, addr: u16) -> u8 {
+        if self.dma_blocks_cpu(addr) {
+            return 0xFF;
         }
-        if self.owner == Comp::Ppu && self.dma_blocks_oam(addr) {
-            self.data = 0xFF;
-            return;
+        if self.oam_busy && (0xFE00..0xFEA0).contains(&addr) {
+            return 0xFF;
         }
-        self.data = match self.addr {
-            0x0000..=0x7FFF => self.mbc_read(),
-            0xA000..=0xBFFF => self.mbc_read(),
+        if self.vram_busy && (0x8000..0xA000).contains(&addr) {
+            return 0xFF;
+        }
+        match addr {
+            0x0000..=0x7FFF => self.mbc_read(addr),
+            0xA000..=0xBFFF => self.mbc_read(addr),
             _ => self.mem[addr as usize],
         }
     }
@@ -182,38 +170,56 @@ impl Memory {
         self.mem[addr as usize]
     }
 
-    pub fn write(&mut self) {
-        let addr = self.addr();
-        let data = self.data();
+    /// Reads `addr` through the MBC, as the CPU currently sees it
+    pub fn dbg_read_mapped(&self, addr: u16) -> u8 {
+        match (&self.mbc, addr) {
+            (Mbc::MBC1, 0x0000..=0x7FFF) => self.cartridge[self.mbc1_rom_addr(addr)],
+            _ => self.mem[addr as usize],
+        }
+    }
 
-        if self.owner == Comp::Cpu {
-            if self.tima_overflow && addr == TIMA {
-                return;
-            }
-            if addr == DMA {
-                let start = (data as usize) << 8;
-                let was_blocking = self.dma_bus_blocked();
-                self.dma_start_delay = DMA_START_DELAY_CYCLES;
-                self.dma_delay_block = was_blocking;
-                self.dma_source = Some(start);
-                self.dma = DMA_TRANSFER_CYCLES;
-            }
-            if self.dma_blocks_cpu(addr) {
-                return;
-            }
-            if self.oam_busy && (0xFE00..0xFEA0).contains(&addr) {
-                return;
-            }
-            if self.vram_busy && (0x8000..0xA000).contains(&addr) {
-                return;
-            }
+    /// The ROM bank currently mapped at `addr`
+    pub fn rom_bank(&self, addr: u16) -> usize {
+        match (&self.mbc, addr) {
+            (Mbc::MBC1, 0x0000..=0x3FFF) => self.mbc1_fixed_rom_bank(),
+            (Mbc::MBC1, 0x4000..=0x7FFF) => self.mbc1_switchable_rom_bank(),
+            (_, 0x4000..=0x7FFF) => 1,
+            _ => 0,
+        }
+    }
+
+    /// The bank a `.sym` file gives labels at `addr` under the current mapping
+    pub fn symbol_bank(&self, addr: u16) -> usize {
+        match addr {
+            0x0000..=0x7FFF => self.rom_bank(addr),
+            // rgblink numbers WRAMX from 1 unless linked with -w
+            0xD000..=0xDFFF => 1,
+            _ => 0,
+        }
+    }
+
+    /// Writes `addr` as the CPU, a write to DMA starts a transfer
+    pub fn write(&mut self, addr: u16, data: u8) {
+        if addr == DMA {
+            let start = (data as usize) << 8;
+            let was_blocking = self.dma_bus_blocked();
+            self.dma_start_delay = DMA_START_DELAY_CYCLES;
+            self.dma_delay_block = was_blocking;
+            self.dma_source = Some(start);
+            self.dma = DMA_TRANSFER_CYCLES;
+        }
+        if self.dma_blocks_cpu(addr) {
+            return;
+        }
+        if self.oam_busy && (0xFE00..0xFEA0).contains(&addr) {
+            return;
         }
-        if self.owner == Comp::Ppu && self.dma_blocks_oam(addr) {
+        if self.vram_busy && (0x8000..0xA000).contains(&addr) {
             return;
         }
 
         match addr {
-            0x0000..0x8000 => self.mbc_rom_write(),
+            0x0000..0x8000 => self.mbc_rom_write(addr, data),
             0x8000..0xA000 => self.mem[addr as usize] = data, // 8 KiB VRAM (GBC Bank 00-01)
             0xA000..0xC000 => self.mem[addr as usize] = data, // 8 KiB External RAM
             0xC000..0xD000 => {
@@ -238,22 +244,8 @@ impl Memory {
                 self.mem[addr as usize] = data;
             }
             0xFE00..0xFEA0 => self.write_oam(addr, data),
-            0xFEA0..0xFF00 => (), // Not Usable
-            P1 => {
-                if self.owner == Comp::Cpu {
-                    let current = self.mem[addr as usize] & 0x0F;
-                    let select = data & 0x30;
-                    self.mem[addr as usize] = 0xC0 | select | current;
-                } else {
-                    self.mem[addr as usize] = data;
-                }
-            }
-            DIV => {
-                self.mem[addr as usize] = 0;
-                self.write_div = true;
-            }
-            TAC => self.set_tac(data),
-            0xFF01..0xFF80 => self.mem[addr as usize] = data, // I/O Registers
+            0xFEA0..0xFF00 => (),                             // Not Usable
+            0xFF00..0xFF80 => self.mem[addr as usize] = data, // I/O Registers
             0xFF80..0xFFFF => self.mem[addr as usize] = data, // High RAM (HRAM)
             0xFFFF => self.mem[addr as usize] = data,         // Interrupt Enable
         }
@@ -263,78 +255,156 @@ impl Memory {
         self.mem[addr as usize] = data
     }
 
+    /// Writes `addr` as `comp` does its own registers, which watchpoints see
+    pub fn write_as(&mut self, comp: Comp, addr: u16, data: u8) {
+        self.observe(Access::Write, addr, data, comp);
+        self.dbg_write(addr, data);
+    }
+
+    /// Checks an access by `comp` against the watchpoints
+    pub fn observe(&mut self, access: Access, addr: u16, value: u8, comp: Comp) {
+        if !self.watches.is_empty() {
+            self.watches.check(access, addr, value, comp);
+        }
+    }
+
     pub fn bulk_write(&mut self, addr: u16, newmem: &[u8]) {
         self.mem[addr as usize..newmem.len()].copy_from_slice(newmem);
     }
 
-    pub fn addr(&self) -> u16 {
-        self.addr
+    /// Powers back on with the same cartridge, keeping its RAM, the Super
+    /// Game Boy mode and the debugger's watchpoints
+    pub fn reset(&mut self) {
+        let mut mem = if self.cartridge.is_empty() {
+            Memory::empty()
+        } else {
+            Memory::new(&self.cartridge)
+        };
+        mem.mem[0xA000..0xC000].copy_from_slice(&self.mem[0xA000..0xC000]);
+        mem.sgb = self.sgb.as_ref().map(|_| Sgb::new());
+        mem.watches = std::mem::take(&mut self.watches);
+        *self = mem;
     }
 
-    pub fn data(&self) -> u8 {
-        self.data
+    pub fn enable_sgb(&mut self) {
+        self.sgb = Some(Sgb::new());
     }
 
-    pub fn set_addr(&mut self, addr: u16) {
-        self.addr = addr
+    pub fn sgb(&self) -> Option<&Sgb> {
+        self.sgb.as_ref()
     }
 
-    pub fn set_data(&mut self, data: u8) {
-        self.data = data
+    pub fn sgb_mut(&mut self) -> Option<&mut Sgb> {
+        self.sgb.as_mut()
     }
 
-    pub fn set_tac(&mut self, tac: u8) {
-        self.mem[0xFF07] = (self.mem[0xFF07] & 0xF8) + (tac & 0x07);
-        self.write_tac = true;
+    /// Passes the select bits the CPU wrote to P1 on to the Super Game Boy
+    pub fn write_sgb_p1(&mut self, select: u8, lcdc: u8) {
+        if let Some(sgb) = self.sgb.as_mut() {
+            sgb.write_p1(select, &self.mem, lcdc);
+        }
     }
 
-    pub fn set_tima_overflow(&mut self, tima_overflow: bool) {
-        self.tima_overflow = tima_overflow;
+    pub fn watches(&self) -> &Watches {
+        &self.watches
     }
 
-    pub fn tima_overflow(&self) -> bool {
-        self.tima_overflow
+    pub fn watches_mut(&mut self) -> &mut Watches {
+        &mut self.watches
     }
 
-    pub fn owner(&self) -> Comp {
-        self.owner.clone()
+    /// The cartridge ROM as loaded, save states are tied to it
+    pub fn cartridge(&self) -> &[u8] {
+        &self.cartridge
     }
 
-    pub fn set_owner(&mut self, owner: Comp) {
-        self.owner = owner;
+    /// Saves the DMA and banking state, the address space including VRAM,
+    /// OAM and cartridge RAM with `io` as the IO registers, and the SGB state
+    pub fn save(&self, out: &mut Writer, io: &[u8]) {
+        out.usize(self.dma);
+        out.u8(self.dma_start_delay);
+        out.bool(self.dma_delay_block);
+        out.bool(self.dma_source.is_some());
+        out.usize(self.dma_source.unwrap_or(0));
+        out.bool(self.oam_busy);
+        out.bool(self.vram_busy);
+        let mut mem = self.mem;
+        mem[0xFF00..0xFF00 + io.len()].copy_from_slice(io);
+        out.bytes(&mem);
+        out.bool(self.ram_enable);
+        out.array(&[self.mbc1rombank, self.mbc1rambank, self.mbc1bankmode]);
+        out.bool(self.sgb.is_some());
+        if let Some(sgb) = &self.sgb {
+            sgb.save(out);
+        }
     }
 
-    pub fn check_write_div(&mut self) -> bool {
-        let result = self.write_div;
-        self.write_div = false;
-        result
+    /// Loads what `save` wrote, leaving the IO registers in memory for the
+    /// bus to hand to the components keeping them
+    pub fn load(&mut self, input: &mut Reader) -> Result<(), String> {
+        self.dma = input.usize()?;
+        self.dma_start_delay = input.u8()?;
+        self.dma_delay_block = input.bool()?;
+        let dma_source = input.bool()?;
+        let source = input.usize()?;
+        self.dma_source = dma_source.then_some(source);
+        self.oam_busy = input.bool()?;
+        self.vram_busy = input.bool()?;
+        input.bytes_into(&mut self.mem)?;
+        self.ram_enable = input.bool()?;
+        [self.mbc1rombank, self.mbc1rambank, self.mbc1bankmode] = input.array()?;
+        match (input.bool()?, self.sgb.as_mut()) {
+            (true, Some(sgb)) => sgb.load(input)?,
+            (false, None) => {}
+            (true, None) => return Err("state is from a Super Game Boy".to_string()),
+            (false, Some(_)) => return Err("state is not from a Super Game Boy".to_string()),
+        }
+        Ok(())
     }
 
-    pub fn check_write_tac(&mut self) -> bool {
-        let result = self.write_tac;
-        self.write_tac = false;
-        result
+    /// Overwrites memory without going through the bus, keeping echo RAM in
+    /// step with work RAM
+    pub fn restore(&mut self, addr: u16, data: &[u8]) {
+        let start = addr as usize;
+        self.mem[start..start + data.len()].copy_from_slice(data);
+        self.mem.copy_within(0xC000..0xDE00, 0xE000);
+    }
+
+    /// The cartridge RAM size in bytes from the header, of which only one
+    /// bank is kept at $A000-$BFFF
+    pub fn cartridge_ram_size(&self) -> usize {
+        self.ram_bank_count as usize * 0x2000
+    }
+
+    /// Writes that put the MBC registers back in their current state
+    pub fn mbc_writes(&self) -> Vec<(u16, u8)> {
+        match self.mbc {
+            Mbc::MBC1 => vec![
+                (0x0000, if self.ram_enable { 0x0A } else { 0x00 }),
+                (0x2000, self.mbc1rombank),
+                (0x4000, self.mbc1rambank),
+                (0x6000, self.mbc1bankmode),
+            ],
+            _ => Vec::new(),
+        }
     }
 
-    pub fn read_vram(&mut self, addr: u16) -> u8 {
-        match self.owner {
-            Comp::Cpu => {
-                if self.vram_busy {
-                    return 0xFF;
-                }
+    /// Writes to the MBC registers as the CPU would, then drops any DMA and
+    /// bus state left over from before
+    pub fn replay_mbc_writes(&mut self, writes: &[(u16, u8)]) {
+        for (addr, data) in writes {
+            if *addr < 0x8000 {
+                self.mbc_rom_write(*addr, *data);
             }
-            Comp::Ppu => {
-                if self.dma_bus_blocked() {
-                    return 0xFF;
-                }
-            }
-            _ => (),
-        };
-        self.mem[addr as usize]
+        }
+        self.dma = 0;
+        self.dma_start_delay = 0;
+        self.dma_delay_block = false;
+        self.dma_source = None;
     }
 
     pub fn write_oam(&mut self, addr: u16, data: u8) {
-        if self.owner == Comp::Cpu && (self.oam_busy || self.vram_busy) {
+        if self.oam_busy || self.vram_busy {
             return;
         }
         self.mem[addr as usize] = data
@@ -369,10 +439,6 @@ impl Memory {
         addr < 0xFF00 && self.dma_bus_blocked()
     }
 
-    fn dma_blocks_oam(&self, addr: u16) -> bool {
-        (0xFE00..0xFEA0).contains(&addr) && self.dma_bus_blocked()
-    }
-
     fn dma_bus_blocked(&self) -> bool {
         if self.dma_start_delay > 0 {
             self.dma_delay_block
@@ -387,44 +453,40 @@ impl Memory {
         right[..OAM_LEN].copy_from_slice(&left[start..end]);
     }
 
-    pub fn mbc_rom_write(&mut self) {
+    pub fn mbc_rom_write(&mut self, addr: u16, data: u8) {
         match &self.mbc {
             Mbc::None => (),
-            Mbc::MBC1 => self.mbc1_register_write(),
-            x => todo!(
-                "ROM write on unimplemented MBC:{:?} addr:{:04X}",
-                x,
-                self.addr
-            ),
+            Mbc::MBC1 => self.mbc1_register_write(addr, data),
+            x => todo!("ROM write on unimplemented MBC:{:?} addr:{:04X}", x, addr),
         }
     }
 
-    pub fn mbc1_register_write(&mut self) {
-        match self.addr {
-            0x0000..=0x1FFF => self.ram_enable = self.data & 0x0A == 0x0A,
-            0x2000..=0x3FFF => self.mbc1rombank = self.data & 0x1F,
-            0x4000..=0x5FFF => self.mbc1rambank = self.data & 0x3,
-            0x6000..=0x7FFF => self.mbc1bankmode = self.data & 0x1,
-            _ => unreachable!("Invalid addr:{:04X} for MBC1 write", self.addr),
+    pub fn mbc1_register_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enable = data & 0x0A == 0x0A,
+            0x2000..=0x3FFF => self.mbc1rombank = data & 0x1F,
+            0x4000..=0x5FFF => self.mbc1rambank = data & 0x3,
+            0x6000..=0x7FFF => self.mbc1bankmode = data & 0x1,
+            _ => unreachable!("Invalid addr:{:04X} for MBC1 write", addr),
         }
     }
 
-    pub fn mbc_read(&mut self) -> u8 {
+    pub fn mbc_read(&mut self, addr: u16) -> u8 {
         match &self.mbc {
-            Mbc::None => self.mem[self.addr as usize],
-            Mbc::MBC1 => self.mbc1_read(),
-            x => todo!("Read on unimplemented MBC:{:?} addr:{:04X}", x, self.addr),
+            Mbc::None => self.mem[addr as usize],
+            Mbc::MBC1 => self.mbc1_read(addr),
+            x => todo!("Read on unimplemented MBC:{:?} addr:{:04X}", x, addr),
         }
     }
 
-    pub fn mbc1_read(&mut self) -> u8 {
-        match self.addr {
+    pub fn mbc1_read(&mut self, addr: u16) -> u8 {
+        match addr {
             0x0000..=0x7FFF => {
-                let cart_addr = self.mbc1_rom_addr(self.addr);
+                let cart_addr = self.mbc1_rom_addr(addr);
                 self.cartridge[cart_addr]
             }
-            0xA000..=0xBFFF => self.mem[self.addr as usize],
-            _ => unreachable!("Invalid mbc1 read decode addr:{:04X}", self.addr),
+            0xA000..=0xBFFF => self.mem[addr as usize],
+            _ => unreachable!("Invalid mbc1 read decode addr:{:04X}", addr),
         }
     }
 
@@ -1,8 +1,22 @@
+pub mod batch;
+pub mod bess;
+pub mod bus;
 pub mod cpu;
+pub mod disasm;
 pub mod gb;
 pub mod joypad;
+pub mod log;
 pub mod mem;
+pub mod png;
 pub mod ppu;
 pub mod regs;
+pub mod rewind;
+pub mod sched;
 pub mod serial;
+pub mod sgb;
+pub mod state;
+pub mod symbols;
+pub mod testrom;
 pub mod timer;
+pub mod trace;
+pub mod watch;
@@ -0,0 +1,394 @@
+/// Largest stored deflate block
+const BLOCK: usize = 0xFFFF;
+
+/// Encodes RGBA pixels as a PNG, the image data is stored uncompressed
+pub fn encode(width: usize, height: usize, rgba: &[u8]) -> Vec<u8> {
+    assert_eq!(rgba.len(), width * height * 4, "frame size doesn't match");
+
+    // Each row starts with its filter type, none
+    let mut raw = Vec::with_capacity((width * 4 + 1) * height);
+    for row in rgba.chunks_exact(width * 4) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let blocks = raw.chunks(BLOCK).count();
+    for (i, block) in raw.chunks(BLOCK).enumerate() {
+        zlib.push((i + 1 == blocks) as u8);
+        zlib.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        zlib.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel RGBA, default compression, filtering and no interlace
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut out, b"IHDR", &header);
+    chunk(&mut out, b"IDAT", &zlib);
+    chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, b| {
+        (0..8).fold(crc ^ *b as u32, |crc, _| {
+            (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), x| {
+        let a = (a + *x as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    (b << 16) | a
+}
+
+/// Decodes a PNG to `(width, height, RGBA pixels)`. Every colour type is
+/// supported at 8 bits per channel, greyscale and palette images at fewer
+/// too, but not interlacing
+pub fn decode(png: &[u8]) -> Result<(usize, usize, Vec<u8>), String> {
+    let Some(mut rest) = png.strip_prefix(b"\x89PNG\r\n\x1a\n") else {
+        return Err("not a PNG".to_string());
+    };
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut alpha: &[u8] = &[];
+    let mut zlib = Vec::new();
+    while rest.len() >= 12 {
+        let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let Some(data) = rest.get(8..8 + len) else {
+            return Err("truncated chunk".to_string());
+        };
+        match &rest[4..8] {
+            b"IHDR" => header = Some(data),
+            b"PLTE" => palette = data,
+            b"tRNS" => alpha = data,
+            b"IDAT" => zlib.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+        rest = rest.get(len + 12..).unwrap_or_default();
+    }
+
+    let Some(header) = header.filter(|header| header.len() == 13) else {
+        return Err("missing IHDR".to_string());
+    };
+    let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let (depth, color) = (header[8], header[9]);
+    if header[12] != 0 {
+        return Err("interlaced PNGs aren't supported".to_string());
+    }
+    let channels = match (color, depth) {
+        (0 | 3, 1 | 2 | 4 | 8) => 1,
+        (4, 8) => 2,
+        (2, 8) => 3,
+        (6, 8) => 4,
+        _ => {
+            return Err(format!(
+                "unsupported colour type {} at {} bits",
+                color, depth
+            ));
+        }
+    };
+
+    // The zlib header is skipped and the checksum not checked
+    let raw = inflate(zlib.get(2..).unwrap_or_default())?;
+    let bits = channels * depth as usize;
+    let stride = (width * bits).div_ceil(8);
+    let bpp = bits.div_ceil(8);
+    if raw.len() < (stride + 1) * height {
+        return Err("image data too short".to_string());
+    }
+
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    let mut prev = vec![0; stride];
+    for row in raw.chunks_exact(stride + 1).take(height) {
+        let mut line = row[1..].to_vec();
+        unfilter(row[0], &mut line, &prev, bpp)?;
+        for x in 0..width {
+            let pixel = match color {
+                0 => {
+                    let grey = (sample(&line, x, depth) as u32 * 255 / ((1 << depth) - 1)) as u8;
+                    [grey, grey, grey, 0xFF]
+                }
+                3 => {
+                    let index = sample(&line, x, depth) as usize;
+                    let Some(rgb) = palette.get(index * 3..index * 3 + 3) else {
+                        return Err(format!("palette index {} out of range", index));
+                    };
+                    let a = alpha.get(index).copied().unwrap_or(0xFF);
+                    [rgb[0], rgb[1], rgb[2], a]
+                }
+                4 => [line[x * 2], line[x * 2], line[x * 2], line[x * 2 + 1]],
+                2 => [line[x * 3], line[x * 3 + 1], line[x * 3 + 2], 0xFF],
+                _ => [
+                    line[x * 4],
+                    line[x * 4 + 1],
+                    line[x * 4 + 2],
+                    line[x * 4 + 3],
+                ],
+            };
+            rgba.extend_from_slice(&pixel);
+        }
+        prev = line;
+    }
+    Ok((width, height, rgba))
+}
+
+/// The `index`th sample of a row with `depth` bit samples, packed from the
+/// most significant bit
+fn sample(line: &[u8], index: usize, depth: u8) -> u8 {
+    if depth == 8 {
+        return line[index];
+    }
+    let per_byte = 8 / depth as usize;
+    let shift = 8 - depth as usize * (index % per_byte + 1);
+    (line[index / per_byte] >> shift) & ((1 << depth) - 1)
+}
+
+/// Undoes a row's filter, `prev` being the row above unfiltered
+fn unfilter(filter: u8, line: &mut [u8], prev: &[u8], bpp: usize) -> Result<(), String> {
+    for i in 0..line.len() {
+        let a = if i >= bpp { line[i - bpp] } else { 0 };
+        let b = prev[i];
+        let c = if i >= bpp { prev[i - bpp] } else { 0 };
+        line[i] = line[i].wrapping_add(match filter {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            4 => paeth(a, b, c),
+            _ => return Err(format!("invalid filter type {}", filter)),
+        });
+    }
+    Ok(())
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// The order a dynamic block lists the code length code's lengths in
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Reads a deflate stream, least significant bit first
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Bits<'_> {
+    fn bit(&mut self) -> Result<u16, String> {
+        let Some(byte) = self.data.get(self.pos / 8) else {
+            return Err("truncated image data".to_string());
+        };
+        let bit = (byte >> (self.pos % 8)) & 1;
+        self.pos += 1;
+        Ok(bit as u16)
+    }
+
+    fn bits(&mut self, count: u8) -> Result<u16, String> {
+        (0..count).try_fold(0, |value, i| Ok(value | (self.bit()? << i)))
+    }
+}
+
+/// A canonical Huffman code, as the number of codes of each length and the
+/// symbols in code order
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0; 16];
+        for len in 1..16 {
+            offsets[len] = offsets[len - 1] + counts[len - 1];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, String> {
+        // The first code of each length follows on from the last of the
+        // one before
+        let (mut code, mut first, mut index) = (0u32, 0u32, 0u32);
+        for &count in &self.counts[1..] {
+            let count = count as u32;
+            code |= bits.bit()? as u32;
+            if code >= first && code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code".to_string())
+    }
+}
+
+/// Decompresses a raw deflate stream
+fn inflate(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut bits = Bits { data, pos: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.bit()? == 1;
+        match bits.bits(2)? {
+            0 => {
+                let start = bits.pos.div_ceil(8);
+                let Some(header) = data.get(start..start + 4) else {
+                    return Err("truncated image data".to_string());
+                };
+                let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                let Some(block) = data.get(start + 4..start + 4 + len) else {
+                    return Err("truncated image data".to_string());
+                };
+                out.extend_from_slice(block);
+                bits.pos = (start + 4 + len) * 8;
+            }
+            1 => {
+                let mut lengths = [8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                let literals = Huffman::new(&lengths);
+                let distances = Huffman::new(&[5; 30]);
+                inflate_block(&mut bits, &mut out, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut bits)?;
+                inflate_block(&mut bits, &mut out, &literals, &distances)?;
+            }
+            _ => return Err("invalid deflate block".to_string()),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+/// Reads the literal/length and distance codes of a dynamic block
+fn dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman), String> {
+    let literals = bits.bits(5)? as usize + 257;
+    let distances = bits.bits(5)? as usize + 1;
+    let code_lengths = bits.bits(4)? as usize + 4;
+
+    let mut lengths = [0; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[symbol] = bits.bits(3)? as u8;
+    }
+    let code = Huffman::new(&lengths);
+
+    let mut lengths = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let (len, repeat) = match code.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => match lengths.last() {
+                Some(&len) => (len, 3 + bits.bits(2)?),
+                None => return Err("repeated code length with none before".to_string()),
+            },
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(len, repeat as usize));
+    }
+    if lengths.len() > literals + distances {
+        return Err("too many code lengths".to_string());
+    }
+    Ok((
+        Huffman::new(&lengths[..literals]),
+        Huffman::new(&lengths[literals..]),
+    ))
+}
+
+/// Decodes a compressed block's symbols up to its end
+fn inflate_block(
+    bits: &mut Bits,
+    out: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(bits)? as usize;
+        if symbol < 256 {
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+        let Some(&base) = LENGTH_BASE.get(symbol - 257) else {
+            return Err(format!("invalid length code {}", symbol));
+        };
+        let len = base as usize + bits.bits(LENGTH_EXTRA[symbol - 257])? as usize;
+        let code = distances.decode(bits)? as usize;
+        let Some(&base) = DISTANCE_BASE.get(code) else {
+            return Err(format!("invalid distance code {}", code));
+        };
+        let distance = base as usize + bits.bits(DISTANCE_EXTRA[code])? as usize;
+        if distance > out.len() {
+            return Err("distance too far back".to_string());
+        }
+        for _ in 0..len {
+            out.push(out[out.len() - distance]);
+        }
+    }
+}
@@ -1,11 +1,11 @@
 use crate::app::window::{FrameSender, SCREEN_HEIGHT, SCREEN_WIDTH};
-use crate::emu::gb::Comp;
 use crate::emu::mem::Memory;
 use crate::emu::regs::*;
-use crate::{bit, isbitset, setbit};
-use std::cell::RefCell;
+use crate::emu::sched::NEVER;
+use crate::emu::sgb::{SGB_SCREEN_HEIGHT, SGB_SCREEN_WIDTH};
+use crate::emu::state::{Reader, Writer};
+use crate::{bit, isbitset, log, setbit};
 use std::fmt;
-use std::rc::Rc;
 
 pub const BLACK: [u8; 4] = [0x29, 0x41, 0x39, 0xFF];
 pub const DARK_GREY: [u8; 4] = [0x39, 0x59, 0x4A, 0xFF];
@@ -13,8 +13,9 @@ pub const LIGHT_GREY: [u8; 4] = [0x5A, 0x79, 0x42, 0xFF];
 pub const WHITE: [u8; 4] = [0x7B, 0x82, 0x10, 0xFF];
 
 const FRAME_BYTES: usize = (SCREEN_WIDTH as usize) * (SCREEN_HEIGHT as usize) * 4;
+const SHADE_BYTES: usize = (SCREEN_WIDTH as usize) * (SCREEN_HEIGHT as usize);
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 enum Mode {
     M0,
     M1,
@@ -33,7 +34,7 @@ impl Mode {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum Fetch {
     Tile_,
     Tile,
@@ -46,7 +47,21 @@ enum Fetch {
     Push,
 }
 
+const MODES: [Mode; 4] = [Mode::M0, Mode::M1, Mode::M2, Mode::M3];
+
 impl Fetch {
+    const ALL: [Fetch; 9] = [
+        Fetch::Tile_,
+        Fetch::Tile,
+        Fetch::DataLo_,
+        Fetch::DataLo,
+        Fetch::DataHi_,
+        Fetch::DataHi,
+        Fetch::Sleep0_,
+        Fetch::Sleep1_,
+        Fetch::Push,
+    ];
+
     fn next(&self) -> Self {
         match self {
             Fetch::Tile_ => Fetch::Tile,
@@ -94,18 +109,31 @@ impl std::fmt::Display for Oa {
     }
 }
 
-type TileData = [u8; 16];
+pub type TileData = [u8; 16];
 
 #[allow(dead_code)]
 pub struct Ppu {
     frame_tx: Option<FrameSender>,
-    mem: Rc<RefCell<Memory>>,
+    /// Finished frames aren't sent while set
+    skip_frames: bool,
+    lcdc: u8,
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
     objects: Vec<Oa>,
     bg_fifo: Vec<Pixel>,
     obj_fifo: Vec<Pixel>,
     x: u8,
     pub testing: usize,
     back_buffer: Vec<u8>,
+    shades: Vec<u8>,
     mode: Mode,
     dot: u16,
     dotlimit: u16,
@@ -115,6 +143,9 @@ pub struct Ppu {
     fetch_tile_datahi: u8,
     lcd_was_enabled: bool, // Track LCD emut nable state
     already_interrupted: bool,
+    /// The first dot not ticked yet, countdowns catch up from here after
+    /// sleeping
+    synced: u128,
 }
 
 #[allow(dead_code)]
@@ -126,16 +157,28 @@ pub struct Pixel {
 }
 
 impl Ppu {
-    pub fn headless_dmg(mem: Rc<RefCell<Memory>>) -> Self {
-        let mut ppu = Ppu {
+    pub fn headless_dmg() -> Self {
+        Ppu {
             frame_tx: None,
-            mem,
+            skip_frames: false,
+            lcdc: 0x91,
+            stat: 0x80,
+            scy: 0x00,
+            scx: 0x00,
+            ly: 0x00,
+            lyc: 0x00,
+            bgp: 0xFC,
+            obp0: 0x00,
+            obp1: 0x00,
+            wy: 0x00,
+            wx: 0x00,
             bg_fifo: Vec::<Pixel>::new(),
             obj_fifo: Vec::<Pixel>::new(),
             objects: Vec::<Oa>::new(),
             x: 0,
             testing: 0,
             back_buffer: vec![0; FRAME_BYTES],
+            shades: vec![0; SHADE_BYTES],
             mode: Mode::M0,
             dot: 0x0000,
             dotlimit: 0x0000,
@@ -145,59 +188,101 @@ impl Ppu {
             fetch_tile_datahi: 0x00,
             lcd_was_enabled: false,
             already_interrupted: false,
-        };
+            synced: 0,
+        }
+    }
 
-        ppu.mem_write(LCDC, 0x91);
-        ppu.mem_write(STAT, 0x80);
-        ppu.mem_write(SCY, 0x00);
-        ppu.mem_write(SCX, 0x00);
-        ppu.mem_write(LY, 0x00);
-        ppu.mem_write(LYC, 0x00);
-        ppu.mem_write(BGP, 0xFC);
-        ppu.mem_write(WY, 0x00);
-        ppu.mem_write(WX, 0x00);
+    pub fn init_dmg(frame_tx: FrameSender) -> Self {
+        Ppu {
+            frame_tx: Some(frame_tx),
+            ..Ppu::headless_dmg()
+        }
+    }
 
-        ppu
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            LCDC => self.lcdc,
+            STAT => self.stat,
+            SCY => self.scy,
+            SCX => self.scx,
+            LY => self.ly,
+            LYC => self.lyc,
+            BGP => self.bgp,
+            OBP0 => self.obp0,
+            OBP1 => self.obp1,
+            WY => self.wy,
+            _ => self.wx,
+        }
     }
 
-    pub fn init_dmg(frame_tx: FrameSender, mem: Rc<RefCell<Memory>>) -> Self {
-        let mut ppu = Ppu {
-            frame_tx: Some(frame_tx),
-            mem,
-            bg_fifo: Vec::<Pixel>::new(),
-            obj_fifo: Vec::<Pixel>::new(),
-            objects: Vec::<Oa>::new(),
-            x: 0,
-            testing: 0,
-            back_buffer: vec![0; FRAME_BYTES],
-            mode: Mode::M0,
-            dot: 0x0000,
-            dotlimit: 0x0000,
-            fetch_state: Fetch::Tile_,
-            fetch_tile: 0x00,
-            fetch_tile_datalo: 0x00,
-            fetch_tile_datahi: 0x00,
-            lcd_was_enabled: false,
-            already_interrupted: false,
-        };
+    pub fn write(&mut self, addr: u16, data: u8) {
+        self.poke(addr, data);
+    }
+
+    pub fn poke(&mut self, addr: u16, data: u8) {
+        match addr {
+            LCDC => self.lcdc = data,
+            STAT => self.stat = data,
+            SCY => self.scy = data,
+            SCX => self.scx = data,
+            LY => self.ly = data,
+            LYC => self.lyc = data,
+            BGP => self.bgp = data,
+            OBP0 => self.obp0 = data,
+            OBP1 => self.obp1 = data,
+            WY => self.wy = data,
+            _ => self.wx = data,
+        }
+    }
+
+    /// Runs T-cycle `t`, returning when the PPU next has work to do
+    pub fn tick(&mut self, t: u128, mem: &mut Memory) -> u128 {
+        self.catch_up(t);
+        self.advance_dot(mem);
+        self.synced = t + 1;
+        self.next_event(t)
+    }
+
+    /// Carries on from `t` after the state was replaced
+    pub fn resume_from(&mut self, t: u128) {
+        self.synced = t;
+    }
+
+    /// Keeps the PPU still at `t`, while the CPU is in STOP
+    pub fn hold(&mut self, t: u128) {
+        self.catch_up(t);
+        self.synced = t + 1;
+    }
+
+    /// The dot countdown at `t` had the PPU been ticked all along
+    fn dot_at(&self, t: u128) -> u16 {
+        if self.lcd_was_enabled {
+            self.dot - t.saturating_sub(self.synced) as u16
+        } else {
+            self.dot
+        }
+    }
 
-        ppu.mem_write(LCDC, 0x91);
-        ppu.mem_write(STAT, 0x80);
-        ppu.mem_write(SCY, 0x00);
-        ppu.mem_write(SCX, 0x00);
-        ppu.mem_write(LY, 0x00);
-        ppu.mem_write(LYC, 0x00);
-        ppu.mem_write(BGP, 0xFC);
-        ppu.mem_write(WY, 0x00);
-        ppu.mem_write(WX, 0x00);
+    fn catch_up(&mut self, t: u128) {
+        self.dot = self.dot_at(t);
+        self.synced = self.synced.max(t);
+    }
 
-        ppu
+    /// The next dot that does more than count down, drawing and the OAM
+    /// scan read memory so they run every dot
+    fn next_event(&self, t: u128) -> u128 {
+        if !self.lcd_was_enabled {
+            // Until LCDC turns the LCD on
+            NEVER
+        } else if self.mode == Mode::M3 || (self.mode == Mode::M2 && self.dot == 80) {
+            t + 1
+        } else {
+            t + self.dot as u128
+        }
     }
 
-    pub fn tick(&mut self, t: u128) {
-        let _ = t;
-        let lcdc = self.mem_read(LCDC);
-        let lcd_enabled = (lcdc & 0x80) != 0;
+    fn advance_dot(&mut self, mem: &mut Memory) {
+        let lcd_enabled = (self.lcdc & 0x80) != 0;
 
         if !lcd_enabled {
             self.lcd_was_enabled = false;
@@ -216,52 +301,59 @@ impl Ppu {
             for chunk in self.back_buffer.chunks_exact_mut(4) {
                 chunk.copy_from_slice(&WHITE);
             }
+            self.shades.fill(0);
         }
 
-        let mode = self.mode.clone();
+        let mode = self.mode;
         match mode {
-            Mode::M0 => self.hblank(),
-            Mode::M1 => self.vblank(),
-            Mode::M2 => self.oamscan(),
-            Mode::M3 => self.draw(),
+            Mode::M0 => self.hblank(mem),
+            Mode::M1 => self.vblank(mem),
+            Mode::M2 => self.oamscan(mem),
+            Mode::M3 => self.draw(mem),
         }
         if mode != self.mode {
             self.set_mode(self.mode.bits());
-            self.check_interrupt();
+            self.check_interrupt(mem);
         }
         self.update_stat();
     }
 
-    pub fn read_oam(&mut self) {
+    pub fn read_oam(&mut self, mem: &Memory) {
         let mut addr = 0xFE00;
         while addr < 0xFEA0 {
-            let oa = self.mem_read_oa(addr);
+            let oa = Ppu::mem_read_oa(mem, addr);
             addr += 4;
             if oa.y == self.ly() {
                 if oa.x != 0 || oa.y != 0 {
-                    eprintln!("OBJECT {}", oa);
+                    log!(Ppu, Trace, "OBJECT {}", oa);
                 }
                 self.objects.push(oa);
             }
         }
     }
 
-    pub fn oamscan(&mut self) {
+    pub fn oamscan(&mut self, mem: &mut Memory) {
         if self.dot == 80 {
-            self.read_oam();
+            self.read_oam(mem);
         }
         self.dot -= 1;
         if self.dot == 0 {
             // Next mode is Drawing
             self.mode = Mode::M3;
-            self.set_oam_busy(false);
-            self.set_vram_busy(true);
+            mem.set_oam_busy(false);
+            mem.set_vram_busy(true);
             self.dot = 289;
-            //           eprintln!("Entering Drawing mode:{:?} dot:#{}", self.mode, self.dot);
+            log!(
+                Ppu,
+                Trace,
+                "Entering Drawing mode:{:?} dot:#{}",
+                self.mode,
+                self.dot
+            );
         }
     }
 
-    pub fn hblank(&mut self) {
+    pub fn hblank(&mut self, mem: &mut Memory) {
         self.dot -= 1;
         if self.dot == 0 {
             self.x = 0;
@@ -270,62 +362,64 @@ impl Ppu {
             self.dot = if ly == 144 {
                 // Next mode is VBLANK
                 self.mode = Mode::M1;
-                /*
-                                eprintln!(
-                                    "Entering VBLANK mode:{:?} dot:#{} ly:#{}",
-                                    self.mode, self.dot, ly
-                                );
-                */
-                let intflags = self.mem_read(IF) | 0x1;
-                self.mem_write(0xFF0F, intflags);
+                log!(
+                    Ppu,
+                    Trace,
+                    "Entering VBLANK mode:{:?} ly:#{}",
+                    self.mode,
+                    ly
+                );
+                let intflags = mem.dbg_read(IF) | 0x1;
+                mem.dbg_write(0xFF0F, intflags);
                 456
             } else {
                 // Next mode is OAM scan
                 self.mode = Mode::M2;
-                self.set_oam_busy(true);
-                /*
-                                eprintln!(
-                                    "Entering OAM mode:{:?} dot:#{} ly:#{}",
-                                    self.mode, self.dot, ly
-                                );
-                */
+                mem.set_oam_busy(true);
+                log!(Ppu, Trace, "Entering OAM mode:{:?} ly:#{}", self.mode, ly);
                 self.reset_fetch_pipeline();
                 80
             };
         }
     }
 
-    pub fn draw(&mut self) {
-        self.fifo_pixel_fetcher();
+    pub fn draw(&mut self, mem: &mut Memory) {
+        self.fifo_pixel_fetcher(mem);
         self.render();
         self.dot -= 1;
         if u32::from(self.x) >= SCREEN_WIDTH {
             self.mode = Mode::M0;
-            self.set_vram_busy(false);
-            //eprintln!("Entering HBLANK mode:{:?} dot:#{}", self.mode, self.dot);
+            mem.set_vram_busy(false);
+            log!(
+                Ppu,
+                Trace,
+                "Entering HBLANK mode:{:?} dot:#{}",
+                self.mode,
+                self.dot
+            );
             self.dot += 87;
         }
     }
 
-    pub fn vblank(&mut self) {
+    pub fn vblank(&mut self, mem: &mut Memory) {
         self.dot -= 1;
         if self.dot == 0 {
             let ly = self.ly();
             if ly >= 153 {
                 // Next mode is OAM
                 self.mode = Mode::M2;
-                //eprintln!("Entering OAM mode:{:?} dot:#{}", self.mode, self.dot);
+                log!(
+                    Ppu,
+                    Trace,
+                    "Entering OAM mode:{:?} dot:#{}",
+                    self.mode,
+                    self.dot
+                );
                 self.dot = 80;
                 self.set_ly(0);
                 self.x = 0;
                 self.reset_fetch_pipeline();
-                let Some(frame_tx) = &self.frame_tx else {
-                    return;
-                };
-
-                if let Err(err) = frame_tx.send(self.back_buffer.clone()) {
-                    eprintln!("failed to deliver frame: {err}");
-                }
+                self.send_frame(mem);
             } else {
                 self.set_ly(ly.wrapping_add(1));
                 self.dot = 456;
@@ -333,6 +427,56 @@ impl Ppu {
         }
     }
 
+    /// Blanks the screen to white, as the LCD does while the CPU is in STOP
+    pub fn blank(&mut self, mem: &mut Memory) {
+        for chunk in self.back_buffer.chunks_exact_mut(4) {
+            chunk.copy_from_slice(&WHITE);
+        }
+        self.shades.fill(0);
+        self.send_frame(mem);
+    }
+
+    /// The screen as `(width, height, RGBA pixels)`, with the border when
+    /// running as a Super Game Boy
+    pub fn frame(&self, mem: &mut Memory) -> (usize, usize, Vec<u8>) {
+        match mem.sgb_mut().map(|sgb| sgb.compose(&self.shades)) {
+            Some(frame) => (SGB_SCREEN_WIDTH, SGB_SCREEN_HEIGHT, frame),
+            None => (
+                SCREEN_WIDTH as usize,
+                SCREEN_HEIGHT as usize,
+                self.back_buffer.clone(),
+            ),
+        }
+    }
+
+    /// Back to how the PPU powers on, still sending frames to the same place
+    pub fn reset(&mut self) {
+        *self = match self.frame_tx.take() {
+            Some(frame_tx) => Ppu::init_dmg(frame_tx),
+            None => Ppu::headless_dmg(),
+        };
+    }
+
+    /// Stops handing finished frames to the frontend, for running faster
+    /// than it can show them
+    pub fn skip_frames(&mut self, skip: bool) {
+        self.skip_frames = skip;
+    }
+
+    fn send_frame(&self, mem: &mut Memory) {
+        let Some(frame_tx) = &self.frame_tx else {
+            return;
+        };
+        if self.skip_frames {
+            return;
+        }
+
+        let (_, _, frame) = self.frame(mem);
+        if let Err(err) = frame_tx.send(frame) {
+            log!(Ppu, Warn, "failed to deliver frame: {err}");
+        }
+    }
+
     pub fn render(&mut self) {
         let pixel = if self.bg_fifo.is_empty() {
             return;
@@ -344,6 +488,9 @@ impl Ppu {
         if let Some(target) = self.back_buffer.get_mut((index * 4)..((index + 1) * 4)) {
             target.copy_from_slice(&Ppu::get_color(pixel.color));
         }
+        if let Some(shade) = self.shades.get_mut(index) {
+            *shade = pixel.color;
+        }
         self.x += 1;
     }
 
@@ -358,8 +505,7 @@ impl Ppu {
 
     //
     pub fn tile_address_lo(&self, obj: bool, id: u8, y: u8) -> u16 {
-        let lcdc = self.mem_read(LCDC);
-        if obj || isbitset!(lcdc, 4) {
+        if obj || isbitset!(self.lcdc, 4) {
             0x8000 + ((id as u16) * 16) + ((y as u16) % 0x8) * 2
         } else {
             match id {
@@ -369,9 +515,8 @@ impl Ppu {
         }
     }
 
-    pub fn read_whole_tile_data(&mut self, obj: bool, id: u8, _bank: u8) -> TileData {
-        let lcdc = self.mem_read(LCDC);
-        let addr = if obj || isbitset!(lcdc, 4) {
+    pub fn read_whole_tile_data(&self, mem: &Memory, obj: bool, id: u8, _bank: u8) -> TileData {
+        let addr = if obj || isbitset!(self.lcdc, 4) {
             0x8000 + ((id as u16) * 16)
         } else {
             match id {
@@ -379,8 +524,10 @@ impl Ppu {
                 128..=255 => 0x8800 + (((id - 128) as u16) * 16),
             }
         };
-        let data = self.mem_read_16(addr);
-        println!(
+        let data = mem.dbg_read_16(addr);
+        log!(
+            Ppu,
+            Trace,
             "id:{:02X} tiledata: {}",
             id,
             data.iter()
@@ -390,32 +537,22 @@ impl Ppu {
         data
     }
 
-    pub fn read_tile(&mut self, x: u8, y: u8) -> u8 {
+    pub fn read_tile(&self, mem: &Memory, x: u8, y: u8) -> u8 {
         // TODO: Need to handle windowing and tilemapping
 
         let map = 0x00;
         match map {
-            0x00 => self.mem_read(0x9800 + x as u16 + (y as u16) * 32),
-            0x01 => self.mem_read(0x9C00 + x as u16 + (y as u16) * 32),
+            0x00 => mem.dbg_read(0x9800 + x as u16 + (y as u16) * 32),
+            0x01 => mem.dbg_read(0x9C00 + x as u16 + (y as u16) * 32),
             _ => panic!("Invalid tile map value used!"),
         }
     }
 
-    fn with_mem_mut<R>(&self, f: impl FnOnce(&mut Memory) -> R) -> R {
-        let mut mem = self.mem.borrow_mut();
-        f(&mut mem)
-    }
-
-    fn with_mem<R>(&self, f: impl FnOnce(&Memory) -> R) -> R {
-        let mem = self.mem.borrow();
-        f(&mem)
-    }
-
-    pub fn mem_read_oa(&self, addr: u16) -> Oa {
-        let x = self.with_mem(|mem| mem.dbg_read(addr));
-        let y = self.with_mem(|mem| mem.dbg_read(addr));
-        let index = self.with_mem(|mem| mem.dbg_read(addr));
-        let attr = self.with_mem(|mem| mem.dbg_read(addr));
+    pub fn mem_read_oa(mem: &Memory, addr: u16) -> Oa {
+        let x = mem.dbg_read(addr);
+        let y = mem.dbg_read(addr);
+        let index = mem.dbg_read(addr);
+        let attr = mem.dbg_read(addr);
         Oa {
             x,
             y,
@@ -429,73 +566,44 @@ impl Ppu {
         }
     }
 
-    pub fn set_oam_busy(&self, oam_busy: bool) {
-        self.with_mem_mut(|mem| mem.set_oam_busy(oam_busy))
-    }
-
-    pub fn set_vram_busy(&self, vram_busy: bool) {
-        self.with_mem_mut(|mem| mem.set_vram_busy(vram_busy))
-    }
-
-    pub fn mem_read(&self, addr: u16) -> u8 {
-        self.with_mem(|mem| mem.dbg_read(addr))
-    }
-
-    pub fn mem_read_16(&self, addr: u16) -> [u8; 16] {
-        self.with_mem(|mem| mem.dbg_read_16(addr))
-    }
-
-    pub fn mem_write(&mut self, addr: u16, data: u8) {
-        self.with_mem_mut(|mem| mem.dbg_write(addr, data));
-    }
-
-    pub fn own(&mut self, own: bool) {
-        let owner = if own { Comp::Ppu } else { Comp::None };
-        self.with_mem_mut(|mem| mem.set_owner(owner))
-    }
-
     pub fn set_mode(&mut self, mode: u8) {
-        let mut stat = self.mem_read(STAT);
-        stat = (stat & 0xFC) | (mode & 0x3);
-        self.mem_write(STAT, stat);
+        self.stat = (self.stat & 0xFC) | (mode & 0x3);
     }
 
     pub fn mode(&self) -> u8 {
-        self.mem_read(STAT) & 0x3
+        self.stat & 0x3
     }
 
     pub fn ly(&self) -> u8 {
-        self.mem_read(0xFF44)
+        self.ly
     }
 
     pub fn set_ly(&mut self, ly: u8) {
-        self.mem_write(0xFF44, ly)
+        self.ly = ly
     }
 
     pub fn lyc(&self) -> u8 {
-        self.mem_read(0xFF45)
+        self.lyc
     }
 
     pub fn set_lyc(&mut self, lyc: u8) {
-        self.mem_write(LYC, lyc)
+        self.lyc = lyc
     }
 
     pub fn stat(&self) -> u8 {
-        self.mem_read(STAT)
+        self.stat
     }
 
     pub fn update_stat(&mut self) {
-        let mut stat = self.mem_read(STAT);
         if self.ly() == self.lyc() {
-            stat &= bit!(2);
+            self.stat &= bit!(2);
         } else {
-            stat &= !(bit!(2));
+            self.stat &= !(bit!(2));
         }
-        self.mem_write(STAT, stat);
     }
 
-    pub fn check_interrupt(&mut self) {
-        let stat = self.mem_read(STAT);
+    pub fn check_interrupt(&mut self, mem: &mut Memory) {
+        let stat = self.stat;
         let hit = match self.mode {
             Mode::M0 => isbitset!(stat, 2),
             Mode::M1 => isbitset!(stat, 3),
@@ -505,23 +613,23 @@ impl Ppu {
         if hit {
             if !self.already_interrupted {
                 self.already_interrupted = true;
-                let mut reg_if = self.mem_read(IF);
+                let mut reg_if = mem.dbg_read(IF);
                 setbit!(reg_if, 2);
-                self.mem_write(IF, reg_if);
+                mem.dbg_write(IF, reg_if);
             }
         } else {
             self.already_interrupted = false;
         }
     }
 
-    pub fn fifo_pixel_fetcher(&mut self) {
+    pub fn fifo_pixel_fetcher(&mut self, mem: &Memory) {
         let x = self.x;
         let y = self.ly();
 
         match self.fetch_state {
             Fetch::Tile => {
-                let scx = self.mem_read(SCX);
-                let scy = self.mem_read(SCY);
+                let scx = self.scx;
+                let scy = self.scy;
 
                 let screen_x = x.wrapping_add(self.bg_fifo.len() as u8);
                 let bg_x = screen_x.wrapping_add(scx);
@@ -530,19 +638,19 @@ impl Ppu {
                 let tile_x = (bg_x / 8) % 32;
                 let tile_y = (bg_y / 8) % 32;
 
-                self.fetch_tile = self.read_tile(tile_x, tile_y);
+                self.fetch_tile = self.read_tile(mem, tile_x, tile_y);
             }
             Fetch::DataLo => {
-                let scy = self.mem_read(SCY);
+                let scy = self.scy;
                 let tile_row = y.wrapping_add(scy) % 8;
                 let addr = self.tile_address_lo(false, self.fetch_tile, tile_row);
-                self.fetch_tile_datalo = self.mem_read(addr);
+                self.fetch_tile_datalo = mem.dbg_read(addr);
             }
             Fetch::DataHi => {
-                let scy = self.mem_read(SCY);
+                let scy = self.scy;
                 let tile_row = y.wrapping_add(scy) % 8;
                 let addr = self.tile_address_lo(false, self.fetch_tile, tile_row) + 1;
-                self.fetch_tile_datahi = self.mem_read(addr);
+                self.fetch_tile_datahi = mem.dbg_read(addr);
             }
             Fetch::Push => {
                 if self.bg_fifo.len() > 8 {
@@ -577,7 +685,7 @@ impl Ppu {
     }
 
     pub fn palette_decode(&mut self, id: u8) -> u8 {
-        let bgp = self.mem_read(BGP);
+        let bgp = self.bgp;
         match id {
             0x0 => bgp & 0x3,
             0x1 => (bgp >> 2) & 0x3,
@@ -586,4 +694,101 @@ impl Ppu {
             _ => unreachable!("invalid palette index"),
         }
     }
+
+    /// Restarts the current line from LY after the registers were replaced
+    /// from outside
+    pub fn sync(&mut self, mem: &mut Memory) {
+        self.x = 0;
+        self.objects.clear();
+        self.reset_fetch_pipeline();
+        self.lcd_was_enabled = isbitset!(self.lcdc, 7);
+        self.already_interrupted = false;
+        let vblank = self.ly() >= 144;
+        (self.mode, self.dot) = if vblank {
+            (Mode::M1, 456)
+        } else {
+            (Mode::M2, 80)
+        };
+        mem.set_oam_busy(!vblank);
+        mem.set_vram_busy(false);
+    }
+
+    /// Saves the PPU as of `t`, counting the dots it slept through
+    pub fn save(&self, out: &mut Writer, t: u128) {
+        out.u32(self.objects.len() as u32);
+        for oa in &self.objects {
+            out.array(&[oa.y, oa.x, oa.index, oa.cgb_palette]);
+            for flag in [oa.priority, oa.yflip, oa.xflip, oa.dmg_palette, oa.bank] {
+                out.bool(flag);
+            }
+        }
+        for fifo in [&self.bg_fifo, &self.obj_fifo] {
+            out.u32(fifo.len() as u32);
+            for pixel in fifo {
+                out.array(&[pixel.color, pixel.palette, pixel.bg_priority]);
+            }
+        }
+        out.u8(self.x);
+        out.usize(self.testing);
+        out.bytes(&self.back_buffer);
+        out.bytes(&self.shades);
+        out.u8(self.mode.bits());
+        out.u16(self.dot_at(t));
+        out.u16(self.dotlimit);
+        out.u8(self.fetch_state as u8);
+        out.array(&[
+            self.fetch_tile,
+            self.fetch_tile_datalo,
+            self.fetch_tile_datahi,
+        ]);
+        out.bool(self.lcd_was_enabled);
+        out.bool(self.already_interrupted);
+    }
+
+    pub fn load(&mut self, input: &mut Reader) -> Result<(), String> {
+        self.objects.clear();
+        for _ in 0..input.u32()? {
+            let [y, x, index, cgb_palette] = input.array()?;
+            self.objects.push(Oa {
+                y,
+                x,
+                index,
+                cgb_palette,
+                priority: input.bool()?,
+                yflip: input.bool()?,
+                xflip: input.bool()?,
+                dmg_palette: input.bool()?,
+                bank: input.bool()?,
+            });
+        }
+        for fifo in [&mut self.bg_fifo, &mut self.obj_fifo] {
+            fifo.clear();
+            for _ in 0..input.u32()? {
+                let [color, palette, bg_priority] = input.array()?;
+                fifo.push(Pixel {
+                    color,
+                    palette,
+                    bg_priority,
+                });
+            }
+        }
+        self.x = input.u8()?;
+        self.testing = input.usize()?;
+        input.bytes_into(&mut self.back_buffer)?;
+        input.bytes_into(&mut self.shades)?;
+        self.mode = *MODES.get(input.u8()? as usize).ok_or("invalid PPU mode")?;
+        self.dot = input.u16()?;
+        self.dotlimit = input.u16()?;
+        self.fetch_state = *Fetch::ALL
+            .get(input.u8()? as usize)
+            .ok_or("invalid fetcher state")?;
+        [
+            self.fetch_tile,
+            self.fetch_tile_datalo,
+            self.fetch_tile_datahi,
+        ] = input.array()?;
+        self.lcd_was_enabled = input.bool()?;
+        self.already_interrupted = input.bool()?;
+        Ok(())
+    }
 }
@@ -0,0 +1,283 @@
+use crate::emu::gb::FRAME_CYCLES;
+use crate::emu::state;
+use std::collections::VecDeque;
+
+/// Memory kept for snapshots unless configured otherwise
+pub const DEFAULT_BUDGET: usize = 32 << 20;
+
+/// Frames between snapshots unless configured otherwise
+pub const DEFAULT_INTERVAL: u32 = 4;
+
+/// Snapshots in a group, the first is stored whole and the rest as the XOR
+/// against it, which is mostly zeros
+const GROUP_LEN: usize = 30;
+
+/// A keyframe and the deltas against it, evicted together
+struct Group {
+    keyframe: Vec<u8>,
+    deltas: Vec<Vec<u8>>,
+}
+
+impl Group {
+    fn size(&self) -> usize {
+        self.keyframe.len() + self.deltas.iter().map(Vec::len).sum::<usize>()
+    }
+}
+
+/// A ring buffer of compressed save states taken every few frames, the
+/// oldest are dropped to stay within a memory budget
+pub struct Rewind {
+    budget: usize,
+    interval: u32,
+    groups: VecDeque<Group>,
+    /// Bytes used by `groups`
+    size: usize,
+    /// The newest keyframe uncompressed, while new deltas can be taken
+    /// against it
+    base: Option<Vec<u8>>,
+    /// When the next snapshot is due
+    next: u128,
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Self::new(DEFAULT_BUDGET)
+    }
+}
+
+impl Rewind {
+    /// Keeps snapshots in at most `budget` bytes
+    pub fn new(budget: usize) -> Self {
+        Rewind {
+            budget,
+            interval: DEFAULT_INTERVAL,
+            groups: VecDeque::new(),
+            size: 0,
+            base: None,
+            next: 0,
+        }
+    }
+
+    /// Takes a snapshot every `frames` frames
+    pub fn every(mut self, frames: u32) -> Self {
+        self.interval = frames.max(1);
+        self
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    pub fn interval(&self) -> u32 {
+        self.interval
+    }
+
+    /// The number of snapshots kept
+    pub fn len(&self) -> usize {
+        self.groups.iter().map(|g| 1 + g.deltas.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Bytes in use, the compressed snapshots and the current keyframe
+    pub fn size(&self) -> usize {
+        self.size + self.base.as_ref().map_or(0, Vec::len)
+    }
+
+    pub fn clear(&mut self) {
+        self.groups.clear();
+        self.size = 0;
+        self.base = None;
+    }
+
+    /// Whether a snapshot should be taken at `t`
+    pub fn due(&self, t: u128) -> bool {
+        t >= self.next
+    }
+
+    /// Waits a whole interval from `t` before the next snapshot
+    pub fn schedule(&mut self, t: u128) {
+        self.next = t + self.interval as u128 * FRAME_CYCLES;
+    }
+
+    /// Stores a snapshot taken at `t`, dropping the oldest ones when over
+    /// the budget
+    pub fn push(&mut self, t: u128, state: &[u8]) {
+        self.schedule(t);
+        let group = self
+            .groups
+            .back_mut()
+            .filter(|g| g.deltas.len() + 1 < GROUP_LEN);
+        match (group, &self.base) {
+            (Some(group), Some(base)) => {
+                let delta = compress(&delta(state, base));
+                self.size += delta.len();
+                group.deltas.push(delta);
+            }
+            _ => {
+                let keyframe = compress(state);
+                self.size += keyframe.len();
+                self.groups.push_back(Group {
+                    keyframe,
+                    deltas: Vec::new(),
+                });
+                self.base = Some(state.to_vec());
+            }
+        }
+
+        while self.size() > self.budget && self.groups.len() > 1 {
+            let group = self.groups.pop_front().unwrap();
+            self.size -= group.size();
+        }
+    }
+
+    /// Removes the newest snapshot and returns it uncompressed
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let group = self.groups.back_mut()?;
+        match group.deltas.pop() {
+            Some(delta) => {
+                self.size -= delta.len();
+                let base = match &self.base {
+                    Some(base) => base,
+                    None => self.base.insert(decompress(&group.keyframe)),
+                };
+                Some(apply(&decompress(&delta), base))
+            }
+            None => {
+                let group = self.groups.pop_back()?;
+                self.size -= group.size();
+                // Deltas are only added to a group while its keyframe is at hand
+                self.base = None;
+                Some(decompress(&group.keyframe))
+            }
+        }
+    }
+}
+
+/// `state` XOR `base` section by section, so a section that grew doesn't
+/// shift the rest of the state out of line
+fn delta(state: &[u8], base: &[u8]) -> Vec<u8> {
+    let base = chunks(base);
+    chunks(state)
+        .iter()
+        .enumerate()
+        .flat_map(|(i, chunk)| xor(chunk, base.get(i).copied().unwrap_or_default()))
+        .collect()
+}
+
+/// Undoes `delta`, a chunk's length comes from the section header before it
+fn apply(delta: &[u8], base: &[u8]) -> Vec<u8> {
+    let base = chunks(base);
+    let mut out = Vec::with_capacity(delta.len());
+    let mut pos = 0;
+    for i in 0.. {
+        if pos >= delta.len() {
+            break;
+        }
+        let len = match i {
+            0 => state::HEADER_LEN,
+            _ if i % 2 == 1 => 8,
+            _ => u32::from_le_bytes(out[out.len() - 4..].try_into().unwrap()) as usize,
+        };
+        let end = (pos + len).min(delta.len());
+        out.extend(xor(
+            &delta[pos..end],
+            base.get(i).copied().unwrap_or_default(),
+        ));
+        pos = end;
+    }
+    out
+}
+
+/// Splits a save state into the header, then the tag and length, and data
+/// of each section
+fn chunks(state: &[u8]) -> Vec<&[u8]> {
+    let mut pos = state::HEADER_LEN.min(state.len());
+    let mut chunks = vec![&state[..pos]];
+    while pos < state.len() {
+        let data = (pos + 8).min(state.len());
+        chunks.push(&state[pos..data]);
+        let len = match state[pos..data].get(4..8) {
+            Some(len) => u32::from_le_bytes(len.try_into().unwrap()) as usize,
+            None => 0,
+        };
+        pos = data.saturating_add(len).min(state.len());
+        chunks.push(&state[data..pos]);
+    }
+    chunks
+}
+
+/// `data` XOR `base`, as long as `data` with `base` padded by zeros
+fn xor(data: &[u8], base: &[u8]) -> Vec<u8> {
+    data.iter()
+        .enumerate()
+        .map(|(i, b)| b ^ base.get(i).copied().unwrap_or(0))
+        .collect()
+}
+
+/// Runs shorter than this are cheaper to keep among the literals
+const MIN_ZERO_RUN: usize = 4;
+
+/// Encodes `data` as `zeros, literals, literal bytes` groups with the
+/// lengths as LEB128, zero runs make up most of deltas and memory
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let zeros = data[i..].iter().take_while(|b| **b == 0).count();
+        let start = i + zeros;
+        let mut end = start;
+        while end < data.len() {
+            let run = data[end..]
+                .iter()
+                .take(MIN_ZERO_RUN)
+                .take_while(|b| **b == 0);
+            if run.count() == MIN_ZERO_RUN {
+                break;
+            }
+            end += 1;
+        }
+        varint(&mut out, zeros);
+        varint(&mut out, end - start);
+        out.extend_from_slice(&data[start..end]);
+        i = end;
+    }
+    out
+}
+
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let zeros = read_varint(data, &mut i);
+        let literals = read_varint(data, &mut i);
+        out.resize(out.len() + zeros, 0);
+        out.extend_from_slice(&data[i..i + literals]);
+        i += literals;
+    }
+    out
+}
+
+fn varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], i: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let b = data[*i];
+        *i += 1;
+        value |= ((b & 0x7F) as usize) << shift;
+        if b & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
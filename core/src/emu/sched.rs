@@ -0,0 +1,39 @@
+use crate::emu::gb::Comp;
+
+/// Not due until woken
+pub const NEVER: u128 = u128::MAX;
+
+const COMPS: usize = 6;
+
+/// When each component next has work to do. A component schedules its next
+/// event at the end of each tick and isn't ticked before it, catching up on
+/// the cycles it slept through when it runs again. Writes to a component's
+/// registers wake it early
+#[derive(Default)]
+pub struct Scheduler {
+    next: [u128; COMPS],
+}
+
+impl Scheduler {
+    pub fn schedule(&mut self, comp: Comp, t: u128) {
+        self.next[comp as usize] = t;
+    }
+
+    /// Makes `comp` due right away
+    pub fn wake(&mut self, comp: Comp) {
+        self.next[comp as usize] = 0;
+    }
+
+    pub fn wake_all(&mut self) {
+        self.next = [0; COMPS];
+    }
+
+    pub fn due(&self, comp: Comp, t: u128) -> bool {
+        t >= self.next[comp as usize]
+    }
+
+    /// When `comp` is next due
+    pub fn next(&self, comp: Comp) -> u128 {
+        self.next[comp as usize]
+    }
+}
@@ -1,77 +1,81 @@
 use crate::emu::gb::Comp;
 use crate::emu::mem::Memory;
-use crate::{clearbit, isbitset};
-use std::cell::RefCell;
-use std::rc::Rc;
+use crate::emu::sched::NEVER;
+use crate::emu::state::{Reader, Writer};
+use crate::emu::watch::Access;
+use crate::{clearbit, isbitset, log};
 
 pub const SB: u16 = 0xFF01;
 pub const SC: u16 = 0xFF02;
 
 pub struct Serial {
-    mem: Rc<RefCell<Memory>>,
+    sb: u8,
+    sc: u8,
     pub buf: Vec<u8>,
 }
 
 impl Serial {
-    pub fn init_dmg(mem: Rc<RefCell<Memory>>) -> Self {
-        let sb = 0x00;
-        let sc = 0x00;
-        let mut serial = Serial {
-            mem,
+    pub fn init_dmg() -> Self {
+        Serial {
+            sb: 0x00,
+            sc: 0x00,
             buf: Vec::new(),
-        };
+        }
+    }
 
-        serial.mem_write(SB, sb);
-        serial.mem_write(SC, sc);
-        serial
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            SB => self.sb,
+            _ => self.sc,
+        }
     }
 
-    pub fn tick(&mut self, t: u128) {
-        self.own(true);
+    pub fn write(&mut self, addr: u16, data: u8) {
+        self.poke(addr, data);
+    }
 
-        if t.is_multiple_of(4) {
-            self.transfer();
+    pub fn poke(&mut self, addr: u16, data: u8) {
+        match addr {
+            SB => self.sb = data,
+            _ => self.sc = data,
         }
+    }
 
-        self.own(false);
+    /// Runs T-cycle `t`, returning when the serial port next has work to do
+    pub fn tick(&mut self, t: u128, mem: &mut Memory) -> u128 {
+        // Sleeps until SC is written, a transfer starts on the next M-cycle
+        if t.is_multiple_of(4) {
+            self.transfer(mem);
+            NEVER
+        } else {
+            t.next_multiple_of(4)
+        }
     }
 
-    fn transfer(&mut self) {
-        let mut sc = self.mem_read(SC);
-        let sb = self.mem_read(SB);
+    fn transfer(&mut self, mem: &mut Memory) {
+        let mut sc = self.sc;
 
         // TODO: actually do timed serial transfers
         if isbitset!(sc, 7) {
-            self.buf.push(sb);
+            log!(Serial, Debug, "Transferred {:02X}", self.sb);
+            self.buf.push(self.sb);
         }
 
         clearbit!(sc, 7);
-        self.mem_write(SC, sc);
+        mem.observe(Access::Write, SC, sc, Comp::Serial);
+        self.sc = sc;
     }
 
     pub fn buffmt(&self) -> String {
         String::from_utf8_lossy(&self.buf).to_string()
     }
 
-    fn with_mem_mut<R>(&self, f: impl FnOnce(&mut Memory) -> R) -> R {
-        let mut mem = self.mem.borrow_mut();
-        f(&mut mem)
-    }
-
-    fn with_mem<R>(&self, f: impl FnOnce(&Memory) -> R) -> R {
-        let mem = self.mem.borrow();
-        f(&mem)
-    }
-
-    pub fn mem_read(&self, addr: u16) -> u8 {
-        self.with_mem(|mem| mem.dbg_read(addr))
-    }
-    pub fn mem_write(&mut self, addr: u16, data: u8) {
-        self.with_mem_mut(|mem| mem.dbg_write(addr, data));
+    pub fn save(&self, out: &mut Writer) {
+        out.bytes(&self.buf);
     }
 
-    pub fn own(&mut self, own: bool) {
-        let owner = if own { Comp::Serial } else { Comp::None };
-        self.with_mem_mut(|mem| mem.set_owner(owner))
+    pub fn load(&mut self, input: &mut Reader) -> Result<(), String> {
+        self.buf = input.bytes()?.to_vec();
+        Ok(())
     }
 }
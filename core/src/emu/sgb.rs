@@ -0,0 +1,732 @@
+use crate::emu::state::{Reader, Writer};
+use crate::isbitset;
+
+pub const SGB_SCREEN_WIDTH: usize = 256;
+pub const SGB_SCREEN_HEIGHT: usize = 224;
+
+const GB_WIDTH: usize = 160;
+const GB_HEIGHT: usize = 144;
+const GB_X: usize = 48;
+const GB_Y: usize = 40;
+const ATTR_WIDTH: usize = GB_WIDTH / 8;
+const ATTR_HEIGHT: usize = GB_HEIGHT / 8;
+const ATTR_FILES: usize = 45;
+const ATTR_FILE_BYTES: usize = 90;
+const PACKET_BYTES: usize = 16;
+const PACKET_BITS: usize = PACKET_BYTES * 8;
+const TRANSFER_BYTES: usize = 0x1000;
+const SYSTEM_PALETTES: usize = 512;
+const BORDER_TILES: usize = 256;
+const BORDER_TILE_BYTES: usize = 32;
+const BORDER_MAP_WIDTH: usize = 32;
+const BORDER_MAP_HEIGHT: usize = 28;
+
+// Default colours shown until the game uploads its own palettes
+const DEFAULT_PALETTE: [u16; 4] = [0x67BF, 0x265B, 0x10B5, 0x2866];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SgbCommand {
+    Pal01,
+    Pal23,
+    Pal03,
+    Pal12,
+    AttrBlk,
+    AttrLin,
+    AttrDiv,
+    AttrChr,
+    PalSet,
+    PalTrn,
+    MltReq,
+    ChrTrn,
+    PctTrn,
+    AttrTrn,
+    AttrSet,
+    MaskEn,
+    Unsupported(u8),
+}
+
+impl SgbCommand {
+    pub fn from(cmd: u8) -> Self {
+        match cmd {
+            0x00 => SgbCommand::Pal01,
+            0x01 => SgbCommand::Pal23,
+            0x02 => SgbCommand::Pal03,
+            0x03 => SgbCommand::Pal12,
+            0x04 => SgbCommand::AttrBlk,
+            0x05 => SgbCommand::AttrLin,
+            0x06 => SgbCommand::AttrDiv,
+            0x07 => SgbCommand::AttrChr,
+            0x0A => SgbCommand::PalSet,
+            0x0B => SgbCommand::PalTrn,
+            0x11 => SgbCommand::MltReq,
+            0x13 => SgbCommand::ChrTrn,
+            0x14 => SgbCommand::PctTrn,
+            0x15 => SgbCommand::AttrTrn,
+            0x16 => SgbCommand::AttrSet,
+            0x17 => SgbCommand::MaskEn,
+            x => SgbCommand::Unsupported(x),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SgbMask {
+    Cancel,
+    Freeze,
+    Black,
+    Color0,
+}
+
+impl SgbMask {
+    pub fn from(mask: u8) -> Self {
+        match mask & 0x3 {
+            0 => SgbMask::Cancel,
+            1 => SgbMask::Freeze,
+            2 => SgbMask::Black,
+            3 => SgbMask::Color0,
+            _ => unreachable!(),
+        }
+    }
+}
+
+pub struct Sgb {
+    // Packet receiver
+    receiving: bool,
+    armed: bool,
+    bit: usize,
+    packet: [u8; PACKET_BYTES],
+    packets: Vec<[u8; PACKET_BYTES]>,
+    last_select: u8,
+
+    // Multiplayer
+    players: u8,
+    player: u8,
+
+    // Display state
+    palettes: [[u16; 4]; 4],
+    system_palettes: Vec<[u16; 4]>,
+    attrs: [u8; ATTR_WIDTH * ATTR_HEIGHT],
+    attr_files: Vec<u8>,
+    border_tiles: Vec<u8>,
+    border_map: Vec<u16>,
+    border_palettes: [[u16; 16]; 4],
+    mask: SgbMask,
+    frozen: Vec<u8>,
+}
+
+impl Default for Sgb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sgb {
+    pub fn new() -> Self {
+        Sgb {
+            receiving: false,
+            armed: false,
+            bit: 0,
+            packet: [0; PACKET_BYTES],
+            packets: Vec::new(),
+            last_select: 0x30,
+            players: 1,
+            player: 0,
+            palettes: [DEFAULT_PALETTE; 4],
+            system_palettes: vec![DEFAULT_PALETTE; SYSTEM_PALETTES],
+            attrs: [0; ATTR_WIDTH * ATTR_HEIGHT],
+            attr_files: vec![0; ATTR_FILES * ATTR_FILE_BYTES],
+            border_tiles: vec![0; BORDER_TILES * BORDER_TILE_BYTES],
+            border_map: vec![0; BORDER_MAP_WIDTH * BORDER_MAP_HEIGHT],
+            border_palettes: [[0; 16]; 4],
+            mask: SgbMask::Cancel,
+            frozen: vec![0; GB_WIDTH * GB_HEIGHT],
+        }
+    }
+
+    // {{{ P1 packet decoding
+    /// Feeds the P14/P15 select bits of a CPU write to P1 into the packet
+    /// receiver. `mem` is the full address space and `lcdc` the LCD control
+    /// register, used by the VRAM transfer commands.
+    pub fn write_p1(&mut self, select: u8, mem: &[u8], lcdc: u8) {
+        let select = select & 0x30;
+        let previous = self.last_select;
+        self.last_select = select;
+
+        if self.players > 1 && !isbitset!(previous, 5) && isbitset!(select, 5) {
+            self.player = (self.player + 1) % self.players;
+        }
+
+        match select {
+            0x00 => {
+                // Reset pulse starts a new packet
+                self.receiving = true;
+                self.armed = false;
+                self.bit = 0;
+                self.packet = [0; PACKET_BYTES];
+            }
+            0x30 => self.armed = true,
+            0x10 | 0x20 => {
+                if !self.receiving || !self.armed {
+                    return;
+                }
+                self.armed = false;
+                let one = select == 0x10;
+
+                if self.bit < PACKET_BITS {
+                    if one {
+                        self.packet[self.bit / 8] |= 1 << (self.bit % 8);
+                    }
+                    self.bit += 1;
+                    return;
+                }
+
+                // Stop bit, which must be a zero
+                self.receiving = false;
+                if !one {
+                    self.receive_packet(mem, lcdc);
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn receive_packet(&mut self, mem: &[u8], lcdc: u8) {
+        self.packets.push(self.packet);
+        let length = (self.packets[0][0] & 0x07).max(1) as usize;
+        if self.packets.len() < length {
+            return;
+        }
+
+        let data: Vec<u8> = self.packets.drain(..).flatten().collect();
+        self.execute(&data, mem, lcdc);
+    }
+
+    /// Returns the currently selected controller while multiplayer mode is
+    /// active, which the joypad reports when neither P14 nor P15 are selected.
+    pub fn joypad_id(&self) -> Option<u8> {
+        if self.players > 1 {
+            Some(self.player)
+        } else {
+            None
+        }
+    }
+    // }}}
+
+    // {{{ Command execution
+    pub fn execute(&mut self, data: &[u8], mem: &[u8], lcdc: u8) {
+        match SgbCommand::from(data[0] >> 3) {
+            SgbCommand::Pal01 => self.pal_pair(data, 0, 1),
+            SgbCommand::Pal23 => self.pal_pair(data, 2, 3),
+            SgbCommand::Pal03 => self.pal_pair(data, 0, 3),
+            SgbCommand::Pal12 => self.pal_pair(data, 1, 2),
+            SgbCommand::AttrBlk => self.attr_blk(data),
+            SgbCommand::AttrLin => self.attr_lin(data),
+            SgbCommand::AttrDiv => self.attr_div(data),
+            SgbCommand::AttrChr => self.attr_chr(data),
+            SgbCommand::PalSet => self.pal_set(data),
+            SgbCommand::PalTrn => {
+                let vram = Sgb::vram_transfer(mem, lcdc);
+                for (i, palette) in self.system_palettes.iter_mut().enumerate() {
+                    for (c, color) in palette.iter_mut().enumerate() {
+                        *color = Sgb::color_at(&vram, i * 8 + c * 2);
+                    }
+                }
+            }
+            SgbCommand::MltReq => {
+                self.players = match data[1] & 0x3 {
+                    0x1 => 2,
+                    0x3 => 4,
+                    _ => 1,
+                };
+                self.player = 0;
+            }
+            SgbCommand::ChrTrn => {
+                let vram = Sgb::vram_transfer(mem, lcdc);
+                let start = (data[1] & 0x1) as usize * TRANSFER_BYTES;
+                self.border_tiles[start..start + TRANSFER_BYTES].copy_from_slice(&vram);
+            }
+            SgbCommand::PctTrn => {
+                let vram = Sgb::vram_transfer(mem, lcdc);
+                // Map entries use all 16 bits, bit 15 flips the tile vertically
+                for (i, entry) in self.border_map.iter_mut().enumerate() {
+                    *entry = u16::from_le_bytes([vram[i * 2], vram[i * 2 + 1]]);
+                }
+                for (p, palette) in self.border_palettes.iter_mut().enumerate() {
+                    for (c, color) in palette.iter_mut().enumerate() {
+                        *color = Sgb::color_at(&vram, 0x800 + p * 32 + c * 2);
+                    }
+                }
+            }
+            SgbCommand::AttrTrn => {
+                let vram = Sgb::vram_transfer(mem, lcdc);
+                let len = self.attr_files.len();
+                self.attr_files.copy_from_slice(&vram[..len]);
+            }
+            SgbCommand::AttrSet => {
+                self.apply_attr_file((data[1] & 0x3F) as usize);
+                if isbitset!(data[1], 6) {
+                    self.mask = SgbMask::Cancel;
+                }
+            }
+            SgbCommand::MaskEn => {
+                self.mask = SgbMask::from(data[1]);
+            }
+            SgbCommand::Unsupported(_) => (),
+        }
+    }
+
+    fn pal_pair(&mut self, data: &[u8], first: usize, second: usize) {
+        let color0 = Sgb::color_at(data, 1);
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color0;
+        }
+        for c in 1..4 {
+            self.palettes[first][c] = Sgb::color_at(data, 1 + c * 2);
+            self.palettes[second][c] = Sgb::color_at(data, 7 + c * 2);
+        }
+    }
+
+    fn attr_blk(&mut self, data: &[u8]) {
+        let count = (data[1] & 0x1F) as usize;
+        for set in data[2..].chunks_exact(6).take(count) {
+            let ctrl = set[0] & 0x7;
+            let inside = set[1] & 0x3;
+            let mut border = (set[1] >> 2) & 0x3;
+            let outside = (set[1] >> 4) & 0x3;
+            let (x1, y1) = (set[2] as usize & 0x1F, set[3] as usize & 0x1F);
+            let (x2, y2) = (set[4] as usize & 0x1F, set[5] as usize & 0x1F);
+
+            // A lone inside or outside block also colours its border
+            if ctrl == 0x1 {
+                border = inside;
+            } else if ctrl == 0x4 {
+                border = outside;
+            }
+
+            for y in 0..ATTR_HEIGHT {
+                for x in 0..ATTR_WIDTH {
+                    let within_x = (x1..=x2).contains(&x);
+                    let within_y = (y1..=y2).contains(&y);
+                    let on_edge =
+                        (within_x && (y == y1 || y == y2)) || (within_y && (x == x1 || x == x2));
+                    let pal = if on_edge {
+                        (ctrl & 0x2 != 0 || ctrl == 0x1 || ctrl == 0x4).then_some(border)
+                    } else if within_x && within_y {
+                        (ctrl & 0x1 != 0).then_some(inside)
+                    } else {
+                        (ctrl & 0x4 != 0).then_some(outside)
+                    };
+                    if let Some(pal) = pal {
+                        self.attrs[y * ATTR_WIDTH + x] = pal;
+                    }
+                }
+            }
+        }
+    }
+
+    fn attr_lin(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+        for &line in data[2..].iter().take(count) {
+            let index = (line & 0x1F) as usize;
+            let pal = (line >> 5) & 0x3;
+            if isbitset!(line, 7) {
+                if index < ATTR_HEIGHT {
+                    self.attrs[index * ATTR_WIDTH..(index + 1) * ATTR_WIDTH].fill(pal);
+                }
+            } else if index < ATTR_WIDTH {
+                for y in 0..ATTR_HEIGHT {
+                    self.attrs[y * ATTR_WIDTH + index] = pal;
+                }
+            }
+        }
+    }
+
+    fn attr_div(&mut self, data: &[u8]) {
+        let after = data[1] & 0x3;
+        let before = (data[1] >> 2) & 0x3;
+        let on_line = (data[1] >> 4) & 0x3;
+        let horizontal = isbitset!(data[1], 6);
+        let split = (data[2] & 0x1F) as usize;
+
+        for y in 0..ATTR_HEIGHT {
+            for x in 0..ATTR_WIDTH {
+                let pos = if horizontal { y } else { x };
+                self.attrs[y * ATTR_WIDTH + x] = match pos.cmp(&split) {
+                    std::cmp::Ordering::Less => before,
+                    std::cmp::Ordering::Equal => on_line,
+                    std::cmp::Ordering::Greater => after,
+                };
+            }
+        }
+    }
+
+    fn attr_chr(&mut self, data: &[u8]) {
+        let mut x = (data[1] & 0x1F) as usize;
+        let mut y = (data[2] & 0x1F) as usize;
+        let count = (u16::from_le_bytes([data[3], data[4]]) as usize).min(ATTR_WIDTH * ATTR_HEIGHT);
+        let vertical = data[5] & 0x1 != 0;
+
+        for i in 0..count {
+            let Some(&byte) = data.get(6 + i / 4) else {
+                break;
+            };
+            let pal = (byte >> (6 - (i % 4) * 2)) & 0x3;
+            if x < ATTR_WIDTH && y < ATTR_HEIGHT {
+                self.attrs[y * ATTR_WIDTH + x] = pal;
+            }
+
+            if vertical {
+                y += 1;
+                if y >= ATTR_HEIGHT {
+                    y = 0;
+                    x += 1;
+                }
+            } else {
+                x += 1;
+                if x >= ATTR_WIDTH {
+                    x = 0;
+                    y += 1;
+                }
+            }
+        }
+    }
+
+    fn pal_set(&mut self, data: &[u8]) {
+        for i in 0..4 {
+            let id = u16::from_le_bytes([data[1 + i * 2], data[2 + i * 2]]) as usize;
+            self.palettes[i] = self.system_palettes[id % SYSTEM_PALETTES];
+        }
+        // Colour 0 is shared and always comes from the first palette
+        let color0 = self.palettes[0][0];
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color0;
+        }
+
+        let flags = data[9];
+        if isbitset!(flags, 7) {
+            self.apply_attr_file((flags & 0x3F) as usize);
+        }
+        if isbitset!(flags, 6) {
+            self.mask = SgbMask::Cancel;
+        }
+    }
+
+    fn apply_attr_file(&mut self, file: usize) {
+        if file >= ATTR_FILES {
+            return;
+        }
+        let bytes = &self.attr_files[file * ATTR_FILE_BYTES..(file + 1) * ATTR_FILE_BYTES];
+        for (i, attr) in self.attrs.iter_mut().enumerate() {
+            *attr = (bytes[i / 4] >> (6 - (i % 4) * 2)) & 0x3;
+        }
+    }
+
+    /// Captures the 4 KiB block a *_TRN command sends over the screen: the
+    /// first 256 tiles of the background map, in the order they are displayed.
+    fn vram_transfer(mem: &[u8], lcdc: u8) -> Vec<u8> {
+        let map = if isbitset!(lcdc, 3) { 0x9C00 } else { 0x9800 };
+        let mut out = Vec::with_capacity(TRANSFER_BYTES);
+        for i in 0..TRANSFER_BYTES / 16 {
+            let id = mem[map + (i / ATTR_WIDTH) * 32 + (i % ATTR_WIDTH)];
+            let addr = if isbitset!(lcdc, 4) {
+                0x8000 + id as usize * 16
+            } else {
+                (0x9000 + (id as i8 as isize) * 16) as usize
+            };
+            out.extend_from_slice(&mem[addr..addr + 16]);
+        }
+        out
+    }
+
+    fn color_at(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([data[offset], data[offset + 1]]) & 0x7FFF
+    }
+    // }}}
+
+    // {{{ Frame composition
+    /// Composes the 256×224 SGB output from a 160×144 frame of DMG shades
+    /// (0..=3, after BGP), applying the per-region palettes and the border.
+    pub fn compose(&mut self, shades: &[u8]) -> Vec<u8> {
+        // A frozen mask keeps showing whatever was on screen when it went up
+        if self.mask != SgbMask::Freeze {
+            self.frozen.copy_from_slice(shades);
+        }
+
+        let backdrop = Sgb::rgba(self.palettes[0][0]);
+        let mut frame = vec![0; SGB_SCREEN_WIDTH * SGB_SCREEN_HEIGHT * 4];
+        for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
+            let (x, y) = (i % SGB_SCREEN_WIDTH, i / SGB_SCREEN_WIDTH);
+            let color = match self.border_pixel(x, y) {
+                Some(color) => Sgb::rgba(color),
+                None if (GB_X..GB_X + GB_WIDTH).contains(&x)
+                    && (GB_Y..GB_Y + GB_HEIGHT).contains(&y) =>
+                {
+                    self.game_pixel(x - GB_X, y - GB_Y)
+                }
+                None => backdrop,
+            };
+            pixel.copy_from_slice(&color);
+        }
+        frame
+    }
+
+    fn game_pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        match self.mask {
+            SgbMask::Black => [0x00, 0x00, 0x00, 0xFF],
+            SgbMask::Color0 => Sgb::rgba(self.palettes[0][0]),
+            SgbMask::Cancel | SgbMask::Freeze => {
+                let pal = self.attrs[(y / 8) * ATTR_WIDTH + x / 8] as usize;
+                let shade = self.frozen[y * GB_WIDTH + x] as usize & 0x3;
+                Sgb::rgba(self.palettes[pal][shade])
+            }
+        }
+    }
+
+    fn border_pixel(&self, x: usize, y: usize) -> Option<u16> {
+        let entry = self.border_map[(y / 8) * BORDER_MAP_WIDTH + x / 8];
+        let tile = (entry & 0xFF) as usize;
+        let pal = ((entry >> 10) & 0x3) as usize;
+        let col = if isbitset!(entry, 14) {
+            x % 8
+        } else {
+            7 - x % 8
+        };
+        let row = if isbitset!(entry, 15) {
+            7 - y % 8
+        } else {
+            y % 8
+        };
+
+        let data = &self.border_tiles[tile * BORDER_TILE_BYTES..(tile + 1) * BORDER_TILE_BYTES];
+        let index = (0..4).fold(0, |index, plane| {
+            let byte = data[(plane / 2) * 16 + row * 2 + (plane % 2)];
+            index | (((byte >> col) & 0x1) << plane)
+        }) as usize;
+
+        // Colour 0 is transparent
+        (index != 0).then(|| self.border_palettes[pal][index])
+    }
+
+    fn rgba(color: u16) -> [u8; 4] {
+        let expand = |c: u16| {
+            let c = (c & 0x1F) as u8;
+            (c << 3) | (c >> 2)
+        };
+        [expand(color), expand(color >> 5), expand(color >> 10), 0xFF]
+    }
+    // }}}
+
+    // {{{ Save states
+    pub fn save(&self, out: &mut Writer) {
+        out.bool(self.receiving);
+        out.bool(self.armed);
+        out.usize(self.bit);
+        out.array(&self.packet);
+        out.u32(self.packets.len() as u32);
+        for packet in &self.packets {
+            out.array(packet);
+        }
+        out.array(&[self.last_select, self.players, self.player]);
+        let colors = self
+            .palettes
+            .iter()
+            .chain(&self.system_palettes)
+            .flatten()
+            .chain(self.border_palettes.iter().flatten())
+            .chain(&self.border_map);
+        for color in colors {
+            out.u16(*color);
+        }
+        out.array(&self.attrs);
+        out.bytes(&self.attr_files);
+        out.bytes(&self.border_tiles);
+        out.u8(self.mask as u8);
+        out.bytes(&self.frozen);
+    }
+
+    pub fn load(&mut self, input: &mut Reader) -> Result<(), String> {
+        self.receiving = input.bool()?;
+        self.armed = input.bool()?;
+        self.bit = input.usize()?.min(PACKET_BITS);
+        self.packet = input.array()?;
+        self.packets.clear();
+        for _ in 0..input.u32()? {
+            self.packets.push(input.array()?);
+        }
+        [self.last_select, self.players, self.player] = input.array()?;
+        let colors = self
+            .palettes
+            .iter_mut()
+            .chain(&mut self.system_palettes)
+            .flatten()
+            .chain(self.border_palettes.iter_mut().flatten())
+            .chain(&mut self.border_map);
+        for color in colors {
+            *color = input.u16()?;
+        }
+        self.attrs = input.array()?;
+        input.bytes_into(&mut self.attr_files)?;
+        input.bytes_into(&mut self.border_tiles)?;
+        self.mask = SgbMask::from(input.u8()?);
+        input.bytes_into(&mut self.frozen)?;
+        Ok(())
+    }
+    // }}}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send_packet(sgb: &mut Sgb, packet: &[u8; PACKET_BYTES], mem: &[u8]) {
+        sgb.write_p1(0x00, mem, 0);
+        sgb.write_p1(0x30, mem, 0);
+        for i in 0..PACKET_BITS {
+            let one = packet[i / 8] & (1 << (i % 8)) != 0;
+            sgb.write_p1(if one { 0x10 } else { 0x20 }, mem, 0);
+            sgb.write_p1(0x30, mem, 0);
+        }
+        sgb.write_p1(0x20, mem, 0);
+        sgb.write_p1(0x30, mem, 0);
+    }
+
+    /// Memory that shows `data` as the 4 KiB block of a *_TRN command with
+    /// LCDC at 0: tile ids 0..=255 from $9000, 20 to a row of the map
+    fn transfer_mem(data: &[u8]) -> Vec<u8> {
+        let mut mem = vec![0u8; 0x10000];
+        for (i, tile) in data.chunks(16).enumerate() {
+            mem[0x9800 + (i / ATTR_WIDTH) * 32 + i % ATTR_WIDTH] = i as u8;
+            let addr = (0x9000 + (i as u8 as i8 as isize) * 16) as usize;
+            mem[addr..addr + 16].copy_from_slice(tile);
+        }
+        mem
+    }
+
+    fn trn_packet(cmd: u8, arg: u8) -> [u8; PACKET_BYTES] {
+        let mut packet = [0u8; PACKET_BYTES];
+        packet[0] = (cmd << 3) | 1;
+        packet[1] = arg;
+        packet
+    }
+
+    #[test]
+    fn sgb_pal01_packet_sets_palettes() {
+        let mem = vec![0u8; 0x10000];
+        let mut sgb = Sgb::new();
+        let mut packet = [0u8; PACKET_BYTES];
+        packet[0] = (0x00 << 3) | 1;
+        let colors: [u16; 7] = [0x7FFF, 0x0001, 0x0002, 0x0003, 0x0004, 0x0005, 0x0006];
+        for (i, color) in colors.iter().enumerate() {
+            packet[1 + i * 2..3 + i * 2].copy_from_slice(&color.to_le_bytes());
+        }
+
+        send_packet(&mut sgb, &packet, &mem);
+
+        assert_eq!(sgb.palettes[0], [0x7FFF, 0x0001, 0x0002, 0x0003]);
+        assert_eq!(sgb.palettes[1], [0x7FFF, 0x0004, 0x0005, 0x0006]);
+        assert_eq!(sgb.palettes[2][0], 0x7FFF);
+    }
+
+    #[test]
+    fn sgb_mlt_req_cycles_joypad_id() {
+        let mem = vec![0u8; 0x10000];
+        let mut sgb = Sgb::new();
+        let mut packet = [0u8; PACKET_BYTES];
+        packet[0] = (0x11 << 3) | 1;
+        packet[1] = 0x01;
+
+        send_packet(&mut sgb, &packet, &mem);
+        assert_eq!(sgb.joypad_id(), Some(0));
+
+        sgb.write_p1(0x10, &mem, 0);
+        sgb.write_p1(0x30, &mem, 0);
+        assert_eq!(sgb.joypad_id(), Some(1));
+    }
+
+    #[test]
+    fn sgb_attr_div_splits_screen() {
+        let mem = vec![0u8; 0x10000];
+        let mut sgb = Sgb::new();
+        let mut packet = [0u8; PACKET_BYTES];
+        packet[0] = (0x06 << 3) | 1;
+        packet[1] = 0x40 | (0x2 << 4) | (0x1 << 2) | 0x3;
+        packet[2] = 9;
+
+        send_packet(&mut sgb, &packet, &mem);
+
+        assert_eq!(sgb.attrs[0], 1);
+        assert_eq!(sgb.attrs[9 * ATTR_WIDTH], 2);
+        assert_eq!(sgb.attrs[17 * ATTR_WIDTH + 19], 3);
+    }
+
+    #[test]
+    fn sgb_compose_places_game_inside_border() {
+        let mut sgb = Sgb::new();
+        let shades = vec![3u8; GB_WIDTH * GB_HEIGHT];
+        let frame = sgb.compose(&shades);
+
+        assert_eq!(frame.len(), SGB_SCREEN_WIDTH * SGB_SCREEN_HEIGHT * 4);
+        let inside = (GB_Y * SGB_SCREEN_WIDTH + GB_X) * 4;
+        assert_eq!(frame[inside..inside + 4], Sgb::rgba(DEFAULT_PALETTE[3]));
+        assert_eq!(frame[0..4], Sgb::rgba(DEFAULT_PALETTE[0]));
+    }
+
+    #[test]
+    fn sgb_chr_trn_fills_either_half_of_the_tiles() {
+        let data: Vec<u8> = (0..TRANSFER_BYTES).map(|i| (i * 7) as u8).collect();
+        let mem = transfer_mem(&data);
+        let mut sgb = Sgb::new();
+
+        send_packet(&mut sgb, &trn_packet(0x13, 0x01), &mem);
+        assert!(sgb.border_tiles[..TRANSFER_BYTES].iter().all(|&b| b == 0));
+        assert_eq!(sgb.border_tiles[TRANSFER_BYTES..], data[..]);
+
+        send_packet(&mut sgb, &trn_packet(0x13, 0x00), &mem);
+        assert_eq!(sgb.border_tiles[..TRANSFER_BYTES], data[..]);
+    }
+
+    #[test]
+    fn sgb_pct_trn_draws_the_border() {
+        const RED: u16 = 0x001F;
+        const BLUE: u16 = 0x7C00;
+        let mut sgb = Sgb::new();
+
+        // Tile 1 has colour 5 in its top left pixel and colour 2 in its
+        // bottom right one
+        let mut tiles = vec![0u8; TRANSFER_BYTES];
+        let tile = &mut tiles[BORDER_TILE_BYTES..2 * BORDER_TILE_BYTES];
+        tile[0] = 0x80; // plane 0, row 0
+        tile[16] = 0x80; // plane 2, row 0
+        tile[7 * 2 + 1] = 0x01; // plane 1, row 7
+        send_packet(&mut sgb, &trn_packet(0x13, 0x00), &transfer_mem(&tiles));
+
+        let mut map = vec![0u8; TRANSFER_BYTES];
+        let mut entry = |tx: usize, ty: usize, value: u16| {
+            let i = (ty * BORDER_MAP_WIDTH + tx) * 2;
+            map[i..i + 2].copy_from_slice(&value.to_le_bytes());
+        };
+        entry(0, 0, (1 << 10) | 1);
+        // Flipped both ways
+        entry(1, 0, 0xC000 | (1 << 10) | 1);
+        // Over the top left corner of the game screen
+        entry(GB_X / 8, GB_Y / 8, (1 << 10) | 1);
+        let palette = 0x800 + BORDER_TILE_BYTES;
+        map[palette + 5 * 2..palette + 5 * 2 + 2].copy_from_slice(&RED.to_le_bytes());
+        map[palette + 2 * 2..palette + 2 * 2 + 2].copy_from_slice(&BLUE.to_le_bytes());
+        send_packet(&mut sgb, &trn_packet(0x14, 0x00), &transfer_mem(&map));
+
+        let frame = sgb.compose(&vec![3u8; GB_WIDTH * GB_HEIGHT]);
+        let pixel = |x: usize, y: usize| {
+            let i = (y * SGB_SCREEN_WIDTH + x) * 4;
+            [frame[i], frame[i + 1], frame[i + 2], frame[i + 3]]
+        };
+        assert_eq!(pixel(0, 0), Sgb::rgba(RED));
+        assert_eq!(pixel(7, 7), Sgb::rgba(BLUE));
+        assert_eq!(pixel(15, 7), Sgb::rgba(RED));
+        assert_eq!(pixel(8, 0), Sgb::rgba(BLUE));
+        // Colour 0 shows the backdrop, or the game inside the border
+        assert_eq!(pixel(1, 0), Sgb::rgba(DEFAULT_PALETTE[0]));
+        assert_eq!(pixel(GB_X, GB_Y), Sgb::rgba(RED));
+        assert_eq!(pixel(GB_X + 1, GB_Y), Sgb::rgba(DEFAULT_PALETTE[3]));
+    }
+}
@@ -0,0 +1,226 @@
+/// Identifies a gamezoea save state
+pub const MAGIC: &[u8; 4] = b"GZST";
+
+/// Bumped whenever a component changes what it saves, `Reader::version`
+/// lets loaders keep accepting older states
+pub const VERSION: u16 = 2;
+
+/// States older than this never shipped and aren't read
+const OLDEST_VERSION: u16 = 2;
+
+/// Bytes before the first section, `MAGIC`, version and ROM hash
+pub const HEADER_LEN: usize = 14;
+
+/// A save state is the header, `MAGIC`, `VERSION` and the ROM hash, then
+/// one `tag, length, data` section per component
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Default for Writer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Writer { buf: Vec::new() }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u128(&mut self, value: u128) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn usize(&mut self, value: usize) {
+        self.u64(value as u64);
+    }
+
+    /// Raw bytes of a fixed size the reader knows
+    pub fn array(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Bytes prefixed with their length
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.array(bytes);
+    }
+
+    pub fn section(&mut self, tag: &[u8; 4], f: impl FnOnce(&mut Writer)) {
+        let mut section = Writer::new();
+        f(&mut section);
+        self.array(tag);
+        self.bytes(&section.buf);
+    }
+}
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    version: u16,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8], version: u16) -> Self {
+        Reader {
+            data,
+            pos: 0,
+            version,
+        }
+    }
+
+    /// The format version the state was saved with
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.data.len()
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let bytes = self.take(N)?;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, String> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    pub fn u64(&mut self) -> Result<u64, String> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    pub fn u128(&mut self) -> Result<u128, String> {
+        self.array().map(u128::from_le_bytes)
+    }
+
+    pub fn usize(&mut self) -> Result<usize, String> {
+        usize::try_from(self.u64()?).map_err(|e| e.to_string())
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    /// Fills `out` from length prefixed bytes of exactly its size
+    pub fn bytes_into(&mut self, out: &mut [u8]) -> Result<(), String> {
+        let bytes = self.bytes()?;
+        if bytes.len() != out.len() {
+            return Err(format!(
+                "expected {} bytes, found {}",
+                out.len(),
+                bytes.len()
+            ));
+        }
+        out.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// The next `len` bytes as they are
+    pub fn slice(&mut self, len: usize) -> Result<&'a [u8], String> {
+        self.take(len)
+    }
+
+    /// Splits the remaining data into `(tag, data)` sections
+    pub fn sections(&mut self) -> Result<Vec<([u8; 4], Reader<'a>)>, String> {
+        let mut sections = Vec::new();
+        while !self.is_empty() {
+            let tag = self.array()?;
+            let data = self.bytes()?;
+            sections.push((tag, Reader::new(data, self.version)));
+        }
+        Ok(sections)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or("state is truncated")?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+}
+
+pub fn write_header(out: &mut Writer, rom_hash: u64) {
+    out.array(MAGIC);
+    out.u16(VERSION);
+    out.u64(rom_hash);
+}
+
+/// Checks the header of a state saved for the ROM with `rom_hash` and splits
+/// the rest into `(tag, data)` sections
+pub fn read(data: &[u8], rom_hash: u64) -> Result<Vec<([u8; 4], Reader<'_>)>, String> {
+    let mut input = Reader::new(data, VERSION);
+    if input.array::<4>().ok().as_ref() != Some(MAGIC) {
+        return Err("not a save state".to_string());
+    }
+    let version = input.u16()?;
+    if version > VERSION {
+        return Err(format!(
+            "state version {} is newer than the supported {}",
+            version, VERSION
+        ));
+    }
+    if version < OLDEST_VERSION {
+        return Err(format!("state version {} is no longer supported", version));
+    }
+    if input.u64()? != rom_hash {
+        return Err("state is for a different ROM".to_string());
+    }
+    input.version = version;
+    input.sections()
+}
+
+/// 64-bit FNV-1a
+pub fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Hash of the cartridge, so a state is only loaded for the ROM it came from
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    fnv1a(rom)
+}
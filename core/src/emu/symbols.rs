@@ -0,0 +1,139 @@
+use std::fs;
+use std::path::Path;
+
+/// A label from a `.sym` or `.map` file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub bank: usize,
+    pub addr: u16,
+    pub name: String,
+}
+
+/// Labels as written by rgblink and no$gmb, one `bank:addr label` per line
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    // Sorted by bank then address
+    symbols: Vec<Symbol>,
+}
+
+impl Symbols {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut symbols = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let invalid = || format!("line {}: expected bank:addr label", n + 1);
+            let (location, name) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            let (bank, addr) = location.split_once(':').ok_or_else(invalid)?;
+            symbols.push(Symbol {
+                bank: usize::from_str_radix(bank, 16).map_err(|_| invalid())?,
+                addr: u16::from_str_radix(addr, 16).map_err(|_| invalid())?,
+                name: name.trim().to_string(),
+            });
+        }
+        Ok(Self::sorted(symbols))
+    }
+
+    /// Reads the labels out of the `.map` file rgblink writes, where they
+    /// follow their section as `$addr = label` under a `TYPE bank #n:` line
+    pub fn parse_map(text: &str) -> Result<Self, String> {
+        let mut symbols = Vec::new();
+        let mut bank = None;
+        let mut parent = String::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if let Some((_, number)) = line.split_once(" bank #") {
+                let number = number.trim_end_matches(':');
+                let invalid = || format!("line {}: invalid bank {}", n + 1, number);
+                bank = Some(number.parse::<usize>().map_err(|_| invalid())?);
+                continue;
+            }
+            let Some((addr, name)) = line.strip_prefix('$').and_then(|l| l.split_once(" = "))
+            else {
+                continue;
+            };
+
+            let invalid = || format!("line {}: expected $addr = label in a bank", n + 1);
+            let name = name.split_whitespace().next().ok_or_else(invalid)?;
+            let name = if name.starts_with('.') {
+                format!("{}{}", parent, name)
+            } else {
+                parent = name.split('.').next().unwrap_or(name).to_string();
+                name.to_string()
+            };
+            symbols.push(Symbol {
+                bank: bank.ok_or_else(invalid)?,
+                addr: u16::from_str_radix(addr, 16).map_err(|_| invalid())?,
+                name,
+            });
+        }
+        Ok(Self::sorted(symbols))
+    }
+
+    /// Reads a `.map` file by its extension, or else a `.sym` file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let symbols = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("map") => Self::parse_map(&text),
+            _ => Self::parse(&text),
+        };
+        symbols.map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn sorted(mut symbols: Vec<Symbol>) -> Self {
+        symbols.sort_by_key(|s| (s.bank, s.addr));
+        Symbols { symbols }
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter()
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|s| s.name == name)
+    }
+
+    /// The label exactly at `addr` in `bank`
+    pub fn name(&self, bank: usize, addr: u16) -> Option<&str> {
+        self.symbols
+            .iter()
+            .find(|s| s.bank == bank && s.addr == addr)
+            .map(|s| s.name.as_str())
+    }
+
+    /// The closest label at or before `addr` in `bank` and the same memory
+    /// region, and the offset from it
+    pub fn nearest(&self, bank: usize, addr: u16) -> Option<(&Symbol, u16)> {
+        self.symbols
+            .iter()
+            .rev()
+            .find(|s| s.bank == bank && s.addr <= addr && region(s.addr) == region(addr))
+            .map(|s| (s, addr - s.addr))
+    }
+}
+
+fn region(addr: u16) -> u8 {
+    match addr {
+        0x0000..=0x3FFF => 0,
+        0x4000..=0x7FFF => 1,
+        0x8000..=0x9FFF => 2,
+        0xA000..=0xBFFF => 3,
+        0xC000..=0xDFFF => 4,
+        0xE000..=0xFDFF => 5,
+        0xFE00..=0xFEFF => 6,
+        0xFF00..=0xFF7F => 7,
+        0xFF80..=0xFFFE => 8,
+        0xFFFF => 9,
+    }
+}
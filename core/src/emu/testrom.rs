@@ -0,0 +1,278 @@
+use crate::emu::batch::Budget;
+use crate::emu::cpu::LD_B_B;
+use crate::emu::gb::{FRAME_CYCLES, Gameboy};
+use crate::emu::png;
+use crate::emu::ppu::{BLACK, DARK_GREY, LIGHT_GREY, WHITE};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Registers B, C, D, E, H and L of a passing mooneye-test-suite ROM
+pub const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+/// What blargg's memory protocol writes to $A001-$A003 once $A000 is valid
+const BLARGG_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+
+/// How a test ROM ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Pass,
+    Fail(String),
+    Timeout,
+}
+
+impl Verdict {
+    pub fn passed(&self) -> bool {
+        *self == Verdict::Pass
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Verdict::Pass => write!(f, "passed"),
+            Verdict::Fail(reason) => write!(f, "failed: {}", reason),
+            Verdict::Timeout => write!(f, "timed out"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    pub verdict: Verdict,
+    /// The T-cycle the machine stopped at
+    pub cycles: u128,
+    pub serial: String,
+}
+
+/// How a test ROM reports its result
+pub trait TestProtocol {
+    /// Looks at the machine after each instruction, returning the verdict
+    /// once the ROM has given one
+    fn check(&mut self, _gb: &mut Gameboy) -> Option<Verdict> {
+        None
+    }
+
+    /// Called when the ROM executes `LD B,B`, which `run` makes a software
+    /// breakpoint
+    fn soft_break(&mut self, _gb: &mut Gameboy) -> Option<Verdict> {
+        None
+    }
+
+    /// The verdict when the budget runs out without one
+    fn timeout(&mut self, _gb: &mut Gameboy) -> Verdict {
+        Verdict::Timeout
+    }
+}
+
+/// blargg's ROMs print their name, then "Passed" or "Failed" over serial
+pub struct BlarggSerial;
+
+impl TestProtocol for BlarggSerial {
+    fn check(&mut self, gb: &mut Gameboy) -> Option<Verdict> {
+        let text = gb.bus().serial.buffmt();
+        // Waits for the end of the line, which has the failed test number
+        if !text.ends_with('\n') {
+            return None;
+        }
+        if text.contains("Passed") {
+            Some(Verdict::Pass)
+        } else if text.contains("Failed") {
+            Some(Verdict::Fail(text.trim().to_string()))
+        } else {
+            None
+        }
+    }
+}
+
+/// blargg's ROMs without serial output, like dmg_sound and oam_bug, write
+/// their result code to $A000 and text from $A004 in cartridge RAM
+#[derive(Default)]
+pub struct BlarggMemory {
+    /// When the ROM asked for the reset button to be pressed
+    reset_at: Option<u128>,
+}
+
+impl TestProtocol for BlarggMemory {
+    fn check(&mut self, gb: &mut Gameboy) -> Option<Verdict> {
+        let signature = [0xA001, 0xA002, 0xA003].map(|addr| gb.mem_dbg_read(addr));
+        if signature != BLARGG_SIGNATURE {
+            return None;
+        }
+        match gb.mem_dbg_read(0xA000) {
+            0x80 => None,
+            // Reset is to be pressed after at least 100 ms
+            0x81 => {
+                let requested = *self.reset_at.get_or_insert(gb.t);
+                if gb.t >= requested + 6 * FRAME_CYCLES {
+                    self.reset_at = None;
+                    gb.reset();
+                }
+                None
+            }
+            0x00 => Some(Verdict::Pass),
+            code => {
+                let text: Vec<u8> = (0xA004..0xC000)
+                    .map(|addr| gb.mem_dbg_read(addr))
+                    .take_while(|&c| c != 0)
+                    .collect();
+                let text = String::from_utf8_lossy(&text);
+                Some(Verdict::Fail(format!("result {}: {}", code, text.trim())))
+            }
+        }
+    }
+}
+
+/// mooneye-test-suite ROMs execute `LD B,B` with the Fibonacci numbers in
+/// the registers when they pass, and $42 in them when they fail
+pub struct Mooneye;
+
+impl TestProtocol for Mooneye {
+    fn soft_break(&mut self, gb: &mut Gameboy) -> Option<Verdict> {
+        let cpu = &gb.cpu;
+        let regs = [cpu.b(), cpu.c(), cpu.d(), cpu.e(), cpu.h(), cpu.l()];
+        if regs == MOONEYE_PASS {
+            return Some(Verdict::Pass);
+        }
+        Some(Verdict::Fail(format!(
+            "B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X}",
+            regs[0], regs[1], regs[2], regs[3], regs[4], regs[5]
+        )))
+    }
+}
+
+/// Compares the screen with a reference image, like the acid tests have,
+/// when the ROM executes `LD B,B` or else when the budget runs out
+pub struct Screenshot {
+    width: usize,
+    height: usize,
+    shades: Vec<u8>,
+}
+
+impl Screenshot {
+    pub fn new(png: &[u8]) -> Result<Self, String> {
+        let (width, height, rgba) = png::decode(png)?;
+        Ok(Screenshot {
+            width,
+            height,
+            shades: rgba.chunks_exact(4).map(shade).collect(),
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let png = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Screenshot::new(&png).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn compare(&self, gb: &mut Gameboy) -> Verdict {
+        let (width, height, frame) = gb.bus_mut().frame();
+        if (width, height) != (self.width, self.height) {
+            return Verdict::Fail(format!(
+                "screen is {}x{}, reference is {}x{}",
+                width, height, self.width, self.height
+            ));
+        }
+        let mut wrong = frame
+            .chunks_exact(4)
+            .map(shade)
+            .zip(&self.shades)
+            .enumerate()
+            .filter(|(_, (shade, expected))| shade != *expected);
+        let Some((first, _)) = wrong.next() else {
+            return Verdict::Pass;
+        };
+        Verdict::Fail(format!(
+            "{} pixels differ, the first at {},{}",
+            wrong.count() + 1,
+            first % width,
+            first / width
+        ))
+    }
+}
+
+impl TestProtocol for Screenshot {
+    fn soft_break(&mut self, gb: &mut Gameboy) -> Option<Verdict> {
+        Some(self.compare(gb))
+    }
+
+    fn timeout(&mut self, gb: &mut Gameboy) -> Verdict {
+        self.compare(gb)
+    }
+}
+
+/// The DMG shade of a pixel, 0 being white, so references drawn in grey
+/// match the screen's green
+fn shade(pixel: &[u8]) -> u8 {
+    match [WHITE, LIGHT_GREY, DARK_GREY, BLACK]
+        .iter()
+        .position(|color| color[..] == *pixel)
+    {
+        Some(shade) => shade as u8,
+        None => {
+            let luma =
+                (299 * pixel[0] as u32 + 587 * pixel[1] as u32 + 114 * pixel[2] as u32) / 1000;
+            3 - ((luma + 42) / 85) as u8
+        }
+    }
+}
+
+/// Parses `blargg`, `blargg-mem`, `mooneye` or `screenshot:<reference.png>`
+pub fn protocol(name: &str) -> Result<Box<dyn TestProtocol + Send>, String> {
+    match name {
+        "blargg" => return Ok(Box::new(BlarggSerial)),
+        "blargg-mem" => return Ok(Box::new(BlarggMemory::default())),
+        "mooneye" => return Ok(Box::new(Mooneye)),
+        _ => {}
+    }
+    match name.strip_prefix("screenshot:") {
+        Some(path) => Ok(Box::new(Screenshot::load(Path::new(path))?)),
+        None => Err(format!(
+            "expected blargg, blargg-mem, mooneye or screenshot:<png>, got: {}",
+            name
+        )),
+    }
+}
+
+/// Reads a test ROM into a headless DMG
+pub fn open(path: &Path) -> Result<Gameboy, String> {
+    let rom = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(Gameboy::headless_dmg(&rom))
+}
+
+/// Runs the machine until the ROM gives a verdict by `protocol`, the CPU
+/// locks up or the budget runs out. Leaves `LD B,B` enabled as a software
+/// breakpoint
+pub fn run(gb: &mut Gameboy, protocol: &mut dyn TestProtocol, budget: Budget) -> TestResult {
+    gb.cpu.set_soft_break(LD_B_B, true);
+    gb.cpu.take_soft_break();
+    let end = gb.t + budget.cycles();
+    let verdict = loop {
+        if gb.t >= end {
+            break protocol.timeout(gb);
+        }
+        let retired = gb.cpu.retired();
+        gb.tick(1);
+        if gb.cpu.locked() {
+            break Verdict::Fail(format!(
+                "locked at ${:04X} (${:02X})",
+                gb.cpu.cur_pc(),
+                gb.cpu.ir()
+            ));
+        }
+        if retired == gb.cpu.retired() {
+            continue;
+        }
+        let verdict = gb
+            .cpu
+            .take_soft_break()
+            .and_then(|_| protocol.soft_break(gb));
+        if let Some(verdict) = verdict.or_else(|| protocol.check(gb)) {
+            break verdict;
+        }
+    };
+    TestResult {
+        verdict,
+        cycles: gb.t,
+        serial: gb.bus().serial.buffmt(),
+    }
+}
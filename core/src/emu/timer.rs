@@ -1,37 +1,175 @@
 use crate::emu::gb::Comp;
 use crate::emu::mem::Memory;
 use crate::emu::regs::*;
-use std::cell::RefCell;
-use std::rc::Rc;
+use crate::emu::state::{Reader, Writer};
+use crate::emu::watch::Access;
+use crate::log;
 
 pub struct Timer {
-    mem: Rc<RefCell<Memory>>,
     system_counter: u16,
+    div: u8,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    /// The CPU wrote DIV or TAC since the last tick
+    write_div: bool,
+    write_tac: bool,
+    /// TIMA was reloaded from TMA this M-cycle, CPU writes to it are lost
+    tima_overflow: bool,
     internal_tma: u8,
     prev_signal: bool,
     last_tac: u8,
     overflow_delay: u8,
+    /// The first cycle not ticked yet, the counter catches up from here
+    /// after sleeping
+    synced: u128,
 }
 
 impl Timer {
-    pub fn init_dmg(mem: Rc<RefCell<Memory>>) -> Self {
-        let tima = 0x00;
-        let internal_tma = 0x00;
-        let last_tac = 0xF8;
-
-        let mut timer = Timer {
-            mem,
+    pub fn init_dmg() -> Self {
+        Timer {
             system_counter: 0xAC << 6,
-            internal_tma,
+            div: 0x00,
+            tima: 0x00,
+            tma: 0x00,
+            tac: 0xF8,
+            write_div: false,
+            write_tac: false,
+            tima_overflow: false,
+            internal_tma: 0x00,
             prev_signal: false,
-            last_tac,
+            last_tac: 0xF8,
             overflow_delay: 0,
+            synced: 0,
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            DIV => self.div,
+            TIMA => self.tima,
+            TMA => self.tma,
+            _ => self.tac,
+        }
+    }
+
+    /// Writes a register as the CPU does, writing DIV clears it and TIMA
+    /// can't be written the M-cycle it is reloaded
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            DIV => {
+                self.div = 0;
+                self.write_div = true;
+            }
+            TIMA if self.tima_overflow => {}
+            TAC => {
+                self.tac = (self.tac & 0xF8) + (data & 0x07);
+                self.write_tac = true;
+            }
+            _ => self.poke(addr, data),
+        }
+    }
+
+    /// Sets a register as is
+    pub fn poke(&mut self, addr: u16, data: u8) {
+        match addr {
+            DIV => self.div = data,
+            TIMA => self.tima = data,
+            TMA => self.tma = data,
+            _ => self.tac = data,
+        }
+    }
+
+    /// Saves the timer as of `t`, counting the cycles it slept through
+    pub fn save(&self, out: &mut Writer, t: u128) {
+        let counter = self.counter_at(t);
+        let signal = if counter == self.system_counter {
+            self.prev_signal
+        } else {
+            self.timer_signal(counter, self.tac)
         };
+        out.u16(counter);
+        out.u8(self.internal_tma);
+        out.bool(signal);
+        out.u8(self.last_tac);
+        out.u8(self.overflow_delay);
+        out.bool(self.write_div);
+        out.bool(self.write_tac);
+        out.bool(self.tima_overflow);
+    }
+
+    /// Rebuilds the internal counter from DIV, TMA and TAC after they were
+    /// replaced from outside, the bits of the counter below DIV are lost
+    pub fn sync(&mut self) {
+        self.system_counter = (self.div as u16) << 6;
+        self.internal_tma = self.tma;
+        self.last_tac = self.tac;
+        self.prev_signal = self.timer_signal(self.system_counter, self.last_tac);
+        self.overflow_delay = 0;
+        self.write_div = false;
+        self.write_tac = false;
+        self.tima_overflow = false;
+    }
+
+    pub fn load(&mut self, input: &mut Reader) -> Result<(), String> {
+        self.system_counter = input.u16()?;
+        self.internal_tma = input.u8()?;
+        self.prev_signal = input.bool()?;
+        self.last_tac = input.u8()?;
+        self.overflow_delay = input.u8()?;
+        self.write_div = input.bool()?;
+        self.write_tac = input.bool()?;
+        self.tima_overflow = input.bool()?;
+        Ok(())
+    }
+
+    /// Carries on from `t` after the state was replaced
+    pub fn resume_from(&mut self, t: u128) {
+        self.synced = t;
+    }
+
+    /// The system counter at `t` had it been ticked all along, it counts
+    /// every fourth cycle
+    fn counter_at(&self, t: u128) -> u16 {
+        let ticks = t.div_ceil(4).saturating_sub(self.synced.div_ceil(4));
+        ((self.system_counter as u128 + ticks) & 0x3FFF) as u16
+    }
+
+    /// Counts the cycles slept through up to `t`
+    pub fn catch_up(&mut self, t: u128) {
+        let counter = self.counter_at(t);
+        if counter != self.system_counter {
+            self.system_counter = counter;
+            self.prev_signal = self.timer_signal(counter, self.tac);
+        }
+        self.synced = self.synced.max(t);
+    }
+
+    /// Keeps the counter still at `t`, as STOP halts the system clock
+    pub fn hold(&mut self, t: u128) {
+        self.catch_up(t);
+        self.synced = t + 1;
+    }
 
-        timer.mem_write(TIMA, tima);
-        timer.mem_write(TMA, internal_tma);
-        timer.mem_write(TAC, last_tac);
-        timer
+    /// The next cycle that changes DIV, increments TIMA or is part of an
+    /// overflow, nothing the timer does in between is visible
+    fn next_event(&self, t: u128) -> u128 {
+        if self.overflow_delay > 0 {
+            return t + 1;
+        }
+        let next_tick = (t + 1).next_multiple_of(4);
+        if self.tima_overflow {
+            return next_tick;
+        }
+        // DIV is the top 8 bits of the counter, TIMA counts the falling
+        // edges of the bit TAC selects
+        let period = if self.tac & 0x4 == 0 {
+            64
+        } else {
+            (Self::timer_bit_mask(self.tac) << 1).min(64)
+        };
+        let ticks = period - self.system_counter % period;
+        next_tick + (ticks as u128 - 1) * 4
     }
 
     fn timer_bit_mask(tac: u8) -> u16 {
@@ -51,21 +189,22 @@ impl Timer {
         (counter & Self::timer_bit_mask(tac)) != 0
     }
 
-    pub fn tick(&mut self, t: u128) {
-        self.own(true);
+    /// Runs T-cycle `t`, returning when the timer next has work to do
+    pub fn tick(&mut self, t: u128, mem: &mut Memory) -> u128 {
+        self.catch_up(t);
 
         if t.is_multiple_of(4) {
-            self.set_tima_overflow(false);
+            self.tima_overflow = false;
         }
-        self.internal_tma = self.mem_read(TMA);
+        self.internal_tma = self.tma;
         let mut overflowed = false;
 
         if self.overflow_delay > 0 {
             self.overflow_delay -= 1;
             if self.overflow_delay == 0 {
-                self.mem_write(TIMA, self.internal_tma);
-                self.mem_write(IF, self.mem_read(IF) | 0x4);
-                self.set_tima_overflow(true);
+                self.set(mem, TIMA, self.internal_tma);
+                mem.write_as(Comp::Timer, IF, mem.dbg_read(IF) | 0x4);
+                self.tima_overflow = true;
                 overflowed = true;
             }
         }
@@ -73,30 +212,29 @@ impl Timer {
         let mut prev_signal = self.prev_signal;
         let mut skip_counter_tick = false;
 
-        if self.check_write_div() {
-            let tac = self.mem_read(TAC);
-            let signal_before = self.timer_signal(self.system_counter, tac);
+        if std::mem::take(&mut self.write_div) {
+            let signal_before = self.timer_signal(self.system_counter, self.tac);
             self.system_counter = 0;
-            self.mem_write(DIV, (self.system_counter >> 6) as u8);
-            let signal_after = self.timer_signal(self.system_counter, tac);
+            self.set(mem, DIV, (self.system_counter >> 6) as u8);
+            let signal_after = self.timer_signal(self.system_counter, self.tac);
 
             if signal_before && !signal_after {
-                self.increment_tima();
+                self.increment_tima(mem);
             }
             prev_signal = signal_after;
             skip_counter_tick = true;
         }
 
-        if self.check_write_tac() {
+        if std::mem::take(&mut self.write_tac) {
             let old_tac = self.last_tac;
-            let new_tac = self.mem_read(TAC);
+            let new_tac = self.tac;
             self.last_tac = new_tac;
 
             let signal_before = self.timer_signal(self.system_counter, old_tac);
             let signal_after = self.timer_signal(self.system_counter, new_tac);
 
             if signal_before && !signal_after {
-                self.increment_tima();
+                self.increment_tima(mem);
             }
             prev_signal = signal_after;
         }
@@ -104,65 +242,43 @@ impl Timer {
         if t.is_multiple_of(4) && !skip_counter_tick {
             self.system_counter = (self.system_counter + 1) & 0x3FFF;
             let div = (self.system_counter >> 6) as u8;
-            self.mem_write(DIV, div);
-            //println!("SYSTEM_COUNTER:{:04X} DIV:{:02X}", self.system_counter, div);
+            self.set(mem, DIV, div);
+            log!(
+                Timer,
+                Trace,
+                "SYSTEM_COUNTER:{:04X} DIV:{:02X}",
+                self.system_counter,
+                div
+            );
             if !overflowed {
-                self.set_tima_overflow(false);
+                self.tima_overflow = false;
             }
         }
 
-        let new_signal = self.timer_signal(self.system_counter, self.mem_read(TAC));
+        let new_signal = self.timer_signal(self.system_counter, self.tac);
         if prev_signal && !new_signal {
-            self.increment_tima();
+            self.increment_tima(mem);
         }
 
         self.prev_signal = new_signal;
-        self.own(false);
+        self.synced = t + 1;
+        self.next_event(t)
     }
 
-    fn increment_tima(&mut self) {
-        let tima = self.mem_read(TIMA);
-        let (result, overflow) = tima.overflowing_add(1);
+    fn increment_tima(&mut self, mem: &mut Memory) {
+        let (result, overflow) = self.tima.overflowing_add(1);
 
         if overflow {
-            self.mem_write(TIMA, 0);
+            self.set(mem, TIMA, 0);
             self.overflow_delay = 4;
         } else {
-            self.mem_write(TIMA, result);
+            self.set(mem, TIMA, result);
         }
     }
 
-    fn with_mem_mut<R>(&self, f: impl FnOnce(&mut Memory) -> R) -> R {
-        let mut mem = self.mem.borrow_mut();
-        f(&mut mem)
-    }
-
-    fn with_mem<R>(&self, f: impl FnOnce(&Memory) -> R) -> R {
-        let mem = self.mem.borrow();
-        f(&mem)
-    }
-
-    pub fn mem_read(&self, addr: u16) -> u8 {
-        self.with_mem(|mem| mem.dbg_read(addr))
-    }
-    pub fn mem_write(&mut self, addr: u16, data: u8) {
-        self.with_mem_mut(|mem| mem.dbg_write(addr, data));
-    }
-
-    pub fn check_write_div(&mut self) -> bool {
-        self.with_mem_mut(|mem| mem.check_write_div())
-    }
-
-    pub fn check_write_tac(&mut self) -> bool {
-        self.with_mem_mut(|mem| mem.check_write_tac())
-    }
-
-    pub fn set_tima_overflow(&mut self, tima_overflow: bool) {
-        self.with_mem_mut(|mem| mem.set_tima_overflow(tima_overflow))
-    }
-
-    pub fn own(&mut self, own: bool) {
-        let owner = if own { Comp::Timer } else { Comp::None };
-        self.with_mem_mut(|mem| mem.set_owner(owner))
+    /// Sets a register as the timer, which watchpoints see
+    fn set(&mut self, mem: &mut Memory, addr: u16, data: u8) {
+        mem.observe(Access::Write, addr, data, Comp::Timer);
+        self.poke(addr, data);
     }
 }
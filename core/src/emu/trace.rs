@@ -0,0 +1,227 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+/// What gameboy-doctor expects every read of LY to return
+pub const DOCTOR_LY: u8 = 0x90;
+
+/// Matching lines shown before a divergence
+const DIFF_CONTEXT: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `A:.. F:.. B:.. C:.. D:.. E:.. H:.. L:.. SP:.. PC:.. PCMEM:..,..,..,..`
+    Doctor,
+    /// The doctor line followed by retired count, timer and interrupt
+    /// registers and the label of PC
+    Verbose,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    /// PC reaches the address
+    Pc(u16),
+    /// The number of instructions have run, or been traced when stopping
+    Instructions(u64),
+}
+
+impl Condition {
+    /// Parses `pc:<label or hex addr>` or an instruction count, labels that
+    /// read as hex like `cafe` winning over the number
+    pub fn parse(value: &str, label: impl Fn(&str) -> Option<u16>) -> Result<Self, String> {
+        match value.strip_prefix("pc:") {
+            Some(addr) => {
+                let digits = addr.trim_start_matches("0x").trim_start_matches('$');
+                label(addr)
+                    .or_else(|| u16::from_str_radix(digits, 16).ok())
+                    .map(Condition::Pc)
+                    .ok_or_else(|| format!("invalid address or unknown label: {}", addr))
+            }
+            None => value
+                .parse()
+                .map(Condition::Instructions)
+                .map_err(|_| format!("expected pc:<addr> or a count, got: {}", value)),
+        }
+    }
+}
+
+/// Writes one line per instruction with the CPU state before it runs
+pub struct Trace {
+    out: Box<dyn Write + Send>,
+    format: Format,
+    start: Option<Condition>,
+    stop: Option<Condition>,
+    active: bool,
+    done: bool,
+    instructions: u64,
+    lines: u64,
+}
+
+impl Trace {
+    pub fn new(out: impl Write + Send + 'static, format: Format) -> Self {
+        Trace {
+            out: Box::new(out),
+            format,
+            start: None,
+            stop: None,
+            active: true,
+            done: false,
+            instructions: 0,
+            lines: 0,
+        }
+    }
+
+    pub fn starting(mut self, condition: Condition) -> Self {
+        self.start = Some(condition);
+        self.active = false;
+        self
+    }
+
+    pub fn stopping(mut self, condition: Condition) -> Self {
+        self.stop = Some(condition);
+        self
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn lines(&self) -> u64 {
+        self.lines
+    }
+
+    /// Whether the stop condition was met
+    pub fn done(&self) -> bool {
+        self.done
+    }
+
+    /// Logs the instruction at `pc` with the line `line` builds, when the
+    /// start and stop conditions allow it
+    pub fn instruction(&mut self, pc: u16, line: impl FnOnce() -> String) {
+        let index = self.instructions;
+        self.instructions += 1;
+        if self.done {
+            return;
+        }
+
+        if !self.active {
+            self.active = match self.start {
+                Some(Condition::Pc(addr)) => pc == addr,
+                Some(Condition::Instructions(n)) => index >= n,
+                None => true,
+            };
+            if !self.active {
+                return;
+            }
+        }
+
+        self.done = match self.stop {
+            Some(Condition::Pc(addr)) => pc == addr,
+            Some(Condition::Instructions(n)) => self.lines >= n,
+            None => false,
+        };
+        if self.done {
+            let _ = self.out.flush();
+            return;
+        }
+
+        if let Err(err) = writeln!(self.out, "{}", line()) {
+            eprintln!("Trace stopped: {err}");
+            self.done = true;
+            return;
+        }
+        self.lines += 1;
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// The first line where two traces disagree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// 1-based line number
+    pub line: usize,
+    pub ours: Option<String>,
+    pub reference: Option<String>,
+    /// The matching lines right before it
+    pub context: Vec<String>,
+}
+
+impl Divergence {
+    /// The names of the `NAME:value` fields that differ
+    pub fn fields(&self) -> Vec<String> {
+        let (Some(ours), Some(reference)) = (&self.ours, &self.reference) else {
+            return Vec::new();
+        };
+        let fields = |line: &str| -> Vec<(String, String)> {
+            line.split_whitespace()
+                .filter_map(|field| field.split_once(':'))
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect()
+        };
+        let ours = fields(ours);
+        fields(reference)
+            .into_iter()
+            .filter(|field| !ours.contains(field))
+            .map(|(name, _)| name)
+            .collect()
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let end = "<end of trace>".to_string();
+        writeln!(f, "Traces diverge at line {}:", self.line)?;
+        for line in &self.context {
+            writeln!(f, "  {}", line)?;
+        }
+        writeln!(f, "- {}", self.reference.as_ref().unwrap_or(&end))?;
+        write!(f, "+ {}", self.ours.as_ref().unwrap_or(&end))?;
+        let fields = self.fields();
+        if !fields.is_empty() {
+            write!(f, "\nDiffering: {}", fields.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares a trace against a reference log, returning the first line
+/// that differs and the number of lines compared
+pub fn diff(
+    ours: impl BufRead,
+    reference: impl BufRead,
+) -> io::Result<(Option<Divergence>, usize)> {
+    let mut ours = ours.lines();
+    let mut reference = reference.lines();
+    let mut context = VecDeque::with_capacity(DIFF_CONTEXT);
+    let mut line = 0;
+    loop {
+        let a = ours.next().transpose()?;
+        let b = reference.next().transpose()?;
+        let (a, b) = match (a, b) {
+            (None, None) => return Ok((None, line)),
+            (a, b) => (
+                a.map(|l| l.trim_end().to_string()),
+                b.map(|l| l.trim_end().to_string()),
+            ),
+        };
+        line += 1;
+        if a != b {
+            return Ok((
+                Some(Divergence {
+                    line,
+                    ours: a,
+                    reference: b,
+                    context: context.into(),
+                }),
+                line,
+            ));
+        }
+        if context.len() == DIFF_CONTEXT {
+            context.pop_front();
+        }
+        context.extend(a);
+    }
+}
@@ -0,0 +1,151 @@
+use crate::emu::gb::Comp;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+/// A bus access that matched a watchpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hit {
+    pub id: usize,
+    pub access: Access,
+    pub addr: u16,
+    pub value: u8,
+    pub comp: Comp,
+}
+
+impl fmt::Display for Hit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "watch #{}: {:?} {:04X} = {:02X} by {:?}",
+            self.id, self.access, self.addr, self.value, self.comp
+        )
+    }
+}
+
+pub type WatchCallback = Box<dyn FnMut(&Hit) + Send>;
+
+pub enum Action {
+    /// Stops `Gameboy::step` with an `Event::Watch`
+    Stop,
+    Callback(WatchCallback),
+}
+
+pub struct Watchpoint {
+    pub range: RangeInclusive<u16>,
+    pub access: Vec<Access>,
+    pub comp: Option<Comp>,
+    pub value: Option<u8>,
+    pub action: Action,
+}
+
+impl Watchpoint {
+    pub fn new(range: RangeInclusive<u16>, access: &[Access]) -> Self {
+        Watchpoint {
+            range,
+            access: access.to_vec(),
+            comp: None,
+            value: None,
+            action: Action::Stop,
+        }
+    }
+
+    pub fn with_comp(mut self, comp: Comp) -> Self {
+        self.comp = Some(comp);
+        self
+    }
+
+    pub fn with_value(mut self, value: u8) -> Self {
+        self.value = Some(value);
+        self
+    }
+
+    pub fn with_callback(mut self, callback: impl FnMut(&Hit) + Send + 'static) -> Self {
+        self.action = Action::Callback(Box::new(callback));
+        self
+    }
+
+    fn matches(&self, access: Access, addr: u16, value: u8, comp: Comp) -> bool {
+        self.range.contains(&addr)
+            && self.access.contains(&access)
+            && self.comp.is_none_or(|c| c == comp)
+            && self.value.is_none_or(|v| v == value)
+    }
+}
+
+impl fmt::Debug for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        f.debug_struct("Watchpoint")
+            .field("range", &self.range)
+            .field("access", &self.access)
+            .field("comp", &self.comp)
+            .field("value", &self.value)
+            .finish()
+    }
+}
+
+#[derive(Default)]
+pub struct Watches {
+    next_id: usize,
+    points: Vec<(usize, Watchpoint)>,
+    pending: Option<Hit>,
+}
+
+impl Watches {
+    pub fn add(&mut self, watchpoint: Watchpoint) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.points.push((id, watchpoint));
+        id
+    }
+
+    pub fn remove(&mut self, id: usize) -> bool {
+        let len = self.points.len();
+        self.points.retain(|(i, _)| *i != id);
+        self.points.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.points.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(usize, Watchpoint)> {
+        self.points.iter()
+    }
+
+    pub fn check(&mut self, access: Access, addr: u16, value: u8, comp: Comp) {
+        for (id, watchpoint) in self.points.iter_mut() {
+            if !watchpoint.matches(access, addr, value, comp) {
+                continue;
+            }
+            let hit = Hit {
+                id: *id,
+                access,
+                addr,
+                value,
+                comp,
+            };
+            match &mut watchpoint.action {
+                // Only the first hit is kept until it is taken
+                Action::Stop => {
+                    self.pending.get_or_insert(hit);
+                }
+                Action::Callback(callback) => callback(&hit),
+            }
+        }
+    }
+
+    pub fn take_hit(&mut self) -> Option<Hit> {
+        self.pending.take()
+    }
+}
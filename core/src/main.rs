@@ -1,14 +1,61 @@
-use gamezoea::app::{control, window};
+use gamezoea::app::{control, dap::DapServer, debugger::Debugger, gdb::GdbStub, window};
+use gamezoea::emu::batch::{self, Budget, Manifest};
+use gamezoea::emu::cpu;
+use gamezoea::emu::disasm;
 use gamezoea::emu::gb::*;
+use gamezoea::emu::log;
+use gamezoea::emu::rewind::{self, Rewind};
+use gamezoea::emu::sgb::{SGB_SCREEN_HEIGHT, SGB_SCREEN_WIDTH};
+use gamezoea::emu::symbols::Symbols;
+use gamezoea::emu::testrom;
+use gamezoea::emu::trace::{self, Condition, Format, Trace};
 
-use std::{env, fs, process, sync::mpsc, thread};
+use std::{env, fs, io, process, sync::mpsc, thread};
 
 const DEFAULT_SCALE: u32 = 1;
 
+struct Args {
+    scale: u32,
+    rom: Option<std::path::PathBuf>,
+    steps: Option<u64>,
+    sgb: bool,
+    debug: bool,
+    gdb: Option<u16>,
+    dap: Option<u16>,
+    sym: Option<std::path::PathBuf>,
+    trace: Option<std::path::PathBuf>,
+    trace_start: Option<String>,
+    trace_stop: Option<String>,
+    trace_format: Format,
+    log: Option<String>,
+    rewind: Option<usize>,
+    rewind_every: u32,
+    speed: Speed,
+    soft_breaks: Vec<u8>,
+}
+
 fn main() {
-    let (scale, rom, steps) = parse_args();
+    if env::args().nth(1).as_deref() == Some("disasm") {
+        run_disasm();
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("trace-diff") {
+        run_trace_diff();
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("batch") {
+        run_batch();
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("test") {
+        run_test();
+        return;
+    }
 
-    let rom_path = match rom {
+    let args = parse_args();
+    configure_logging(args.log.as_deref());
+
+    let rom_path = match args.rom.clone() {
         Some(rom) => {
             eprintln!("Opening rom {:?}", rom.display());
             rom
@@ -47,22 +94,120 @@ fn main() {
     }
 
     let rom_data = rom_bytes.into_boxed_slice();
+    let symbols = load_symbols(args.sym.as_deref(), &rom_path);
+    let trace = open_trace(&args, &symbols);
+
+    if args.scale == 0 {
+        run_headless(rom_data, symbols, trace, args);
+        return;
+    }
+
+    run_windowed(rom_data, symbols, trace, args);
+}
 
-    if scale == 0 {
-        run_headless(rom_data, steps);
+/// `--log` wins over the environment, only warnings and errors show otherwise
+fn configure_logging(spec: Option<&str>) {
+    let Some(spec) = spec
+        .map(str::to_string)
+        .or_else(|| env::var(log::LOG_ENV).ok())
+    else {
         return;
+    };
+    if let Err(err) = log::configure(&spec) {
+        eprintln!("Invalid log filter: {err}");
+        usage();
+        process::exit(1);
+    }
+}
+
+fn open_trace(args: &Args, symbols: &Symbols) -> Option<Trace> {
+    let path = args.trace.as_ref()?;
+    let file = match fs::File::create(path) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("Failed to create trace {:?}: {err}", path.display());
+            process::exit(1);
+        }
+    };
+
+    let condition = |value: &str| {
+        Condition::parse(value, |name| symbols.lookup(name).map(|s| s.addr)).unwrap_or_else(|err| {
+            eprintln!("Invalid trace condition: {err}");
+            usage();
+            process::exit(1);
+        })
+    };
+    let mut trace = Trace::new(io::BufWriter::new(file), args.trace_format);
+    if let Some(start) = &args.trace_start {
+        trace = trace.starting(condition(start));
+    }
+    if let Some(stop) = &args.trace_stop {
+        trace = trace.stopping(condition(stop));
     }
+    Some(trace)
+}
+
+/// The rewind buffer from --rewind, on by default for the debuggers to go
+/// back in time with
+fn rewind_buffer(args: &Args) -> Option<Rewind> {
+    let budget = match args.rewind {
+        Some(0) => return None,
+        Some(budget) => budget,
+        None if args.debug || args.gdb.is_some() => rewind::DEFAULT_BUDGET,
+        None => return None,
+    };
+    Some(Rewind::new(budget).every(args.rewind_every))
+}
 
-    run_windowed(rom_data, scale);
+/// Loads `sym`, or the `.sym` or else `.map` file rgblink writes next to the
+/// ROM if there is one
+fn load_symbols(sym: Option<&std::path::Path>, rom_path: &std::path::Path) -> Symbols {
+    let path = match sym {
+        Some(path) => path.to_path_buf(),
+        None => {
+            let found = ["sym", "map"]
+                .map(|ext| rom_path.with_extension(ext))
+                .into_iter()
+                .find(|path| path.exists());
+            match found {
+                Some(path) => path,
+                None => return Symbols::default(),
+            }
+        }
+    };
+    match Symbols::load(&path) {
+        Ok(symbols) => {
+            eprintln!("Loaded {} symbols from {:?}", symbols.len(), path.display());
+            symbols
+        }
+        Err(err) => {
+            eprintln!("Failed to load symbols: {err}");
+            process::exit(1);
+        }
+    }
 }
 
-fn parse_args() -> (u32, Option<std::path::PathBuf>, Option<u64>) {
+fn parse_args() -> Args {
     let mut args = env::args();
     let _ = args.next();
 
     let mut scale = DEFAULT_SCALE;
     let mut path = None;
     let mut steps = None;
+    let mut sgb = false;
+    let mut debug = false;
+    let mut gdb = None;
+    let mut dap = None;
+    let mut sym = None;
+    let mut trace = None;
+    let mut trace_start = None;
+    let mut trace_stop = None;
+    let mut trace_format = Format::Doctor;
+    let mut log = None;
+    let mut rewind = None;
+    let mut rewind_every = rewind::DEFAULT_INTERVAL;
+    let mut speed = Speed::NORMAL;
+    let mut soft_breaks = Vec::new();
 
     while let Some(arg) = args.next() {
         match arg.as_str() {
@@ -134,6 +279,124 @@ fn parse_args() -> (u32, Option<std::path::PathBuf>, Option<u64>) {
                 });
             }
 
+            "--sym" => {
+                let value = args.next().unwrap_or_else(|| {
+                    eprintln!("Missing value for {arg}");
+                    usage();
+                    process::exit(1);
+                });
+                sym = Some(std::path::PathBuf::from(value));
+            }
+
+            "--log" => {
+                let value = args.next().unwrap_or_else(|| {
+                    eprintln!("Missing value for {arg}");
+                    usage();
+                    process::exit(1);
+                });
+                log = Some(value);
+            }
+
+            "--speed" => {
+                let value = args.next().unwrap_or_else(|| {
+                    eprintln!("Missing value for {arg}");
+                    usage();
+                    process::exit(1);
+                });
+
+                speed = value.parse().unwrap_or_else(|err| {
+                    eprintln!("{err}");
+                    usage();
+                    process::exit(1);
+                });
+            }
+
+            "--rewind" | "--rewind-every" => {
+                let value = args.next().unwrap_or_else(|| {
+                    eprintln!("Missing value for {arg}");
+                    usage();
+                    process::exit(1);
+                });
+
+                match (arg.as_str(), value.parse::<u32>()) {
+                    ("--rewind", Ok(mib)) => rewind = Some((mib as usize) << 20),
+                    (_, Ok(frames)) if frames > 0 => rewind_every = frames,
+                    _ => {
+                        eprintln!("Invalid {arg} value: {value}");
+                        usage();
+                        process::exit(1);
+                    }
+                }
+            }
+
+            "--trace" | "--trace-start" | "--trace-stop" | "--trace-format" => {
+                let value = args.next().unwrap_or_else(|| {
+                    eprintln!("Missing value for {arg}");
+                    usage();
+                    process::exit(1);
+                });
+
+                match arg.as_str() {
+                    "--trace" => trace = Some(std::path::PathBuf::from(value)),
+                    "--trace-start" => trace_start = Some(value),
+                    "--trace-stop" => trace_stop = Some(value),
+                    _ => {
+                        trace_format = match value.as_str() {
+                            "doctor" => Format::Doctor,
+                            "verbose" => Format::Verbose,
+                            _ => {
+                                eprintln!("Invalid trace format: {value}");
+                                usage();
+                                process::exit(1);
+                            }
+                        }
+                    }
+                }
+            }
+
+            "--sgb" => sgb = true,
+
+            "--debug" => debug = true,
+
+            "--gdb" | "--dap" => {
+                let value = args.next().unwrap_or_else(|| {
+                    eprintln!("Missing value for {arg}");
+                    usage();
+                    process::exit(1);
+                });
+
+                let port = match value.parse::<u16>() {
+                    Ok(port) => Some(port),
+                    Err(_) => {
+                        eprintln!("Invalid {arg} port: {value}");
+                        usage();
+                        process::exit(1);
+                    }
+                };
+                if arg == "--gdb" {
+                    gdb = port;
+                } else {
+                    dap = port;
+                }
+            }
+
+            "--soft-break" => {
+                let value = args.next().unwrap_or_else(|| {
+                    eprintln!("Missing value for {arg}");
+                    usage();
+                    process::exit(1);
+                });
+
+                match u8::from_str_radix(value.trim_start_matches('$'), 16) {
+                    Ok(opcode) if cpu::is_ld_self(opcode) => soft_breaks.push(opcode),
+                    _ => {
+                        eprintln!("Invalid {arg} opcode: {value}");
+                        usage();
+                        process::exit(1);
+                    }
+                }
+            }
+
             "--help" | "-h" => {
                 usage();
                 process::exit(0);
@@ -146,7 +409,25 @@ fn parse_args() -> (u32, Option<std::path::PathBuf>, Option<u64>) {
         }
     }
 
-    (scale, path, steps)
+    Args {
+        scale,
+        rom: path,
+        steps,
+        sgb,
+        debug,
+        gdb,
+        dap,
+        sym,
+        trace,
+        trace_start,
+        trace_stop,
+        trace_format,
+        log,
+        rewind,
+        rewind_every,
+        speed,
+        soft_breaks,
+    }
 }
 
 fn usage() {
@@ -156,15 +437,351 @@ fn usage() {
     );
     println!("                [--rom <rom.gb>]");
     println!("                [--steps <number of CPU cycles to run, 0 or omitted = run forever>]");
+    println!("                [--sgb (run as a Super Game Boy with borders and palettes)]");
+    println!("                [--sym <rom.sym|rom.map> (debugger labels, default <rom>.sym|map)]");
+    println!("                [--log <[target=]level,...> (cpu, ppu, mem, timer, serial, joypad)]");
+    println!("                [--speed <0.25..8 or uncapped> (hold Tab to fast-forward)]");
+    println!("                [--rewind <MiB> (snapshots to go back to with Backspace, 0 = off)]");
+    println!("                [--rewind-every <frames between snapshots, default 4>]");
+    println!("                [--trace <file> (log each instruction in gameboy-doctor format)]");
+    println!("                [--trace-start <pc:addr|n>] [--trace-stop <pc:addr|n>]");
+    println!("                [--trace-format <doctor|verbose>]");
+    println!("                [--debug (control the emulator from a debugger prompt on stdin)]");
+    println!("                [--gdb <port> (wait for a gdb remote connection on localhost)]");
+    println!("                [--dap <port> (wait for a DAP client, e.g. VS Code, on localhost)]");
+    println!("                [--soft-break <40|49|52|5B|64|6D|7F> (stop on LD B,B .. LD A,A, the");
+    println!("                 window pauses, 52 = LD D,D also logs BGB debug messages)]");
+    println!("       gamezoea disasm <rom.gb> [--bank <n>] [--from <hex addr>] [--count <n>]");
+    println!("                       [--sym <rom.sym|rom.map>]");
+    println!("       gamezoea trace-diff <trace.log> <reference.log>");
+    println!("       gamezoea batch <manifest.toml> [--threads <n>]");
+    println!(
+        "       gamezoea test <rom.gb> --protocol <blargg|blargg-mem|mooneye|screenshot:<png>> [--frames <n>]"
+    );
+}
+
+fn run_disasm() {
+    let mut args = env::args().skip(2);
+
+    let mut rom = None;
+    let mut bank = None;
+    let mut from = None;
+    let mut count = 32;
+    let mut sym = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bank" | "--from" | "--count" => {
+                let value = args.next().unwrap_or_else(|| {
+                    eprintln!("Missing value for {arg}");
+                    usage();
+                    process::exit(1);
+                });
+
+                let parsed = if arg == "--from" {
+                    let hex = value.trim_start_matches("0x").trim_start_matches('$');
+                    u16::from_str_radix(hex, 16).map(|addr| from = Some(addr))
+                } else if arg == "--bank" {
+                    value.parse::<usize>().map(|n| bank = Some(n))
+                } else {
+                    value.parse::<usize>().map(|n| count = n)
+                };
+
+                if parsed.is_err() {
+                    eprintln!("Invalid {arg} value: {value}");
+                    usage();
+                    process::exit(1);
+                }
+            }
+            "--sym" => {
+                let value = args.next().unwrap_or_else(|| {
+                    eprintln!("Missing value for {arg}");
+                    usage();
+                    process::exit(1);
+                });
+                sym = Some(std::path::PathBuf::from(value));
+            }
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(arg),
+            _ => {
+                eprintln!("Unknown argument: {arg}");
+                usage();
+                process::exit(1);
+            }
+        }
+    }
+
+    let Some(rom) = rom else {
+        eprintln!("No rom specified! Use gamezoea disasm <file>.gb");
+        process::exit(1);
+    };
+
+    let rom_bytes = match fs::read(&rom) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("Failed to read rom {:?}: {err}", rom);
+            process::exit(1);
+        }
+    };
+
+    // Bank 0 is fixed at $0000, any other bank is switched in at $4000
+    let from = match (from, bank) {
+        (Some(from), _) => from,
+        (None, None | Some(0)) => 0x0100,
+        (None, Some(_)) => 0x4000,
+    };
+    let bank = match bank {
+        Some(0) if from >= 0x4000 => {
+            eprintln!("Bank 0 is fixed at $0000-$3FFF, it can't be listed from ${from:04X}");
+            process::exit(1);
+        }
+        // Past $3FFF the MBC maps bank 1 when asked for bank 0
+        Some(0) | None => 1,
+        Some(bank) => bank,
+    };
+    let symbols = load_symbols(sym.as_deref(), std::path::Path::new(&rom));
+    for line in disasm::listing(&rom_bytes, bank, from, count, &symbols) {
+        println!("{}", line);
+    }
+}
+
+fn run_trace_diff() {
+    let paths: Vec<String> = env::args().skip(2).collect();
+    let [ours, reference] = &paths[..] else {
+        eprintln!("Expected a trace and a reference log");
+        usage();
+        process::exit(1);
+    };
+
+    let open = |path: &String| match fs::File::open(path) {
+        Ok(file) => io::BufReader::new(file),
+        Err(err) => {
+            eprintln!("Failed to open {:?}: {err}", path);
+            process::exit(1);
+        }
+    };
+    match trace::diff(open(ours), open(reference)) {
+        Ok((None, lines)) => println!("Traces match ({lines} lines)"),
+        Ok((Some(divergence), _)) => {
+            println!("{}", divergence);
+            process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("Failed to read traces: {err}");
+            process::exit(1);
+        }
+    }
+}
+
+fn run_batch() {
+    let mut args = env::args().skip(2);
+
+    let mut manifest = None;
+    let mut threads = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--threads" => {
+                let value = args.next().unwrap_or_else(|| {
+                    eprintln!("Missing value for {arg}");
+                    usage();
+                    process::exit(1);
+                });
+
+                threads = match value.parse::<usize>() {
+                    Ok(n) if n > 0 => Some(n),
+                    _ => {
+                        eprintln!("Invalid {arg} value: {value}");
+                        usage();
+                        process::exit(1);
+                    }
+                };
+            }
+            _ if manifest.is_none() && !arg.starts_with("--") => manifest = Some(arg),
+            _ => {
+                eprintln!("Unknown argument: {arg}");
+                usage();
+                process::exit(1);
+            }
+        }
+    }
+
+    let Some(manifest) = manifest else {
+        eprintln!("No manifest specified! Use gamezoea batch <manifest.toml>");
+        process::exit(1);
+    };
+
+    let manifest = Manifest::load(std::path::Path::new(&manifest)).unwrap_or_else(|err| {
+        eprintln!("Invalid manifest {err}");
+        process::exit(1);
+    });
+    let jobs = manifest.jobs().unwrap_or_else(|err| {
+        eprintln!("Failed to read rom {err}");
+        process::exit(1);
+    });
+    // One machine per core unless told otherwise
+    let threads = threads
+        .or(manifest.threads)
+        .or_else(|| thread::available_parallelism().ok().map(|n| n.get()))
+        .unwrap_or(1);
+
+    let outcomes = batch::run(jobs, threads);
+    let width = outcomes.iter().map(|o| o.name.len()).max().unwrap_or(0);
+    for outcome in &outcomes {
+        let regs = outcome.regs;
+        println!(
+            "{:<width$}  {:<9}  {:>11} cycles  screen:{:016X}  A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X}",
+            outcome.name,
+            outcome.status.to_string(),
+            outcome.cycles,
+            outcome.screen,
+            regs.a,
+            regs.f,
+            regs.b,
+            regs.c,
+            regs.d,
+            regs.e,
+            regs.h,
+            regs.l,
+            regs.sp,
+            outcome.pc,
+        );
+        if !outcome.serial.is_empty() {
+            println!(
+                "{:<width$}  serial: {:?}",
+                "",
+                String::from_utf8_lossy(&outcome.serial)
+            );
+        }
+    }
+    let ok = outcomes.iter().filter(|o| o.status.ok()).count();
+    println!("{} of {} ROMs ok", ok, outcomes.len());
+    if ok != outcomes.len() {
+        process::exit(1);
+    }
+}
+
+fn run_test() {
+    let mut args = env::args().skip(2);
+
+    let mut rom = None;
+    let mut protocol = None;
+    let mut frames = batch::DEFAULT_FRAMES;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--protocol" | "--frames" => {
+                let value = args.next().unwrap_or_else(|| {
+                    eprintln!("Missing value for {arg}");
+                    usage();
+                    process::exit(1);
+                });
+
+                if arg == "--protocol" {
+                    protocol = Some(testrom::protocol(&value).unwrap_or_else(|err| {
+                        eprintln!("Invalid {arg} value: {err}");
+                        process::exit(1);
+                    }));
+                } else {
+                    frames = value.parse().unwrap_or_else(|_| {
+                        eprintln!("Invalid {arg} value: {value}");
+                        usage();
+                        process::exit(1);
+                    });
+                }
+            }
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(arg),
+            _ => {
+                eprintln!("Unknown argument: {arg}");
+                usage();
+                process::exit(1);
+            }
+        }
+    }
+
+    let Some(rom) = rom else {
+        eprintln!("No ROM specified! Use gamezoea test <rom.gb> --protocol <protocol>");
+        process::exit(1);
+    };
+    let Some(mut protocol) = protocol else {
+        eprintln!(
+            "No protocol specified! Use --protocol blargg, blargg-mem, mooneye or screenshot:<png>"
+        );
+        process::exit(1);
+    };
+
+    let mut gb = testrom::open(std::path::Path::new(&rom)).unwrap_or_else(|err| {
+        eprintln!("Failed to read rom {err}");
+        process::exit(1);
+    });
+    let report = testrom::run(&mut gb, protocol.as_mut(), Budget::Frames(frames));
+    if !report.serial.is_empty() {
+        println!("serial: {:?}", report.serial);
+    }
+    println!("{} after {} cycles", report.verdict, report.cycles);
+    if !report.verdict.passed() {
+        process::exit(1);
+    }
 }
 
-fn run_headless(rom_data: Box<[u8]>, steps: Option<u64>) {
+/// Sets the machine up as the command line asks, for either frontend
+fn configure(gameboy: &mut Gameboy, args: &Args, symbols: Symbols, trace: Option<Trace>) {
+    gameboy.load_symbols(symbols);
+    if let Some(trace) = trace {
+        gameboy.set_trace(trace);
+    }
+    gameboy.set_rewind(rewind_buffer(args));
+    gameboy.set_speed(args.speed);
+    for &opcode in &args.soft_breaks {
+        gameboy.cpu.set_soft_break(opcode, true);
+    }
+    // Slot states and screenshots go next to the ROM
+    if let Some(rom) = &args.rom {
+        gameboy.set_file_base(rom.with_extension(""));
+    }
+}
+
+/// Hands the machine to the debugger, gdb or DAP client asked for until it
+/// is done, false when none was
+fn attach_debugger(gameboy: &mut Gameboy, args: &Args) -> bool {
+    if args.debug {
+        Debugger::new().run(gameboy, io::stdin().lock(), io::stdout());
+    } else if let Some(port) = args.gdb {
+        if let Err(err) = GdbStub::new().listen(gameboy, port) {
+            eprintln!("gdb error: {err}");
+        }
+    } else if let Some(port) = args.dap {
+        if let Err(err) = DapServer::new().listen(gameboy, port) {
+            eprintln!("Debug adapter error: {err}");
+        }
+    } else {
+        return false;
+    }
+    true
+}
+
+fn run_headless(rom_data: Box<[u8]>, symbols: Symbols, trace: Option<Trace>, args: Args) {
     let gameboy_thread = thread::spawn(move || {
-        let mut gameboy = Gameboy::headless_dmg(&rom_data);
-        match steps {
+        let mut gameboy = if args.sgb {
+            Gameboy::headless_sgb(&rom_data)
+        } else {
+            Gameboy::headless_dmg(&rom_data)
+        };
+        configure(&mut gameboy, &args, symbols, trace);
+        if attach_debugger(&mut gameboy, &args) {
+            return;
+        }
+        match args.steps {
             Some(n) => {
                 for _ in 0..n {
-                    gameboy.step(1);
+                    match gameboy.step(1) {
+                        // BGB only logs debug messages, without stopping
+                        Some(Event::Message { text, .. }) => eprintln!("{text}"),
+                        Some(event) => {
+                            eprintln!("Stopped: {:?}", event);
+                            break;
+                        }
+                        None => {}
+                    }
                 }
             }
             None => gameboy.run(None),
@@ -174,20 +791,34 @@ fn run_headless(rom_data: Box<[u8]>, steps: Option<u64>) {
     gameboy_thread.join().unwrap();
 }
 
-fn run_windowed(rom_data: Box<[u8]>, scale: u32) {
+fn run_windowed(rom_data: Box<[u8]>, symbols: Symbols, trace: Option<Trace>, args: Args) {
     let mut threads = vec![];
+    let dimensions = if args.sgb {
+        (SGB_SCREEN_WIDTH as u32, SGB_SCREEN_HEIGHT as u32)
+    } else {
+        (window::SCREEN_WIDTH, window::SCREEN_HEIGHT)
+    };
     let (frame_tx, frame_rx) = window::create_frame_channel();
     let (control_tx, control_rx) = mpsc::channel::<control::ControlMessage>();
 
+    let scale = args.scale;
     let window_thread = thread::spawn(move || {
-        if let Err(err) = window::run(scale, frame_rx, control_tx) {
+        if let Err(err) = window::run(scale, dimensions, frame_rx, control_tx) {
             eprintln!("Window error: {err}");
         }
     });
     threads.push(window_thread);
 
     let gameboy_thread = thread::spawn(move || {
-        let mut gameboy = Gameboy::dmg(&rom_data, frame_tx);
+        let mut gameboy = if args.sgb {
+            Gameboy::sgb(&rom_data, frame_tx)
+        } else {
+            Gameboy::dmg(&rom_data, frame_tx)
+        };
+        configure(&mut gameboy, &args, symbols, trace);
+        if attach_debugger(&mut gameboy, &args) {
+            return;
+        }
         gameboy.run(Some(control_rx));
     });
     threads.push(gameboy_thread);
@@ -0,0 +1,202 @@
+mod common;
+
+use gamezoea::emu::batch::*;
+use gamezoea::emu::gb::*;
+use macros::*;
+use std::fs;
+use std::path::Path;
+
+const ROM: &[u8] = gbasm! {r#"
+  ld a, $4F          ; O
+  ldh [$01], a
+  ld a, $81
+  ldh [$02], a
+  ld a, $4B          ; K
+  ldh [$01], a
+  ld a, $81
+  ldh [$02], a
+Loop:
+  jr Loop
+"#};
+
+const LOCKS: &[u8] = gbasm! {r#"
+  nop
+  db $D3
+"#};
+
+// {{{ test batch_run
+#[test]
+fn batch_run() {
+    let jobs = vec![
+        Job::new("serial", || Gameboy::headless_dmg(ROM))
+            .budget(Budget::Frames(10))
+            .until(Until::Serial("OK".to_string())),
+        Job::new("pc", || Gameboy::headless_dmg(ROM))
+            .budget(Budget::Frames(10))
+            .until(Until::Pc(0x0160)),
+        Job::new("ran", || Gameboy::headless_dmg(ROM)).budget(Budget::Cycles(10_000)),
+        Job::new("timed out", || Gameboy::headless_dmg(ROM))
+            .budget(Budget::Cycles(10_000))
+            .until(Until::Mooneye),
+    ];
+    let outcomes = run(jobs, 2);
+    let names: Vec<_> = outcomes.iter().map(|o| o.name.as_str()).collect();
+    assert_eq!(names, ["serial", "pc", "ran", "timed out"]);
+
+    assert_eq!(outcomes[0].status, Status::Done);
+    assert_eq!(outcomes[0].serial, b"OK");
+    assert_eq!(outcomes[1].status, Status::Done);
+    assert_eq!(outcomes[1].pc, 0x0160);
+    assert_eq!(outcomes[2].status, Status::Ran);
+    assert_eq!(outcomes[2].cycles, 10_000);
+    assert_eq!(outcomes[3].status, Status::TimedOut);
+    assert!(outcomes.iter().all(|o| o.regs.a == 0x81));
+}
+// }}}
+
+// {{{ test batch_run_matches_serial
+#[test]
+fn batch_run_matches_serial() {
+    let jobs = |n| {
+        (0..n)
+            .map(|i| {
+                Job::new(&i.to_string(), || Gameboy::headless_dmg(ROM)).budget(Budget::Frames(3))
+            })
+            .collect::<Vec<_>>()
+    };
+    let alone = run(jobs(1), 1);
+    for outcome in run(jobs(6), 4) {
+        assert_eq!(outcome.status, Status::Ran);
+        assert_eq!(outcome.cycles, alone[0].cycles);
+        assert_eq!(outcome.screen, alone[0].screen);
+    }
+}
+// }}}
+
+// {{{ test batch_failures
+#[test]
+fn batch_failures() {
+    let jobs = vec![
+        Job::new("locks", || Gameboy::headless_dmg(LOCKS)),
+        Job::new("panics", || panic!("unsupported cartridge")),
+    ];
+    let outcomes = run(jobs, 2);
+    assert_eq!(
+        outcomes[0].status,
+        Status::Locked {
+            pc: 0x0151,
+            opcode: 0xD3
+        }
+    );
+    assert_eq!(outcomes[0].status.to_string(), "locked at $0151 ($D3)");
+    assert_eq!(
+        outcomes[1].status,
+        Status::Panicked("unsupported cartridge".to_string())
+    );
+    assert!(!outcomes.iter().any(|o| o.status.ok()));
+}
+// }}}
+
+// {{{ test batch_until_parse
+#[test]
+fn batch_until_parse() {
+    assert!(matches!(Until::parse("mooneye"), Ok(Until::Mooneye)));
+    assert!(matches!(Until::parse("pc:$C000"), Ok(Until::Pc(0xC000))));
+    assert!(matches!(Until::parse("pc:0x150"), Ok(Until::Pc(0x0150))));
+    match Until::parse("serial:Passed") {
+        Ok(Until::Serial(text)) => assert_eq!(text, "Passed"),
+        _ => panic!("expected serial"),
+    }
+    assert!(Until::parse("pc:nope").is_err());
+    assert!(Until::parse("halt").is_err());
+}
+// }}}
+
+// {{{ test batch_manifest_parse
+#[test]
+fn batch_manifest_parse() {
+    let text = r#"
+threads = 3
+frames = 600  # defaults
+
+[[rom]]
+path = "blargg/cpu_instrs.gb"
+until = "serial:Passed"
+
+[[rom]]
+name = "sgb # border"
+path = "border.gb"
+sgb = true
+cycles = 0x1_000
+"#;
+    let manifest = Manifest::parse(text, Path::new("roms")).unwrap();
+    assert_eq!(manifest.threads, Some(3));
+    assert_eq!(
+        manifest.entries,
+        [
+            Entry {
+                name: "cpu_instrs".to_string(),
+                rom: Path::new("roms").join("blargg/cpu_instrs.gb"),
+                sgb: false,
+                budget: Budget::Frames(600),
+                until: Some("serial:Passed".to_string()),
+            },
+            Entry {
+                name: "sgb # border".to_string(),
+                rom: Path::new("roms").join("border.gb"),
+                sgb: true,
+                budget: Budget::Cycles(0x1000),
+                until: None,
+            },
+        ]
+    );
+
+    let manifest = Manifest::parse("[[rom]]\npath = \"a.gb\"", Path::new("")).unwrap();
+    assert_eq!(manifest.threads, None);
+    assert_eq!(manifest.entries[0].budget, Budget::Frames(DEFAULT_FRAMES));
+}
+// }}}
+
+// {{{ test batch_manifest_errors
+#[test]
+fn batch_manifest_errors() {
+    let error = |text: &str| Manifest::parse(text, Path::new("")).unwrap_err();
+    assert_eq!(error("path = \"a.gb\""), "1: path belongs in a [[rom]]");
+    assert_eq!(error("\n[[rom]]\nsgb = true"), "2: [[rom]] has no path");
+    assert_eq!(error("[[rom]]\npath = 1"), "2: invalid value for path");
+    assert_eq!(error("[[rom]]\nspeed = 2"), "2: unknown key speed");
+    assert_eq!(error("[rom]"), "1: unknown table [rom]");
+    assert_eq!(error("threads"), "1: expected key = value");
+    assert_eq!(error("threads = 0"), "1: invalid value for threads");
+    assert_eq!(error("name = \"a"), "1: unterminated string");
+    assert!(error("until = \"halt\"").starts_with("1: expected serial:"));
+}
+// }}}
+
+// {{{ test batch_manifest_jobs
+#[test]
+fn batch_manifest_jobs() {
+    let dir = common::temp_dir("batch-jobs");
+    fs::write(dir.join("ok.gb"), ROM).unwrap();
+    let path = dir.join("manifest.toml");
+    fs::write(
+        &path,
+        "frames = 10\n[[rom]]\npath = \"ok.gb\"\nuntil = \"serial:OK\"\n[[rom]]\npath = \"ok.gb\"\nname = \"sgb\"\nsgb = true\nuntil = \"serial:OK\"\n",
+    )
+    .unwrap();
+
+    let manifest = Manifest::load(&path).unwrap();
+    let outcomes = run(manifest.jobs().unwrap(), 2);
+    assert_eq!(outcomes[0].name, "ok");
+    assert_eq!(outcomes[1].name, "sgb");
+    assert!(outcomes.iter().all(|o| o.status == Status::Done));
+
+    fs::write(&path, "[[rom]]\npath = \"missing.gb\"\n").unwrap();
+    let manifest = Manifest::load(&path).unwrap();
+    assert!(manifest.jobs().is_err());
+    fs::write(&path, "[[rom]]\n").unwrap();
+    let error = Manifest::load(&path).unwrap_err();
+    assert!(error.ends_with("manifest.toml:1: [[rom]] has no path"));
+    fs::remove_dir_all(dir).unwrap();
+}
+// }}}
@@ -0,0 +1,182 @@
+use gamezoea::emu::bess::*;
+use gamezoea::emu::gb::*;
+use macros::*;
+
+const ROM: &[u8] = gbasm! {r#"
+  ld a, $42
+  ld [$C123], a
+  ld hl, $8010
+  ld [hl], $99
+  ld sp, $DFF0
+Loop:
+  inc b
+  jr Loop
+"#};
+
+/// A CORE block for a DMG, with every buffer stored before the blocks
+fn sameboy_state(model: &[u8; 4]) -> Vec<u8> {
+    let mut file = vec![0u8; 0x2000 + 0x2000 + 0xA0 + 0x7F];
+    file[0x0123] = 0x42; // $C123
+    file[0x2010] = 0x99; // $8010
+    let blocks = file.len() as u32;
+
+    let name = b"SameBoy v0.16";
+    file.extend(b"NAME");
+    file.extend((name.len() as u32).to_le_bytes());
+    file.extend(name);
+
+    file.extend(b"CORE");
+    file.extend(0xD0u32.to_le_bytes());
+    file.extend(1u16.to_le_bytes());
+    file.extend(1u16.to_le_bytes());
+    file.extend(model);
+    for r in [0x015Du16, 0x1280, 0x0300, 0x0000, 0x8010, 0xDFF0] {
+        file.extend(r.to_le_bytes());
+    }
+    file.extend([0x00, 0x00, 0x00, 0x00]);
+    let mut io = [0u8; 0x80];
+    io[0x40] = 0x91; // LCDC
+    io[0x44] = 0x10; // LY
+    file.extend(io);
+    let buffers = [
+        (0x2000, 0),
+        (0x2000, 0x2000),
+        (0, 0),
+        (0xA0, 0x4000),
+        (0x7F, 0x40A0),
+        (0, 0),
+        (0, 0),
+    ];
+    for (size, offset) in buffers {
+        file.extend((size as u32).to_le_bytes());
+        file.extend((offset as u32).to_le_bytes());
+    }
+
+    // Blocks we don't know are skipped
+    file.extend(b"XOAM");
+    file.extend(4u32.to_le_bytes());
+    file.extend([1, 2, 3, 4]);
+    file.extend(b"END ");
+    file.extend(0u32.to_le_bytes());
+    file.extend(blocks.to_le_bytes());
+    file.extend(b"BESS");
+    file
+}
+
+// {{{ test bess_read_foreign
+#[test]
+fn bess_read_foreign() {
+    let state = Bess::read(&sameboy_state(b"GDB ")).unwrap();
+    assert_eq!(state.name.as_deref(), Some("SameBoy v0.16"));
+    assert_eq!(state.core.model, Model::Dmg);
+    assert_eq!(state.core.pc, 0x015D);
+    assert_eq!(state.core.execution, Execution::Running);
+    assert_eq!(state.core.ram[0x0123], 0x42);
+    assert!(state.core.mbc_ram.is_empty());
+    assert_eq!(state.mbc, vec![]);
+
+    let mut gb = Gameboy::headless_dmg(ROM);
+    gb.import_bess(&sameboy_state(b"GDB ")).unwrap();
+    assert_eq!(gb.cpu.cur_pc(), 0x015D);
+    assert_eq!(
+        (gb.cpu.af(), gb.cpu.bc(), gb.cpu.sp()),
+        (0x1280, 0x0300, 0xDFF0)
+    );
+    assert_eq!(gb.mem_dbg_read(0xC123), 0x42);
+    assert_eq!(gb.mem_dbg_read(0xE123), 0x42);
+    assert_eq!(gb.mem_dbg_read(0x8010), 0x99);
+    gb.step(2);
+    assert_eq!(gb.cpu.b(), 0x04);
+
+    let err = Bess::read(&sameboy_state(b"CCE ")).unwrap_err();
+    assert_eq!(err, "Game Boy Color states are not supported");
+    let err = gb.import_bess(&sameboy_state(b"SN  ")).unwrap_err();
+    assert_eq!(err, "state is from a Super Game Boy");
+    assert_eq!(Bess::read(b"nonsense").unwrap_err(), "no BESS footer");
+}
+// }}}
+
+// {{{ test bess_round_trip
+#[test]
+fn bess_round_trip() {
+    let mut gb = Gameboy::headless_dmg(ROM);
+    gb.step(20);
+    let data = gb.export_bess().unwrap();
+    let state = Bess::read(&data).unwrap();
+    assert_eq!(
+        state.info.map(|info| info[0x10..].to_vec()),
+        Some(ROM[0x14E..0x150].to_vec())
+    );
+    assert_eq!(Bess::read(&state.write()).unwrap(), state);
+
+    let mut restored = Gameboy::headless_dmg(ROM);
+    restored.import_bess(&data).unwrap();
+    assert_eq!(restored.cpu.cur_pc(), gb.cpu.cur_pc());
+    assert_eq!(restored.cpu.af(), gb.cpu.af());
+    assert_eq!(restored.cpu.bc(), gb.cpu.bc());
+    assert_eq!(restored.cpu.hl(), gb.cpu.hl());
+    assert_eq!(restored.cpu.sp(), gb.cpu.sp());
+    for addr in (0x8000..=0xFFFF).step_by(7) {
+        assert_eq!(
+            restored.mem_dbg_read(addr),
+            gb.mem_dbg_read(addr),
+            "{:04X}",
+            addr
+        );
+    }
+    gb.step(10);
+    restored.step(10);
+    assert_eq!(restored.cpu.bc(), gb.cpu.bc());
+
+    let mut other = ROM.to_vec();
+    other[0x134] = b'X';
+    let err = Gameboy::headless_dmg(&other).import_bess(&data);
+    assert_eq!(err.unwrap_err(), "state is for a different ROM");
+}
+// }}}
+
+// {{{ test bess_cartridge_ram
+#[test]
+fn bess_cartridge_ram() {
+    // MBC1 with 8 KiB of RAM
+    let mut rom = ROM.to_vec();
+    rom.resize(0x8000, 0);
+    rom[0x147] = 0x03;
+    rom[0x149] = 0x02;
+
+    let mut gb = Gameboy::headless_dmg(&rom);
+    gb.mem_dbg_write(0xA000, 0x12);
+    gb.mem_dbg_write(0xBFFF, 0x34);
+    let data = gb.export_bess().unwrap();
+    let mut state = Bess::read(&data).unwrap();
+    assert_eq!(state.core.mbc_ram.len(), 0x2000);
+
+    let mut restored = Gameboy::headless_dmg(&rom);
+    restored.import_bess(&data).unwrap();
+    assert_eq!(restored.mem_dbg_read(0xA000), 0x12);
+    assert_eq!(restored.mem_dbg_read(0xBFFF), 0x34);
+
+    state.core.mbc_ram.truncate(0x800);
+    let err = restored.import_bess(&state.write()).unwrap_err();
+    assert_eq!(
+        err,
+        "state has 2048 bytes of cartridge RAM, the cartridge has 8192"
+    );
+
+    state.core.mbc_ram.resize(0x2000, 0);
+    state.rtc = Some(Rtc {
+        current: [0; 5],
+        latched: [0; 5],
+        timestamp: 0,
+    });
+    let err = restored.import_bess(&state.write()).unwrap_err();
+    assert_eq!(err, "state has an RTC, which the cartridge doesn't");
+
+    // Only the bank at $A000-$BFFF of larger RAM is kept
+    rom[0x149] = 0x03;
+    let mut gb = Gameboy::headless_dmg(&rom);
+    let err = "unsupported RAM size: 32 KiB, only 8 KiB of cartridge RAM is emulated";
+    assert_eq!(gb.export_bess().unwrap_err(), err);
+    assert_eq!(gb.import_bess(&data).unwrap_err(), err);
+}
+// }}}
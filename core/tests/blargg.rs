@@ -1,6 +1,10 @@
+use gamezoea::emu::batch::Budget;
 use gamezoea::emu::gb::*;
+use gamezoea::emu::testrom::{self, BlarggSerial};
 use macros::*;
 
+const BLARGG_BUDGET: Budget = Budget::Frames(3000);
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -11,8 +15,8 @@ mod tests {
         let result = "01-special";
         const ROM: &[u8] = gbrom!("tests/roms/blargg/cpu_instrs/individual/01-special.gb");
         let mut gb = Gameboy::headless_dmg(ROM);
-        gb.step_blargg(8000000, result);
-        assert_blargg!(gb.serial.buffmt(), result);
+        let report = testrom::run(&mut gb, &mut BlarggSerial, BLARGG_BUDGET);
+        assert_blargg!(report.serial, result);
     }
 
     #[test]
@@ -20,8 +24,8 @@ mod tests {
         let result = "02-interrupts";
         const ROM: &[u8] = gbrom!("tests/roms/blargg/cpu_instrs/individual/02-interrupts.gb");
         let mut gb = Gameboy::headless_dmg(ROM);
-        gb.step_blargg(8000000, result);
-        assert_blargg!(gb.serial.buffmt(), result);
+        let report = testrom::run(&mut gb, &mut BlarggSerial, BLARGG_BUDGET);
+        assert_blargg!(report.serial, result);
     }
 
     #[test]
@@ -29,8 +33,8 @@ mod tests {
         let result = "03-op sp,hl";
         const ROM: &[u8] = gbrom!("tests/roms/blargg/cpu_instrs/individual/03-op sp,hl.gb");
         let mut gb = Gameboy::headless_dmg(ROM);
-        gb.step_blargg(8000000, result);
-        assert_blargg!(gb.serial.buffmt(), result);
+        let report = testrom::run(&mut gb, &mut BlarggSerial, BLARGG_BUDGET);
+        assert_blargg!(report.serial, result);
     }
 
     #[test]
@@ -38,8 +42,8 @@ mod tests {
         let result = "04-op r,imm";
         const ROM: &[u8] = gbrom!("tests/roms/blargg/cpu_instrs/individual/04-op r,imm.gb");
         let mut gb = Gameboy::headless_dmg(ROM);
-        gb.step_blargg(8000000, result);
-        assert_blargg!(gb.serial.buffmt(), result);
+        let report = testrom::run(&mut gb, &mut BlarggSerial, BLARGG_BUDGET);
+        assert_blargg!(report.serial, result);
     }
 
     #[test]
@@ -47,8 +51,8 @@ mod tests {
         let result = "05-op rp";
         const ROM: &[u8] = gbrom!("tests/roms/blargg/cpu_instrs/individual/05-op rp.gb");
         let mut gb = Gameboy::headless_dmg(ROM);
-        gb.step_blargg(8000000, result);
-        assert_blargg!(gb.serial.buffmt(), result);
+        let report = testrom::run(&mut gb, &mut BlarggSerial, BLARGG_BUDGET);
+        assert_blargg!(report.serial, result);
     }
 
     #[test]
@@ -56,8 +60,8 @@ mod tests {
         let result = "06-ld r,r";
         const ROM: &[u8] = gbrom!("tests/roms/blargg/cpu_instrs/individual/06-ld r,r.gb");
         let mut gb = Gameboy::headless_dmg(ROM);
-        gb.step_blargg(8000000, result);
-        assert_blargg!(gb.serial.buffmt(), result);
+        let report = testrom::run(&mut gb, &mut BlarggSerial, BLARGG_BUDGET);
+        assert_blargg!(report.serial, result);
     }
 
     #[test]
@@ -66,8 +70,8 @@ mod tests {
         const ROM: &[u8] =
             gbrom!("tests/roms/blargg/cpu_instrs/individual/07-jr,jp,call,ret,rst.gb");
         let mut gb = Gameboy::headless_dmg(ROM);
-        gb.step_blargg(8000000, result);
-        assert_blargg!(gb.serial.buffmt(), result);
+        let report = testrom::run(&mut gb, &mut BlarggSerial, BLARGG_BUDGET);
+        assert_blargg!(report.serial, result);
     }
 
     #[test]
@@ -75,8 +79,8 @@ mod tests {
         let result = "08-misc instrs";
         const ROM: &[u8] = gbrom!("tests/roms/blargg/cpu_instrs/individual/08-misc instrs.gb");
         let mut gb = Gameboy::headless_dmg(ROM);
-        gb.step_blargg(8000000, result);
-        assert_blargg!(gb.serial.buffmt(), result);
+        let report = testrom::run(&mut gb, &mut BlarggSerial, BLARGG_BUDGET);
+        assert_blargg!(report.serial, result);
     }
 
     #[test]
@@ -84,8 +88,8 @@ mod tests {
         let result = "09-op r,r";
         const ROM: &[u8] = gbrom!("tests/roms/blargg/cpu_instrs/individual/09-op r,r.gb");
         let mut gb = Gameboy::headless_dmg(ROM);
-        gb.step_blargg(8000000, result);
-        assert_blargg!(gb.serial.buffmt(), result);
+        let report = testrom::run(&mut gb, &mut BlarggSerial, BLARGG_BUDGET);
+        assert_blargg!(report.serial, result);
     }
 
     #[test]
@@ -93,8 +97,8 @@ mod tests {
         let result = "10-bit ops";
         const ROM: &[u8] = gbrom!("tests/roms/blargg/cpu_instrs/individual/10-bit ops.gb");
         let mut gb = Gameboy::headless_dmg(ROM);
-        gb.step_blargg(8000000, result);
-        assert_blargg!(gb.serial.buffmt(), result);
+        let report = testrom::run(&mut gb, &mut BlarggSerial, BLARGG_BUDGET);
+        assert_blargg!(report.serial, result);
     }
 
     #[test]
@@ -102,8 +106,8 @@ mod tests {
         let result = "11-op a,(hl)";
         const ROM: &[u8] = gbrom!("tests/roms/blargg/cpu_instrs/individual/11-op a,(hl).gb");
         let mut gb = Gameboy::headless_dmg(ROM);
-        gb.step_blargg(8000000, result);
-        assert_blargg!(gb.serial.buffmt(), result);
+        let report = testrom::run(&mut gb, &mut BlarggSerial, BLARGG_BUDGET);
+        assert_blargg!(report.serial, result);
     }
 
     #[test]
@@ -111,17 +115,18 @@ mod tests {
         let result = "instr_timing";
         const ROM: &[u8] = gbrom!("tests/roms/blargg/instr_timing/instr_timing.gb");
         let mut gb = Gameboy::headless_dmg(ROM);
-        gb.step_blargg(8000000, result);
-        assert_blargg!(gb.serial.buffmt(), result);
+        let report = testrom::run(&mut gb, &mut BlarggSerial, BLARGG_BUDGET);
+        assert_blargg!(report.serial, result);
     }
 
     #[test]
-    #[ignore = "TODO"]
+    #[ignore = "CGB only, needs double speed mode"]
     fn blargg_interrupt_time() {
+        let result = "interrupt_time";
         const ROM: &[u8] = gbrom!("tests/roms/blargg/interrupt_time/interrupt_time.gb");
         let mut gb = Gameboy::headless_dmg(ROM);
-        gb.step(800000);
-        assert_blargg!(gb.serial.buffmt(), "interrupt_time");
+        let report = testrom::run(&mut gb, &mut BlarggSerial, BLARGG_BUDGET);
+        assert_blargg!(report.serial, result);
     }
 
     #[test]
@@ -129,8 +134,8 @@ mod tests {
         let result = "01-read_timing";
         const ROM: &[u8] = gbrom!("tests/roms/blargg/mem_timing/individual/01-read_timing.gb");
         let mut gb = Gameboy::headless_dmg(ROM);
-        gb.step_blargg(8000000, result);
-        assert_blargg!(gb.serial.buffmt(), result);
+        let report = testrom::run(&mut gb, &mut BlarggSerial, BLARGG_BUDGET);
+        assert_blargg!(report.serial, result);
     }
 
     #[test]
@@ -138,8 +143,8 @@ mod tests {
         let result = "02-write_timing";
         const ROM: &[u8] = gbrom!("tests/roms/blargg/mem_timing/individual/02-write_timing.gb");
         let mut gb = Gameboy::headless_dmg(ROM);
-        gb.step_blargg(8000000, result);
-        assert_blargg!(gb.serial.buffmt(), result);
+        let report = testrom::run(&mut gb, &mut BlarggSerial, BLARGG_BUDGET);
+        assert_blargg!(report.serial, result);
     }
 
     #[test]
@@ -147,18 +152,16 @@ mod tests {
         let result = "03-modify_timing";
         const ROM: &[u8] = gbrom!("tests/roms/blargg/mem_timing/individual/03-modify_timing.gb");
         let mut gb = Gameboy::headless_dmg(ROM);
-        gb.step_blargg(8000000, result);
-        assert_blargg!(gb.serial.buffmt(), result);
+        let report = testrom::run(&mut gb, &mut BlarggSerial, BLARGG_BUDGET);
+        assert_blargg!(report.serial, result);
     }
 
     #[test]
-    #[ignore = "TODO: implement ei and rst halt bugs"]
     fn blargg_halt_bug() {
         let result = "halt_bug";
         const ROM: &[u8] = gbrom!("tests/roms/blargg/halt_bug/halt_bug.gb");
         let mut gb = Gameboy::headless_dmg(ROM);
-        gb.step(8000000);
-        gb.step_blargg(8000000, result);
-        assert_blargg!(gb.serial.buffmt(), result);
+        let report = testrom::run(&mut gb, &mut BlarggSerial, Budget::Frames(6000));
+        assert_blargg!(report.serial, result);
     }
 }
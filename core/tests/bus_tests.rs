@@ -0,0 +1,78 @@
+use gamezoea::emu::bus;
+use gamezoea::emu::gb::*;
+use gamezoea::emu::regs::*;
+use gamezoea::emu::watch::*;
+use macros::*;
+use std::thread;
+
+const ROM: &[u8] = gbasm! {r#"
+  ld a, $20
+  ldh [$00], a
+  ld a, $05
+  ldh [$07], a
+  ld a, $AB
+  ldh [$04], a
+Loop:
+  jr Loop
+"#};
+
+// {{{ test bus_owner
+#[test]
+fn bus_owner() {
+    assert_eq!(bus::owner(P1), Some(Comp::Joypad));
+    assert_eq!(bus::owner(TAC), Some(Comp::Timer));
+    assert_eq!(bus::owner(STAT), Some(Comp::Ppu));
+    assert_eq!(bus::owner(DMA), None);
+    assert_eq!(bus::owner(IF), None);
+    assert_eq!(bus::owner(0xC000), None);
+}
+// }}}
+
+// {{{ test bus_routes_writes
+#[test]
+fn bus_routes_writes() {
+    let mut gb = Gameboy::headless_dmg(ROM);
+    gb.step(8);
+    // Only the select bits of P1 and the low bits of TAC can be written,
+    // writing DIV clears it
+    assert_eq!(gb.mem_dbg_read(P1), 0xEF);
+    assert_eq!(gb.bus().joypad.read(), 0xEF);
+    assert_eq!(gb.mem_dbg_read(TAC), 0xFD);
+    assert_eq!(gb.bus().timer.read(TAC), 0xFD);
+    assert_eq!(gb.mem_dbg_read(DIV), 0x00);
+}
+// }}}
+
+// {{{ test bus_component_writes_watched
+#[test]
+fn bus_component_writes_watched() {
+    let mut gb = Gameboy::headless_dmg(ROM);
+    gb.add_watchpoint(Watchpoint::new(TIMA..=TIMA, &[Access::Write]));
+    match gb.step(1000) {
+        Some(Event::Watch(hit)) => {
+            assert_eq!(hit.comp, Comp::Timer);
+            assert_eq!(hit.value, 0x01);
+        }
+        other => panic!("unexpected event: {:?}", other),
+    }
+}
+// }}}
+
+// {{{ test bus_gameboy_is_send
+#[test]
+fn bus_gameboy_is_send() {
+    fn assert_send<T: Send>() {}
+    assert_send::<Gameboy>();
+
+    let mut here = Gameboy::headless_dmg(ROM);
+    here.tick(50_000);
+    let mut there = Gameboy::headless_dmg(ROM);
+    let there = thread::spawn(move || {
+        there.tick(50_000);
+        there
+    })
+    .join()
+    .unwrap();
+    assert_eq!(there.save_state(), here.save_state());
+}
+// }}}
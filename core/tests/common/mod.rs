@@ -0,0 +1,9 @@
+use std::path::PathBuf;
+use std::{env, fs};
+
+/// A directory for the files of test `name`, apart from other test runs
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("gamezoea-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
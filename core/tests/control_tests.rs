@@ -0,0 +1,91 @@
+mod common;
+
+use gamezoea::app::control::ControlMessage;
+use gamezoea::emu::gb::*;
+use gamezoea::emu::watch::{Access, Watchpoint};
+use macros::*;
+use std::sync::mpsc;
+use std::{fs, thread};
+
+const ROM: &[u8] = gbasm! {r#"
+  ld hl, $C000
+Loop:
+  inc a
+  ld [hl+], a
+  res 5, h
+  jr Loop
+"#};
+
+// {{{ test control_pause_and_advance
+#[test]
+fn control_pause_and_advance() {
+    let (frame_tx, frame_rx) = mpsc::sync_channel(4);
+    let mut gb = Gameboy::dmg(ROM, frame_tx);
+    let (control_tx, control_rx) = mpsc::channel();
+    control_tx.send(ControlMessage::Pause).unwrap();
+    control_tx.send(ControlMessage::FrameAdvance).unwrap();
+    control_tx.send(ControlMessage::FrameAdvance).unwrap();
+    // Once the second frame is out, the machine is done with it and pauses
+    // until the next message
+    let exit = thread::spawn(move || {
+        frame_rx.recv().unwrap();
+        frame_rx.recv().unwrap();
+        control_tx.send(ControlMessage::Exit).unwrap();
+    });
+
+    gb.run(Some(control_rx));
+    exit.join().unwrap();
+    assert_eq!(gb.t, 2 * FRAME_CYCLES);
+}
+// }}}
+
+// {{{ test control_reset
+#[test]
+fn control_reset() {
+    let mut gb = Gameboy::headless_dmg(ROM);
+    gb.step(1000);
+    gb.mem_dbg_write(0xA000, 0x42);
+    gb.add_watchpoint(Watchpoint::new(0xC000..=0xC000, &[Access::Write]));
+    assert_ne!(gb.mem_dbg_read(0xC001), 0x00);
+    let t = gb.t;
+
+    gb.reset();
+    assert_eq!(gb.cpu.cur_pc(), 0x0100);
+    assert_eq!(gb.t, t);
+    assert_eq!(gb.mem_dbg_read(0xA000), 0x42);
+    assert_eq!(gb.mem_dbg_read(0xC001), 0x00);
+    assert_eq!(gb.watchpoints().len(), 1);
+    assert!(matches!(gb.step(10), Some(Event::Watch(_))));
+}
+// }}}
+
+// {{{ test control_files
+#[test]
+fn control_files() {
+    let mut gb = Gameboy::headless_dmg(ROM);
+    assert_eq!(gb.save_slot(1).unwrap_err(), "no path for saved files");
+    let base = common::temp_dir("control-files").join("rom");
+    gb.set_file_base(base.clone());
+
+    gb.step(500);
+    let (control_tx, control_rx) = mpsc::channel();
+    control_tx.send(ControlMessage::SaveState(2)).unwrap();
+    control_tx.send(ControlMessage::Screenshot).unwrap();
+    control_tx.send(ControlMessage::Exit).unwrap();
+    gb.run(Some(control_rx));
+    let t = gb.t;
+
+    gb.step(500);
+    assert_eq!(gb.load_slot(2).unwrap(), base.with_extension("ss2"));
+    assert_eq!(gb.t, t);
+    assert!(gb.load_slot(3).is_err());
+
+    let png = fs::read(base.with_file_name("rom-1.png")).unwrap();
+    assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+    // Width and height in the header
+    assert_eq!(png[16..24], [0, 0, 0, 160, 0, 0, 0, 144]);
+    assert_eq!(gb.screenshot().unwrap(), base.with_file_name("rom-2.png"));
+
+    fs::remove_dir_all(base.parent().unwrap()).unwrap();
+}
+// }}}
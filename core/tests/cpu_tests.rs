@@ -4,6 +4,8 @@ use macros::*;
 #[cfg(test)]
 mod tests {
     use super::*;
+    use gamezoea::emu::cpu::{LD_B_B, LD_D_D};
+    use gamezoea::emu::joypad::JoypadButton;
     use gamezoea::*;
 
     // {{{ Register Tests
@@ -668,6 +670,256 @@ mod tests {
     }
     // }}}
 
+    // {{{ test halt_bug
+    #[test]
+    fn halt_bug_ime0() {
+        const ROM: &[u8] = gbasm! {r#"
+  di
+  ld a, $01
+  ld [$FFFF], a      ; IE = VBLANK
+  ld [$FF0F], a      ; IF = VBLANK
+  halt               ; IME=0 with a pending interrupt, the next byte runs twice
+  inc b
+  ld c, b
+Hang:
+  jr Hang
+        "#};
+        let mut gb = Gameboy::headless_dmg(ROM);
+        gb.tick(4 * 200);
+        assert_hex_eq!(gb.cpu.b(), 0x02);
+        assert_hex_eq!(gb.cpu.c(), 0x02);
+        assert!(!gb.cpu.halted());
+    }
+
+    #[test]
+    fn halt_bug_ei() {
+        const ROM: &[u8] = gbasm! {r#"
+  di
+  ld c, $00          ; counts the handler runs
+  ld a, $01
+  ld [$FFFF], a      ; IE = VBLANK
+  ld [$FF0F], a      ; IF = VBLANK
+  ei
+  halt               ; serviced, then returns to this halt and halts again
+  inc b
+Hang:
+  jr Hang
+
+SECTION "VBlankInterrupt", ROM0[$40]
+VBlankHandler:
+  inc c
+  xor a
+  ld [$FF0F], a
+  reti
+        "#};
+        let mut gb = Gameboy::headless_dmg(ROM);
+        gb.tick(4 * 200);
+        assert_hex_eq!(gb.cpu.b(), 0x00);
+        // The handler clears IF and no other VBlank comes within 800 T-cycles
+        assert_hex_eq!(gb.cpu.c(), 0x01);
+        assert!(gb.cpu.halted());
+        assert_hex_eq!(gb.cpu.cur_pc(), 0x015C);
+    }
+
+    #[test]
+    fn halt_bug_rst() {
+        const ROM: &[u8] = gbasm! {r#"
+  di
+  ld a, $01
+  ld [$FFFF], a      ; IE = VBLANK
+  ld [$FF0F], a      ; IF = VBLANK
+  halt
+  rst $08            ; pushes its own address, so it runs twice
+Hang:
+  jr Hang
+
+SECTION "Rst08", ROM0[$08]
+Rst08:
+  inc b
+  ret
+        "#};
+        let mut gb = Gameboy::headless_dmg(ROM);
+        gb.tick(4 * 200);
+        assert_hex_eq!(gb.cpu.b(), 0x02);
+    }
+    // }}}
+
+    // {{{ test stop
+    #[test]
+    fn stop_enter() {
+        const ROM: &[u8] = gbasm! {r#"
+  ld a, $20
+  ld [$FF00], a      ; select the direction keys
+  db $10             ; STOP, swallows the next byte
+  inc b
+  inc b
+        "#};
+        let mut gb = Gameboy::headless_dmg(ROM);
+        gb.tick(4 * 200);
+        assert!(gb.cpu.stopped());
+        assert_hex_eq!(gb.cpu.b(), 0x00);
+        assert_hex_eq!(gb.cpu.mem_dbg_read(0xFF04), 0x00);
+    }
+
+    #[test]
+    fn stop_wake_joypad() {
+        const ROM: &[u8] = gbasm! {r#"
+  ld a, $20
+  ld [$FF00], a      ; select the direction keys
+  db $10             ; STOP, swallows the next byte
+  inc b
+  inc b
+        "#};
+        let mut gb = Gameboy::headless_dmg(ROM);
+        gb.tick(4 * 200);
+        gb.bus_mut().enqueue_input(JoypadButton::A, true);
+        gb.tick(4 * 200);
+        assert!(gb.cpu.stopped());
+        gb.bus_mut().enqueue_input(JoypadButton::Right, true);
+        gb.tick(4 * 200);
+        assert!(!gb.cpu.stopped());
+        assert_hex_eq!(gb.cpu.b(), 0x01);
+    }
+
+    #[test]
+    fn stop_pending_interrupt() {
+        const ROM: &[u8] = gbasm! {r#"
+  di
+  ld a, $01
+  ld [$FFFF], a      ; IE = VBLANK
+  ld [$FF0F], a      ; IF = VBLANK
+  ld a, $20
+  ld [$FF00], a
+  db $10             ; STOP is a single byte with an interrupt pending
+  inc b
+  inc b
+        "#};
+        let mut gb = Gameboy::headless_dmg(ROM);
+        gb.tick(4 * 200);
+        gb.bus_mut().enqueue_input(JoypadButton::Right, true);
+        gb.tick(4 * 200);
+        assert_hex_eq!(gb.cpu.b(), 0x02);
+    }
+    // }}}
+
+    // {{{ test illegal
+    #[test]
+    fn illegal_opcode_locks() {
+        const ROM: &[u8] = gbasm! {r#"
+  inc b
+  db $ED
+  inc b
+        "#};
+        let mut gb = Gameboy::headless_dmg(ROM);
+        let event = gb.step(10);
+        assert_eq!(
+            event,
+            Some(Event::CpuLocked {
+                pc: 0x0151,
+                opcode: 0xED
+            })
+        );
+        gb.tick(4 * 200);
+        assert!(gb.cpu.locked());
+        assert_hex_eq!(gb.cpu.b(), 0x01);
+    }
+
+    #[test]
+    fn illegal_opcode_ignores_interrupts() {
+        const ROM: &[u8] = gbasm! {r#"
+  ld a, $01
+  ld [$FFFF], a      ; IE = VBLANK
+  ei
+  db $D3
+  ld [$FF0F], a      ; never reached
+
+SECTION "VBlankInterrupt", ROM0[$40]
+VBlankHandler:
+  inc b
+  reti
+        "#};
+        let mut gb = Gameboy::headless_dmg(ROM);
+        gb.tick(4 * 200);
+        gb.cpu.mem_dbg_write(0xFF0F, 0x01);
+        gb.tick(4 * 200);
+        assert!(gb.cpu.locked());
+        assert_hex_eq!(gb.cpu.b(), 0x00);
+    }
+    // }}}
+
+    // {{{ test soft_break
+    #[test]
+    fn soft_break_ld_b_b() {
+        const ROM: &[u8] = gbasm! {r#"
+  inc b
+  ld b, b
+  inc b
+Loop:
+  jr Loop
+        "#};
+        let mut gb = Gameboy::headless_dmg(ROM);
+        assert_eq!(gb.step(10), None);
+
+        let mut gb = Gameboy::headless_dmg(ROM);
+        assert!(gb.cpu.set_soft_break(LD_B_B, true));
+        assert_eq!(
+            gb.step(10),
+            Some(Event::SoftBreak {
+                pc: 0x0151,
+                opcode: LD_B_B
+            })
+        );
+        assert_hex_eq!(gb.cpu.b(), 0x01);
+        assert_eq!(gb.step(10), None);
+        assert_hex_eq!(gb.cpu.b(), 0x02);
+
+        // Still set after a reset, until turned off
+        gb.reset();
+        assert!(matches!(gb.step(10), Some(Event::SoftBreak { .. })));
+        gb.reset();
+        assert!(gb.cpu.set_soft_break(LD_B_B, false));
+        assert_eq!(gb.step(10), None);
+
+        // Only LD r,r into the same register does nothing
+        assert!(!gb.cpu.set_soft_break(0x41, true));
+        assert!(!gb.cpu.set_soft_break(0x76, true));
+        assert!(gb.cpu.set_soft_break(0x7F, true));
+    }
+
+    #[test]
+    fn soft_break_ld_d_d_message() {
+        const ROM: &[u8] = gbasm! {r#"
+  ld d, d
+  jr End
+  dw $6464
+  dw $0000
+  db $48, $69        ; Hi
+End:
+  ld d, d
+  inc b
+Loop:
+  jr Loop
+        "#};
+        let mut gb = Gameboy::headless_dmg(ROM);
+        gb.cpu.set_soft_break(LD_D_D, true);
+        assert_eq!(
+            gb.step(10),
+            Some(Event::Message {
+                pc: 0x0150,
+                text: "Hi".to_string()
+            })
+        );
+        // Without the message after it, LD D,D is a plain breakpoint
+        assert_eq!(
+            gb.step(10),
+            Some(Event::SoftBreak {
+                pc: 0x0159,
+                opcode: LD_D_D
+            })
+        );
+    }
+    // }}}
+
     // {{{ test add_a_r8
     #[test]
     fn execute_add_a_r8() {
@@ -1693,6 +1945,65 @@ VBlankHandler:
         assert_hex_eq!(gb.cpu.mem_dbg_read(0xFFFC), 0x59);
     }
 
+    #[test]
+    fn interrupt_ie_push_redirect() {
+        const ROM: &[u8] = gbasm! {r#"
+  di
+  ld sp, $0000       ; PCH ($01) is pushed into IE
+  ld a, $02
+  ld [$FFFF], a      ; IE = STAT
+  ld a, $03
+  ld [$FF0F], a      ; IF = VBLANK | STAT
+  ei
+  nop
+Hang:
+  jr Hang
+
+SECTION "VBlankInterrupt", ROM0[$40]
+VBlankHandler:
+  ld b, $40
+  halt
+
+SECTION "StatInterrupt", ROM0[$48]
+StatHandler:
+  ld b, $48
+  halt
+        "#};
+        let mut gb = Gameboy::headless_dmg(ROM);
+        gb.tick(4 * 200);
+        assert_hex_eq!(gb.cpu.b(), 0x40);
+        assert_hex_eq!(gb.cpu.mem_dbg_read(0xFF0F) & 0x03, 0x02);
+    }
+
+    #[test]
+    fn interrupt_ie_push_cancel() {
+        const ROM: &[u8] = gbasm! {r#"
+  di
+  ld sp, $0000       ; PCH ($01) is pushed into IE
+  ld a, $02
+  ld [$FFFF], a      ; IE = STAT
+  ld [$FF0F], a      ; IF = STAT
+  ei
+  nop
+Hang:
+  jr Hang
+
+SECTION "Reset", ROM0[$00]
+Reset:
+  ld b, $99
+  halt
+
+SECTION "StatInterrupt", ROM0[$48]
+StatHandler:
+  ld b, $48
+  halt
+        "#};
+        let mut gb = Gameboy::headless_dmg(ROM);
+        gb.tick(4 * 200);
+        assert_hex_eq!(gb.cpu.b(), 0x99);
+        assert_hex_eq!(gb.cpu.mem_dbg_read(0xFF0F) & 0x02, 0x02);
+    }
+
     // {{{ test timer_basic_0
     #[test]
     fn timer_basic_0() {
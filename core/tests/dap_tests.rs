@@ -0,0 +1,256 @@
+mod common;
+
+use gamezoea::app::dap::*;
+use gamezoea::app::json::Json;
+use gamezoea::emu::gb::*;
+use macros::*;
+use std::fs;
+
+const ROM: &[u8] = gbasm! {r#"
+  ld a, $01
+  call Sub
+  inc b
+  ld [hl], b
+Loop:
+  jr Loop
+
+Sub:
+  inc c
+  inc c
+  ret
+    "#};
+
+const SOURCE: &str = "\
+Main:
+  ld a, $01
+  call Sub
+  inc b
+  ld [hl], b
+.loop
+  jr .loop
+
+Sub: ; increments c twice
+  inc c
+  inc c
+  ret
+";
+
+const SYMBOLS: &str = "\
+; File generated by rgblink
+00:0150 Main
+00:0157 Main.loop
+00:0159 Sub
+";
+
+const MAP: &str = "\
+ROM0 bank #0:
+\tSECTION: $0150-$015b ($000c bytes) [\"Main\"]
+\t         $0150 = Main
+\t         $0157 = .loop
+\t         $0159 = Sub
+\tEMPTY: $015c-$3fff ($3ea4 bytes)
+";
+
+fn request(seq: i64, command: &str, arguments: &str) -> Json {
+    Json::parse(&format!(
+        r#"{{"seq":{},"type":"request","command":"{}","arguments":{}}}"#,
+        seq, command, arguments
+    ))
+    .unwrap()
+}
+
+fn start(name: &str) -> (DapServer, Gameboy) {
+    start_with(name, "main.sym", SYMBOLS)
+}
+
+/// Launches with the labels in `symbols` written to `file`
+fn start_with(name: &str, file: &str, symbols: &str) -> (DapServer, Gameboy) {
+    let mut gb = Gameboy::headless_dmg(ROM);
+    // Run up to the entry point
+    gb.step(2);
+
+    let dir = common::temp_dir(&format!("dap-{}", name));
+    fs::write(dir.join("main.asm"), SOURCE).unwrap();
+    fs::write(dir.join(file), symbols).unwrap();
+
+    let mut dap = DapServer::new();
+    let launch = format!(r#"{{"symbols":"{}"}}"#, dir.join(file).display());
+    let replies = dap.handle(&mut gb, &request(1, "launch", &launch));
+    assert_eq!(replies[0].get("success"), &Json::Bool(true));
+    (dap, gb)
+}
+
+fn source(name: &str) -> String {
+    let path = common::temp_dir(&format!("dap-{}", name));
+    path.join("main.asm").display().to_string()
+}
+
+// {{{ test dap_initialize
+#[test]
+fn dap_initialize() {
+    let mut gb = Gameboy::headless_dmg(ROM);
+    let mut dap = DapServer::new();
+    let replies = dap.handle(&mut gb, &request(1, "initialize", "{}"));
+    assert_eq!(replies[0].get("type").as_str(), Some("response"));
+    assert_eq!(replies[0].get("request_seq").as_i64(), Some(1));
+    assert_eq!(replies[1].get("event").as_str(), Some("initialized"));
+
+    let launch = dap.handle(&mut gb, &request(2, "launch", r#"{"stopOnEntry":true}"#));
+    assert_eq!(launch[0].get("success"), &Json::Bool(true));
+    let done = dap.handle(&mut gb, &request(3, "configurationDone", "{}"));
+    assert_eq!(done[1].get("body").get("reason").as_str(), Some("entry"));
+    assert!(!dap.running());
+
+    let missing = dap.handle(
+        &mut gb,
+        &request(4, "launch", r#"{"symbols":"/nonexistent.sym"}"#),
+    );
+    assert_eq!(missing[0].get("success"), &Json::Bool(false));
+}
+// }}}
+
+// {{{ test dap_source_breakpoints
+#[test]
+fn dap_source_breakpoints() {
+    let (mut dap, mut gb) = start("breakpoints");
+    let args = format!(
+        r#"{{"source":{{"path":"{}"}},"breakpoints":[{{"line":10}},{{"line":3}},{{"line":7}}]}}"#,
+        source("breakpoints")
+    );
+    let replies = dap.handle(&mut gb, &request(2, "setBreakpoints", &args));
+    let breakpoints = replies[0].get("body").get("breakpoints").as_array();
+    assert_eq!(breakpoints[0].get("verified"), &Json::Bool(true));
+    assert_eq!(
+        breakpoints[0].get("instructionReference").as_str(),
+        Some("0x0159")
+    );
+    assert_eq!(breakpoints[1].get("verified"), &Json::Bool(false));
+    assert_eq!(
+        breakpoints[2].get("instructionReference").as_str(),
+        Some("0x0157")
+    );
+
+    dap.handle(&mut gb, &request(3, "configurationDone", "{}"));
+    assert!(dap.running());
+    let stopped = dap.run(&mut gb, 1000);
+    assert_eq!(
+        stopped[0].get("body").get("reason").as_str(),
+        Some("breakpoint")
+    );
+    assert_eq!(gb.cpu.cur_pc(), 0x0159);
+
+    let trace = dap.handle(&mut gb, &request(4, "stackTrace", r#"{"threadId":1}"#));
+    let frame = &trace[0].get("body").get("stackFrames").as_array()[0];
+    assert_eq!(frame.get("name").as_str(), Some("Sub"));
+    assert_eq!(frame.get("line").as_i64(), Some(10));
+
+    dap.handle(&mut gb, &request(5, "continue", r#"{"threadId":1}"#));
+    dap.run(&mut gb, 1000);
+    assert_eq!(gb.cpu.cur_pc(), 0x0157);
+}
+// }}}
+
+// {{{ test dap_map_file
+#[test]
+fn dap_map_file() {
+    let (mut dap, mut gb) = start_with("map", "main.map", MAP);
+    let args = format!(
+        r#"{{"source":{{"path":"{}"}},"breakpoints":[{{"line":10}},{{"line":7}}]}}"#,
+        source("map")
+    );
+    let replies = dap.handle(&mut gb, &request(2, "setBreakpoints", &args));
+    let breakpoints = replies[0].get("body").get("breakpoints").as_array();
+    assert_eq!(
+        breakpoints[0].get("instructionReference").as_str(),
+        Some("0x0159")
+    );
+    assert_eq!(
+        breakpoints[1].get("instructionReference").as_str(),
+        Some("0x0157")
+    );
+}
+// }}}
+
+// {{{ test dap_stepping
+#[test]
+fn dap_stepping() {
+    let (mut dap, mut gb) = start("stepping");
+    dap.handle(&mut gb, &request(2, "next", r#"{"threadId":1}"#));
+    dap.handle(&mut gb, &request(3, "next", r#"{"threadId":1}"#));
+    assert_eq!(gb.cpu.cur_pc(), 0x0155);
+    assert_eq!(gb.cpu.c(), 0x15);
+
+    let (mut dap, mut gb) = start("stepping");
+    dap.handle(&mut gb, &request(2, "stepIn", r#"{"threadId":1}"#));
+    let replies = dap.handle(&mut gb, &request(3, "stepIn", r#"{"threadId":1}"#));
+    assert_eq!(replies[1].get("body").get("reason").as_str(), Some("step"));
+    assert_eq!(gb.cpu.cur_pc(), 0x0159);
+
+    let trace = dap.handle(&mut gb, &request(4, "stackTrace", r#"{"threadId":1}"#));
+    let frame = &trace[0].get("body").get("stackFrames").as_array()[0];
+    assert_eq!(frame.get("name").as_str(), Some("Sub"));
+
+    dap.handle(&mut gb, &request(5, "stepOut", r#"{"threadId":1}"#));
+    assert_eq!(gb.cpu.cur_pc(), 0x0155);
+    assert_eq!(gb.cpu.sp(), 0xFFFE);
+}
+// }}}
+
+// {{{ test dap_variables
+#[test]
+fn dap_variables() {
+    let (mut dap, mut gb) = start("variables");
+    let replies = dap.handle(&mut gb, &request(2, "scopes", r#"{"frameId":0}"#));
+    let scopes = replies[0].get("body").get("scopes").as_array();
+    let reference = |name: &str| {
+        scopes
+            .iter()
+            .find(|s| s.get("name").as_str() == Some(name))
+            .and_then(|s| s.get("variablesReference").as_i64())
+            .unwrap()
+    };
+
+    let args = format!(r#"{{"variablesReference":{}}}"#, reference("Registers"));
+    let replies = dap.handle(&mut gb, &request(3, "variables", &args));
+    let value = |replies: &[Json], name: &str| {
+        replies[0]
+            .get("body")
+            .get("variables")
+            .as_array()
+            .iter()
+            .find(|v| v.get("name").as_str() == Some(name))
+            .and_then(|v| v.get("value").as_str())
+            .map(str::to_string)
+    };
+    assert_eq!(value(&replies, "A").as_deref(), Some("$01"));
+    assert_eq!(value(&replies, "PC").as_deref(), Some("$0150"));
+    assert_eq!(value(&replies, "Flags").as_deref(), Some("Z-HC"));
+
+    gb.mem_dbg_write(0xFF45, 0x42);
+    let args = format!(r#"{{"variablesReference":{}}}"#, reference("IO"));
+    let replies = dap.handle(&mut gb, &request(4, "variables", &args));
+    assert_eq!(value(&replies, "LYC").as_deref(), Some("$42"));
+}
+// }}}
+
+// {{{ test dap_json_parse
+#[test]
+fn dap_json_parse() {
+    assert_eq!(
+        Json::parse(r#""\ud83d\ude00 \u00e9""#),
+        Ok(Json::String("\u{1F600} \u{e9}".to_string()))
+    );
+    // A lone surrogate can't be a char
+    assert_eq!(
+        Json::parse(r#""\ud83d!""#),
+        Ok(Json::String("\u{FFFD}!".to_string()))
+    );
+
+    let nested = |depth| "[".repeat(depth) + &"]".repeat(depth);
+    assert!(Json::parse(&nested(128)).is_ok());
+    assert_eq!(
+        Json::parse(&nested(100_000)),
+        Err("nested too deep at 128".to_string())
+    );
+}
+// }}}
@@ -0,0 +1,253 @@
+use gamezoea::app::debugger::*;
+use gamezoea::emu::cpu::{LD_B_B, LD_D_D};
+use gamezoea::emu::gb::*;
+use gamezoea::emu::rewind::Rewind;
+use gamezoea::emu::symbols::Symbols;
+use macros::*;
+
+const ROM: &[u8] = gbasm! {r#"
+  ld a, $01
+  call Sub
+  inc b
+  ld [hl], b
+Loop:
+  jr Loop
+
+Sub:
+  inc c
+  inc c
+  ret
+    "#};
+
+fn text(reply: Result<Reply, String>) -> String {
+    match reply {
+        Ok(Reply::Text(text)) => text,
+        other => panic!("unexpected reply: {:?}", other),
+    }
+}
+
+fn start() -> (Debugger, Gameboy) {
+    let mut gb = Gameboy::headless_dmg(ROM);
+    // Run up to the entry point
+    gb.step(2);
+    (Debugger::new(), gb)
+}
+
+// {{{ test debugger_step
+#[test]
+fn debugger_step() {
+    let (mut dbg, mut gb) = start();
+    assert_eq!(gb.cpu.cur_pc(), 0x0150);
+    let out = text(dbg.execute(&mut gb, "step 2"));
+    assert!(out.contains("inc c"), "{}", out);
+    assert_eq!(gb.cpu.a(), 0x01);
+}
+// }}}
+
+// {{{ test debugger_next
+#[test]
+fn debugger_next() {
+    let (mut dbg, mut gb) = start();
+    text(dbg.execute(&mut gb, "n 2"));
+    assert_eq!(gb.cpu.cur_pc(), 0x0155);
+    assert_eq!(gb.cpu.c(), 0x15);
+}
+// }}}
+
+// {{{ test debugger_breakpoints
+#[test]
+fn debugger_breakpoints() {
+    let (mut dbg, mut gb) = start();
+    text(dbg.execute(&mut gb, "break op 70"));
+    text(dbg.execute(&mut gb, "b $015A"));
+    assert_eq!(
+        dbg.breakpoints(),
+        &[Breakpoint::Opcode(0x70), Breakpoint::Pc(0x015A)]
+    );
+
+    let out = text(dbg.execute(&mut gb, "c"));
+    assert!(out.starts_with("Hit Pc(346)"), "{}", out);
+    assert_eq!(gb.cpu.cur_pc(), 0x015A);
+
+    text(dbg.execute(&mut gb, "continue"));
+    assert_eq!(gb.cpu.cur_pc(), 0x0156);
+    assert_eq!(gb.cpu.ir(), 0x70);
+
+    text(dbg.execute(&mut gb, "delete 0"));
+    assert_eq!(dbg.breakpoints(), &[Breakpoint::Pc(0x015A)]);
+    assert!(dbg.execute(&mut gb, "delete 4").is_err());
+}
+// }}}
+
+// {{{ test debugger_soft_break
+#[test]
+fn debugger_soft_break() {
+    const BREAKS: &[u8] = gbasm! {r#"
+  inc b
+  ld b, b
+  inc b
+Loop:
+  jr Loop
+    "#};
+    let mut gb = Gameboy::headless_dmg(BREAKS);
+    gb.cpu.set_soft_break(LD_B_B, true);
+    let mut dbg = Debugger::new();
+    let out = text(dbg.execute(&mut gb, "continue"));
+    assert!(
+        out.starts_with("Software breakpoint $40 at $0151"),
+        "{}",
+        out
+    );
+    assert!(out.contains("inc b"), "{}", out);
+}
+// }}}
+
+// {{{ test debugger_message
+#[test]
+fn debugger_message() {
+    const MESSAGE: &[u8] = gbasm! {r#"
+  ld d, d
+  jr End
+  dw $6464
+  dw $0000
+  db $48, $69        ; Hi
+End:
+  ld b, b
+Loop:
+  jr Loop
+    "#};
+    let mut gb = Gameboy::headless_dmg(MESSAGE);
+    gb.cpu.set_soft_break(LD_B_B, true);
+    gb.cpu.set_soft_break(LD_D_D, true);
+    let mut dbg = Debugger::new();
+    // Debug messages are shown without stopping
+    let out = text(dbg.execute(&mut gb, "continue"));
+    assert!(
+        out.starts_with("Message: Hi\nSoftware breakpoint $40 at $0159"),
+        "{}",
+        out
+    );
+}
+// }}}
+
+// {{{ test debugger_registers
+#[test]
+fn debugger_registers() {
+    let (mut dbg, mut gb) = start();
+    text(dbg.execute(&mut gb, "set hl C000"));
+    text(dbg.execute(&mut gb, "set b 7F"));
+    text(dbg.execute(&mut gb, "flag z 0"));
+    let out = text(dbg.execute(&mut gb, "regs"));
+    assert!(out.starts_with("A:01 F:30 B:7F"), "{}", out);
+    assert_eq!(gb.cpu.hl(), 0xC000);
+    assert!(dbg.execute(&mut gb, "set b 100").is_err());
+
+    text(dbg.execute(&mut gb, "set pc 0155"));
+    text(dbg.execute(&mut gb, "s 2"));
+    assert_eq!(gb.cpu.b(), 0x80);
+    assert_eq!(gb.cpu.mem_dbg_read(0xC000), 0x80);
+}
+// }}}
+
+// {{{ test debugger_memory
+#[test]
+fn debugger_memory() {
+    let (mut dbg, mut gb) = start();
+    let out = text(dbg.execute(&mut gb, "poke C010 48 49"));
+    assert_eq!(out, format!("C010: 48 49{}  HI", " ".repeat(42)));
+    let out = text(dbg.execute(&mut gb, "x $0150 4"));
+    assert!(out.starts_with("0150: 3E 01 CD 59"), "{}", out);
+
+    let out = text(dbg.execute(&mut gb, "d"));
+    let first = out.lines().next().unwrap();
+    assert_eq!(first, "=> 00:0150  3E 01     ld a, $01");
+    assert_eq!(out.lines().count(), 10);
+}
+// }}}
+
+// {{{ test debugger_watch
+#[test]
+fn debugger_watch() {
+    let (mut dbg, mut gb) = start();
+    assert_eq!(
+        text(dbg.execute(&mut gb, "watch w 014D cpu =01")),
+        "watch #0"
+    );
+    assert!(dbg.execute(&mut gb, "watch q 014D").is_err());
+
+    let out = text(dbg.execute(&mut gb, "c"));
+    assert!(
+        out.starts_with("Hit watch #0: Write 014D = 01 by Cpu"),
+        "{}",
+        out
+    );
+    assert_eq!(gb.cpu.cur_pc(), 0x0156);
+
+    text(dbg.execute(&mut gb, "unwatch"));
+    assert!(gb.watchpoints().is_empty());
+}
+// }}}
+
+// {{{ test debugger_symbols
+#[test]
+fn debugger_symbols() {
+    let (mut dbg, mut gb) = start();
+    gb.load_symbols(Symbols::parse("00:0150 Main\n00:0157 Main.loop\n00:0159 Sub\n").unwrap());
+
+    text(dbg.execute(&mut gb, "b Sub"));
+    assert_eq!(dbg.breakpoints(), &[Breakpoint::Pc(0x0159)]);
+    assert!(dbg.execute(&mut gb, "b Nowhere").is_err());
+
+    let out = text(dbg.execute(&mut gb, "c"));
+    assert!(out.ends_with("inc c  ; Sub"), "{}", out);
+    assert_eq!(
+        text(dbg.execute(&mut gb, "bt")),
+        "#0 00:0159 Sub\n#1 00:0155 Main+$5"
+    );
+
+    let out = text(dbg.execute(&mut gb, "d Main 3"));
+    assert!(out.starts_with("Main:\n"), "{}", out);
+    assert!(out.contains("call Sub"), "{}", out);
+
+    text(dbg.execute(&mut gb, "b 01:4000"));
+    assert_eq!(dbg.breakpoints()[1], Breakpoint::Banked(1, 0x4000));
+}
+// }}}
+
+// {{{ test debugger_reverse
+#[test]
+fn debugger_reverse() {
+    let (mut dbg, mut gb) = start();
+    assert_eq!(dbg.execute(&mut gb, "rs").unwrap_err(), "rewind is off");
+    gb.set_rewind(Some(Rewind::default()));
+
+    text(dbg.execute(&mut gb, "s 4"));
+    assert_eq!(gb.cpu.cur_pc(), 0x015B);
+    text(dbg.execute(&mut gb, "rstep"));
+    assert_eq!(gb.cpu.cur_pc(), 0x015A);
+    text(dbg.execute(&mut gb, "rs 2"));
+    assert_eq!(gb.cpu.cur_pc(), 0x0152);
+
+    // Finding who wrote a value, then the breakpoint before that
+    text(dbg.execute(&mut gb, "s 20"));
+    text(dbg.execute(&mut gb, "watch w 014D"));
+    text(dbg.execute(&mut gb, "b $0155"));
+    let out = text(dbg.execute(&mut gb, "rc"));
+    assert!(out.starts_with("Hit watch #0: Write 014D = 01"), "{}", out);
+    assert_eq!(gb.cpu.cur_pc(), 0x0156);
+    let out = text(dbg.execute(&mut gb, "rcontinue"));
+    assert!(out.starts_with("Hit Pc(341)"), "{}", out);
+    assert_eq!(gb.cpu.b(), 0x00);
+
+    // The first snapshot was taken after the first step
+    let out = text(dbg.execute(&mut gb, "rc"));
+    assert!(out.starts_with("Reached the oldest snapshot"), "{}", out);
+    assert_eq!(gb.cpu.cur_pc(), 0x0152);
+
+    // Running forward again replays the same
+    let out = text(dbg.execute(&mut gb, "c"));
+    assert!(out.starts_with("Hit Pc(341)"), "{}", out);
+    let out = text(dbg.execute(&mut gb, "c"));
+    assert!(out.starts_with("Hit watch #0"), "{}", out);
+}
+// }}}
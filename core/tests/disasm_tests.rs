@@ -0,0 +1,174 @@
+use gamezoea::emu::disasm;
+use gamezoea::emu::gb::*;
+use gamezoea::emu::symbols::Symbols;
+use macros::*;
+
+fn texts(rom: &[u8], from: u16, count: usize) -> Vec<String> {
+    disasm::disassemble(rom, 1, from, count)
+        .into_iter()
+        .map(|i| i.text)
+        .collect()
+}
+
+// {{{ test disasm_block0
+#[test]
+fn disasm_block0() {
+    const ROM: &[u8] = gbasm! {r#"
+  nop
+  ld bc, $1234
+  ld [hl+], a
+  ld a, [de]
+  ld [$C000], sp
+  inc sp
+  add hl, de
+  inc [hl]
+  dec e
+  ld h, $42
+  rlca
+  daa
+Back:
+  jr Back
+  jr nz, Back
+  stop
+        "#};
+    assert_eq!(
+        texts(ROM, 0x0150, 15),
+        vec![
+            "nop",
+            "ld bc, $1234",
+            "ld [hl+], a",
+            "ld a, [de]",
+            "ld [$C000], sp",
+            "inc sp",
+            "add hl, de",
+            "inc [hl]",
+            "dec e",
+            "ld h, $42",
+            "rlca",
+            "daa",
+            "jr $0161",
+            "jr nz, $0161",
+            "stop",
+        ]
+    );
+}
+// }}}
+
+// {{{ test disasm_blocks123
+#[test]
+fn disasm_blocks123() {
+    const ROM: &[u8] = gbasm! {r#"
+  ld b, [hl]
+  ld [hl], e
+  add a, c
+  sbc a, [hl]
+  cp a, $10
+  ret z
+  jp c, $4000
+  call $0200
+  rst $38
+  push af
+  ldh [$FF44], a
+  ldh a, [c]
+  add sp, -2
+  ld hl, sp+5
+  ei
+        "#};
+    assert_eq!(
+        texts(ROM, 0x0150, 15),
+        vec![
+            "ld b, [hl]",
+            "ld [hl], e",
+            "add a, c",
+            "sbc a, [hl]",
+            "cp a, $10",
+            "ret z",
+            "jp c, $4000",
+            "call $0200",
+            "rst $38",
+            "push af",
+            "ldh [$FF44], a",
+            "ldh a, [c]",
+            "add sp, -$02",
+            "ld hl, sp+$05",
+            "ei",
+        ]
+    );
+}
+// }}}
+
+// {{{ test disasm_cb_and_illegal
+#[test]
+fn disasm_cb_and_illegal() {
+    const ROM: &[u8] = gbasm! {r#"
+  rlc b
+  swap [hl]
+  bit 7, h
+  res 0, a
+  set 3, [hl]
+  db $ED
+        "#};
+    let instructions = disasm::disassemble(ROM, 1, 0x0150, 6);
+    let texts: Vec<_> = instructions.iter().map(|i| i.text.as_str()).collect();
+    assert_eq!(
+        texts,
+        vec![
+            "rlc b",
+            "swap [hl]",
+            "bit 7, h",
+            "res 0, a",
+            "set 3, [hl]",
+            "db $ED"
+        ]
+    );
+    assert_eq!(instructions[0].bytes, vec![0xCB, 0x00]);
+    assert_eq!(instructions[5].addr, 0x015A);
+    assert_eq!(instructions[5].to_string(), "00:015A  ED        db $ED");
+}
+// }}}
+
+// {{{ test disasm_gameboy
+#[test]
+fn disasm_gameboy() {
+    const ROM: &[u8] = gbasm! {r#"
+  ld a, $01
+  jp $0150
+        "#};
+    let gb = Gameboy::headless_dmg(ROM);
+    assert_eq!(gb.disasm(0x0100).text, "jp $0150");
+    assert_eq!(gb.disasm(0x0152).text, "jp $0150");
+    assert_eq!(gb.disasm(0x0152).bytes, vec![0xC3, 0x50, 0x01]);
+}
+// }}}
+
+// {{{ test disasm_listing_symbols
+#[test]
+fn disasm_listing_symbols() {
+    const ROM: &[u8] = gbasm! {r#"
+  ld hl, $C000
+  call Sub
+Loop:
+  jr Loop
+Sub:
+  ldh [$FF80], a
+  ret
+        "#};
+    let symbols = Symbols::parse(
+        "00:0150 Main\n00:0156 Main.loop\n00:0158 Sub\n00:C000 wBuffer\n00:FF80 hTemp\n",
+    )
+    .unwrap();
+    assert_eq!(
+        disasm::listing(ROM, 1, 0x0150, 5, &symbols),
+        vec![
+            "Main:",
+            "00:0150  21 00 C0  ld hl, wBuffer",
+            "00:0153  CD 58 01  call Sub",
+            "Main.loop:",
+            "00:0156  18 FE     jr Main.loop",
+            "Sub:",
+            "00:0158  E0 80     ldh [hTemp], a",
+            "00:015A  C9        ret",
+        ]
+    );
+}
+// }}}
@@ -0,0 +1,128 @@
+use gamezoea::app::gdb::*;
+use gamezoea::emu::gb::*;
+use gamezoea::emu::rewind::Rewind;
+use macros::*;
+
+const ROM: &[u8] = gbasm! {r#"
+  ld a, $01
+  call Sub
+  inc b
+  ld [hl], b
+Loop:
+  jr Loop
+
+Sub:
+  inc c
+  inc c
+  ret
+    "#};
+
+fn reply(response: Response) -> String {
+    match response {
+        Response::Reply(reply) => reply,
+        other => panic!("unexpected response: {:?}", other),
+    }
+}
+
+fn start() -> (GdbStub, Gameboy) {
+    let mut gb = Gameboy::headless_dmg(ROM);
+    // Run up to the entry point
+    gb.step(2);
+    (GdbStub::new(), gb)
+}
+
+// {{{ test gdb_registers
+#[test]
+fn gdb_registers() {
+    let (mut gdb, mut gb) = start();
+    assert_eq!(reply(gdb.packet(&mut gb, "g")), "b0011300d8004d01feff5001");
+
+    assert_eq!(reply(gdb.packet(&mut gb, "P1=3412")), "OK");
+    assert_eq!(gb.cpu.bc(), 0x1234);
+    assert_eq!(reply(gdb.packet(&mut gb, "p1")), "3412");
+    assert_eq!(reply(gdb.packet(&mut gb, "p6")), "E01");
+
+    assert_eq!(
+        reply(gdb.packet(&mut gb, "G0000010002000300fcff5001")),
+        "OK"
+    );
+    assert_eq!(gb.cpu.hl(), 0x0003);
+    assert_eq!(gb.cpu.sp(), 0xFFFC);
+    assert_eq!(gb.cpu.cur_pc(), 0x0150);
+}
+// }}}
+
+// {{{ test gdb_memory
+#[test]
+fn gdb_memory() {
+    let (mut gdb, mut gb) = start();
+    assert_eq!(reply(gdb.packet(&mut gb, "m150,3")), "3e01cd");
+    assert_eq!(reply(gdb.packet(&mut gb, "Mc000,2:abcd")), "OK");
+    assert_eq!(reply(gdb.packet(&mut gb, "mc000,2")), "abcd");
+    assert_eq!(reply(gdb.packet(&mut gb, "Mc000,2:ab")), "E01");
+}
+// }}}
+
+// {{{ test gdb_non_ascii_command
+#[test]
+fn gdb_non_ascii_command() {
+    let (mut gdb, mut gb) = start();
+    let packet = String::from_utf8_lossy(b"\xffm150,3").into_owned();
+    assert_eq!(reply(gdb.packet(&mut gb, &packet)), "E01");
+    assert_eq!(reply(gdb.packet(&mut gb, "\u{e9}")), "E01");
+}
+// }}}
+
+// {{{ test gdb_step_and_continue
+#[test]
+fn gdb_step_and_continue() {
+    let (mut gdb, mut gb) = start();
+    assert_eq!(gdb.packet(&mut gb, "s"), Response::Resume { step: true });
+    assert_eq!(gdb.resume(&mut gb, true, || false), "S05");
+    assert_eq!(gb.cpu.cur_pc(), 0x0152);
+
+    assert_eq!(reply(gdb.packet(&mut gb, "Z0,15a,1")), "OK");
+    assert_eq!(gdb.breakpoints(), &[(0, 0x015A)]);
+    assert_eq!(gdb.packet(&mut gb, "c"), Response::Resume { step: false });
+    assert_eq!(gdb.resume(&mut gb, false, || false), "T05swbreak:;");
+    assert_eq!(gb.cpu.cur_pc(), 0x015A);
+
+    assert_eq!(reply(gdb.packet(&mut gb, "z0,15a,1")), "OK");
+    assert!(gdb.breakpoints().is_empty());
+    assert_eq!(gdb.resume(&mut gb, false, || true), "S02");
+}
+// }}}
+
+// {{{ test gdb_watchpoint
+#[test]
+fn gdb_watchpoint() {
+    let (mut gdb, mut gb) = start();
+    assert_eq!(reply(gdb.packet(&mut gb, "Z2,14d,1")), "OK");
+    assert_eq!(gdb.resume(&mut gb, false, || false), "T05watch:014d;");
+    assert_eq!(gb.cpu.b(), 0x01);
+
+    assert_eq!(reply(gdb.packet(&mut gb, "z2,14d,1")), "OK");
+    assert!(gb.watchpoints().is_empty());
+    assert_eq!(reply(gdb.packet(&mut gb, "z2,14d,1")), "E01");
+}
+// }}}
+
+// {{{ test gdb_reverse
+#[test]
+fn gdb_reverse() {
+    let (mut gdb, mut gb) = start();
+    assert_eq!(reply(gdb.packet(&mut gb, "bs")), "E01");
+    gb.set_rewind(Some(Rewind::default()));
+    assert!(reply(gdb.packet(&mut gb, "qSupported")).contains("ReverseStep+;ReverseContinue+"));
+
+    assert_eq!(reply(gdb.packet(&mut gb, "Z0,159,1")), "OK");
+    gb.step(7);
+    assert_eq!(gb.cpu.cur_pc(), 0x0157);
+    assert_eq!(reply(gdb.packet(&mut gb, "bs")), "S05");
+    assert_eq!(gb.cpu.cur_pc(), 0x0156);
+    assert_eq!(reply(gdb.packet(&mut gb, "bc")), "T05swbreak:;");
+    assert_eq!(gb.cpu.cur_pc(), 0x0159);
+    assert_eq!(reply(gdb.packet(&mut gb, "bc")), "T05replaylog:begin;");
+    assert_eq!(gb.cpu.cur_pc(), 0x0152);
+}
+// }}}
@@ -0,0 +1,37 @@
+use gamezoea::emu::log::*;
+
+// {{{ test log_parse
+#[test]
+fn log_parse() {
+    assert_eq!(
+        parse("info, ppu=trace,timer=off").unwrap(),
+        vec![
+            (None, Level::Info),
+            (Some(Target::Ppu), Level::Trace),
+            (Some(Target::Timer), Level::Off),
+        ]
+    );
+    assert_eq!(parse("").unwrap(), vec![]);
+    assert_eq!(parse("apu=debug").unwrap_err(), "unknown log target: apu");
+    assert_eq!(parse("cpu=loud").unwrap_err(), "unknown log level: loud");
+}
+// }}}
+
+// {{{ test log_configure
+#[test]
+fn log_configure() {
+    assert_eq!(level(Target::Mem), Level::Warn);
+    assert!(enabled(Target::Mem, Level::Error));
+    assert!(!enabled(Target::Mem, Level::Info));
+
+    configure("debug,mem=trace,serial=off").unwrap();
+    assert_eq!(level(Target::Cpu), Level::Debug);
+    assert!(enabled(Target::Mem, Level::Trace));
+    assert!(!enabled(Target::Cpu, Level::Trace));
+    assert!(!enabled(Target::Serial, Level::Error));
+
+    // Nothing changes when part of the filter is invalid
+    assert!(configure("joypad=trace,bogus").is_err());
+    assert_eq!(level(Target::Joypad), Level::Debug);
+}
+// }}}
@@ -1,28 +1,22 @@
-use gamezoea::emu::gb::*;
+use gamezoea::emu::batch::Budget;
+use gamezoea::emu::testrom::{self, Mooneye, Verdict};
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs;
-    use std::path::PathBuf;
+    use std::path::Path;
 
-    const MOONEYE_STEPS: u128 = 80_000_000;
-
-    fn load_mooneye_rom(path: &str) -> Vec<u8> {
-        let manifest_dir = env!("CARGO_MANIFEST_DIR");
-        let rom_path = PathBuf::from(manifest_dir).join(path);
-        fs::read(&rom_path)
-            .unwrap_or_else(|e| panic!("Failed to read ROM '{}': {}", rom_path.display(), e))
-    }
+    const MOONEYE_BUDGET: Budget = Budget::Frames(1200);
 
     macro_rules! mooneye_test {
         ($(#[$meta:meta])* $name:ident, $path:literal) => {
             #[test]
             $(#[$meta])*
             fn $name() {
-                let rom = load_mooneye_rom($path);
-                let mut gb = Gameboy::headless_dmg(&rom);
-                gb.step_mooneye(MOONEYE_STEPS);
+                let path = Path::new(env!("CARGO_MANIFEST_DIR")).join($path);
+                let mut gb = testrom::open(&path).unwrap_or_else(|e| panic!("{}", e));
+                let report = testrom::run(&mut gb, &mut Mooneye, MOONEYE_BUDGET);
+                assert_eq!(report.verdict, Verdict::Pass, "serial: {:?}", report.serial);
             }
         };
     }
@@ -166,7 +160,6 @@ mod tests {
         "tests/roms/mooneye/acceptance/instr/daa.gb"
     );
     mooneye_test!(
-        #[ignore = "TODO"]
         mooneye_acceptance_interrupts_ie_push_gb,
         "tests/roms/mooneye/acceptance/interrupts/ie_push.gb"
     );
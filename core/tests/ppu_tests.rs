@@ -50,7 +50,10 @@ Forever:
     gb.step(80000);
     assert_hex_eq!(gb.cpu.mem_dbg_read(0x8000), 0x3C);
     assert_hex_eq!(gb.cpu.a(), 0x3C);
-    let td = gb.ppu.read_whole_tile_data(false, 0x00, 0x00);
+    let td = gb
+        .bus()
+        .ppu
+        .read_whole_tile_data(&gb.bus().mem, false, 0x00, 0x00);
     assert_hex_eq!(td[0], 0x3C);
     assert_hex_eq!(td[1], 0x7E);
     assert_hex_eq!(td[2], 0x42);
@@ -160,17 +163,26 @@ TileHollow:
     "#};
     let mut gb = Gameboy::headless_dmg(ROM);
     gb.step(80000);
-    let td = gb.ppu.read_whole_tile_data(false, 0x00, 0x00);
+    let td = gb
+        .bus()
+        .ppu
+        .read_whole_tile_data(&gb.bus().mem, false, 0x00, 0x00);
     assert_hex_eq!(td[0], 0xFF);
     assert_hex_eq!(td[1], 0x00);
     assert_hex_eq!(td[2], 0xFF);
     assert_hex_eq!(td[3], 0x00);
-    let td = gb.ppu.read_whole_tile_data(false, 0x01, 0x00);
+    let td = gb
+        .bus()
+        .ppu
+        .read_whole_tile_data(&gb.bus().mem, false, 0x01, 0x00);
     assert_hex_eq!(td[0], 0xAA);
     assert_hex_eq!(td[1], 0x55);
     assert_hex_eq!(td[2], 0x55);
     assert_hex_eq!(td[3], 0xAA);
-    let td = gb.ppu.read_whole_tile_data(false, 0xFF, 0x00);
+    let td = gb
+        .bus()
+        .ppu
+        .read_whole_tile_data(&gb.bus().mem, false, 0xFF, 0x00);
     assert_hex_eq!(td[0], 0xFF);
     assert_hex_eq!(td[1], 0x22);
     assert_hex_eq!(td[2], 0x81);
@@ -299,17 +311,26 @@ TilesEnd:
     "#};
     let mut gb = Gameboy::headless_dmg(ROM);
     gb.step(80000);
-    let td = gb.ppu.read_whole_tile_data(false, 0x00, 0x00);
+    let td = gb
+        .bus()
+        .ppu
+        .read_whole_tile_data(&gb.bus().mem, false, 0x00, 0x00);
     assert_hex_eq!(td[0], 0x00);
     assert_hex_eq!(td[1], 0x00);
     assert_hex_eq!(td[2], 0x00);
     assert_hex_eq!(td[3], 0x00);
-    let td = gb.ppu.read_whole_tile_data(false, 0x01, 0x00);
+    let td = gb
+        .bus()
+        .ppu
+        .read_whole_tile_data(&gb.bus().mem, false, 0x01, 0x00);
     assert_hex_eq!(td[0], 0x18);
     assert_hex_eq!(td[1], 0x00);
     assert_hex_eq!(td[2], 0x38);
     assert_hex_eq!(td[3], 0x00);
-    let td = gb.ppu.read_whole_tile_data(false, 0x02, 0x00);
+    let td = gb
+        .bus()
+        .ppu
+        .read_whole_tile_data(&gb.bus().mem, false, 0x02, 0x00);
     assert_hex_eq!(td[0], 0x3C);
     assert_hex_eq!(td[1], 0x00);
     assert_hex_eq!(td[2], 0x66);
@@ -324,24 +345,24 @@ fn ppu_line_progression() {
 
     let mut gb = Gameboy::cartless_dmg();
 
-    assert_eq!(gb.ppu.ly(), 0);
+    assert_eq!(gb.bus().ppu.ly(), 0);
 
     gb.tick(456);
-    assert_eq!(gb.ppu.ly(), 1);
+    assert_eq!(gb.bus().ppu.ly(), 1);
 
     gb.tick(456 * 9);
-    assert_eq!(gb.ppu.ly(), 10);
+    assert_eq!(gb.bus().ppu.ly(), 10);
 
     gb.tick(456 * 134);
-    assert_eq!(gb.ppu.ly(), 144);
+    assert_eq!(gb.bus().ppu.ly(), 144);
 
     for expected in 145..=153 {
         gb.tick(456);
-        assert_eq!(gb.ppu.ly(), expected);
+        assert_eq!(gb.bus().ppu.ly(), expected);
     }
 
     gb.tick(456);
-    assert_eq!(gb.ppu.ly(), 0);
+    assert_eq!(gb.bus().ppu.ly(), 0);
 }
 
 // {{{ test ppu_timing
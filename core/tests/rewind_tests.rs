@@ -0,0 +1,100 @@
+use gamezoea::emu::gb::*;
+use gamezoea::emu::rewind::Rewind;
+use macros::*;
+
+const ROM: &[u8] = gbasm! {r#"
+  ld a, $05
+  ldh [$07], a
+  ld a, $04
+  ldh [$FF], a
+  ei
+  ld hl, $C000
+Loop:
+  inc b
+  ld a, b
+  ld [hl+], a
+  set 7, h
+  set 6, h
+  res 5, h
+  jr Loop
+"#};
+
+// {{{ test rewind_buffer
+#[test]
+fn rewind_buffer() {
+    let states: Vec<Vec<u8>> = (0..70u8)
+        .map(|i| {
+            let mut state = vec![0; 1000 + i as usize];
+            state[i as usize * 3] = i;
+            state[999] = 0xFF;
+            state
+        })
+        .collect();
+    let mut rewind = Rewind::new(1 << 20).every(2);
+    for (t, state) in states.iter().enumerate() {
+        assert!(rewind.due(t as u128 * 2 * FRAME_CYCLES));
+        rewind.push(t as u128 * 2 * FRAME_CYCLES, state);
+        assert!(!rewind.due(t as u128 * 2 * FRAME_CYCLES + 1));
+    }
+    assert_eq!(rewind.len(), 70);
+    // Mostly zeros compress well below the raw size
+    assert!(rewind.size() < 70 * 1000 / 4);
+
+    // Popping back and forth keeps working across keyframes
+    assert_eq!(rewind.pop().as_ref(), states.get(69));
+    rewind.push(0, &states[69]);
+    for state in states.iter().rev() {
+        assert_eq!(rewind.pop().as_ref(), Some(state));
+    }
+    assert!(rewind.pop().is_none());
+    assert_eq!(rewind.size(), 0);
+}
+// }}}
+
+// {{{ test rewind_budget
+#[test]
+fn rewind_budget() {
+    const BUDGET: usize = 320 << 10;
+    let mut gb = Gameboy::headless_dmg(ROM);
+    gb.set_rewind(Some(Rewind::new(BUDGET).every(1)));
+    gb.tick(60 * FRAME_CYCLES);
+
+    let rewind = gb.rewind_buffer().unwrap();
+    assert!(rewind.size() <= BUDGET);
+    assert!(!rewind.is_empty() && rewind.len() < 60);
+}
+// }}}
+
+// {{{ test rewind_replays
+#[test]
+fn rewind_replays() {
+    let mut gb = Gameboy::headless_dmg(ROM);
+    assert_eq!(gb.rewind().unwrap_err(), "rewind is off");
+
+    gb.set_rewind(Some(Rewind::default().every(1)));
+    gb.tick(45 * FRAME_CYCLES + 123);
+    let snapshots = gb.rewind_buffer().unwrap().len();
+    assert_eq!(snapshots, 46);
+
+    let now = gb.t;
+    gb.rewind().unwrap();
+    assert!(gb.t < now && now - gb.t < FRAME_CYCLES);
+
+    // Back past a keyframe, running forward again gives the same machine
+    for _ in 0..20 {
+        gb.rewind().unwrap();
+    }
+    let mut fresh = Gameboy::headless_dmg(ROM);
+    fresh.tick(gb.t);
+    assert!(gb.save_state() == fresh.save_state());
+    gb.tick(10_000);
+    fresh.tick(10_000);
+    assert_eq!(gb.cpu.cur_pc(), fresh.cpu.cur_pc());
+    assert!(gb.save_state() == fresh.save_state());
+
+    while gb.rewind_buffer().is_some_and(|r| !r.is_empty()) {
+        gb.rewind().unwrap();
+    }
+    assert_eq!(gb.rewind().unwrap_err(), "nothing left to rewind");
+}
+// }}}
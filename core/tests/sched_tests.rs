@@ -0,0 +1,66 @@
+use gamezoea::emu::gb::*;
+use gamezoea::emu::regs::*;
+use gamezoea::emu::sched::{NEVER, Scheduler};
+use macros::*;
+
+const ROM: &[u8] = gbasm! {r#"
+  ld hl, $C000
+Loop:
+  ldh a, [$44]
+  ld [hl+], a
+  res 5, h
+  jr Loop
+"#};
+
+// {{{ test sched_scheduler
+#[test]
+fn sched_scheduler() {
+    let mut scheduler = Scheduler::default();
+    assert!(scheduler.due(Comp::Timer, 0));
+    scheduler.schedule(Comp::Timer, 100);
+    assert!(!scheduler.due(Comp::Timer, 99));
+    assert!(scheduler.due(Comp::Timer, 100));
+
+    scheduler.schedule(Comp::Serial, NEVER);
+    assert!(!scheduler.due(Comp::Serial, u128::MAX - 1));
+    scheduler.wake(Comp::Serial);
+    assert!(scheduler.due(Comp::Serial, 0));
+
+    scheduler.schedule(Comp::Ppu, NEVER);
+    scheduler.wake_all();
+    assert_eq!(scheduler.next(Comp::Ppu), 0);
+}
+// }}}
+
+// {{{ test sched_timer_catches_up
+#[test]
+fn sched_timer_catches_up() {
+    let mut gb = Gameboy::headless_dmg(ROM);
+    gb.tick(1001);
+    let div = gb.mem_dbg_read(DIV);
+    gb.tick(3 * 256);
+    assert_eq!(gb.mem_dbg_read(DIV), div.wrapping_add(3));
+
+    // Poking TAC wakes the timer, TIMA then counts every 16 cycles
+    gb.mem_dbg_write(TIMA, 0x00);
+    gb.mem_dbg_write(TAC, 0x05);
+    gb.tick(10 * 16);
+    assert_eq!(gb.mem_dbg_read(TIMA), 10);
+}
+// }}}
+
+// {{{ test sched_state_mid_sleep
+#[test]
+fn sched_state_mid_sleep() {
+    let mut gb = Gameboy::headless_dmg(ROM);
+    gb.tick(12_345);
+    let state = gb.save_state();
+
+    let mut loaded = Gameboy::headless_dmg(ROM);
+    loaded.load_state(&state).unwrap();
+    gb.tick(3 * FRAME_CYCLES + 77);
+    loaded.tick(3 * FRAME_CYCLES + 77);
+    assert_eq!(gb.mem_dbg_read(LY), loaded.mem_dbg_read(LY));
+    assert!(gb.save_state() == loaded.save_state());
+}
+// }}}
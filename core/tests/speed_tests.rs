@@ -0,0 +1,93 @@
+use gamezoea::app::control::ControlMessage;
+use gamezoea::app::window;
+use gamezoea::emu::cpu::{LD_B_B, LD_D_D};
+use gamezoea::emu::gb::*;
+use macros::*;
+use std::sync::mpsc;
+
+const ROM: &[u8] = gbasm! {r#"
+Loop:
+  jr Loop
+"#};
+
+const MESSAGE: &[u8] = gbasm! {r#"
+  ld d, d
+  jr End
+  dw $6464
+  dw $0000
+  db $48, $69        ; Hi
+End:
+  inc b
+  ld b, b
+  inc b
+Loop:
+  jr Loop
+"#};
+
+// {{{ test speed_parse
+#[test]
+fn speed_parse() {
+    assert_eq!("2".parse(), Ok(Speed::Times(2.0)));
+    assert_eq!("0.25x".parse(), Ok(Speed::Times(0.25)));
+    assert_eq!("uncapped".parse(), Ok(Speed::Uncapped));
+    assert!("9".parse::<Speed>().is_err());
+    assert!("0.1".parse::<Speed>().is_err());
+    assert!("fast".parse::<Speed>().is_err());
+
+    assert_eq!(Speed::Times(0.5).to_string(), "0.5x");
+    assert!(Speed::Times(2.0).frame_time().unwrap() < Speed::NORMAL.frame_time().unwrap());
+    assert_eq!(Speed::Uncapped.frame_time(), None);
+    assert!(!Speed::NORMAL.faster() && Speed::Uncapped.faster());
+}
+// }}}
+
+// {{{ test speed_control
+#[test]
+fn speed_control() {
+    let mut gb = Gameboy::headless_dmg(ROM);
+    let (control_tx, control_rx) = mpsc::channel();
+    control_tx
+        .send(ControlMessage::SetSpeed(Speed::Times(4.0)))
+        .unwrap();
+    control_tx.send(ControlMessage::FastForward(true)).unwrap();
+    control_tx.send(ControlMessage::Exit).unwrap();
+
+    // Messages are handled before each frame, the first one exits
+    gb.run(Some(control_rx));
+    assert_eq!(gb.speed(), Speed::Times(4.0));
+    assert_eq!(gb.t, 0);
+}
+// }}}
+
+// {{{ test speed_frame_skip
+#[test]
+fn speed_frame_skip() {
+    let (frame_tx, frame_rx) = window::create_frame_channel();
+    let mut gb = Gameboy::dmg(ROM, frame_tx);
+    gb.tick(FRAME_CYCLES);
+    assert!(frame_rx.try_recv().is_ok());
+
+    gb.bus_mut().ppu.skip_frames(true);
+    gb.tick(3 * FRAME_CYCLES);
+    assert!(frame_rx.try_recv().is_err());
+
+    gb.bus_mut().ppu.skip_frames(false);
+    gb.tick(FRAME_CYCLES);
+    assert!(frame_rx.try_recv().is_ok());
+}
+// }}}
+
+// {{{ test speed_soft_break
+#[test]
+fn speed_soft_break() {
+    let mut gb = Gameboy::headless_dmg(MESSAGE);
+    gb.cpu.set_soft_break(LD_B_B, true);
+    gb.cpu.set_soft_break(LD_D_D, true);
+
+    // The message is only logged, and without a frontend to resume from
+    // the software breakpoint stops the run
+    gb.run(None);
+    assert_eq!(gb.cpu.b(), 0x01);
+    assert!(gb.t < FRAME_CYCLES);
+}
+// }}}
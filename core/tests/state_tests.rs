@@ -0,0 +1,75 @@
+use gamezoea::emu::gb::*;
+use gamezoea::emu::joypad::JoypadButton;
+use macros::*;
+
+const ROM: &[u8] = gbasm! {r#"
+  ld a, $05
+  ldh [$07], a
+  ld a, $04
+  ldh [$FF], a
+  ld a, $C0
+  ldh [$46], a
+  ei
+Loop:
+  inc b
+  ld a, b
+  ldh [$01], a
+  ld a, $81
+  ldh [$02], a
+  jr Loop
+"#};
+
+// {{{ test state_round_trip
+#[test]
+fn state_round_trip() {
+    let mut gb = Gameboy::headless_dmg(ROM);
+    // Stop in the middle of an M-cycle with a DMA and an input in flight
+    gb.tick(1001);
+    gb.bus_mut().enqueue_input(JoypadButton::Start, true);
+    let state = gb.save_state();
+    gb.tick(20_000);
+    let expected = gb.save_state();
+
+    let mut restored = Gameboy::headless_dmg(ROM);
+    restored.load_state(&state).unwrap();
+    assert_eq!(restored.t, 1001);
+    restored.tick(20_000);
+    assert_eq!(restored.cpu.cur_pc(), gb.cpu.cur_pc());
+    assert_eq!(restored.bus().serial.buffmt(), gb.bus().serial.buffmt());
+    assert!(restored.save_state() == expected);
+}
+// }}}
+
+// {{{ test state_rejected
+#[test]
+fn state_rejected() {
+    let mut gb = Gameboy::headless_dmg(ROM);
+    gb.tick(5000);
+    let state = gb.save_state();
+    let before = gb.save_state();
+
+    let mut other = ROM.to_vec();
+    other[0x150] = 0x00;
+    let err = Gameboy::headless_dmg(&other).load_state(&state);
+    assert_eq!(err.unwrap_err(), "state is for a different ROM");
+
+    let err = Gameboy::headless_sgb(ROM).load_state(&state);
+    assert_eq!(err.unwrap_err(), "state is not from a Super Game Boy");
+
+    assert_eq!(gb.load_state(b"nonsense").unwrap_err(), "not a save state");
+    let mut newer = state.clone();
+    newer[4] = 0xFF;
+    assert!(gb.load_state(&newer).unwrap_err().contains("newer"));
+    let mut older = state.clone();
+    older[4..6].copy_from_slice(&1u16.to_le_bytes());
+    assert_eq!(
+        gb.load_state(&older).unwrap_err(),
+        "state version 1 is no longer supported"
+    );
+
+    // A broken state leaves the machine as it was
+    let truncated = &state[..state.len() - 100];
+    assert_eq!(gb.load_state(truncated).unwrap_err(), "state is truncated");
+    assert!(gb.save_state() == before);
+}
+// }}}
@@ -0,0 +1,118 @@
+use gamezoea::emu::gb::*;
+use gamezoea::emu::symbols::*;
+use macros::*;
+
+const SYMBOLS: &str = "\
+; File generated by rgblink
+00:0150 Main
+00:C000 wBuffer
+00:FF80 hCounter
+01:4000 BankOne
+02:4000 BankTwo
+02:4010 BankTwo.loop
+";
+
+// {{{ test symbols_parse
+#[test]
+fn symbols_parse() {
+    let symbols = Symbols::parse(SYMBOLS).unwrap();
+    assert_eq!(symbols.len(), 6);
+    assert_eq!(
+        symbols.lookup("BankTwo.loop"),
+        Some(&Symbol {
+            bank: 2,
+            addr: 0x4010,
+            name: "BankTwo.loop".to_string()
+        })
+    );
+    assert_eq!(symbols.name(1, 0x4000), Some("BankOne"));
+    assert_eq!(symbols.name(0, 0x4000), None);
+
+    let err = Symbols::parse("00:0150 Main\nbogus\n").unwrap_err();
+    assert_eq!(err, "line 2: expected bank:addr label");
+}
+// }}}
+
+// {{{ test symbols_parse_map
+#[test]
+fn symbols_parse_map() {
+    const MAP: &str = "\
+SUMMARY:
+\tROM0: 336 bytes used / 16048 free
+
+ROM0 bank #0:
+\tSECTION: $0150-$015f ($0010 bytes) [\"Main\"]
+\t         $0150 = Main
+\t         $0157 = .loop
+\tEMPTY: $0160-$3fff ($3ea0 bytes)
+
+ROMX bank #2:
+\tSECTION: $4000-$40ff ($0100 bytes) [\"Two\"]
+\t         $4000 = BankTwo
+\t         $4010 = BankTwo.loop
+
+HRAM bank #0:
+\tSECTION: $ff80-$ff80 ($0001 byte) [\"Counter\"]
+\t         $ff80 = hCounter
+";
+    let symbols = Symbols::parse_map(MAP).unwrap();
+    assert_eq!(symbols.len(), 5);
+    assert_eq!(symbols.name(0, 0x0157), Some("Main.loop"));
+    assert_eq!(symbols.name(2, 0x4010), Some("BankTwo.loop"));
+    assert_eq!(symbols.lookup("hCounter").map(|s| s.addr), Some(0xFF80));
+
+    let err = Symbols::parse_map("\t$0150 = Main\n").unwrap_err();
+    assert_eq!(err, "line 1: expected $addr = label in a bank");
+}
+// }}}
+
+// {{{ test symbols_nearest
+#[test]
+fn symbols_nearest() {
+    let symbols = Symbols::parse(SYMBOLS).unwrap();
+    let nearest = |bank, addr| {
+        symbols
+            .nearest(bank, addr)
+            .map(|(s, o)| (s.name.as_str(), o))
+    };
+    assert_eq!(nearest(0, 0x0155), Some(("Main", 5)));
+    assert_eq!(nearest(2, 0x4012), Some(("BankTwo.loop", 2)));
+    assert_eq!(nearest(0, 0xC123), Some(("wBuffer", 0x123)));
+    // Labels never reach into another memory region
+    assert_eq!(nearest(0, 0x4000), None);
+    assert_eq!(nearest(0, 0xFF40), None);
+}
+// }}}
+
+// {{{ test symbols_banked_labels
+#[test]
+fn symbols_banked_labels() {
+    const ROM: &[u8] = gbasm! {r#"
+  ld a, $02
+  ld [$2000], a
+  call Sub
+Sub:
+  ret
+    "#};
+    // Four banks of MBC1
+    let mut rom = ROM.to_vec();
+    rom.resize(0x10000, 0);
+    rom[0x147] = 0x01;
+    rom[0x148] = 0x01;
+
+    let mut gb = Gameboy::headless_dmg(&rom);
+    gb.load_symbols(Symbols::parse(SYMBOLS).unwrap());
+    gb.step(2);
+    assert_eq!(gb.label(0x4000), Some("BankOne"));
+    assert_eq!(gb.symbolize(0x4012).as_deref(), Some("BankOne+$12"));
+
+    gb.step(2);
+    assert_eq!(gb.label(0x4000), Some("BankTwo"));
+    assert_eq!(gb.symbolize(0x4012).as_deref(), Some("BankTwo.loop+$2"));
+    assert_eq!(gb.symbolize(0xFF81).as_deref(), Some("hCounter+$1"));
+
+    gb.step(1);
+    assert_eq!(gb.symbolize(gb.cpu.cur_pc()).as_deref(), Some("Main+$8"));
+    assert_eq!(gb.call_stack(), vec![0x0158]);
+}
+// }}}
@@ -0,0 +1,321 @@
+use gamezoea::emu::batch::Budget;
+use gamezoea::emu::gb::*;
+use gamezoea::emu::png;
+use gamezoea::emu::ppu::{BLACK, DARK_GREY, LIGHT_GREY, WHITE};
+use gamezoea::emu::testrom::{self, *};
+use macros::*;
+
+const MOONEYE_PASS: &[u8] = gbasm! {r#"
+  ld b, 3
+  ld c, 5
+  ld d, 8
+  ld e, 13
+  ld h, 21
+  ld l, 34
+  ld b, b
+Loop:
+  jr Loop
+"#};
+
+const MOONEYE_FAIL: &[u8] = gbasm! {r#"
+  ld b, $42
+  ld c, $42
+  ld d, $42
+  ld e, $42
+  ld h, $42
+  ld l, $42
+  ld b, b
+Loop:
+  jr Loop
+"#};
+
+const BLARGG_PASS: &[u8] = gbasm! {r#"
+  ld a, $50          ; P
+  call Send
+  ld a, $61          ; a
+  call Send
+  ld a, $73          ; s
+  call Send
+  ld a, $73          ; s
+  call Send
+  ld a, $65          ; e
+  call Send
+  ld a, $64          ; d
+  call Send
+  ld a, $0A          ; \n
+  call Send
+Loop:
+  jr Loop
+Send:
+  ldh [$01], a
+  ld a, $81
+  ldh [$02], a
+  ret
+"#};
+
+const BLARGG_FAIL: &[u8] = gbasm! {r#"
+  ld a, $46          ; F
+  call Send
+  ld a, $61          ; a
+  call Send
+  ld a, $69          ; i
+  call Send
+  ld a, $6C          ; l
+  call Send
+  ld a, $65          ; e
+  call Send
+  ld a, $64          ; d
+  call Send
+  ld a, $20          ; space
+  call Send
+  ld a, $23          ; #
+  call Send
+  ld a, $32          ; 2
+  call Send
+  ld a, $0A          ; \n
+  call Send
+Loop:
+  jr Loop
+Send:
+  ldh [$01], a
+  ld a, $81
+  ldh [$02], a
+  ret
+"#};
+
+const BLARGG_MEM_RESET: &[u8] = gbasm! {r#"
+  ld a, [$A004]       ; set before the reset
+  and a
+  jr nz, Reset
+  ld a, $01
+  ld [$A004], a
+  ld a, $81           ; press reset
+  jr Signal
+Reset:
+  xor a
+  ld [$A004], a
+Signal:
+  ld [$A000], a
+  ld a, $DE
+  ld [$A001], a
+  ld a, $B0
+  ld [$A002], a
+  ld a, $61
+  ld [$A003], a
+Loop:
+  jr Loop
+"#};
+
+const BLARGG_MEM_FAIL: &[u8] = gbasm! {r#"
+  ld a, $42           ; B
+  ld [$A004], a
+  ld a, $02
+  ld [$A000], a
+  ld a, $DE
+  ld [$A001], a
+  ld a, $B0
+  ld [$A002], a
+  ld a, $61
+  ld [$A003], a
+Loop:
+  jr Loop
+"#};
+
+const LOOPS: &[u8] = gbasm! {r#"
+Loop:
+  jr Loop
+"#};
+
+const LOCKS: &[u8] = gbasm! {r#"
+  nop
+  db $D3
+"#};
+
+const BUDGET: Budget = Budget::Frames(60);
+
+/// An 8x8 RGB image using every filter type, compressed with a dynamic
+/// Huffman code
+const RGB_PNG: &[u8] = &[
+    0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44, 0x52,
+    0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x08, 0x08, 0x02, 0x00, 0x00, 0x00, 0x4B, 0x6D, 0x29,
+    0xDC, 0x00, 0x00, 0x00, 0x72, 0x49, 0x44, 0x41, 0x54, 0x78, 0xDA, 0x65, 0x8E, 0x01, 0x09, 0xC3,
+    0x40, 0x10, 0x04, 0x27, 0x4D, 0x05, 0x9C, 0x84, 0x95, 0xF0, 0x12, 0x4E, 0x42, 0x24, 0xBC, 0x84,
+    0x48, 0x88, 0x84, 0x4A, 0x58, 0x29, 0x2F, 0x21, 0x12, 0x22, 0xE1, 0x25, 0xF4, 0x9B, 0xD2, 0x52,
+    0x52, 0x58, 0x86, 0x81, 0x3D, 0xD8, 0x03, 0x10, 0x91, 0xA8, 0x52, 0x36, 0xD2, 0x2C, 0x8D, 0x7A,
+    0xB0, 0x4E, 0x28, 0x44, 0x17, 0xE5, 0xC2, 0xDB, 0x28, 0x50, 0x41, 0x3B, 0xEA, 0xBF, 0x3E, 0x93,
+    0x8A, 0xE8, 0x11, 0x9C, 0xCC, 0xAF, 0xDF, 0x5F, 0x57, 0x63, 0x85, 0x38, 0xF3, 0xF8, 0xC8, 0x88,
+    0x17, 0x39, 0xD3, 0x6B, 0x75, 0xDD, 0x1C, 0x36, 0xCD, 0xE5, 0xB0, 0x26, 0x5A, 0x1D, 0x5F, 0x89,
+    0xFD, 0xC2, 0xF7, 0x78, 0xFF, 0xE7, 0x13, 0xDB, 0x8F, 0x2A, 0x0E, 0x0F, 0x05, 0x54, 0x25, 0x00,
+    0x00, 0x00, 0x00, 0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82,
+];
+
+/// A 5x2 2-bit palette image with transparent white, compressed with the
+/// fixed Huffman code
+const PALETTE_PNG: &[u8] = &[
+    0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44, 0x52,
+    0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x02, 0x02, 0x03, 0x00, 0x00, 0x00, 0xED, 0x04, 0xFE,
+    0xCE, 0x00, 0x00, 0x00, 0x0C, 0x50, 0x4C, 0x54, 0x45, 0xFF, 0xFF, 0xFF, 0xAA, 0xAA, 0xAA, 0x55,
+    0x55, 0x55, 0x00, 0x00, 0x00, 0x01, 0x33, 0x5B, 0x34, 0x00, 0x00, 0x00, 0x01, 0x74, 0x52, 0x4E,
+    0x53, 0x00, 0x40, 0xE6, 0xD8, 0x66, 0x00, 0x00, 0x00, 0x0E, 0x49, 0x44, 0x41, 0x54, 0x78, 0x01,
+    0x63, 0x90, 0x66, 0x60, 0x78, 0x72, 0x00, 0x00, 0x03, 0x15, 0x01, 0xC0, 0x03, 0xAC, 0xBB, 0xAB,
+    0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82,
+];
+
+// {{{ test testrom_mooneye
+#[test]
+fn testrom_mooneye() {
+    let mut gb = Gameboy::headless_dmg(MOONEYE_PASS);
+    let report = testrom::run(&mut gb, &mut Mooneye, BUDGET);
+    assert_eq!(report.verdict, Verdict::Pass);
+    assert!(report.cycles < FRAME_CYCLES);
+
+    let mut gb = Gameboy::headless_dmg(MOONEYE_FAIL);
+    let report = testrom::run(&mut gb, &mut Mooneye, BUDGET);
+    assert_eq!(
+        report.verdict,
+        Verdict::Fail("B:42 C:42 D:42 E:42 H:42 L:42".to_string())
+    );
+}
+// }}}
+
+// {{{ test testrom_blargg_serial
+#[test]
+fn testrom_blargg_serial() {
+    let mut gb = Gameboy::headless_dmg(BLARGG_PASS);
+    let report = testrom::run(&mut gb, &mut BlarggSerial, BUDGET);
+    assert_eq!(report.verdict, Verdict::Pass);
+    assert_eq!(report.serial, "Passed\n");
+
+    let mut gb = Gameboy::headless_dmg(BLARGG_FAIL);
+    let report = testrom::run(&mut gb, &mut BlarggSerial, BUDGET);
+    assert_eq!(report.verdict, Verdict::Fail("Failed #2".to_string()));
+    assert_eq!(report.verdict.to_string(), "failed: Failed #2");
+}
+// }}}
+
+// {{{ test testrom_blargg_memory
+#[test]
+fn testrom_blargg_memory() {
+    // Passes once reset is pressed
+    let mut gb = Gameboy::headless_dmg(BLARGG_MEM_RESET);
+    let report = testrom::run(&mut gb, &mut BlarggMemory::default(), BUDGET);
+    assert_eq!(report.verdict, Verdict::Pass);
+    assert!(report.cycles > 6 * FRAME_CYCLES);
+
+    let mut gb = Gameboy::headless_dmg(BLARGG_MEM_FAIL);
+    let report = testrom::run(&mut gb, &mut BlarggMemory::default(), BUDGET);
+    assert_eq!(report.verdict, Verdict::Fail("result 2: B".to_string()));
+}
+// }}}
+
+// {{{ test testrom_screenshot
+#[test]
+fn testrom_screenshot() {
+    let mut gb = Gameboy::headless_dmg(MOONEYE_PASS);
+    testrom::run(&mut gb, &mut Mooneye, BUDGET);
+    let (width, height, frame) = gb.bus_mut().frame();
+
+    // References are drawn in grey rather than the screen's green
+    let mut grey: Vec<u8> = frame
+        .chunks_exact(4)
+        .flat_map(|pixel| {
+            let shade = [WHITE, LIGHT_GREY, DARK_GREY, BLACK]
+                .iter()
+                .position(|color| color[..] == *pixel)
+                .unwrap();
+            let level = 0xFF - 0x55 * shade as u8;
+            [level, level, level, 0xFF]
+        })
+        .collect();
+    let reference = png::encode(width, height, &grey);
+    let mut gb = Gameboy::headless_dmg(MOONEYE_PASS);
+    let mut screenshot = Screenshot::new(&reference).unwrap();
+    let report = testrom::run(&mut gb, &mut screenshot, BUDGET);
+    assert_eq!(report.verdict, Verdict::Pass);
+
+    // Without LD B,B the screen is compared when the budget runs out
+    let mut gb = Gameboy::headless_dmg(LOOPS);
+    let report = testrom::run(&mut gb, &mut screenshot, Budget::Frames(2));
+    assert_eq!(report.verdict, Verdict::Pass);
+
+    let pixel = (2 * width + 3) * 4;
+    grey[pixel..pixel + 3].copy_from_slice(&[0x80; 3]);
+    let reference = png::encode(width, height, &grey);
+    let mut gb = Gameboy::headless_dmg(MOONEYE_PASS);
+    let mut screenshot = Screenshot::new(&reference).unwrap();
+    let report = testrom::run(&mut gb, &mut screenshot, BUDGET);
+    assert_eq!(
+        report.verdict,
+        Verdict::Fail("1 pixels differ, the first at 3,2".to_string())
+    );
+
+    let reference = png::encode(1, 1, &[0xFF; 4]);
+    let mut screenshot = Screenshot::new(&reference).unwrap();
+    let mut gb = Gameboy::headless_dmg(MOONEYE_PASS);
+    let report = testrom::run(&mut gb, &mut screenshot, BUDGET);
+    assert_eq!(
+        report.verdict,
+        Verdict::Fail("screen is 160x144, reference is 1x1".to_string())
+    );
+}
+// }}}
+
+// {{{ test testrom_timeout_and_lock
+#[test]
+fn testrom_timeout_and_lock() {
+    let mut gb = Gameboy::headless_dmg(LOOPS);
+    let report = testrom::run(&mut gb, &mut Mooneye, Budget::Cycles(10_000));
+    assert_eq!(report.verdict, Verdict::Timeout);
+    assert_eq!(report.cycles, 10_000);
+    assert!(!report.verdict.passed());
+
+    let mut gb = Gameboy::headless_dmg(LOCKS);
+    let report = testrom::run(&mut gb, &mut BlarggSerial, BUDGET);
+    assert_eq!(
+        report.verdict,
+        Verdict::Fail("locked at $0151 ($D3)".to_string())
+    );
+}
+// }}}
+
+// {{{ test testrom_protocol
+#[test]
+fn testrom_protocol() {
+    assert!(testrom::protocol("blargg").is_ok());
+    assert!(testrom::protocol("blargg-mem").is_ok());
+    assert!(testrom::protocol("mooneye").is_ok());
+    assert!(testrom::protocol("acid").is_err());
+    let error = testrom::protocol("screenshot:/nonexistent/acid2.png")
+        .err()
+        .unwrap();
+    assert!(error.starts_with("/nonexistent/acid2.png: "));
+}
+// }}}
+
+// {{{ test testrom_png_decode
+#[test]
+fn testrom_png_decode() {
+    let rgba: Vec<u8> = (0..12 * 7 * 4).map(|i| (i * 7) as u8).collect();
+    assert_eq!(png::decode(&png::encode(12, 7, &rgba)), Ok((12, 7, rgba)));
+
+    let (width, height, rgba) = png::decode(RGB_PNG).unwrap();
+    assert_eq!((width, height), (8, 8));
+    for (i, pixel) in rgba.chunks_exact(4).enumerate() {
+        let (x, y) = ((i % 8) as u8, (i / 8) as u8);
+        assert_eq!(pixel, [x * 32, y * 32, (x ^ y) * 16, 0xFF]);
+    }
+
+    let (width, height, rgba) = png::decode(PALETTE_PNG).unwrap();
+    assert_eq!((width, height), (5, 2));
+    let levels: Vec<u8> = rgba.chunks_exact(4).map(|pixel| pixel[0]).collect();
+    assert_eq!(
+        levels,
+        [0xFF, 0xAA, 0x55, 0x00, 0xFF, 0x00, 0x55, 0xAA, 0xFF, 0x00]
+    );
+    assert_eq!(rgba[3], 0x00);
+    assert_eq!(rgba[7], 0xFF);
+
+    assert!(png::decode(b"GIF89a").is_err());
+    assert!(png::decode(&RGB_PNG[..RGB_PNG.len() / 2]).is_err());
+}
+// }}}
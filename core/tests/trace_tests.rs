@@ -0,0 +1,146 @@
+use gamezoea::emu::gb::*;
+use gamezoea::emu::trace::*;
+use macros::*;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+const ROM: &[u8] = gbasm! {r#"
+  ld a, $01
+  ldh a, [$44]
+  ld b, a
+Loop:
+  inc c
+  jr Loop
+"#};
+
+/// Collects what the trace writes so the test can read it back
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+impl Output {
+    fn lines(&self) -> Vec<String> {
+        let bytes = self.0.lock().unwrap();
+        String::from_utf8_lossy(&bytes)
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// {{{ test trace_doctor
+#[test]
+fn trace_doctor() {
+    let out = Output::default();
+    let mut gb = Gameboy::headless_dmg(ROM);
+    gb.set_trace(Trace::new(out.clone(), Format::Doctor));
+    gb.step(5);
+    gb.take_trace();
+
+    let lines = out.lines();
+    assert_eq!(lines.len(), 5);
+    assert_eq!(
+        lines[0],
+        "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:C3,50,01,00"
+    );
+    assert!(lines[1].ends_with("PC:0150 PCMEM:3E,01,F0,44"));
+    // LY always reads as $90 while tracing for gameboy-doctor
+    assert!(lines[4].starts_with("A:90 "));
+    assert!(lines[4].contains("B:90 "));
+}
+// }}}
+
+// {{{ test trace_conditions
+#[test]
+fn trace_conditions() {
+    let out = Output::default();
+    let mut gb = Gameboy::headless_dmg(ROM);
+    let trace = Trace::new(out.clone(), Format::Verbose)
+        .starting(Condition::Pc(0x0155))
+        .stopping(Condition::Instructions(3));
+    gb.set_trace(trace);
+    gb.step(20);
+
+    let lines = out.lines();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].contains("PC:0155"));
+    assert!(lines[1].contains("PC:0156"));
+    assert!(lines[2].contains("PC:0155"));
+    assert!(lines[0].contains(" || R:"));
+    assert!(gb.take_trace().unwrap().done());
+
+    let label = |name: &str| match name {
+        "Loop" => Some(0x0155),
+        "cafe" => Some(0x0160),
+        _ => None,
+    };
+    assert_eq!(
+        Condition::parse("pc:0150", label),
+        Ok(Condition::Pc(0x0150))
+    );
+    assert_eq!(
+        Condition::parse("pc:Loop", label),
+        Ok(Condition::Pc(0x0155))
+    );
+    assert_eq!(
+        Condition::parse("1000", label),
+        Ok(Condition::Instructions(1000))
+    );
+    assert_eq!(
+        Condition::parse("pc:cafe", label),
+        Ok(Condition::Pc(0x0160))
+    );
+    assert_eq!(
+        Condition::parse("pc:$cafe", label),
+        Ok(Condition::Pc(0xCAFE))
+    );
+    assert!(Condition::parse("pc:Nowhere", label).is_err());
+}
+// }}}
+
+// {{{ test trace_diff
+#[test]
+fn trace_diff() {
+    let reference = "\
+A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:C3,50,01,3E
+A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0150 PCMEM:3E,01,F0,44
+A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0152 PCMEM:F0,44,47,0C
+";
+    let (divergence, lines) = diff(reference.as_bytes(), reference.as_bytes()).unwrap();
+    assert_eq!((divergence, lines), (None, 3));
+
+    let mut ours: Vec<String> = reference.lines().map(str::to_string).collect();
+    ours[2] = ours[2].replace("F:B0", "F:80");
+    let ours = ours.join("\n");
+    let divergence = diff(ours.as_bytes(), reference.as_bytes())
+        .unwrap()
+        .0
+        .unwrap();
+    assert_eq!(divergence.line, 3);
+    assert_eq!(divergence.context.len(), 2);
+    assert_eq!(divergence.fields(), vec!["F"]);
+    assert!(divergence.to_string().ends_with("Differing: F"));
+
+    let short: String = reference
+        .lines()
+        .take(2)
+        .map(|l| format!("{l}\n"))
+        .collect();
+    let divergence = diff(short.as_bytes(), reference.as_bytes())
+        .unwrap()
+        .0
+        .unwrap();
+    assert_eq!(divergence.line, 3);
+    assert_eq!(divergence.ours, None);
+}
+// }}}
@@ -0,0 +1,98 @@
+use gamezoea::emu::gb::*;
+use gamezoea::emu::watch::*;
+use macros::*;
+use std::sync::{Arc, Mutex};
+
+const ROM: &[u8] = gbasm! {r#"
+  ld hl, $C000
+  ld a, $11
+  ld [hl], a
+  ld a, $42
+  ld [hl], a
+  ld b, [hl]
+Loop:
+  jr Loop
+    "#};
+
+// {{{ test watch_write_stops
+#[test]
+fn watch_write_stops() {
+    let mut gb = Gameboy::headless_dmg(ROM);
+    let id = gb.add_watchpoint(Watchpoint::new(0xC000..=0xC0FF, &[Access::Write]));
+    let event = gb.step(100);
+    assert_eq!(
+        event,
+        Some(Event::Watch(Hit {
+            id,
+            access: Access::Write,
+            addr: 0xC000,
+            value: 0x11,
+            comp: Comp::Cpu,
+        }))
+    );
+    assert_eq!(gb.cpu.cur_pc(), 0x0155);
+}
+// }}}
+
+// {{{ test watch_value
+#[test]
+fn watch_value() {
+    let mut gb = Gameboy::headless_dmg(ROM);
+    gb.add_watchpoint(Watchpoint::new(0xC000..=0xC000, &[Access::Write]).with_value(0x42));
+    match gb.step(100) {
+        Some(Event::Watch(hit)) => assert_eq!(hit.value, 0x42),
+        other => panic!("unexpected event: {:?}", other),
+    }
+}
+// }}}
+
+// {{{ test watch_read_and_execute
+#[test]
+fn watch_read_and_execute() {
+    let mut gb = Gameboy::headless_dmg(ROM);
+    let read = gb.add_watchpoint(Watchpoint::new(0xC000..=0xC000, &[Access::Read]));
+    let exec = gb.add_watchpoint(Watchpoint::new(0x015A..=0x015A, &[Access::Execute]));
+
+    match gb.step(100) {
+        Some(Event::Watch(hit)) => {
+            assert_eq!(hit.id, read);
+            assert_eq!(hit.value, 0x42);
+        }
+        other => panic!("unexpected event: {:?}", other),
+    }
+    match gb.step(100) {
+        Some(Event::Watch(hit)) => {
+            assert_eq!(hit.id, exec);
+            assert_eq!(hit.value, 0x18);
+        }
+        other => panic!("unexpected event: {:?}", other),
+    }
+
+    assert!(gb.remove_watchpoint(read));
+    assert!(!gb.remove_watchpoint(read));
+}
+// }}}
+
+// {{{ test watch_callback
+#[test]
+fn watch_callback() {
+    let mut gb = Gameboy::headless_dmg(ROM);
+    let hits = Arc::new(Mutex::new(Vec::new()));
+    let log = hits.clone();
+    gb.add_watchpoint(
+        Watchpoint::new(0xFF04..=0xFF04, &[Access::Write])
+            .with_comp(Comp::Timer)
+            .with_callback(move |hit| log.lock().unwrap().push(*hit)),
+    );
+    gb.add_watchpoint(
+        Watchpoint::new(0xC000..=0xC000, &[Access::Write])
+            .with_comp(Comp::Ppu)
+            .with_callback(|_| panic!("the PPU never writes WRAM")),
+    );
+
+    assert_eq!(gb.step(100), None);
+    let hits = hits.lock().unwrap();
+    assert!(!hits.is_empty());
+    assert!(hits.iter().all(|hit| hit.comp == Comp::Timer));
+}
+// }}}